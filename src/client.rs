@@ -138,14 +138,16 @@
 use crate::codec::{Codec, SendError, UserError};
 use crate::ext::Protocol;
 use crate::frame::{Headers, Pseudo, Reason, Settings, StreamId};
+use crate::observer::StateObserver;
 use crate::proto::{self, Error};
-use crate::{FlowControl, PingPong, RecvStream, SendStream};
+use crate::{EffectiveSettings, FlowControl, PingPong, RecvStream, SendStream};
 
 use bytes::{Buf, Bytes};
 use http::{uri, HeaderMap, Method, Request, Response, Version};
 use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use std::usize;
@@ -323,6 +325,52 @@ pub struct Builder {
     /// Maximum amount of bytes to "buffer" for writing per stream.
     max_send_buffer_size: usize,
 
+    /// Maximum amount of unread received bytes to buffer per stream.
+    max_recv_buffer_size: usize,
+
+    /// Maximum encoded size of a received header block.
+    max_header_block_size: Option<usize>,
+
+    /// Whether WINDOW_UPDATE frames are balanced between the connection and
+    /// stream windows.
+    balance_window_updates: bool,
+
+    /// Whether WINDOW_UPDATE frames held back by the policy are sent once the
+    /// connection has nothing else to send.
+    flush_idle_window_updates: bool,
+
+    /// Round-trip time used to grow the connection window with the rate at
+    /// which DATA is received, if enabled.
+    adaptive_connection_window: Option<Duration>,
+
+    /// Whether a stream whose receive window is overrun is reset, rather
+    /// than the connection closed.
+    reset_overrun_streams: bool,
+
+    /// Decides when released receive capacity is advertised to the peer.
+    window_update_policy: Arc<dyn proto::WindowUpdatePolicy>,
+
+    /// Notified of every stream state transition.
+    state_observer: Option<Arc<dyn StateObserver>>,
+
+    /// Max number of lifecycle events queued per stream, or 0 if disabled.
+    stream_event_capacity: usize,
+
+    /// How long a stream's send window may stay exhausted before it is reset.
+    stalled_stream_timeout: Option<Duration>,
+
+    /// Max number of each rate-limited frame type accepted per interval.
+    frame_rate_limit: Option<(usize, Duration)>,
+
+    /// Max number of empty DATA frames accepted per interval.
+    empty_data_rate_limit: Option<(usize, Duration)>,
+
+    /// Max number of frames read each time the connection is polled.
+    max_frames_per_poll: Option<usize>,
+
+    /// How long the peer may take to acknowledge our SETTINGS.
+    settings_ack_timeout: Option<Duration>,
+
     /// Maximum number of locally reset streams to keep at a time.
     reset_stream_max: usize,
 
@@ -330,12 +378,28 @@ pub struct Builder {
     /// accept queue.
     pending_accept_reset_stream_max: usize,
 
+    /// Maximum number of streams reset because of errors caused by the peer.
+    local_max_error_reset_streams: Option<usize>,
+
     /// Initial `Settings` frame to send as part of the handshake.
     settings: Settings,
 
     /// The stream ID of the first (lowest) stream. Subsequent streams will use
     /// monotonically increasing stream IDs.
     stream_id: StreamId,
+
+    /// Picks the ID of each stream after the first.
+    stream_id_allocator: Arc<dyn proto::StreamIdAllocator>,
+}
+
+/// An alternative service advertised by the server in an `ALTSVC` frame.
+///
+/// Returned by [`Connection::alt_svc`]. See [RFC 7838] for details.
+///
+/// [RFC 7838]: https://tools.ietf.org/html/rfc7838#section-4
+#[derive(Debug, Clone)]
+pub struct AltSvc {
+    inner: crate::frame::AltSvc,
 }
 
 #[derive(Debug)]
@@ -535,11 +599,32 @@ where
     /// This method returns the currently acknowledged value received from the
     /// remote.
     ///
+    /// Until this returns `true`, `send_request` rejects requests carrying a
+    /// [`Protocol`](crate::ext::Protocol) extension.
+    ///
     /// [1]: https://datatracker.ietf.org/doc/html/rfc8441#section-4
     /// [2]: https://datatracker.ietf.org/doc/html/rfc8441#section-3
     pub fn is_extended_connect_protocol_enabled(&self) -> bool {
         self.inner.is_extended_connect_protocol_enabled()
     }
+
+    /// Returns the authorities that requests on this connection were sent
+    /// to, in the order they were first used.
+    ///
+    /// A connection pool can use this to coalesce connections, as described
+    /// in [RFC 7540 §9.1.1][1]: a request for one of these authorities may
+    /// reuse the connection. Reusing it for any other authority also requires
+    /// checking that the server's certificate covers that authority, which
+    /// is up to the caller since `h2` does not see the TLS session.
+    ///
+    /// Authorities are compared case-insensitively, and each is reported once.
+    /// At most 64 authorities are remembered; requests to further authorities
+    /// are still sent but not reported.
+    ///
+    /// [1]: https://datatracker.ietf.org/doc/html/rfc7540#section-9.1.1
+    pub fn authorities(&self) -> Vec<http::uri::Authority> {
+        self.inner.authorities()
+    }
 }
 
 impl<B> fmt::Debug for SendRequest<B>
@@ -584,6 +669,15 @@ where
     pub fn num_wired_streams(&self) -> usize {
         self.inner.num_wired_streams()
     }
+
+    /// Returns the ids of the streams that are able to send DATA.
+    ///
+    /// A stream is included when its send half is open, sending has not been
+    /// paused with `SendStream::pause_send`, and the peer's stream window is
+    /// not exhausted.
+    pub fn streams_ready_to_send(&self) -> Vec<StreamId> {
+        self.inner.ready_to_send()
+    }
 }
 
 // ===== impl ReadySendRequest =====
@@ -638,13 +732,29 @@ impl Builder {
     pub fn new() -> Builder {
         Builder {
             max_send_buffer_size: proto::DEFAULT_MAX_SEND_BUFFER_SIZE,
+            max_recv_buffer_size: usize::MAX,
+            max_header_block_size: None,
+            balance_window_updates: false,
+            flush_idle_window_updates: false,
+            adaptive_connection_window: None,
+            reset_overrun_streams: true,
             reset_stream_duration: Duration::from_secs(proto::DEFAULT_RESET_STREAM_SECS),
             reset_stream_max: proto::DEFAULT_RESET_STREAM_MAX,
             pending_accept_reset_stream_max: proto::DEFAULT_REMOTE_RESET_STREAM_MAX,
+            local_max_error_reset_streams: Some(proto::DEFAULT_LOCAL_RESET_COUNT_MAX),
             initial_target_connection_window_size: None,
             initial_max_send_streams: usize::MAX,
             settings: Default::default(),
             stream_id: 1.into(),
+            stream_id_allocator: Arc::new(proto::MonotonicIds),
+            window_update_policy: Arc::new(proto::ThresholdPolicy),
+            state_observer: None,
+            stream_event_capacity: 0,
+            stalled_stream_timeout: None,
+            frame_rate_limit: None,
+            empty_data_rate_limit: None,
+            max_frames_per_poll: None,
+            settings_ack_timeout: None,
         }
     }
 
@@ -654,6 +764,10 @@ impl Builder {
     /// The initial window of a stream is used as part of flow control. For more
     /// details, see [`FlowControl`].
     ///
+    /// The size only takes effect once the peer has acknowledged it. Streams
+    /// opened before then start with the default window, which is shrunk or
+    /// grown to the configured size on acknowledgement.
+    ///
     /// The default value is 65,535.
     ///
     /// [`FlowControl`]: ../struct.FlowControl.html
@@ -792,6 +906,22 @@ impl Builder {
         self
     }
 
+    /// Sets the max encoded size of a received header block.
+    ///
+    /// A header block is carried by a HEADERS or PUSH_PROMISE frame followed
+    /// by any number of CONTINUATION frames. Unlike [`max_header_list_size`],
+    /// this limit applies to the compressed bytes received, so that a peer
+    /// cannot keep sending CONTINUATION frames indefinitely. A peer exceeding
+    /// it is treated as a connection error of type `ENHANCE_YOUR_CALM`.
+    ///
+    /// The default is currently 16MB, but may change.
+    ///
+    /// [`max_header_list_size`]: #method.max_header_list_size
+    pub fn max_header_block_size(&mut self, max: usize) -> &mut Self {
+        self.max_header_block_size = Some(max);
+        self
+    }
+
     /// Sets the maximum number of concurrent streams.
     ///
     /// The maximum concurrent streams setting only controls the maximum number
@@ -941,6 +1071,10 @@ impl Builder {
     /// this state will be maintained in memory. Once the duration elapses, the
     /// stream state is purged from memory.
     ///
+    /// The duration is also the grace period for the peer to notice the
+    /// reset. DATA received on the stream after it elapses is answered with a
+    /// `STREAM_CLOSED` reset, even if the stream is still in use locally.
+    ///
     /// Once the stream has been fully purged from memory, any additional frames
     /// received for that stream will result in a connection level protocol
     /// error, forcing the connection to terminate.
@@ -1016,6 +1150,23 @@ impl Builder {
         self
     }
 
+    /// Sets the maximum number of streams reset because of errors caused by
+    /// the peer.
+    ///
+    /// A peer that keeps sending malformed or otherwise invalid frames on its
+    /// streams only gets those streams reset, at little cost to itself. Once
+    /// more than `max` streams have been reset this way over the lifetime of
+    /// the connection, the next one is reset as usual and the connection is
+    /// then closed with a GOAWAY carrying `ENHANCE_YOUR_CALM`.
+    ///
+    /// Passing `None` disables the limit.
+    ///
+    /// The default value is currently 1024, but could change.
+    pub fn max_local_error_reset_streams(&mut self, max: Option<usize>) -> &mut Self {
+        self.local_max_error_reset_streams = max;
+        self
+    }
+
     /// Sets the maximum send buffer size per stream.
     ///
     /// Once a stream has buffered up to (or over) the maximum, the stream's
@@ -1023,6 +1174,11 @@ impl Builder {
     /// stream have been written to the connection, the send buffer capacity
     /// will be freed up again.
     ///
+    /// This bounds memory rather than protocol-level flow: the limit applies
+    /// even while the peer's flow control window still has room, so
+    /// `poll_capacity` will wait for buffered data to be flushed before
+    /// yielding more capacity.
+    ///
     /// The default is currently ~400KB, but may change.
     ///
     /// # Panics
@@ -1034,6 +1190,196 @@ impl Builder {
         self
     }
 
+    /// Sets the maximum receive buffer size per stream.
+    ///
+    /// Once a stream has buffered more received data than the maximum, the
+    /// stream's WINDOW_UPDATE frames are held back, even if capacity has been
+    /// released, until the application reads enough of the buffered data.
+    /// This applies backpressure to the peer.
+    ///
+    /// By default, there is no limit.
+    pub fn max_recv_buffer_size(&mut self, max: usize) -> &mut Self {
+        self.max_recv_buffer_size = max;
+        self
+    }
+
+    /// Enables or disables balancing WINDOW_UPDATE frames between the
+    /// connection and stream windows.
+    ///
+    /// Normally, released capacity is only advertised once enough of a
+    /// window has been consumed. When balancing is enabled, it is advertised
+    /// right away on whichever window is holding the peer back: a stream
+    /// whose window is small compared to the connection window, or the
+    /// connection when its window is smaller than the initial stream window.
+    ///
+    /// This is disabled by default.
+    pub fn balance_window_updates(&mut self, enabled: bool) -> &mut Self {
+        self.balance_window_updates = enabled;
+        self
+    }
+
+    /// Enables or disables flushing WINDOW_UPDATE frames when the connection
+    /// goes idle.
+    ///
+    /// Released capacity that the window update policy holds back is
+    /// normally batched until enough has accumulated. When this is enabled,
+    /// it is still batched while other frames are queued, but advertised as
+    /// soon as the connection has nothing else to send, so the peer is never
+    /// left waiting on capacity that was already released.
+    ///
+    /// This is disabled by default.
+    pub fn flush_window_updates_when_idle(&mut self, enabled: bool) -> &mut Self {
+        self.flush_idle_window_updates = enabled;
+        self
+    }
+
+    /// Grows the connection window with the rate at which DATA is received.
+    ///
+    /// The rate is measured over each `rtt`, the expected round-trip time to
+    /// the peer. Whenever the peer sends fast enough that the connection
+    /// window no longer covers twice the data in flight over one round trip,
+    /// the window is grown to match, so a sustained high throughput is not
+    /// held back by flow control. The window is never shrunk.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "unstable")]
+    pub fn adaptive_connection_window(&mut self, rtt: Duration) -> &mut Self {
+        self.adaptive_connection_window = Some(rtt);
+        self
+    }
+
+    /// Sets whether a peer sending more DATA than a stream's receive window
+    /// allows only resets that stream.
+    ///
+    /// When enabled, the stream is reset with `FLOW_CONTROL_ERROR`, and other
+    /// streams on the connection carry on. When disabled, the connection is
+    /// closed with a GOAWAY carrying `FLOW_CONTROL_ERROR` instead. Overrunning
+    /// the connection window always closes the connection.
+    ///
+    /// Either way, the resulting error reports
+    /// [`Error::is_flow_control_violation`].
+    ///
+    /// This is enabled by default.
+    ///
+    /// [`Error::is_flow_control_violation`]: ../struct.Error.html#method.is_flow_control_violation
+    pub fn reset_stream_on_window_overrun(&mut self, enabled: bool) -> &mut Self {
+        self.reset_overrun_streams = enabled;
+        self
+    }
+
+    /// Sets an observer notified each time a stream changes state, or a
+    /// frame of an unknown type is received and discarded.
+    ///
+    /// This can be used to export stream lifecycle events to a metrics or
+    /// tracing system. See [`StateObserver`] for details.
+    ///
+    /// By default, no observer is set.
+    ///
+    /// [`StateObserver`]: ../trait.StateObserver.html
+    #[cfg(feature = "unstable")]
+    pub fn state_observer<O>(&mut self, observer: O) -> &mut Self
+    where
+        O: StateObserver + 'static,
+    {
+        self.state_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Enables a queue of lifecycle events on each stream.
+    ///
+    /// Up to `capacity` events are queued per stream and can be awaited with
+    /// [`RecvStream::event`]. Once a stream's queue is full, new events are
+    /// dropped until the application catches up.
+    ///
+    /// By default, no events are queued.
+    ///
+    /// [`RecvStream::event`]: ../struct.RecvStream.html#method.event
+    #[cfg(feature = "unstable")]
+    pub fn stream_event_queue(&mut self, capacity: usize) -> &mut Self {
+        self.stream_event_capacity = capacity;
+        self
+    }
+
+    /// Sets how long a stream may wait on an exhausted send window.
+    ///
+    /// Once sent data has used up a stream's send window, the peer is expected
+    /// to grow it again with WINDOW_UPDATE. If a stream with data still
+    /// buffered sees no window growth for `dur`, it is reset with `CANCEL`
+    /// instead of waiting forever. A timer wakes the connection when a stream
+    /// stalls, so this must be used within a Tokio runtime with the time
+    /// driver enabled.
+    ///
+    /// By default, stalled streams are never reset.
+    pub fn stalled_stream_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.stalled_stream_timeout = Some(dur);
+        self
+    }
+
+    /// Limits how often the peer may send frames that carry no stream
+    /// progress.
+    ///
+    /// PING, SETTINGS and PRIORITY frames, as well as empty DATA frames that
+    /// do not end their stream, are each counted separately. If the peer
+    /// sends more than `max` frames of any one of these types within
+    /// `interval`, the connection is closed with a GOAWAY carrying
+    /// `ENHANCE_YOUR_CALM`. Acknowledgements are not counted.
+    ///
+    /// By default, these frames are not rate limited.
+    pub fn frame_rate_limit(&mut self, max: usize, interval: Duration) -> &mut Self {
+        self.frame_rate_limit = Some((max, interval));
+        self
+    }
+
+    /// Limits how often the peer may send empty DATA frames.
+    ///
+    /// An empty DATA frame that does not end its stream consumes no flow
+    /// control window, so a peer can send them without limit. If the peer
+    /// sends more than `max` of them within `interval`, the connection is
+    /// closed with a GOAWAY carrying `ENHANCE_YOUR_CALM`.
+    ///
+    /// This replaces the limit set with [`frame_rate_limit`] for empty DATA
+    /// frames only, and applies even if no general limit is set.
+    ///
+    /// By default, empty DATA frames are subject to [`frame_rate_limit`].
+    ///
+    /// [`frame_rate_limit`]: #method.frame_rate_limit
+    pub fn empty_data_rate_limit(&mut self, max: usize, interval: Duration) -> &mut Self {
+        self.empty_data_rate_limit = Some((max, interval));
+        self
+    }
+
+    /// Sets the maximum number of frames read per poll of the connection.
+    ///
+    /// Once `max` frames have been processed, the connection flushes what it
+    /// has to write and yields back to the runtime, scheduling itself to be
+    /// polled again. This keeps a peer that sends frames faster than they can
+    /// be handled from monopolizing the task running the connection.
+    ///
+    /// By default, frames are read for as long as they are available.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `max` is 0.
+    pub fn max_frames_per_poll(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_frames_per_poll must be greater than 0");
+        self.max_frames_per_poll = Some(max);
+        self
+    }
+
+    /// Sets how long the peer may take to acknowledge our SETTINGS.
+    ///
+    /// If a SETTINGS frame we sent, including the one in the connection
+    /// preface, is not acknowledged within `dur`, the connection is closed
+    /// with a GOAWAY carrying `SETTINGS_TIMEOUT`. A timer wakes the
+    /// connection when the timeout elapses, so this must be used within a
+    /// Tokio runtime with the time driver enabled.
+    ///
+    /// By default, the peer may take as long as it likes.
+    pub fn settings_ack_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.settings_ack_timeout = Some(dur);
+        self
+    }
+
     /// Enables or disables server push promises.
     ///
     /// This value is included in the initial SETTINGS handshake.
@@ -1116,6 +1462,33 @@ impl Builder {
         self
     }
 
+    /// Sets how the ID of each stream after the first is picked.
+    ///
+    /// By default, every odd stream ID is used in turn. An allocator may
+    /// instead skip IDs, for example to leave a range unused. Once it has no
+    /// ID left, opening a stream fails as when stream IDs are exhausted.
+    #[cfg(feature = "unstable")]
+    pub fn stream_id_allocator<A>(&mut self, allocator: A) -> &mut Self
+    where
+        A: proto::StreamIdAllocator + 'static,
+    {
+        self.stream_id_allocator = Arc::new(allocator);
+        self
+    }
+
+    /// Sets the policy deciding when released receive capacity is sent to
+    /// the peer in a WINDOW_UPDATE frame.
+    ///
+    /// By default, an update is sent once half of the window is released.
+    #[cfg(feature = "unstable")]
+    pub fn window_update_policy<P>(&mut self, policy: P) -> &mut Self
+    where
+        P: proto::WindowUpdatePolicy + 'static,
+    {
+        self.window_update_policy = Arc::new(policy);
+        self
+    }
+
     /// Creates a new configured HTTP/2 client backed by `io`.
     ///
     /// It is expected that `io` already be in an appropriate state to commence
@@ -1279,21 +1652,42 @@ where
             codec.set_max_recv_header_list_size(max as usize);
         }
 
+        if let Some(max) = builder.max_header_block_size {
+            codec.set_max_recv_header_block_size(max);
+        }
+
         // Send initial settings frame
         codec
-            .buffer(builder.settings.clone().into())
+            .buffer(builder.settings.to_frame())
             .expect("invalid SETTINGS frame");
 
         let inner = proto::Connection::new(
             codec,
             proto::Config {
                 next_stream_id: builder.stream_id,
+                stream_id_allocator: builder.stream_id_allocator.clone(),
                 initial_max_send_streams: builder.initial_max_send_streams,
                 max_send_buffer_size: builder.max_send_buffer_size,
+                max_recv_buffer_size: builder.max_recv_buffer_size,
+                balance_window_updates: builder.balance_window_updates,
+                flush_idle_window_updates: builder.flush_idle_window_updates,
+                adaptive_connection_window: builder.adaptive_connection_window,
+                reset_overrun_streams: builder.reset_overrun_streams,
                 reset_stream_duration: builder.reset_stream_duration,
                 reset_stream_max: builder.reset_stream_max,
                 remote_reset_stream_max: builder.pending_accept_reset_stream_max,
+                local_error_reset_streams_max: builder.local_max_error_reset_streams,
                 settings: builder.settings.clone(),
+                window_update_policy: builder.window_update_policy.clone(),
+                state_observer: builder.state_observer.clone(),
+                stream_event_capacity: builder.stream_event_capacity,
+                stalled_stream_timeout: builder.stalled_stream_timeout,
+                frame_rate_limit: builder.frame_rate_limit,
+                empty_data_rate_limit: builder.empty_data_rate_limit,
+                max_lifetime_streams: None,
+                expected_scheme: None,
+                max_frames_per_poll: builder.max_frames_per_poll,
+                settings_ack_timeout: builder.settings_ack_timeout,
             },
         );
         let send_request = SendRequest {
@@ -1340,6 +1734,14 @@ where
     /// This can be used to increase or decrease the window size for existing
     /// streams.
     ///
+    /// Decreasing it applies the difference to every open stream, so a stream
+    /// that already received more than the new size is left with a negative
+    /// window. The remote may not send DATA on such a stream until enough
+    /// capacity has been released with [`FlowControl::release_capacity`]
+    /// to bring the window back above zero.
+    ///
+    /// [`FlowControl::release_capacity`]: crate::FlowControl::release_capacity
+    ///
     /// # Errors
     ///
     /// Returns an error if a previous call is still pending acknowledgement
@@ -1350,6 +1752,22 @@ where
         Ok(())
     }
 
+    /// Resets up to `n` active streams, starting with the lowest priority
+    /// ones, and returns how many were reset.
+    ///
+    /// This sheds load when the connection is under pressure. Streams are
+    /// ordered by their send weight, which is set with
+    /// `SendStream::set_weight` under the `unstable` feature; among streams
+    /// of equal weight, the most recently opened ones are reset first.
+    /// `reason` is usually [`Reason::ENHANCE_YOUR_CALM`], or
+    /// [`Reason::REFUSED_STREAM`] for streams that have not been processed
+    /// yet.
+    ///
+    /// The connection must continue being polled to send the resets.
+    pub fn reset_lowest_priority_streams(&mut self, n: usize, reason: Reason) -> usize {
+        self.inner.reset_lowest_priority_streams(n, reason)
+    }
+
     /// Takes a `PingPong` instance from the connection.
     ///
     /// # Note
@@ -1359,6 +1777,16 @@ where
         self.inner.take_user_pings().map(PingPong::new)
     }
 
+    /// Takes the oldest alternative service advertisement received from the
+    /// server, if any.
+    ///
+    /// Advertisements are queued as `ALTSVC` frames are received while the
+    /// connection is polled. Only the most recent few are kept, so this should
+    /// be called regularly by clients that make use of them.
+    pub fn alt_svc(&mut self) -> Option<AltSvc> {
+        self.inner.take_alt_svc().map(|inner| AltSvc { inner })
+    }
+
     /// Returns the maximum number of concurrent streams that may be initiated
     /// by this client.
     ///
@@ -1385,6 +1813,28 @@ where
     pub fn max_concurrent_recv_streams(&self) -> usize {
         self.inner.max_recv_streams()
     }
+
+    /// Returns the number of active streams initiated by the server on this
+    /// connection.
+    ///
+    /// These are the pushed streams counted against
+    /// [`max_concurrent_recv_streams`][1]; once the two are equal, further
+    /// pushed streams are refused with `REFUSED_STREAM`.
+    ///
+    /// [1]: #method.max_concurrent_recv_streams
+    pub fn num_active_recv_streams(&self) -> usize {
+        self.inner.num_recv_streams()
+    }
+
+    /// Returns the settings in effect on this connection.
+    ///
+    /// Local settings are included once the remote peer has acknowledged
+    /// them, and remote settings once they have been acknowledged and
+    /// applied. Until then, the previous values (or the protocol defaults)
+    /// are returned.
+    pub fn effective_settings(&self) -> EffectiveSettings {
+        self.inner.effective_settings()
+    }
 }
 
 impl<T, B> Future for Connection<T, B>
@@ -1509,6 +1959,36 @@ impl PushPromise {
     }
 }
 
+// ===== impl AltSvc =====
+
+impl AltSvc {
+    /// Returns the origin the alternative service applies to.
+    ///
+    /// This is `None` when the advertisement was sent on a stream, in which
+    /// case it applies to the origin of that stream's request.
+    pub fn origin(&self) -> Option<&[u8]> {
+        if self.inner.stream_id().is_zero() {
+            Some(self.inner.origin())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the stream the advertisement was sent on, if any.
+    pub fn stream_id(&self) -> Option<crate::StreamId> {
+        if self.inner.stream_id().is_zero() {
+            None
+        } else {
+            Some(crate::StreamId::from_internal(self.inner.stream_id()))
+        }
+    }
+
+    /// Returns the `Alt-Svc` field value, as defined in RFC 7838.
+    pub fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+}
+
 // ===== impl PushedResponseFuture =====
 
 impl Future for PushedResponseFuture {
@@ -49,6 +49,10 @@ pub enum UserError {
 
     /// Tries to send push promise to peer who has disabled server push
     PeerDisabledServerPush,
+
+    /// Tries to send a `:protocol` pseudo-header to a peer that has not
+    /// enabled the extended CONNECT protocol.
+    PeerDisabledExtendedConnect,
 }
 
 // ===== impl SendError =====
@@ -97,6 +101,9 @@ impl fmt::Display for UserError {
             SendPingWhilePending => "send_ping before received previous pong",
             SendSettingsWhilePending => "sending SETTINGS before received previous ACK",
             PeerDisabledServerPush => "sending PUSH_PROMISE to peer who disabled server push",
+            PeerDisabledExtendedConnect => {
+                "sending :protocol to peer who has not enabled extended CONNECT"
+            }
         })
     }
 }
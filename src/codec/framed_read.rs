@@ -5,6 +5,7 @@ use crate::frame::{
 use crate::proto::Error;
 
 use crate::hpack;
+use crate::observer::StateObserver;
 
 use futures_core::Stream;
 
@@ -13,6 +14,7 @@ use bytes::BytesMut;
 use std::io;
 
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::io::AsyncRead;
 use tokio_util::codec::FramedRead as InnerFramedRead;
@@ -21,6 +23,10 @@ use tokio_util::codec::{LengthDelimitedCodec, LengthDelimitedCodecError};
 // 16 MB "sane default" taken from golang http2
 const DEFAULT_SETTINGS_MAX_HEADER_LIST_SIZE: usize = 16 << 20;
 
+// Limits the encoded size of a header block spread over HEADERS and
+// CONTINUATION frames, so a peer cannot keep a block open forever.
+const DEFAULT_MAX_HEADER_BLOCK_SIZE: usize = 16 << 20;
+
 #[derive(Debug)]
 pub struct FramedRead<T> {
     inner: InnerFramedRead<T, LengthDelimitedCodec>,
@@ -30,7 +36,11 @@ pub struct FramedRead<T> {
 
     max_header_list_size: usize,
 
+    max_header_block_size: usize,
+
     partial: Option<Partial>,
+
+    unknown_frame_observer: Option<Arc<dyn StateObserver>>,
 }
 
 /// Partially loaded headers frame
@@ -41,6 +51,9 @@ struct Partial {
 
     /// Partial header payload
     buf: BytesMut,
+
+    /// Encoded bytes received so far for this header block
+    len: usize,
 }
 
 #[derive(Debug)]
@@ -55,7 +68,9 @@ impl<T> FramedRead<T> {
             inner,
             hpack: hpack::Decoder::new(DEFAULT_SETTINGS_HEADER_TABLE_SIZE),
             max_header_list_size: DEFAULT_SETTINGS_MAX_HEADER_LIST_SIZE,
+            max_header_block_size: DEFAULT_MAX_HEADER_BLOCK_SIZE,
             partial: None,
+            unknown_frame_observer: None,
         }
     }
 
@@ -89,11 +104,22 @@ impl<T> FramedRead<T> {
         self.max_header_list_size = val;
     }
 
+    /// Update the max encoded size of a received header block.
+    #[inline]
+    pub fn set_max_header_block_size(&mut self, val: usize) {
+        self.max_header_block_size = val;
+    }
+
     /// Update the header table size setting.
     #[inline]
     pub fn set_header_table_size(&mut self, val: usize) {
         self.hpack.queue_size_update(val);
     }
+
+    /// Set the observer notified of discarded frames of unknown types.
+    pub fn set_unknown_frame_observer(&mut self, observer: Option<Arc<dyn StateObserver>>) {
+        self.unknown_frame_observer = observer;
+    }
 }
 
 /// Decodes a frame.
@@ -102,7 +128,9 @@ impl<T> FramedRead<T> {
 fn decode_frame(
     hpack: &mut hpack::Decoder,
     max_header_list_size: usize,
+    max_header_block_size: usize,
     partial_inout: &mut Option<Partial>,
+    unknown_frame_observer: Option<&dyn StateObserver>,
     mut bytes: BytesMut,
 ) -> Result<Option<Frame>, Error> {
     let span = tracing::trace_span!("FramedRead::decode_frame", offset = bytes.len());
@@ -122,11 +150,17 @@ fn decode_frame(
 
     tracing::trace!(frame.kind = ?kind);
 
+    if !kind.scope().allows(head.stream_id()) {
+        proto_err!(conn: "invalid stream ID for {:?}; stream={:?}", kind, head.stream_id());
+        return Err(Error::library_go_away(Reason::PROTOCOL_ERROR));
+    }
+
     macro_rules! header_block {
         ($frame:ident, $head:ident, $bytes:ident) => ({
             // Drop the frame header
             // TODO: Change to drain: carllerche/bytes#130
             let _ = $bytes.split_to(frame::HEADER_LEN);
+            let block_len = $bytes.len();
 
             // Parse the header frame w/o parsing the payload
             let (mut frame, mut payload) = match frame::$frame::load($head, $bytes) {
@@ -155,6 +189,10 @@ fn decode_frame(
                     proto_err!(stream: "malformed header block; stream={:?}", id);
                     return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
                 },
+                Err(frame::Error::Hpack(hpack::DecoderError::InvalidSizeUpdatePosition)) => {
+                    proto_err!(conn: "dynamic table size update after the start of a header block");
+                    return Err(Error::library_go_away(Reason::COMPRESSION_ERROR));
+                },
                 Err(e) => {
                     proto_err!(conn: "failed HPACK decoding; err={:?}", e);
                     return Err(Error::library_go_away(Reason::PROTOCOL_ERROR));
@@ -165,10 +203,13 @@ fn decode_frame(
                 frame.into()
             } else {
                 tracing::trace!("loaded partial header block");
-                // Defer returning the frame
+                // Defer returning the frame. Its END_STREAM flag, if any, is
+                // kept with it, so the stream only transitions once the
+                // CONTINUATION frames complete the header block.
                 *partial_inout = Some(Partial {
                     frame: Continuable::$frame(frame),
                     buf: payload,
+                    len: block_len,
                 });
 
                 return Ok(None);
@@ -182,7 +223,14 @@ fn decode_frame(
 
             res.map_err(|e| {
                 proto_err!(conn: "failed to load SETTINGS frame; err={:?}", e);
-                Error::library_go_away(Reason::PROTOCOL_ERROR)
+                // > Values above the maximum flow-control window size of
+                // > 2^31-1 MUST be treated as a connection error of type
+                // > FLOW_CONTROL_ERROR.
+                if e == frame::Error::InvalidInitialWindowSize {
+                    Error::library_go_away(Reason::FLOW_CONTROL_ERROR)
+                } else {
+                    Error::library_go_away(Reason::PROTOCOL_ERROR)
+                }
             })?
             .into()
         }
@@ -273,6 +321,12 @@ fn decode_frame(
                 return Err(Error::library_go_away(Reason::PROTOCOL_ERROR));
             }
 
+            partial.len += bytes.len() - frame::HEADER_LEN;
+            if partial.len > max_header_block_size {
+                proto_err!(conn: "CONTINUATION frame header block size over limit; len={}", partial.len);
+                return Err(Error::library_go_away(Reason::ENHANCE_YOUR_CALM));
+            }
+
             // Extend the buf
             if partial.buf.is_empty() {
                 partial.buf = bytes.split_off(frame::HEADER_LEN);
@@ -310,6 +364,10 @@ fn decode_frame(
                     proto_err!(stream: "malformed CONTINUATION frame; stream={:?}", id);
                     return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
                 }
+                Err(frame::Error::Hpack(hpack::DecoderError::InvalidSizeUpdatePosition)) => {
+                    proto_err!(conn: "dynamic table size update in a CONTINUATION frame");
+                    return Err(Error::library_go_away(Reason::COMPRESSION_ERROR));
+                }
                 Err(e) => {
                     proto_err!(conn: "failed HPACK decoding; err={:?}", e);
                     return Err(Error::library_go_away(Reason::PROTOCOL_ERROR));
@@ -323,8 +381,24 @@ fn decode_frame(
                 return Ok(None);
             }
         }
+        Kind::AltSvc => match frame::AltSvc::load(head, &bytes[frame::HEADER_LEN..]) {
+            Ok(frame) => frame.into(),
+            Err(e) => {
+                // Invalid ALTSVC frames MUST be ignored (RFC 7838, Section 4).
+                tracing::debug!("ignoring invalid ALTSVC frame; err={:?}", e);
+                return Ok(None);
+            }
+        },
         Kind::Unknown => {
             // Unknown frames are ignored
+            let len = bytes.len() - frame::HEADER_LEN;
+            tracing::trace!("ignoring unknown frame; type={}; len={}", bytes[3], len);
+
+            if let Some(observer) = unknown_frame_observer {
+                let id = crate::StreamId::from_internal(head.stream_id());
+                observer.on_unknown_frame(bytes[3], id, len);
+            }
+
             return Ok(None);
         }
     };
@@ -353,10 +427,19 @@ where
             let Self {
                 ref mut hpack,
                 max_header_list_size,
+                max_header_block_size,
                 ref mut partial,
+                ref unknown_frame_observer,
                 ..
             } = *self;
-            if let Some(frame) = decode_frame(hpack, max_header_list_size, partial, bytes)? {
+            if let Some(frame) = decode_frame(
+                hpack,
+                max_header_list_size,
+                max_header_block_size,
+                partial,
+                unknown_frame_observer.as_deref(),
+                bytes,
+            )? {
                 tracing::debug!(?frame, "received");
                 return Poll::Ready(Some(Ok(frame)));
             }
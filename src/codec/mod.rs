@@ -8,12 +8,14 @@ use self::framed_read::FramedRead;
 use self::framed_write::FramedWrite;
 
 use crate::frame::{self, Data, Frame};
+use crate::observer::StateObserver;
 use crate::proto::Error;
 
 use bytes::Buf;
 use futures_core::Stream;
 use futures_sink::Sink;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::length_delimited;
@@ -105,6 +107,18 @@ impl<T, B> Codec<T, B> {
         self.inner.set_max_header_list_size(val);
     }
 
+    /// Set the max encoded size of a header block that can be received,
+    /// across its HEADERS or PUSH_PROMISE frame and any CONTINUATION frames.
+    pub fn set_max_recv_header_block_size(&mut self, val: usize) {
+        self.inner.set_max_header_block_size(val);
+    }
+
+    /// Set the observer notified each time a frame of an unknown type is
+    /// received and discarded.
+    pub fn set_unknown_frame_observer(&mut self, observer: Option<Arc<dyn StateObserver>>) {
+        self.inner.set_unknown_frame_observer(observer);
+    }
+
     /// Get a reference to the inner stream.
     #[cfg(feature = "unstable")]
     pub fn get_ref(&self) -> &T {
@@ -22,10 +22,38 @@ pub struct Error {
     kind: Kind,
 }
 
+/// A stream was reset by the remote peer.
+///
+/// Obtained from an [`Error`] with [`stream_error`], this carries the error
+/// code of the received `RST_STREAM` frame, such as `CANCEL` or
+/// `REFUSED_STREAM`.
+///
+/// [`Error`]: struct.Error.html
+/// [`stream_error`]: struct.Error.html#method.stream_error
+#[cfg(feature = "unstable")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamError {
+    stream_id: StreamId,
+    reason: Reason,
+}
+
+/// What an [`Error`] affects.
+///
+/// [`Error`]: struct.Error.html
+#[cfg(feature = "unstable")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorScope {
+    /// A single stream was reset with `RST_STREAM`. The connection and its
+    /// other streams are unaffected.
+    Stream,
+
+    /// The whole connection failed, either with `GOAWAY` or an I/O error.
+    Connection,
+}
+
 #[derive(Debug)]
 enum Kind {
     /// A RST_STREAM frame was received or sent.
-    #[allow(dead_code)]
     Reset(StreamId, Reason, Initiator),
 
     /// A GO_AWAY frame was received or sent.
@@ -40,6 +68,10 @@ enum Kind {
 
     /// An `io::Error` occurred while trying to read or write.
     Io(io::Error),
+
+    /// The client connection preface did not match; holds the bytes read up
+    /// to and including the first mismatch.
+    Preface(Bytes),
 }
 
 // ===== impl Error =====
@@ -54,10 +86,50 @@ impl Error {
             Kind::Reset(_, reason, _) | Kind::GoAway(_, reason, _) | Kind::Reason(reason) => {
                 Some(reason)
             }
+            Kind::Preface(_) => Some(Reason::PROTOCOL_ERROR),
+            _ => None,
+        }
+    }
+
+    /// The stream the error is attributed to, if any.
+    ///
+    /// This is only set for stream errors, i.e. a `RST_STREAM` that was
+    /// received or sent. Connection level errors return `None`.
+    pub fn stream_id(&self) -> Option<crate::StreamId> {
+        match self.kind {
+            Kind::Reset(stream_id, _, _) => Some(crate::StreamId::from_internal(stream_id)),
+            _ => None,
+        }
+    }
+
+    /// Returns the `RST_STREAM` received from the remote peer, if that is
+    /// what caused the error.
+    ///
+    /// Resets sent by this endpoint, and connection level errors, return
+    /// `None`.
+    #[cfg(feature = "unstable")]
+    pub fn stream_error(&self) -> Option<StreamError> {
+        match self.kind {
+            Kind::Reset(stream_id, reason, Initiator::Remote) => {
+                Some(StreamError { stream_id, reason })
+            }
             _ => None,
         }
     }
 
+    /// Returns whether the error affects a single stream or the connection.
+    ///
+    /// Errors that did not come from a frame sent or received, or from the
+    /// transport, such as a misuse of the API by the user, return `None`.
+    #[cfg(feature = "unstable")]
+    pub fn scope(&self) -> Option<ErrorScope> {
+        match self.kind {
+            Kind::Reset(..) => Some(ErrorScope::Stream),
+            Kind::GoAway(..) | Kind::Io(..) | Kind::Preface(..) => Some(ErrorScope::Connection),
+            Kind::Reason(..) | Kind::User(..) => None,
+        }
+    }
+
     /// Returns true if the error is an io::Error
     pub fn is_io(&self) -> bool {
         matches!(self.kind, Kind::Io(..))
@@ -85,6 +157,20 @@ impl Error {
         }
     }
 
+    pub(crate) fn preface(received: Bytes) -> Self {
+        Error {
+            kind: Kind::Preface(received),
+        }
+    }
+
+    /// Returns true if the error is from a client connection preface that
+    /// did not match, such as a peer not speaking HTTP/2.
+    ///
+    /// The reason of such an error is `PROTOCOL_ERROR`.
+    pub fn is_preface_error(&self) -> bool {
+        matches!(self.kind, Kind::Preface(..))
+    }
+
     /// Returns true if the error is from a `GOAWAY`.
     pub fn is_go_away(&self) -> bool {
         matches!(self.kind, Kind::GoAway(..))
@@ -114,6 +200,44 @@ impl Error {
             Kind::GoAway(_, _, Initiator::Library) | Kind::Reset(_, _, Initiator::Library)
         )
     }
+
+    /// Returns true if `h2` reset the stream or closed the connection
+    /// because the peer violated flow control.
+    ///
+    /// Such as the peer sending more DATA than a receive window allowed.
+    /// Whether only the stream was reset can be told with `Error::scope`,
+    /// under the `unstable` feature.
+    pub fn is_flow_control_violation(&self) -> bool {
+        self.is_library() && self.reason() == Some(Reason::FLOW_CONTROL_ERROR)
+    }
+}
+
+/// Returns the reason to reset a stream with when the application fails
+/// with `err`.
+///
+/// This is the reason of the first `Error` made from a `Reason` in the chain
+/// of sources, so an application picks the reason by failing with such an
+/// `Error`. Errors that came from the connection, such as a reset or GOAWAY
+/// received from the peer, are not the application's choice and are
+/// skipped. Any other error, or `NO_ERROR`, resets the stream with
+/// `INTERNAL_ERROR`.
+pub(crate) fn reset_reason(err: &(dyn error::Error + 'static)) -> Reason {
+    let mut next = Some(err);
+
+    while let Some(err) = next {
+        if let Some(Error {
+            kind: Kind::Reason(reason),
+        }) = err.downcast_ref::<Error>()
+        {
+            if *reason == Reason::NO_ERROR {
+                break;
+            }
+            return *reason;
+        }
+        next = err.source();
+    }
+
+    Reason::INTERNAL_ERROR
 }
 
 impl From<proto::Error> for Error {
@@ -186,6 +310,9 @@ impl fmt::Display for Error {
             Kind::Reason(reason) => return write!(fmt, "protocol error: {}", reason),
             Kind::User(ref e) => return write!(fmt, "user error: {}", e),
             Kind::Io(ref e) => return e.fmt(fmt),
+            Kind::Preface(ref received) => {
+                return write!(fmt, "invalid connection preface: {:?}", received)
+            }
         };
 
         if !debug_data.is_empty() {
@@ -198,14 +325,163 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {}
 
+// ===== impl StreamError =====
+
+#[cfg(feature = "unstable")]
+impl StreamError {
+    /// The error code the remote peer reset the stream with.
+    pub fn reason(&self) -> Reason {
+        self.reason
+    }
+
+    /// The stream that was reset.
+    pub fn stream_id(&self) -> crate::StreamId {
+        crate::StreamId::from_internal(self.stream_id)
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl fmt::Display for StreamError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "stream error received: {}", self.reason)
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl error::Error for StreamError {}
+
 #[cfg(test)]
 mod tests {
-    use super::Error;
-    use crate::Reason;
+    #[cfg(feature = "unstable")]
+    use super::ErrorScope;
+    use super::{reset_reason, Error};
+    use crate::codec::UserError;
+    use crate::frame::StreamId;
+    use crate::{proto, Reason};
+
+    use bytes::Bytes;
+    use std::io;
 
     #[test]
     fn error_from_reason() {
         let err = Error::from(Reason::HTTP_1_1_REQUIRED);
         assert_eq!(err.reason(), Some(Reason::HTTP_1_1_REQUIRED));
     }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn scope_of_resets_is_stream() {
+        let id = StreamId::from(1);
+
+        for err in [
+            proto::Error::library_reset(id, Reason::PROTOCOL_ERROR),
+            proto::Error::remote_reset(id, Reason::CANCEL),
+            proto::Error::Reset(id, Reason::CANCEL, proto::Initiator::User),
+        ] {
+            assert_eq!(Error::from(err).scope(), Some(ErrorScope::Stream));
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn scope_of_go_aways_and_io_is_connection() {
+        for err in [
+            proto::Error::library_go_away(Reason::PROTOCOL_ERROR),
+            proto::Error::library_go_away_data(Reason::ENHANCE_YOUR_CALM, "calm"),
+            proto::Error::remote_go_away(Bytes::new(), Reason::NO_ERROR),
+            proto::Error::user_go_away(Reason::NO_ERROR),
+            proto::Error::from(io::ErrorKind::BrokenPipe),
+        ] {
+            assert_eq!(Error::from(err).scope(), Some(ErrorScope::Connection));
+        }
+    }
+
+    #[test]
+    fn preface_error_is_a_connection_protocol_error() {
+        let err = Error::preface(Bytes::from_static(b"GET / HTTP/1.1"));
+
+        assert!(err.is_preface_error());
+        assert!(!err.is_go_away());
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+        #[cfg(feature = "unstable")]
+        assert_eq!(err.scope(), Some(ErrorScope::Connection));
+        assert_eq!(
+            err.to_string(),
+            "invalid connection preface: b\"GET / HTTP/1.1\""
+        );
+    }
+
+    #[test]
+    fn flow_control_violations_are_library_errors() {
+        let id = StreamId::from(1);
+
+        for err in [
+            proto::Error::library_reset(id, Reason::FLOW_CONTROL_ERROR),
+            proto::Error::library_go_away(Reason::FLOW_CONTROL_ERROR),
+        ] {
+            assert!(Error::from(err).is_flow_control_violation());
+        }
+
+        for err in [
+            proto::Error::library_reset(id, Reason::PROTOCOL_ERROR),
+            proto::Error::remote_reset(id, Reason::FLOW_CONTROL_ERROR),
+            proto::Error::remote_go_away(Bytes::new(), Reason::FLOW_CONTROL_ERROR),
+        ] {
+            assert!(!Error::from(err).is_flow_control_violation());
+        }
+        assert!(!Error::from(Reason::FLOW_CONTROL_ERROR).is_flow_control_violation());
+    }
+
+    #[test]
+    fn reset_reason_is_picked_from_error_chain() {
+        #[derive(Debug)]
+        struct AppError(Option<Error>);
+
+        impl std::fmt::Display for AppError {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+                fmt.write_str("application error")
+            }
+        }
+
+        impl std::error::Error for AppError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                self.0.as_ref().map(|err| err as _)
+            }
+        }
+
+        let refused = Error::from(Reason::REFUSED_STREAM);
+        assert_eq!(reset_reason(&refused), Reason::REFUSED_STREAM);
+
+        let wrapped = AppError(Some(Error::from(Reason::CANCEL)));
+        assert_eq!(reset_reason(&wrapped), Reason::CANCEL);
+
+        assert_eq!(reset_reason(&AppError(None)), Reason::INTERNAL_ERROR);
+        let io = io::Error::new(io::ErrorKind::Other, "oops");
+        assert_eq!(reset_reason(&io), Reason::INTERNAL_ERROR);
+        let user = Error::from(UserError::InactiveStreamId);
+        assert_eq!(reset_reason(&user), Reason::INTERNAL_ERROR);
+
+        // NO_ERROR is not a failure
+        let no_error = AppError(Some(Error::from(Reason::NO_ERROR)));
+        assert_eq!(reset_reason(&no_error), Reason::INTERNAL_ERROR);
+
+        // errors received from the peer are not the application's reason
+        let id = StreamId::from(1);
+        let reset = AppError(Some(proto::Error::remote_reset(id, Reason::CANCEL).into()));
+        assert_eq!(reset_reason(&reset), Reason::INTERNAL_ERROR);
+        let go_away = Error::from(proto::Error::remote_go_away(
+            Bytes::new(),
+            Reason::ENHANCE_YOUR_CALM,
+        ));
+        assert_eq!(reset_reason(&go_away), Reason::INTERNAL_ERROR);
+        let local = Error::from(proto::Error::library_reset(id, Reason::PROTOCOL_ERROR));
+        assert_eq!(reset_reason(&local), Reason::INTERNAL_ERROR);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn scope_of_local_errors_is_none() {
+        assert_eq!(Error::from(Reason::CANCEL).scope(), None);
+        assert_eq!(Error::from(UserError::InactiveStreamId).scope(), None);
+    }
 }
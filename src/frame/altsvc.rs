@@ -0,0 +1,164 @@
+use std::fmt;
+
+use bytes::{BufMut, Bytes};
+
+use crate::frame::{self, Error, Head, Kind, StreamId};
+
+/// An ALTSVC frame, advertising an alternative service (RFC 7838).
+///
+/// When sent on stream 0 the frame carries the origin it applies to. When
+/// sent on any other stream it applies to that stream's origin, and the
+/// origin field must be empty.
+#[derive(Clone, Eq, PartialEq)]
+pub struct AltSvc {
+    stream_id: StreamId,
+    origin: Bytes,
+    value: Bytes,
+}
+
+impl AltSvc {
+    #[cfg(feature = "unstable")]
+    pub fn new(stream_id: StreamId, origin: Bytes, value: Bytes) -> Self {
+        AltSvc {
+            stream_id,
+            origin,
+            value,
+        }
+    }
+
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    /// The origin the alternative service applies to.
+    ///
+    /// This is empty for frames sent on a stream other than 0.
+    pub fn origin(&self) -> &Bytes {
+        &self.origin
+    }
+
+    /// The `Alt-Svc` field value.
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    pub fn load(head: Head, payload: &[u8]) -> Result<AltSvc, Error> {
+        debug_assert_eq!(head.kind(), crate::frame::Kind::AltSvc);
+
+        if payload.len() < 2 {
+            return Err(Error::BadFrameSize);
+        }
+
+        let origin_len = ((payload[0] as usize) << 8) | (payload[1] as usize);
+
+        if payload.len() < 2 + origin_len {
+            return Err(Error::InvalidPayloadLength);
+        }
+
+        // > An ALTSVC frame on stream 0 with empty (length 0) "Origin"
+        // > information is invalid and MUST be ignored. An ALTSVC frame on a
+        // > stream other than stream 0 containing non-empty "Origin"
+        // > information is invalid and MUST be ignored.
+        if head.stream_id().is_zero() == (origin_len == 0) {
+            return Err(Error::InvalidAltSvcOrigin);
+        }
+
+        let origin = Bytes::copy_from_slice(&payload[2..2 + origin_len]);
+        let value = Bytes::copy_from_slice(&payload[2 + origin_len..]);
+
+        Ok(AltSvc {
+            stream_id: head.stream_id(),
+            origin,
+            value,
+        })
+    }
+
+    pub fn encode<B: BufMut>(&self, dst: &mut B) {
+        tracing::trace!("encoding ALTSVC; id={:?}", self.stream_id);
+        let head = Head::new(Kind::AltSvc, 0, self.stream_id);
+        head.encode(2 + self.origin.len() + self.value.len(), dst);
+        dst.put_u16(self.origin.len() as u16);
+        dst.put(self.origin.slice(..));
+        dst.put(self.value.slice(..));
+    }
+}
+
+impl<B> From<AltSvc> for frame::Frame<B> {
+    fn from(src: AltSvc) -> Self {
+        frame::Frame::AltSvc(src)
+    }
+}
+
+impl fmt::Debug for AltSvc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut builder = f.debug_struct("AltSvc");
+        builder.field("stream_id", &self.stream_id);
+
+        if !self.origin.is_empty() {
+            builder.field("origin", &self.origin);
+        }
+
+        builder.field("value", &self.value).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(id: u32, origin: &[u8], value: &[u8]) -> Result<AltSvc, Error> {
+        let mut payload = vec![];
+        payload.put_u16(origin.len() as u16);
+        payload.extend_from_slice(origin);
+        payload.extend_from_slice(value);
+
+        AltSvc::load(Head::new(Kind::AltSvc, 0, id.into()), &payload)
+    }
+
+    #[test]
+    fn load_connection_scoped() {
+        let frame = load(0, b"https://example.com", b"h2=\":443\"").unwrap();
+        assert!(frame.stream_id().is_zero());
+        assert_eq!(frame.origin(), "https://example.com");
+        assert_eq!(frame.value(), "h2=\":443\"");
+    }
+
+    #[test]
+    fn load_stream_scoped() {
+        let frame = load(1, b"", b"h2=\":443\"").unwrap();
+        assert_eq!(frame.stream_id(), StreamId::from(1));
+        assert!(frame.origin().is_empty());
+        assert_eq!(frame.value(), "h2=\":443\"");
+    }
+
+    #[test]
+    fn load_invalid_origin() {
+        // stream 0 requires an origin, other streams must not carry one
+        assert_eq!(load(0, b"", b"clear"), Err(Error::InvalidAltSvcOrigin));
+        assert_eq!(
+            load(1, b"https://example.com", b"clear"),
+            Err(Error::InvalidAltSvcOrigin)
+        );
+    }
+
+    #[test]
+    fn load_invalid_length() {
+        let head = Head::new(Kind::AltSvc, 0, StreamId::zero());
+        assert_eq!(AltSvc::load(head, &[0]), Err(Error::BadFrameSize));
+        assert_eq!(
+            AltSvc::load(head, &[0, 4, b'h']),
+            Err(Error::InvalidPayloadLength)
+        );
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let frame = load(0, b"https://example.com", b"clear").unwrap();
+        let mut buf = vec![];
+        frame.encode(&mut buf);
+
+        let head = Head::parse(&buf);
+        assert_eq!(head.kind(), Kind::AltSvc);
+        assert_eq!(AltSvc::load(head, &buf[frame::HEADER_LEN..]), Ok(frame));
+    }
+}
@@ -139,6 +139,14 @@ impl Data<Bytes> {
             pad_len,
         })
     }
+
+    /// Returns the number of bytes this frame counts against flow control.
+    ///
+    /// This includes the pad length octet and any padding that was stripped
+    /// from the payload.
+    pub(crate) fn flow_len(&self) -> usize {
+        self.data.len() + self.pad_len.map_or(0, |pad_len| pad_len as usize + 1)
+    }
 }
 
 impl<T: Buf> Data<T> {
@@ -22,9 +22,24 @@ pub enum Kind {
     GoAway = 7,
     WindowUpdate = 8,
     Continuation = 9,
+    AltSvc = 10,
     Unknown,
 }
 
+/// The streams a frame type may be sent on.
+///
+/// Stream 0 stands for the connection itself. It never follows the stream
+/// state diagram, so only connection-level frames may use it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// Only on stream 0.
+    Connection,
+    /// Only on a stream other than 0.
+    Stream,
+    /// On any stream, including 0.
+    Any,
+}
+
 // ===== impl Head =====
 
 impl Head {
@@ -88,7 +103,35 @@ impl Kind {
             7 => Kind::GoAway,
             8 => Kind::WindowUpdate,
             9 => Kind::Continuation,
+            10 => Kind::AltSvc,
             _ => Kind::Unknown,
         }
     }
+
+    pub fn scope(&self) -> Scope {
+        match self {
+            Kind::Settings | Kind::Ping | Kind::GoAway => Scope::Connection,
+            Kind::Data
+            | Kind::Headers
+            | Kind::Priority
+            | Kind::Reset
+            | Kind::PushPromise
+            | Kind::Continuation => Scope::Stream,
+            // Unknown frame types must be ignored wherever they are sent.
+            Kind::WindowUpdate | Kind::AltSvc | Kind::Unknown => Scope::Any,
+        }
+    }
+}
+
+// ===== impl Scope =====
+
+impl Scope {
+    /// Returns true if a frame with this scope may be sent on `id`.
+    pub fn allows(&self, id: StreamId) -> bool {
+        match self {
+            Scope::Connection => id.is_zero(),
+            Scope::Stream => !id.is_zero(),
+            Scope::Any => true,
+        }
+    }
 }
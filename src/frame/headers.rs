@@ -82,7 +82,7 @@ pub struct Iter {
     fields: header::IntoIter<HeaderValue>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 struct HeaderBlock {
     /// The decoded header fields
     fields: HeaderMap,
@@ -90,11 +90,27 @@ struct HeaderBlock {
     /// Set to true if decoding went over the max header list size.
     is_over_size: bool,
 
+    /// Cleared once a header of the block has been decoded, after which HPACK
+    /// no longer allows a dynamic table size update.
+    can_resize: bool,
+
     /// Pseudo headers, these are broken out as they must be sent as part of the
     /// headers frame.
     pseudo: Pseudo,
 }
 
+// `can_resize` is decoding state rather than part of the frame, so it is left
+// out when comparing header blocks.
+impl PartialEq for HeaderBlock {
+    fn eq(&self, other: &HeaderBlock) -> bool {
+        self.fields == other.fields
+            && self.is_over_size == other.is_over_size
+            && self.pseudo == other.pseudo
+    }
+}
+
+impl Eq for HeaderBlock {}
+
 #[derive(Debug)]
 struct EncodingHeaderBlock {
     hpack: Bytes,
@@ -117,6 +133,7 @@ impl Headers {
             header_block: HeaderBlock {
                 fields,
                 is_over_size: false,
+                can_resize: true,
                 pseudo,
             },
             flags: HeadersFlag::default(),
@@ -133,6 +150,7 @@ impl Headers {
             header_block: HeaderBlock {
                 fields,
                 is_over_size: false,
+                can_resize: true,
                 pseudo: Pseudo::default(),
             },
             flags,
@@ -197,6 +215,7 @@ impl Headers {
             header_block: HeaderBlock {
                 fields: HeaderMap::new(),
                 is_over_size: false,
+                can_resize: true,
                 pseudo: Pseudo::default(),
             },
             flags,
@@ -218,6 +237,10 @@ impl Headers {
         self.stream_id
     }
 
+    pub fn stream_dep(&self) -> Option<StreamDependency> {
+        self.stream_dep
+    }
+
     pub fn is_end_headers(&self) -> bool {
         self.flags.is_end_headers()
     }
@@ -252,6 +275,15 @@ impl Headers {
         self.header_block.pseudo.is_informational()
     }
 
+    pub(crate) fn pseudo(&self) -> &Pseudo {
+        &self.header_block.pseudo
+    }
+
+    /// Whether any pseudo-header is set
+    pub(crate) fn has_pseudo(&self) -> bool {
+        !self.header_block.pseudo.is_empty()
+    }
+
     pub fn fields(&self) -> &HeaderMap {
         &self.header_block.fields
     }
@@ -352,6 +384,7 @@ impl PushPromise {
             header_block: HeaderBlock {
                 fields,
                 is_over_size: false,
+                can_resize: true,
                 pseudo,
             },
             promised_id,
@@ -442,6 +475,7 @@ impl PushPromise {
             header_block: HeaderBlock {
                 fields: HeaderMap::new(),
                 is_over_size: false,
+                can_resize: true,
                 pseudo: Pseudo::default(),
             },
             promised_id,
@@ -622,6 +656,16 @@ impl Pseudo {
         self.status
             .map_or(false, |status| status.is_informational())
     }
+
+    /// Whether no pseudo-header is set
+    pub(crate) fn is_empty(&self) -> bool {
+        self.method.is_none()
+            && self.scheme.is_none()
+            && self.authority.is_none()
+            && self.path.is_none()
+            && self.protocol.is_none()
+            && self.status.is_none()
+    }
 }
 
 // ===== impl EncodingHeaderBlock =====
@@ -865,7 +909,8 @@ impl HeaderBlock {
         // the headers. A malformed header frame is a stream level error, but
         // the hpack state is connection level. In order to maintain correct
         // state for other streams, the hpack decoding process must complete.
-        let res = decoder.decode(&mut cursor, |header| {
+        let mut can_resize = self.can_resize;
+        let res = decoder.decode_continued(&mut cursor, &mut can_resize, |header| {
             use crate::hpack::Header::*;
 
             match header {
@@ -881,7 +926,7 @@ impl HeaderBlock {
                     {
                         tracing::trace!("load_hpack; connection level header");
                         malformed = true;
-                    } else if name == header::TE && value != "trailers" {
+                    } else if name == header::TE && !is_te_trailers(&value) {
                         tracing::trace!(
                             "load_hpack; TE header not set to trailers; val={:?}",
                             value
@@ -908,6 +953,17 @@ impl HeaderBlock {
             }
         });
 
+        self.can_resize = can_resize;
+
+        let res = match res {
+            Err(hpack::DecoderError::UppercaseHeaderName) => {
+                tracing::trace!("load_hpack; uppercase header name");
+                malformed = true;
+                Ok(())
+            }
+            res => res,
+        };
+
         if let Err(e) = res {
             tracing::trace!("hpack decoding error; err={:?}", e);
             return Err(e.into());
@@ -970,6 +1026,13 @@ fn decoded_header_size(name: usize, value: usize) -> usize {
     name + value + 32
 }
 
+/// Returns true if `value` is the only `TE` value HTTP/2 permits.
+///
+/// Transfer coding names are case-insensitive, so `Trailers` is accepted.
+pub(crate) fn is_te_trailers(value: &HeaderValue) -> bool {
+    value.as_bytes().eq_ignore_ascii_case(b"trailers")
+}
+
 #[cfg(test)]
 mod test {
     use std::iter::FromIterator;
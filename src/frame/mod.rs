@@ -35,6 +35,7 @@ mod tests {
     }
 }
 
+mod altsvc;
 mod data;
 mod go_away;
 mod head;
@@ -48,9 +49,11 @@ mod stream_id;
 mod util;
 mod window_update;
 
+pub use self::altsvc::AltSvc;
 pub use self::data::Data;
 pub use self::go_away::GoAway;
 pub use self::head::{Head, Kind};
+pub(crate) use self::headers::is_te_trailers;
 pub use self::headers::{
     parse_u64, Continuation, Headers, Pseudo, PushPromise, PushPromiseHeaderError,
 };
@@ -87,6 +90,7 @@ pub enum Frame<T = Bytes> {
     GoAway(GoAway),
     WindowUpdate(WindowUpdate),
     Reset(Reset),
+    AltSvc(AltSvc),
 }
 
 impl<T> Frame<T> {
@@ -106,6 +110,7 @@ impl<T> Frame<T> {
             GoAway(frame) => frame.into(),
             WindowUpdate(frame) => frame.into(),
             Reset(frame) => frame.into(),
+            AltSvc(frame) => frame.into(),
         }
     }
 }
@@ -124,6 +129,7 @@ impl<T> fmt::Debug for Frame<T> {
             GoAway(ref frame) => fmt::Debug::fmt(frame, fmt),
             WindowUpdate(ref frame) => fmt::Debug::fmt(frame, fmt),
             Reset(ref frame) => fmt::Debug::fmt(frame, fmt),
+            AltSvc(ref frame) => fmt::Debug::fmt(frame, fmt),
         }
     }
 }
@@ -141,6 +147,10 @@ pub enum Error {
     /// An invalid setting value was provided
     InvalidSettingValue,
 
+    /// A SETTINGS_INITIAL_WINDOW_SIZE value above the maximum flow-control
+    /// window size was provided
+    InvalidInitialWindowSize,
+
     /// An invalid window update value
     InvalidWindowUpdateValue,
 
@@ -166,6 +176,11 @@ pub enum Error {
     /// invalid stream identifier.
     InvalidDependencyId,
 
+    /// An ALTSVC frame's origin does not match its stream identifier.
+    ///
+    /// An origin is required on stream zero and forbidden on other streams.
+    InvalidAltSvcOrigin,
+
     /// Failed to perform HPACK decoding
     Hpack(hpack::DecoderError),
 }
@@ -6,7 +6,7 @@ pub struct Priority {
     dependency: StreamDependency,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct StreamDependency {
     /// The ID of the stream dependency target
     dependency_id: StreamId,
@@ -33,6 +33,14 @@ impl Priority {
             dependency,
         })
     }
+
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    pub fn dependency(&self) -> StreamDependency {
+        self.dependency
+    }
 }
 
 impl<B> From<Priority> for Frame<B> {
@@ -1,3 +1,4 @@
+use http::StatusCode;
 use std::fmt;
 
 /// HTTP/2 error codes.
@@ -59,6 +60,22 @@ impl Reason {
     /// The endpoint requires that HTTP/1.1 be used instead of HTTP/2.
     pub const HTTP_1_1_REQUIRED: Reason = Reason(13);
 
+    /// Returns the `Reason` for an error code received on the wire.
+    ///
+    /// Unknown codes are mapped to `INTERNAL_ERROR`, as [RFC 7540] allows.
+    /// Unlike `From<u32>`, which keeps the code as is, this is for callers
+    /// that need to act on the code and must not give unknown ones any
+    /// special meaning.
+    ///
+    /// [RFC 7540]: https://httpwg.org/specs/rfc7540.html#ErrorCodes
+    pub fn from_code(code: u32) -> Reason {
+        if code <= Reason::HTTP_1_1_REQUIRED.0 {
+            Reason(code)
+        } else {
+            Reason::INTERNAL_ERROR
+        }
+    }
+
     /// Get a string description of the error code.
     pub fn description(&self) -> &str {
         match self.0 {
@@ -82,6 +99,47 @@ impl Reason {
             _ => "unknown reason",
         }
     }
+
+    /// Returns an HTTP status code approximating this error code.
+    ///
+    /// This is meant for layers above HTTP/2, such as proxies, that have to
+    /// turn a reset stream into an HTTP response. `NO_ERROR` maps to `None`,
+    /// since it does not describe a failure. A `CANCEL` maps to the
+    /// non-standard 499 (client closed request), and errors caused by the
+    /// peer misbehaving map to 502 (Bad Gateway). Unknown codes are treated
+    /// as `INTERNAL_ERROR`, as [RFC 7540] requires.
+    ///
+    /// [RFC 7540]: https://httpwg.org/specs/rfc7540.html#ErrorCodes
+    pub fn status_code(&self) -> Option<StatusCode> {
+        let status = match *self {
+            Reason::NO_ERROR => return None,
+            Reason::REFUSED_STREAM => StatusCode::SERVICE_UNAVAILABLE,
+            Reason::CANCEL => StatusCode::from_u16(499).unwrap(),
+            Reason::ENHANCE_YOUR_CALM => StatusCode::TOO_MANY_REQUESTS,
+            Reason::HTTP_1_1_REQUIRED => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Reason::PROTOCOL_ERROR
+            | Reason::FLOW_CONTROL_ERROR
+            | Reason::SETTINGS_TIMEOUT
+            | Reason::STREAM_CLOSED
+            | Reason::FRAME_SIZE_ERROR
+            | Reason::COMPRESSION_ERROR
+            | Reason::CONNECT_ERROR
+            | Reason::INADEQUATE_SECURITY => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Some(status)
+    }
+
+    /// Returns true if a request reset with this error code can safely be
+    /// retried, even if it is not idempotent.
+    ///
+    /// Only `REFUSED_STREAM` guarantees that the peer did not process the
+    /// request. See [RFC 7540 Section 8.1.4].
+    ///
+    /// [RFC 7540 Section 8.1.4]: https://httpwg.org/specs/rfc7540.html#Reliability
+    pub fn is_retryable(&self) -> bool {
+        *self == Reason::REFUSED_STREAM
+    }
 }
 
 impl From<u32> for Reason {
@@ -132,3 +190,85 @@ impl fmt::Display for Reason {
         write!(fmt, "{}", self.description())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_code_of_each_reason() {
+        let cases = [
+            (Reason::NO_ERROR, None),
+            (Reason::PROTOCOL_ERROR, Some(502)),
+            (Reason::INTERNAL_ERROR, Some(500)),
+            (Reason::FLOW_CONTROL_ERROR, Some(502)),
+            (Reason::SETTINGS_TIMEOUT, Some(502)),
+            (Reason::STREAM_CLOSED, Some(502)),
+            (Reason::FRAME_SIZE_ERROR, Some(502)),
+            (Reason::REFUSED_STREAM, Some(503)),
+            (Reason::CANCEL, Some(499)),
+            (Reason::COMPRESSION_ERROR, Some(502)),
+            (Reason::CONNECT_ERROR, Some(502)),
+            (Reason::ENHANCE_YOUR_CALM, Some(429)),
+            (Reason::INADEQUATE_SECURITY, Some(502)),
+            (Reason::HTTP_1_1_REQUIRED, Some(505)),
+            (Reason::from(0xff), Some(500)),
+        ];
+
+        for (reason, status) in cases {
+            assert_eq!(
+                reason.status_code().map(|s| s.as_u16()),
+                status,
+                "{:?}",
+                reason
+            );
+        }
+    }
+
+    #[test]
+    fn from_code_round_trips_known_codes() {
+        let known = [
+            Reason::NO_ERROR,
+            Reason::PROTOCOL_ERROR,
+            Reason::INTERNAL_ERROR,
+            Reason::FLOW_CONTROL_ERROR,
+            Reason::SETTINGS_TIMEOUT,
+            Reason::STREAM_CLOSED,
+            Reason::FRAME_SIZE_ERROR,
+            Reason::REFUSED_STREAM,
+            Reason::CANCEL,
+            Reason::COMPRESSION_ERROR,
+            Reason::CONNECT_ERROR,
+            Reason::ENHANCE_YOUR_CALM,
+            Reason::INADEQUATE_SECURITY,
+            Reason::HTTP_1_1_REQUIRED,
+        ];
+
+        for (code, &reason) in known.iter().enumerate() {
+            assert_eq!(u32::from(reason), code as u32);
+            assert_eq!(Reason::from_code(code as u32), reason);
+        }
+    }
+
+    #[test]
+    fn from_code_treats_unknown_codes_as_internal_error() {
+        for code in [14, 0xff, u32::MAX] {
+            assert_eq!(Reason::from_code(code), Reason::INTERNAL_ERROR);
+            // `From<u32>` keeps the code for logging.
+            assert_eq!(u32::from(Reason::from(code)), code);
+        }
+    }
+
+    #[test]
+    fn only_refused_stream_is_retryable() {
+        for code in 0..=0xff {
+            let reason = Reason::from(code);
+            assert_eq!(
+                reason.is_retryable(),
+                reason == Reason::REFUSED_STREAM,
+                "{:?}",
+                reason
+            );
+        }
+    }
+}
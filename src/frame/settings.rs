@@ -125,6 +125,35 @@ impl Settings {
         self.header_table_size = size;
     }
 
+    /// Returns the SETTINGS frame announcing these settings in the connection
+    /// preface.
+    ///
+    /// Settings set to their initial value, as defined by RFC 7540 Section
+    /// 6.5.2, are left out, since the peer assumes them anyway.
+    pub fn to_frame<T>(&self) -> Frame<T> {
+        self.without_defaults().into()
+    }
+
+    fn without_defaults(&self) -> Settings {
+        fn non_default(val: Option<u32>, default: u32) -> Option<u32> {
+            val.filter(|&val| val != default)
+        }
+
+        Settings {
+            flags: self.flags,
+            header_table_size: non_default(
+                self.header_table_size,
+                DEFAULT_SETTINGS_HEADER_TABLE_SIZE as u32,
+            ),
+            enable_push: non_default(self.enable_push, 1),
+            max_concurrent_streams: self.max_concurrent_streams,
+            initial_window_size: non_default(self.initial_window_size, DEFAULT_INITIAL_WINDOW_SIZE),
+            max_frame_size: non_default(self.max_frame_size, DEFAULT_MAX_FRAME_SIZE),
+            max_header_list_size: self.max_header_list_size,
+            enable_connect_protocol: non_default(self.enable_connect_protocol, 0),
+        }
+    }
+
     pub fn load(head: Head, payload: &[u8]) -> Result<Settings, Error> {
         use self::Setting::*;
 
@@ -174,7 +203,7 @@ impl Settings {
                 }
                 Some(InitialWindowSize(val)) => {
                     if val as usize > MAX_INITIAL_WINDOW_SIZE {
-                        return Err(Error::InvalidSettingValue);
+                        return Err(Error::InvalidInitialWindowSize);
                     } else {
                         settings.initial_window_size = Some(val);
                     }
@@ -387,3 +416,65 @@ impl fmt::Debug for SettingsFlags {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the preface SETTINGS frame for `settings` and loads it back.
+    fn round_trip(settings: &Settings) -> (usize, Settings) {
+        let frame = match settings.to_frame::<()>() {
+            Frame::Settings(frame) => frame,
+            frame => panic!("unexpected frame; {:?}", frame),
+        };
+
+        let mut dst = BytesMut::new();
+        frame.encode(&mut dst);
+
+        let head = Head::parse(&dst);
+        let payload = &dst[crate::frame::HEADER_LEN..];
+        (payload.len(), Settings::load(head, payload).unwrap())
+    }
+
+    #[test]
+    fn preface_encodes_non_default_settings() {
+        let mut settings = Settings::default();
+        settings.set_header_table_size(Some(0));
+        settings.set_enable_push(false);
+        settings.set_max_concurrent_streams(Some(100));
+        settings.set_initial_window_size(Some(1 << 20));
+        settings.set_max_frame_size(Some(1 << 16));
+        settings.set_max_header_list_size(Some(16 << 10));
+        settings.set_enable_connect_protocol(Some(1));
+
+        let (len, loaded) = round_trip(&settings);
+        assert_eq!(len, 7 * 6);
+        assert_eq!(loaded, settings);
+    }
+
+    #[test]
+    fn preface_omits_default_settings() {
+        let mut settings = Settings::default();
+        settings.set_header_table_size(Some(DEFAULT_SETTINGS_HEADER_TABLE_SIZE as u32));
+        settings.set_enable_push(true);
+        settings.set_initial_window_size(Some(DEFAULT_INITIAL_WINDOW_SIZE));
+        settings.set_max_frame_size(Some(DEFAULT_MAX_FRAME_SIZE));
+        settings.set_enable_connect_protocol(Some(0));
+
+        let (len, loaded) = round_trip(&settings);
+        assert_eq!(len, 0);
+        assert_eq!(loaded, Settings::default());
+    }
+
+    #[test]
+    fn preface_keeps_settings_without_a_default() {
+        // An unset limit means "unlimited", so any value is worth sending.
+        let mut settings = Settings::default();
+        settings.set_max_concurrent_streams(Some(u32::MAX));
+        settings.set_max_header_list_size(Some(u32::MAX));
+
+        let (len, loaded) = round_trip(&settings);
+        assert_eq!(len, 2 * 6);
+        assert_eq!(loaded, settings);
+    }
+}
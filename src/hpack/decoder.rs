@@ -33,6 +33,8 @@ pub enum DecoderError {
     InvalidStatusCode,
     InvalidPseudoheader,
     InvalidMaxDynamicSize,
+    InvalidSizeUpdatePosition,
+    UppercaseHeaderName,
     IntegerOverflow,
     NeedMore(NeedMore),
 }
@@ -173,9 +175,29 @@ impl Decoder {
     }
 
     /// Decodes the headers found in the given buffer.
-    pub fn decode<F>(
+    #[cfg(any(test, fuzzing))]
+    pub fn decode<F>(&mut self, src: &mut Cursor<&mut BytesMut>, f: F) -> Result<(), DecoderError>
+    where
+        F: FnMut(Header),
+    {
+        self.decode_continued(src, &mut true, f)
+    }
+
+    /// Decodes the next part of a header block that may span several frames.
+    ///
+    /// Dynamic table size updates are only allowed at the start of a header
+    /// block. `can_resize` carries that across the parts of the block: it
+    /// must be true for the first part and is cleared once a header has been
+    /// decoded.
+    ///
+    /// A header name with uppercase characters is lowercased and decoded
+    /// like any other, so that the dynamic table stays in sync with the
+    /// encoder, but `UppercaseHeaderName` is returned once the buffer has
+    /// been decoded.
+    pub fn decode_continued<F>(
         &mut self,
         src: &mut Cursor<&mut BytesMut>,
+        can_resize: &mut bool,
         mut f: F,
     ) -> Result<(), DecoderError>
     where
@@ -183,8 +205,6 @@ impl Decoder {
     {
         use self::Representation::*;
 
-        let mut can_resize = true;
-
         if let Some(size) = self.max_size_update.take() {
             self.last_max_update = size;
         }
@@ -194,6 +214,8 @@ impl Decoder {
 
         tracing::trace!("decode");
 
+        let mut has_uppercase = false;
+
         while let Some(ty) = peek_u8(src) {
             // At this point we are always at the beginning of the next block
             // within the HPACK data. The type of the block can always be
@@ -201,15 +223,15 @@ impl Decoder {
             match Representation::load(ty)? {
                 Indexed => {
                     tracing::trace!(rem = src.remaining(), kind = %"Indexed");
-                    can_resize = false;
+                    *can_resize = false;
                     let entry = self.decode_indexed(src)?;
                     consume(src);
                     f(entry);
                 }
                 LiteralWithIndexing => {
                     tracing::trace!(rem = src.remaining(), kind = %"LiteralWithIndexing");
-                    can_resize = false;
-                    let entry = self.decode_literal(src, true)?;
+                    *can_resize = false;
+                    let entry = self.decode_literal(src, true, &mut has_uppercase)?;
 
                     // Insert the header into the table
                     self.table.insert(entry.clone());
@@ -219,15 +241,15 @@ impl Decoder {
                 }
                 LiteralWithoutIndexing => {
                     tracing::trace!(rem = src.remaining(), kind = %"LiteralWithoutIndexing");
-                    can_resize = false;
-                    let entry = self.decode_literal(src, false)?;
+                    *can_resize = false;
+                    let entry = self.decode_literal(src, false, &mut has_uppercase)?;
                     consume(src);
                     f(entry);
                 }
                 LiteralNeverIndexed => {
                     tracing::trace!(rem = src.remaining(), kind = %"LiteralNeverIndexed");
-                    can_resize = false;
-                    let entry = self.decode_literal(src, false)?;
+                    *can_resize = false;
+                    let entry = self.decode_literal(src, false, &mut has_uppercase)?;
                     consume(src);
 
                     // TODO: Track that this should never be indexed
@@ -236,8 +258,8 @@ impl Decoder {
                 }
                 SizeUpdate => {
                     tracing::trace!(rem = src.remaining(), kind = %"SizeUpdate");
-                    if !can_resize {
-                        return Err(DecoderError::InvalidMaxDynamicSize);
+                    if !*can_resize {
+                        return Err(DecoderError::InvalidSizeUpdatePosition);
                     }
 
                     // Handle the dynamic table size update
@@ -247,6 +269,10 @@ impl Decoder {
             }
         }
 
+        if has_uppercase {
+            return Err(DecoderError::UppercaseHeaderName);
+        }
+
         Ok(())
     }
 
@@ -277,6 +303,7 @@ impl Decoder {
         &mut self,
         buf: &mut Cursor<&mut BytesMut>,
         index: bool,
+        has_uppercase: &mut bool,
     ) -> Result<Header, DecoderError> {
         let prefix = if index { 6 } else { 4 };
 
@@ -292,6 +319,13 @@ impl Decoder {
             // Read the name as a literal
             let name = name_marker.consume(buf);
             let value = value_marker.consume(buf);
+
+            // HTTP/2 requires lower case header names
+            if name.iter().any(u8::is_ascii_uppercase) {
+                *has_uppercase = true;
+                return Header::new(name.to_ascii_lowercase().into(), value);
+            }
+
             Header::new(name, value)
         } else {
             let e = self.table.get(table_idx)?;
@@ -855,6 +889,71 @@ mod test {
         let _: () = de.decode(&mut Cursor::new(&mut buf), |_| {}).unwrap();
     }
 
+    #[test]
+    fn test_decode_size_update_only_at_start_of_block() {
+        let mut de = Decoder::new(4096);
+
+        // size update to 0, then :method GET
+        let mut buf = BytesMut::from(&[0x20, 0x82][..]);
+        de.decode(&mut Cursor::new(&mut buf), |_| {}).unwrap();
+
+        let mut buf = BytesMut::from(&[0x82, 0x20][..]);
+        let err = de.decode(&mut Cursor::new(&mut buf), |_| {}).unwrap_err();
+        assert_eq!(err, DecoderError::InvalidSizeUpdatePosition);
+    }
+
+    #[test]
+    fn test_decode_uppercase_header_name() {
+        let mut de = Decoder::new(4096);
+        let mut headers = vec![];
+
+        // literal with indexing "X-Foo: bar", then :method GET
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0x40, 5]);
+        buf.extend_from_slice(b"X-Foo");
+        buf.extend_from_slice(&[3]);
+        buf.extend_from_slice(b"bar");
+        buf.extend_from_slice(&[0x82]);
+
+        let err = de
+            .decode(&mut Cursor::new(&mut buf), |h| headers.push(h))
+            .unwrap_err();
+        assert_eq!(err, DecoderError::UppercaseHeaderName);
+
+        // the whole block is still decoded, and the name indexed lowercased
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].name().as_slice(), b"x-foo");
+        assert_eq!(de.table.size(), 40);
+
+        let mut buf = BytesMut::from(&[0xbe][..]);
+        de.decode(&mut Cursor::new(&mut buf), |h| {
+            assert_eq!(h.name().as_slice(), b"x-foo");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_decode_continued_size_update_after_header() {
+        let mut de = Decoder::new(4096);
+        let mut can_resize = true;
+
+        let mut buf = BytesMut::from(&[0x20][..]);
+        de.decode_continued(&mut Cursor::new(&mut buf), &mut can_resize, |_| {})
+            .unwrap();
+        assert!(can_resize);
+
+        let mut buf = BytesMut::from(&[0x82][..]);
+        de.decode_continued(&mut Cursor::new(&mut buf), &mut can_resize, |_| {})
+            .unwrap();
+        assert!(!can_resize);
+
+        let mut buf = BytesMut::from(&[0x20][..]);
+        let err = de
+            .decode_continued(&mut Cursor::new(&mut buf), &mut can_resize, |_| {})
+            .unwrap_err();
+        assert_eq!(err, DecoderError::InvalidSizeUpdatePosition);
+    }
+
     #[test]
     fn test_decode_indexed_larger_than_table() {
         let mut de = Decoder::new(0);
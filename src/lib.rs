@@ -126,6 +126,7 @@ pub mod frame;
 
 pub mod client;
 pub mod ext;
+mod observer;
 pub mod server;
 mod share;
 
@@ -134,6 +135,13 @@ mod share;
 pub mod fuzz_bridge;
 
 pub use crate::error::{Error, Reason};
+#[cfg(feature = "unstable")]
+pub use crate::error::{ErrorScope, StreamError};
+#[cfg(feature = "unstable")]
+pub use crate::observer::{
+    StateDurations, StateObserver, StreamEvent, StreamMetrics, StreamState, TransitionCause,
+};
+pub use crate::proto::{EffectiveSettings, PeerSettings};
 pub use crate::share::{FlowControl, Ping, PingPong, Pong, RecvStream, SendStream, StreamId};
 
 #[cfg(feature = "unstable")]
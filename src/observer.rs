@@ -0,0 +1,343 @@
+#[cfg(feature = "unstable")]
+use crate::proto::PeerSettings;
+use crate::{Reason, StreamId};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Receives a notification each time a stream changes state, or a frame of an
+/// unknown type is discarded.
+///
+/// An observer is registered with [`client::Builder::state_observer`] or
+/// [`server::Builder::state_observer`] and lets an application export stream
+/// lifecycle events to its own metrics or tracing system.
+///
+/// The observer is invoked while the connection's internal state is locked,
+/// so implementations should be cheap and must not call back into any handle
+/// belonging to the same connection.
+///
+/// [`client::Builder::state_observer`]: client/struct.Builder.html#method.state_observer
+/// [`server::Builder::state_observer`]: server/struct.Builder.html#method.state_observer
+pub trait StateObserver: fmt::Debug + Send + Sync {
+    /// Called when stream `id` moves from state `from` to state `to`.
+    ///
+    /// The default implementation does nothing.
+    fn on_transition(
+        &self,
+        id: StreamId,
+        from: StreamState,
+        to: StreamState,
+        cause: TransitionCause,
+    ) {
+        let _ = (id, from, to, cause);
+    }
+
+    /// Called when a frame of an unknown type is received.
+    ///
+    /// Such frames are always discarded, as required by [RFC 7540 Section
+    /// 4.1], so this only lets an application count them, for example to
+    /// detect a peer that speaks an extension. `frame_type` is the raw type
+    /// octet and `len` the length of the frame's payload.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// [RFC 7540 Section 4.1]: https://httpwg.org/specs/rfc7540.html#FrameHeader
+    fn on_unknown_frame(&self, frame_type: u8, stream_id: StreamId, len: usize) {
+        let _ = (frame_type, stream_id, len);
+    }
+}
+
+/// The state of a stream, as defined by [RFC 7540 Section 5.1].
+///
+/// [RFC 7540 Section 5.1]: https://httpwg.org/specs/rfc7540.html#StreamStates
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamState {
+    /// The stream has not been opened yet.
+    Idle,
+    /// The stream was promised by the local endpoint.
+    ReservedLocal,
+    /// The stream was promised by the remote endpoint.
+    ReservedRemote,
+    /// Both endpoints may send frames.
+    Open,
+    /// The local endpoint has finished sending.
+    HalfClosedLocal,
+    /// The remote endpoint has finished sending.
+    HalfClosedRemote,
+    /// The stream is closed.
+    Closed,
+}
+
+impl StreamState {
+    /// Merges a restored state and flow control window with the live state of
+    /// the same stream.
+    ///
+    /// This is meant for experiments with connection resumption, where the
+    /// state of a stream is saved and later reconciled with what the new
+    /// connection has observed. The two states are compatible when one can
+    /// lead to the other, in which case the state furthest along the stream
+    /// lifecycle is returned. When neither state can follow from the other,
+    /// for example when each peer has closed a different half,
+    /// `PROTOCOL_ERROR` is returned.
+    ///
+    /// The restored `window` may not exceed the initial window size of the
+    /// live `settings` that govern it, or `FLOW_CONTROL_ERROR` is returned.
+    #[cfg(feature = "unstable")]
+    pub fn merge(
+        self,
+        live: StreamState,
+        window: u32,
+        settings: &PeerSettings,
+    ) -> Result<StreamState, Reason> {
+        if window > settings.initial_window_size() {
+            return Err(Reason::FLOW_CONTROL_ERROR);
+        }
+
+        if self.can_reach(live) {
+            Ok(live)
+        } else if live.can_reach(self) {
+            Ok(self)
+        } else {
+            Err(Reason::PROTOCOL_ERROR)
+        }
+    }
+
+    /// Returns the position of this state in `StateDurations`.
+    fn index(self) -> usize {
+        use self::StreamState::*;
+
+        match self {
+            Idle => 0,
+            ReservedLocal => 1,
+            ReservedRemote => 2,
+            Open => 3,
+            HalfClosedLocal => 4,
+            HalfClosedRemote => 5,
+            Closed => 6,
+        }
+    }
+
+    /// Returns true if a stream in this state may later be in state `to`.
+    #[cfg(feature = "unstable")]
+    fn can_reach(self, to: StreamState) -> bool {
+        use self::StreamState::*;
+
+        match (self, to) {
+            (from, to) if from == to => true,
+            (_, Idle) => false,
+            (Idle, _) => true,
+            (_, Closed) => true,
+            (ReservedLocal, HalfClosedRemote) => true,
+            (ReservedRemote, HalfClosedLocal) => true,
+            (Open, HalfClosedLocal) | (Open, HalfClosedRemote) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Why a stream changed state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionCause {
+    /// A HEADERS or PUSH_PROMISE frame opened or reserved the stream.
+    Headers,
+    /// A frame with the END_STREAM flag closed one side of the stream.
+    EndStream,
+    /// The stream was reset, either locally or by the peer.
+    Reset(Reason),
+    /// The connection was shut down with a GOAWAY frame.
+    GoAway(Reason),
+    /// The connection failed with an I/O error.
+    Io,
+}
+
+/// A lifecycle event of a single stream.
+///
+/// Events are queued on each stream when enabled with
+/// [`client::Builder::stream_event_queue`] or
+/// [`server::Builder::stream_event_queue`], and can be awaited with
+/// [`RecvStream::event`] instead of polling the stream's state.
+///
+/// [`client::Builder::stream_event_queue`]: client/struct.Builder.html#method.stream_event_queue
+/// [`server::Builder::stream_event_queue`]: server/struct.Builder.html#method.stream_event_queue
+/// [`RecvStream::event`]: struct.RecvStream.html#method.event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// A HEADERS frame carrying a request, a response or trailers was
+    /// received.
+    HeadersReceived,
+    /// DATA was received and is ready to be read.
+    DataAvailable,
+    /// The stream was reset, either locally or by the peer.
+    Reset(Reason),
+    /// The stream is closed. No event follows this one.
+    Closed,
+}
+
+/// The time a stream has spent in each state.
+///
+/// This is returned by [`RecvStream::state_durations`] and
+/// [`SendStream::state_durations`], and can help find where a slow stream
+/// spends its time, for example waiting on the first response byte. The
+/// state the stream is currently in is counted up to the moment the
+/// durations were requested.
+///
+/// [`RecvStream::state_durations`]: struct.RecvStream.html#method.state_durations
+/// [`SendStream::state_durations`]: struct.SendStream.html#method.state_durations
+#[derive(Clone, Copy, Debug)]
+pub struct StateDurations {
+    durations: [Duration; 7],
+    current: StreamState,
+    since: Instant,
+}
+
+impl StateDurations {
+    /// Starts timing a stream that entered `Idle` at `now`.
+    pub(crate) fn new(now: Instant) -> StateDurations {
+        StateDurations {
+            durations: [Duration::ZERO; 7],
+            current: StreamState::Idle,
+            since: now,
+        }
+    }
+
+    /// Records that the stream moved to state `to` at `now`.
+    pub(crate) fn transition(&mut self, to: StreamState, now: Instant) {
+        self.elapse(now);
+        self.current = to;
+    }
+
+    /// Returns the durations with the current state counted up to `now`.
+    #[cfg(feature = "unstable")]
+    pub(crate) fn snapshot(mut self, now: Instant) -> StateDurations {
+        self.elapse(now);
+        self
+    }
+
+    /// Returns when the stream entered its current state.
+    pub(crate) fn entered_current_at(&self) -> Instant {
+        self.since
+    }
+
+    fn elapse(&mut self, now: Instant) {
+        self.durations[self.current.index()] += now.saturating_duration_since(self.since);
+        self.since = now;
+    }
+
+    /// Returns the total time spent in `state`.
+    #[cfg(feature = "unstable")]
+    pub fn get(&self, state: StreamState) -> Duration {
+        self.durations[state.index()]
+    }
+
+    /// Returns the state the stream was in when the durations were taken.
+    #[cfg(feature = "unstable")]
+    pub fn current(&self) -> StreamState {
+        self.current
+    }
+
+    /// Returns the time elapsed since the stream was created.
+    #[cfg(feature = "unstable")]
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
+
+/// A snapshot of a stream's state, flow control windows and byte counts.
+///
+/// This is returned by [`RecvStream::summary_metrics`] and
+/// [`SendStream::summary_metrics`], and bundles what an application exporting
+/// per-stream metrics would otherwise read through several calls, each taking
+/// the connection's lock. All values are read at the same instant.
+///
+/// [`RecvStream::summary_metrics`]: struct.RecvStream.html#method.summary_metrics
+/// [`SendStream::summary_metrics`]: struct.SendStream.html#method.summary_metrics
+#[cfg(feature = "unstable")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamMetrics {
+    pub(crate) state: StreamState,
+    pub(crate) send_window: u32,
+    pub(crate) recv_window: u32,
+    pub(crate) bytes_sent: u64,
+    pub(crate) bytes_received: u64,
+    pub(crate) time_in_state: Duration,
+}
+
+#[cfg(feature = "unstable")]
+impl StreamMetrics {
+    /// Returns the state the stream was in.
+    pub fn state(&self) -> StreamState {
+        self.state
+    }
+
+    /// Returns the stream's send window, as advertised by the peer.
+    pub fn send_window(&self) -> u32 {
+        self.send_window
+    }
+
+    /// Returns the stream's receive window, as advertised to the peer.
+    pub fn recv_window(&self) -> u32 {
+        self.recv_window
+    }
+
+    /// Returns the number of DATA bytes written for this stream.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of DATA bytes received on this stream, whether or
+    /// not the application has read them.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns the time the stream has spent in its current state.
+    pub fn time_in_state(&self) -> Duration {
+        self.time_in_state
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn state_durations_accumulate_across_transitions() {
+        let start = Instant::now();
+        let mut durations = StateDurations::new(start);
+
+        durations.transition(StreamState::Open, start + 2 * MS);
+        durations.transition(StreamState::HalfClosedLocal, start + 7 * MS);
+        durations.transition(StreamState::Closed, start + 10 * MS);
+
+        let durations = durations.snapshot(start + 10 * MS);
+        assert_eq!(durations.get(StreamState::Idle), 2 * MS);
+        assert_eq!(durations.get(StreamState::Open), 5 * MS);
+        assert_eq!(durations.get(StreamState::HalfClosedLocal), 3 * MS);
+        assert_eq!(durations.get(StreamState::HalfClosedRemote), Duration::ZERO);
+        assert_eq!(durations.get(StreamState::Closed), Duration::ZERO);
+        assert_eq!(durations.current(), StreamState::Closed);
+        assert_eq!(durations.total(), 10 * MS);
+    }
+
+    #[test]
+    fn state_durations_include_ongoing_state() {
+        let start = Instant::now();
+        let mut durations = StateDurations::new(start);
+        durations.transition(StreamState::Open, start + MS);
+
+        let early = durations.snapshot(start + 3 * MS);
+        assert_eq!(early.get(StreamState::Open), 2 * MS);
+
+        let late = durations.snapshot(start + 8 * MS);
+        assert_eq!(late.get(StreamState::Open), 7 * MS);
+        assert_eq!(late.total(), 8 * MS);
+
+        // Taking a snapshot does not change what is recorded.
+        durations.transition(StreamState::Closed, start + 9 * MS);
+        let done = durations.snapshot(start + 20 * MS);
+        assert_eq!(done.get(StreamState::Open), 8 * MS);
+        assert_eq!(done.get(StreamState::Closed), 11 * MS);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::codec::UserError;
 use crate::frame::{Reason, StreamId};
+use crate::observer::StateObserver;
 use crate::{client, frame, server};
 
 use crate::frame::DEFAULT_INITIAL_WINDOW_SIZE;
@@ -7,9 +8,11 @@ use crate::proto::*;
 
 use bytes::{Buf, Bytes};
 use futures_core::Stream;
+use std::collections::VecDeque;
 use std::io;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -48,6 +51,22 @@ where
     /// Ping/pong handler
     ping_pong: PingPong,
 
+    /// Per-frame-type flood protection
+    frame_rate: FrameRateLimiter,
+
+    /// Received ALTSVC frames not yet taken by the user.
+    alt_svc: VecDeque<frame::AltSvc>,
+
+    /// Number of streams the peer may open before the connection is
+    /// gracefully shut down.
+    max_lifetime_streams: Option<usize>,
+
+    /// Max number of frames read per call to `poll`.
+    max_frames_per_poll: Option<usize>,
+
+    /// True once the peer's preface SETTINGS frame has been received.
+    is_settings_received: bool,
+
     /// Connection settings
     settings: Settings,
 
@@ -71,17 +90,42 @@ struct DynConnection<'a, B: Buf = Bytes> {
     error: &'a mut Option<frame::GoAway>,
 
     ping_pong: &'a mut PingPong,
+
+    frame_rate: &'a mut FrameRateLimiter,
+
+    alt_svc: &'a mut VecDeque<frame::AltSvc>,
+
+    is_settings_received: &'a mut bool,
+
+    peer: peer::Dyn,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Config {
     pub next_stream_id: StreamId,
+    pub stream_id_allocator: Arc<dyn StreamIdAllocator>,
     pub initial_max_send_streams: usize,
     pub max_send_buffer_size: usize,
+    pub max_recv_buffer_size: usize,
+    pub balance_window_updates: bool,
+    pub flush_idle_window_updates: bool,
+    pub adaptive_connection_window: Option<Duration>,
+    pub reset_overrun_streams: bool,
     pub reset_stream_duration: Duration,
     pub reset_stream_max: usize,
     pub remote_reset_stream_max: usize,
+    pub local_error_reset_streams_max: Option<usize>,
     pub settings: frame::Settings,
+    pub window_update_policy: Arc<dyn WindowUpdatePolicy>,
+    pub state_observer: Option<Arc<dyn StateObserver>>,
+    pub stream_event_capacity: usize,
+    pub stalled_stream_timeout: Option<Duration>,
+    pub frame_rate_limit: Option<(usize, Duration)>,
+    pub empty_data_rate_limit: Option<(usize, Duration)>,
+    pub max_lifetime_streams: Option<usize>,
+    pub expected_scheme: Option<http::uri::Scheme>,
+    pub max_frames_per_poll: Option<usize>,
+    pub settings_ack_timeout: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -102,17 +146,19 @@ where
     P: Peer,
     B: Buf,
 {
-    pub fn new(codec: Codec<T, Prioritized<B>>, config: Config) -> Connection<T, P, B> {
-        fn streams_config(config: &Config) -> streams::Config {
+    pub fn new(mut codec: Codec<T, Prioritized<B>>, config: Config) -> Connection<T, P, B> {
+        fn streams_config<P: Peer>(config: &Config) -> streams::Config {
             streams::Config {
-                local_init_window_sz: config
-                    .settings
-                    .initial_window_size()
-                    .unwrap_or(DEFAULT_INITIAL_WINDOW_SIZE),
                 initial_max_send_streams: config.initial_max_send_streams,
                 local_max_buffer_size: config.max_send_buffer_size,
+                local_max_recv_buffer_size: config.max_recv_buffer_size,
+                local_balance_window_updates: config.balance_window_updates,
                 local_next_stream_id: config.next_stream_id,
-                local_push_enabled: config.settings.is_push_enabled().unwrap_or(true),
+                local_stream_id_allocator: config.stream_id_allocator.clone(),
+                local_push_enabled: config
+                    .settings
+                    .is_push_enabled()
+                    .unwrap_or_else(|| P::default_settings().is_push_enabled()),
                 extended_connect_protocol_enabled: config
                     .settings
                     .is_extended_connect_protocol_enabled()
@@ -120,14 +166,24 @@ where
                 local_reset_duration: config.reset_stream_duration,
                 local_reset_max: config.reset_stream_max,
                 remote_reset_max: config.remote_reset_stream_max,
+                local_max_error_reset_streams: config.local_error_reset_streams_max,
                 remote_init_window_sz: DEFAULT_INITIAL_WINDOW_SIZE,
                 remote_max_initiated: config
                     .settings
                     .max_concurrent_streams()
                     .map(|max| max as usize),
+                window_update_policy: config.window_update_policy.clone(),
+                local_flush_idle_window_updates: config.flush_idle_window_updates,
+                local_adaptive_connection_window: config.adaptive_connection_window,
+                local_reset_overrun_streams: config.reset_overrun_streams,
+                state_observer: config.state_observer.clone(),
+                stream_event_capacity: config.stream_event_capacity,
+                local_stalled_stream_timeout: config.stalled_stream_timeout,
+                expected_scheme: config.expected_scheme.clone(),
             }
         }
-        let streams = Streams::new(streams_config(&config));
+        let streams = Streams::new(streams_config::<P>(&config));
+        codec.set_unknown_frame_observer(config.state_observer.clone());
         Connection {
             codec,
             inner: ConnectionInner {
@@ -135,7 +191,19 @@ where
                 error: None,
                 go_away: GoAway::new(),
                 ping_pong: PingPong::new(),
-                settings: Settings::new(config.settings),
+                frame_rate: FrameRateLimiter::new(
+                    config.frame_rate_limit,
+                    config.empty_data_rate_limit,
+                ),
+                alt_svc: VecDeque::new(),
+                max_lifetime_streams: config.max_lifetime_streams,
+                max_frames_per_poll: config.max_frames_per_poll,
+                is_settings_received: false,
+                settings: Settings::new(
+                    config.settings,
+                    P::default_settings(),
+                    config.settings_ack_timeout,
+                ),
                 streams,
                 span: tracing::debug_span!("Connection", peer = %P::NAME),
                 _phantom: PhantomData,
@@ -176,6 +244,21 @@ where
         self.inner.streams.max_recv_streams()
     }
 
+    /// Returns the number of active streams initiated by the remote peer.
+    pub(crate) fn num_recv_streams(&self) -> usize {
+        self.inner.streams.num_recv_streams()
+    }
+
+    /// Resets up to `n` active streams, lowest priority first.
+    pub(crate) fn reset_lowest_priority_streams(&mut self, n: usize, reason: Reason) -> usize {
+        self.inner.streams.reset_lowest_priority(n, reason)
+    }
+
+    /// Returns the acknowledged settings of both peers.
+    pub(crate) fn effective_settings(&self) -> EffectiveSettings {
+        self.inner.settings.effective()
+    }
+
     #[cfg(feature = "unstable")]
     pub fn num_wired_streams(&self) -> usize {
         self.inner.streams.num_wired_streams()
@@ -248,6 +331,11 @@ where
         self.inner.ping_pong.take_user_pings()
     }
 
+    /// Takes the oldest received ALTSVC frame, if any.
+    pub(crate) fn take_alt_svc(&mut self) -> Option<frame::AltSvc> {
+        self.inner.alt_svc.pop_front()
+    }
+
     /// Advances the internal state of the connection.
     pub fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
         // XXX(eliza): cloning the span is unfortunately necessary here in
@@ -282,6 +370,10 @@ where
                                 continue;
                             }
 
+                            // Data sent above may have exhausted a window, so
+                            // the timer is armed only now.
+                            self.inner.streams.poll_stall_timer(cx);
+
                             return Poll::Pending;
                         }
                     };
@@ -308,6 +400,12 @@ where
         // check and then comparison of the queue possibly multiple times a
         // second (and thus, the clock wouldn't have changed enough to matter).
         self.clear_expired_reset_streams();
+        self.inner.streams.reset_stalled_streams();
+        if self.inner.go_away.going_away().is_none() {
+            self.inner.settings.poll_ack_timeout(cx)?;
+        }
+
+        let mut frames = 0;
 
         loop {
             // First, ensure that the `Connection` is able to receive a frame
@@ -334,6 +432,15 @@ where
             }
             ready!(self.poll_ready(cx))?;
 
+            if self.inner.max_frames_per_poll == Some(frames) {
+                // Give other tasks a chance to run. Replies to the frames read
+                // so far are flushed now, and the frames left in the codec
+                // are read the next time the connection is polled.
+                tracing::trace!(frames, "read budget exhausted; yielding");
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
             match self
                 .inner
                 .as_dyn()
@@ -346,11 +453,13 @@ where
                         &mut self.inner.streams,
                     )?;
                 }
-                ReceivedFrame::Continue => (),
+                ReceivedFrame::Continue => self.inner.maybe_rotate(),
                 ReceivedFrame::Done => {
                     return Poll::Ready(Ok(()));
                 }
             }
+
+            frames += 1;
         }
     }
 
@@ -364,6 +473,45 @@ where
     P: Peer,
     B: Buf,
 {
+    /// Starts a graceful shutdown once the peer has opened as many streams as
+    /// the connection may serve over its lifetime.
+    fn maybe_rotate(&mut self) {
+        let max = match self.max_lifetime_streams {
+            Some(max) => max,
+            None => return,
+        };
+
+        if !self.go_away.is_going_away() && self.streams.total_recv_streams() >= max {
+            tracing::debug!(max, "stream lifetime limit reached; going away");
+            self.go_away_gracefully();
+        }
+    }
+
+    fn go_away_gracefully(&mut self) {
+        if self.go_away.is_going_away() {
+            // No reason to start a new one.
+            return;
+        }
+
+        // According to http://httpwg.org/specs/rfc7540.html#GOAWAY:
+        //
+        // > A server that is attempting to gracefully shut down a connection
+        // > SHOULD send an initial GOAWAY frame with the last stream
+        // > identifier set to 2^31-1 and a NO_ERROR code. This signals to the
+        // > client that a shutdown is imminent and that initiating further
+        // > requests is prohibited. After allowing time for any in-flight
+        // > stream creation (at least one round-trip time), the server can
+        // > send another GOAWAY frame with an updated last stream identifier.
+        // > This ensures that a connection can be cleanly shut down without
+        // > losing requests.
+        self.go_away.start_graceful();
+        self.as_dyn().go_away(StreamId::MAX, Reason::NO_ERROR);
+
+        // We take the advice of waiting 1 RTT literally, and wait
+        // for a pong before proceeding.
+        self.ping_pong.ping_shutdown();
+    }
+
     fn as_dyn(&mut self) -> DynConnection<'_, B> {
         let ConnectionInner {
             state,
@@ -371,6 +519,9 @@ where
             streams,
             error,
             ping_pong,
+            frame_rate,
+            alt_svc,
+            is_settings_received,
             ..
         } = self;
         let streams = streams.as_dyn();
@@ -380,6 +531,10 @@ where
             streams,
             error,
             ping_pong,
+            frame_rate,
+            alt_svc,
+            is_settings_received,
+            peer: P::r#dyn(),
         }
     }
 }
@@ -441,9 +596,17 @@ where
                     return Ok(());
                 }
 
+                // A stream error that escalated to this connection error
+                // still resets its stream, with its own reason.
+                let reset = self.streams.take_escalated_reset();
+
                 // Reset all active streams
                 self.streams.handle_error(e);
                 self.go_away_now_data(reason, debug_data);
+
+                if let Some(reset) = reset {
+                    self.go_away.reset_before_go_away(reset);
+                }
                 Ok(())
             }
             // Attempting to read a frame resulted in a stream level error.
@@ -452,8 +615,10 @@ where
             Err(Error::Reset(id, reason, initiator)) => {
                 debug_assert_eq!(initiator, Initiator::Library);
                 tracing::trace!(?id, ?reason, "stream error");
-                self.streams.send_reset(id, reason);
-                Ok(())
+                match self.streams.send_reset(id, reason) {
+                    Ok(()) => Ok(()),
+                    Err(e) => self.handle_poll2_result(Err(e)),
+                }
             }
             // Attempting to read a frame resulted in an I/O error. All
             // active streams must be reset.
@@ -474,6 +639,29 @@ where
 
     fn recv_frame(&mut self, frame: Option<Frame>) -> Result<ReceivedFrame, Error> {
         use crate::frame::Frame::*;
+
+        // The peer's connection preface must start with a SETTINGS frame.
+        if !*self.is_settings_received {
+            match frame {
+                Some(Settings(ref frame)) if !frame.is_ack() => {
+                    *self.is_settings_received = true;
+                }
+                Some(ref frame) => {
+                    let debug_data = unexpected_frame_before_settings(frame);
+                    proto_err!(conn: "recv_frame: {}", debug_data);
+                    return Err(Error::library_go_away_data(
+                        Reason::PROTOCOL_ERROR,
+                        debug_data,
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        if let Some(ref frame) = frame {
+            self.frame_rate.recv_frame(frame)?;
+        }
+
         match frame {
             Some(Headers(frame)) => {
                 tracing::trace!(?frame, "recv HEADERS");
@@ -509,11 +697,14 @@ where
                 let status = self.ping_pong.recv_ping(frame);
                 if status.is_shutdown() {
                     assert!(
-                        self.go_away.is_going_away(),
+                        self.go_away.is_draining(),
                         "received unexpected shutdown ping"
                     );
 
+                    // Streams opened while the first GOAWAY was in flight
+                    // have been accepted; now tell the peer which was last.
                     let last_processed_id = self.streams.last_processed_id();
+                    self.go_away.finish_graceful();
                     self.go_away(last_processed_id, Reason::NO_ERROR);
                 }
             }
@@ -523,7 +714,19 @@ where
             }
             Some(Priority(frame)) => {
                 tracing::trace!(?frame, "recv PRIORITY");
-                // TODO: handle
+                self.streams.recv_priority(frame);
+            }
+            Some(AltSvc(frame)) => {
+                tracing::trace!(?frame, "recv ALTSVC");
+                // Only servers advertise alternative services, so a server
+                // ignores the frame. A client keeps the most recent ones
+                // until they are taken by the user.
+                if !self.peer.is_server() {
+                    if self.alt_svc.len() == MAX_PENDING_ALT_SVC {
+                        self.alt_svc.pop_front();
+                    }
+                    self.alt_svc.push_back(frame);
+                }
             }
             None => {
                 tracing::trace!("codec closed");
@@ -535,6 +738,24 @@ where
     }
 }
 
+/// Returns the GOAWAY debug data sent when `frame` is received before the
+/// peer's preface SETTINGS frame.
+fn unexpected_frame_before_settings(frame: &Frame) -> &'static str {
+    use crate::frame::Frame::*;
+    match frame {
+        Data(_) => "DATA received before SETTINGS",
+        Headers(_) => "HEADERS received before SETTINGS",
+        Priority(_) => "PRIORITY received before SETTINGS",
+        PushPromise(_) => "PUSH_PROMISE received before SETTINGS",
+        Settings(_) => "SETTINGS ACK received before SETTINGS",
+        Ping(_) => "PING received before SETTINGS",
+        GoAway(_) => "GOAWAY received before SETTINGS",
+        WindowUpdate(_) => "WINDOW_UPDATE received before SETTINGS",
+        Reset(_) => "RST_STREAM received before SETTINGS",
+        AltSvc(_) => "ALTSVC received before SETTINGS",
+    }
+}
+
 enum ReceivedFrame {
     Settings(frame::Settings),
     Continue,
@@ -562,27 +783,7 @@ where
 
     // Graceful shutdown only makes sense for server peers.
     pub fn go_away_gracefully(&mut self) {
-        if self.inner.go_away.is_going_away() {
-            // No reason to start a new one.
-            return;
-        }
-
-        // According to http://httpwg.org/specs/rfc7540.html#GOAWAY:
-        //
-        // > A server that is attempting to gracefully shut down a connection
-        // > SHOULD send an initial GOAWAY frame with the last stream
-        // > identifier set to 2^31-1 and a NO_ERROR code. This signals to the
-        // > client that a shutdown is imminent and that initiating further
-        // > requests is prohibited. After allowing time for any in-flight
-        // > stream creation (at least one round-trip time), the server can
-        // > send another GOAWAY frame with an updated last stream identifier.
-        // > This ensures that a connection can be cleanly shut down without
-        // > losing requests.
-        self.inner.as_dyn().go_away(StreamId::MAX, Reason::NO_ERROR);
-
-        // We take the advice of waiting 1 RTT literally, and wait
-        // for a pong before proceeding.
-        self.inner.ping_pong.ping_shutdown();
+        self.inner.go_away_gracefully();
     }
 }
 
@@ -6,7 +6,7 @@ use std::fmt;
 use std::io;
 
 /// Either an H2 reason  or an I/O error
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     Reset(StreamId, Reason, Initiator),
     GoAway(Bytes, Reason, Initiator),
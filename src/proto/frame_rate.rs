@@ -0,0 +1,113 @@
+use crate::frame::{Frame, Reason};
+use crate::proto::Error;
+
+use bytes::Buf;
+use std::time::{Duration, Instant};
+
+/// Limits how often the remote may send frames that cost us work but carry
+/// no stream progress.
+///
+/// Each limited frame type is counted separately over a fixed interval. When
+/// any one type exceeds the configured maximum within the interval, the
+/// connection is torn down with `ENHANCE_YOUR_CALM`.
+///
+/// Empty DATA frames may be given their own limit, as they are a way to make
+/// us do work without ever running into flow control.
+#[derive(Debug)]
+pub(crate) struct FrameRateLimiter {
+    limits: [Option<(usize, Duration)>; Limited::COUNT],
+    interval_starts: [Instant; Limited::COUNT],
+    counts: [usize; Limited::COUNT],
+}
+
+/// Frame types subject to the limiter.
+#[derive(Debug, Clone, Copy)]
+enum Limited {
+    Ping,
+    Settings,
+    Priority,
+    EmptyData,
+}
+
+impl FrameRateLimiter {
+    /// `empty_data_limit` replaces `limit` for empty DATA frames, if set.
+    pub(crate) fn new(
+        limit: Option<(usize, Duration)>,
+        empty_data_limit: Option<(usize, Duration)>,
+    ) -> Self {
+        let mut limits = [limit; Limited::COUNT];
+        limits[Limited::EmptyData as usize] = empty_data_limit.or(limit);
+
+        FrameRateLimiter {
+            limits,
+            interval_starts: [Instant::now(); Limited::COUNT],
+            counts: [0; Limited::COUNT],
+        }
+    }
+
+    /// Counts a received frame, returning a connection error if its type has
+    /// exceeded the configured rate.
+    pub(crate) fn recv_frame<B: Buf>(&mut self, frame: &Frame<B>) -> Result<(), Error> {
+        let kind = match Limited::of(frame) {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+
+        let (max, interval) = match self.limits[kind as usize] {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+        let interval_start = &mut self.interval_starts[kind as usize];
+        let count = &mut self.counts[kind as usize];
+
+        if now.saturating_duration_since(*interval_start) >= interval {
+            *interval_start = now;
+            *count = 0;
+        }
+
+        *count += 1;
+
+        if *count > max {
+            let debug_data = kind.debug_data();
+            tracing::warn!(
+                "recv_frame; {:?} frames exceeded rate limit ({} per {:?})",
+                kind,
+                max,
+                interval,
+            );
+            return Err(Error::library_go_away_data(
+                Reason::ENHANCE_YOUR_CALM,
+                debug_data,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Limited {
+    const COUNT: usize = 4;
+
+    fn of<B: Buf>(frame: &Frame<B>) -> Option<Self> {
+        match frame {
+            Frame::Ping(ping) if !ping.is_ack() => Some(Limited::Ping),
+            Frame::Settings(settings) if !settings.is_ack() => Some(Limited::Settings),
+            Frame::Priority(_) => Some(Limited::Priority),
+            Frame::Data(data) if !data.is_end_stream() && !data.payload().has_remaining() => {
+                Some(Limited::EmptyData)
+            }
+            _ => None,
+        }
+    }
+
+    fn debug_data(self) -> &'static str {
+        match self {
+            Limited::Ping => "too_many_pings",
+            Limited::Settings => "too_many_settings",
+            Limited::Priority => "too_many_priority",
+            Limited::EmptyData => "too_many_empty_data",
+        }
+    }
+}
@@ -15,8 +15,12 @@ pub(super) struct GoAway {
     going_away: Option<GoingAway>,
     /// Whether the user started the GOAWAY by calling `abrupt_shutdown`.
     is_user_initiated: bool,
+    /// Progress of a graceful shutdown, if one was started.
+    graceful: Option<GracefulShutdown>,
     /// A GOAWAY frame that must be buffered in the Codec immediately.
     pending: Option<frame::GoAway>,
+    /// A RST_STREAM frame to buffer just before the pending GOAWAY.
+    pending_reset: Option<frame::Reset>,
 }
 
 /// Keeps a memory of any GOAWAY frames we've sent before.
@@ -37,16 +41,53 @@ pub(crate) struct GoingAway {
     reason: Reason,
 }
 
+/// Tracks a graceful shutdown, which sends two GOAWAY frames.
+///
+/// The first GOAWAY has a last stream ID of `StreamId::MAX`. It tells the
+/// peer to stop opening streams, while streams it opened before seeing the
+/// frame are still accepted. After a round trip, the second GOAWAY carries
+/// the ID of the last stream that was actually processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GracefulShutdown {
+    /// The initial GOAWAY has been queued; new streams are still accepted.
+    Draining,
+    /// The final GOAWAY has been queued.
+    Closing,
+}
+
 impl GoAway {
     pub fn new() -> Self {
         GoAway {
             close_now: false,
             going_away: None,
             is_user_initiated: false,
+            graceful: None,
             pending: None,
+            pending_reset: None,
         }
     }
 
+    /// Begin a graceful shutdown.
+    ///
+    /// The caller is expected to queue the initial GOAWAY frame.
+    pub fn start_graceful(&mut self) {
+        debug_assert!(self.graceful.is_none());
+        self.graceful = Some(GracefulShutdown::Draining);
+    }
+
+    /// Finish a graceful shutdown once the drain period has passed.
+    ///
+    /// The caller is expected to queue the final GOAWAY frame.
+    pub fn finish_graceful(&mut self) {
+        debug_assert!(self.is_draining());
+        self.graceful = Some(GracefulShutdown::Closing);
+    }
+
+    /// Returns true between the two GOAWAY frames of a graceful shutdown.
+    pub fn is_draining(&self) -> bool {
+        self.graceful == Some(GracefulShutdown::Draining)
+    }
+
     /// Enqueue a GOAWAY frame to be written.
     ///
     /// The connection is expected to continue to run until idle.
@@ -80,6 +121,15 @@ impl GoAway {
         self.go_away(f);
     }
 
+    /// Enqueue a RST_STREAM frame to be written ahead of the pending GOAWAY.
+    ///
+    /// This is for a stream error that escalated to the connection error the
+    /// GOAWAY carries, so that the stream still learns its own error code.
+    pub fn reset_before_go_away(&mut self, f: frame::Reset) {
+        debug_assert!(self.pending.is_some());
+        self.pending_reset = Some(f);
+    }
+
     pub fn go_away_from_user(&mut self, f: frame::GoAway) {
         self.is_user_initiated = true;
         self.go_away_now(f);
@@ -101,17 +151,12 @@ impl GoAway {
 
     /// Returns if the connection should close now, or wait until idle.
     pub fn should_close_now(&self) -> bool {
-        self.pending.is_none() && self.close_now
+        self.pending.is_none() && self.pending_reset.is_none() && self.close_now
     }
 
     /// Returns if the connection should be closed when idle.
     pub fn should_close_on_idle(&self) -> bool {
-        !self.close_now
-            && self
-                .going_away
-                .as_ref()
-                .map(|g| g.last_processed_id != StreamId::MAX)
-                .unwrap_or(false)
+        !self.close_now && self.going_away.is_some() && !self.is_draining()
     }
 
     /// Try to write a pending GOAWAY frame to the buffer.
@@ -126,6 +171,15 @@ impl GoAway {
         T: AsyncWrite + Unpin,
         B: Buf,
     {
+        if let Some(frame) = self.pending_reset.take() {
+            if !dst.poll_ready(cx)?.is_ready() {
+                self.pending_reset = Some(frame);
+                return Poll::Pending;
+            }
+
+            dst.buffer(frame.into()).expect("invalid RST_STREAM frame");
+        }
+
         if let Some(frame) = self.pending.take() {
             if !dst.poll_ready(cx)?.is_ready() {
                 self.pending = Some(frame);
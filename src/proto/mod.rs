@@ -1,5 +1,6 @@
 mod connection;
 mod error;
+mod frame_rate;
 mod go_away;
 mod peer;
 mod ping_pong;
@@ -10,11 +11,16 @@ pub(crate) use self::connection::{Config, Connection};
 pub use self::error::{Error, Initiator};
 pub(crate) use self::peer::{Dyn as DynPeer, Peer};
 pub(crate) use self::ping_pong::UserPings;
+pub use self::settings::{EffectiveSettings, PeerSettings};
+#[cfg(feature = "unstable")]
+pub use self::streams::{BdpPolicy, EagerPolicy};
 pub(crate) use self::streams::{DynStreams, OpaqueStreamRef, StreamRef, Streams};
+pub use self::streams::{MonotonicIds, StreamIdAllocator, ThresholdPolicy, WindowUpdatePolicy};
 pub(crate) use self::streams::{Open, PollReset, Prioritized};
 
 use crate::codec::Codec;
 
+use self::frame_rate::FrameRateLimiter;
 use self::go_away::GoAway;
 use self::ping_pong::PingPong;
 use self::settings::Settings;
@@ -34,4 +40,7 @@ pub const MAX_WINDOW_SIZE: WindowSize = (1 << 31) - 1; // i32::MAX as u32
 pub const DEFAULT_REMOTE_RESET_STREAM_MAX: usize = 20;
 pub const DEFAULT_RESET_STREAM_MAX: usize = 10;
 pub const DEFAULT_RESET_STREAM_SECS: u64 = 30;
+pub const DEFAULT_LOCAL_RESET_COUNT_MAX: usize = 1024;
 pub const DEFAULT_MAX_SEND_BUFFER_SIZE: usize = 1024 * 400;
+pub const MAX_PENDING_ALT_SVC: usize = 16;
+pub const MAX_AUTHORITIES: usize = 64;
@@ -1,6 +1,6 @@
 use crate::error::Reason;
 use crate::frame::{Pseudo, StreamId};
-use crate::proto::{Error, Open};
+use crate::proto::{Error, Open, PeerSettings};
 
 use http::{HeaderMap, Request, Response};
 
@@ -16,6 +16,12 @@ pub(crate) trait Peer {
 
     fn is_server() -> bool;
 
+    /// Settings this peer assumes for itself until its own SETTINGS are
+    /// acknowledged, and for any values it never sends.
+    fn default_settings() -> PeerSettings {
+        PeerSettings::default()
+    }
+
     fn convert_poll_message(
         pseudo: Pseudo,
         fields: HeaderMap,
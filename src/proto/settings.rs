@@ -2,7 +2,12 @@ use crate::codec::UserError;
 use crate::error::Reason;
 use crate::frame;
 use crate::proto::*;
+use tokio::time::{Instant, Sleep};
+
+use std::future::Future;
+use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub(crate) struct Settings {
@@ -12,26 +17,71 @@ pub(crate) struct Settings {
     /// the socket first then the settings applied **before** receiving any
     /// further frames.
     remote: Option<frame::Settings>,
+    /// Our local settings, as acknowledged by the remote.
+    local_effective: PeerSettings,
+    /// The remote's settings, as applied after sending the ACK.
+    remote_effective: PeerSettings,
+    /// How long the remote may take to ACK our SETTINGS.
+    ack_timeout: Option<Duration>,
+    /// Wakes the connection once our SETTINGS have waited `ack_timeout`.
+    ack_timer: Option<Pin<Box<Sleep>>>,
+}
+
+/// The settings in effect on a connection.
+///
+/// Only values that have been acknowledged are reflected: local settings
+/// once the remote has sent a SETTINGS ACK for them, and remote settings
+/// once they have been acknowledged and applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EffectiveSettings {
+    local: PeerSettings,
+    remote: PeerSettings,
+}
+
+/// The settings one side of a connection has sent.
+///
+/// Settings the peer never sent have their default value as defined by
+/// [RFC 7540 Section 6.5.2]. A server's own settings are the exception:
+/// push is always reported as disabled, since servers never accept pushes.
+///
+/// [RFC 7540 Section 6.5.2]: https://httpwg.org/specs/rfc7540.html#SettingValues
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerSettings {
+    header_table_size: u32,
+    enable_push: bool,
+    max_concurrent_streams: Option<u32>,
+    initial_window_size: u32,
+    max_frame_size: u32,
+    max_header_list_size: Option<u32>,
+    enable_connect_protocol: bool,
 }
 
 #[derive(Debug)]
 enum Local {
     /// We want to send these SETTINGS to the remote when the socket is ready.
     ToSend(frame::Settings),
-    /// We have sent these SETTINGS and are waiting for the remote to ACK
-    /// before we apply them.
-    WaitingAck(frame::Settings),
+    /// We have sent these SETTINGS at the given time and are waiting for the
+    /// remote to ACK before we apply them.
+    WaitingAck(frame::Settings, Instant),
     /// Our local settings are in sync with the remote.
     Synced,
 }
 
 impl Settings {
-    pub(crate) fn new(local: frame::Settings) -> Self {
+    pub(crate) fn new(
+        local: frame::Settings,
+        local_defaults: PeerSettings,
+        ack_timeout: Option<Duration>,
+    ) -> Self {
         Settings {
             // We assume the initial local SETTINGS were flushed during
             // the handshake process.
-            local: Local::WaitingAck(local),
+            local: Local::WaitingAck(local, Instant::now()),
             remote: None,
+            local_effective: local_defaults,
+            remote_effective: PeerSettings::default(),
+            ack_timeout,
+            ack_timer: None,
         }
     }
 
@@ -49,7 +99,7 @@ impl Settings {
     {
         if frame.is_ack() {
             match &self.local {
-                Local::WaitingAck(local) => {
+                Local::WaitingAck(local, _) => {
                     tracing::debug!("received settings ACK; applying {:?}", local);
 
                     if let Some(max) = local.max_frame_size() {
@@ -65,6 +115,7 @@ impl Settings {
                     }
 
                     streams.apply_local_settings(local)?;
+                    self.local_effective.apply(local);
                     self.local = Local::Synced;
                     Ok(())
                 }
@@ -130,6 +181,8 @@ impl Settings {
             if let Some(val) = settings.max_frame_size() {
                 dst.set_max_send_frame_size(val as usize);
             }
+
+            self.remote_effective.apply(settings);
         }
 
         self.remote = None;
@@ -145,11 +198,226 @@ impl Settings {
                     .expect("invalid settings frame");
                 tracing::trace!("local settings sent; waiting for ack: {:?}", settings);
 
-                self.local = Local::WaitingAck(settings.clone());
+                self.local = Local::WaitingAck(settings.clone(), Instant::now());
             }
             Local::WaitingAck(..) | Local::Synced => {}
         }
 
         Poll::Ready(Ok(()))
     }
+
+    /// Returns a `SETTINGS_TIMEOUT` connection error if our SETTINGS have
+    /// waited for an ACK for longer than the configured timeout.
+    ///
+    /// Otherwise, while an ACK is pending, a timer is registered with `cx` so
+    /// the connection is polled again once the timeout elapses.
+    pub(crate) fn poll_ack_timeout(&mut self, cx: &mut Context) -> Result<(), Error> {
+        let deadline = match (self.ack_timeout, &self.local) {
+            (Some(timeout), Local::WaitingAck(_, sent_at)) => *sent_at + timeout,
+            _ => {
+                self.ack_timer = None;
+                return Ok(());
+            }
+        };
+
+        self.check_ack_timeout(Instant::now())?;
+
+        let timer = self
+            .ack_timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+
+        if timer.deadline() != deadline {
+            timer.as_mut().reset(deadline);
+        }
+
+        // The timeout is reported on the next poll of the connection.
+        if timer.as_mut().poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+
+        Ok(())
+    }
+
+    /// Returns a `SETTINGS_TIMEOUT` connection error if our SETTINGS have
+    /// waited for an ACK for longer than the configured timeout as of `now`.
+    pub(crate) fn check_ack_timeout(&self, now: Instant) -> Result<(), Error> {
+        let (timeout, sent_at) = match (self.ack_timeout, &self.local) {
+            (Some(timeout), Local::WaitingAck(_, sent_at)) => (timeout, *sent_at),
+            _ => return Ok(()),
+        };
+
+        if now.saturating_duration_since(sent_at) >= timeout {
+            proto_err!(conn: "SETTINGS not acknowledged within {:?}", timeout);
+            return Err(Error::library_go_away(Reason::SETTINGS_TIMEOUT));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the acknowledged settings of both sides of the connection.
+    pub(crate) fn effective(&self) -> EffectiveSettings {
+        EffectiveSettings {
+            local: self.local_effective.clone(),
+            remote: self.remote_effective.clone(),
+        }
+    }
+}
+
+// ===== impl EffectiveSettings =====
+
+impl EffectiveSettings {
+    /// Returns the settings sent by this endpoint.
+    pub fn local(&self) -> &PeerSettings {
+        &self.local
+    }
+
+    /// Returns the settings sent by the remote peer.
+    pub fn remote(&self) -> &PeerSettings {
+        &self.remote
+    }
+}
+
+// ===== impl PeerSettings =====
+
+impl PeerSettings {
+    /// Returns the value of `SETTINGS_HEADER_TABLE_SIZE`.
+    pub fn header_table_size(&self) -> u32 {
+        self.header_table_size
+    }
+
+    /// Returns the value of `SETTINGS_ENABLE_PUSH`.
+    pub fn is_push_enabled(&self) -> bool {
+        self.enable_push
+    }
+
+    /// Returns the value of `SETTINGS_MAX_CONCURRENT_STREAMS`, or `None` if
+    /// there is no limit.
+    pub fn max_concurrent_streams(&self) -> Option<u32> {
+        self.max_concurrent_streams
+    }
+
+    /// Returns the value of `SETTINGS_INITIAL_WINDOW_SIZE`.
+    pub fn initial_window_size(&self) -> u32 {
+        self.initial_window_size
+    }
+
+    /// Returns the value of `SETTINGS_MAX_FRAME_SIZE`.
+    pub fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    /// Returns the value of `SETTINGS_MAX_HEADER_LIST_SIZE`, or `None` if
+    /// there is no limit.
+    pub fn max_header_list_size(&self) -> Option<u32> {
+        self.max_header_list_size
+    }
+
+    /// Returns the value of `SETTINGS_ENABLE_CONNECT_PROTOCOL`.
+    pub fn is_extended_connect_protocol_enabled(&self) -> bool {
+        self.enable_connect_protocol
+    }
+
+    pub(crate) fn set_enable_push(&mut self, enable: bool) {
+        self.enable_push = enable;
+    }
+
+    /// Updates the values present in `frame`.
+    fn apply(&mut self, frame: &frame::Settings) {
+        if let Some(val) = frame.header_table_size() {
+            self.header_table_size = val;
+        }
+
+        if let Some(val) = frame.is_push_enabled() {
+            self.enable_push = val;
+        }
+
+        if let Some(val) = frame.max_concurrent_streams() {
+            self.max_concurrent_streams = Some(val);
+        }
+
+        if let Some(val) = frame.initial_window_size() {
+            self.initial_window_size = val;
+        }
+
+        if let Some(val) = frame.max_frame_size() {
+            self.max_frame_size = val;
+        }
+
+        if let Some(val) = frame.max_header_list_size() {
+            self.max_header_list_size = Some(val);
+        }
+
+        if let Some(val) = frame.is_extended_connect_protocol_enabled() {
+            self.enable_connect_protocol = val;
+        }
+    }
+}
+
+impl Default for PeerSettings {
+    fn default() -> Self {
+        PeerSettings {
+            header_table_size: frame::DEFAULT_SETTINGS_HEADER_TABLE_SIZE as u32,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: frame::DEFAULT_INITIAL_WINDOW_SIZE,
+            max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+            enable_connect_protocol: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiting_ack(ack_timeout: Option<Duration>) -> (Settings, Instant) {
+        let settings = Settings::new(
+            frame::Settings::default(),
+            PeerSettings::default(),
+            ack_timeout,
+        );
+        let sent_at = match settings.local {
+            Local::WaitingAck(_, sent_at) => sent_at,
+            _ => unreachable!(),
+        };
+        (settings, sent_at)
+    }
+
+    #[test]
+    fn ack_timeout_not_reached() {
+        let (settings, sent_at) = waiting_ack(Some(Duration::from_secs(5)));
+
+        assert!(settings.check_ack_timeout(sent_at).is_ok());
+        assert!(settings
+            .check_ack_timeout(sent_at + Duration::from_millis(4_999))
+            .is_ok());
+    }
+
+    #[test]
+    fn ack_timeout_reached_is_settings_timeout() {
+        let (settings, sent_at) = waiting_ack(Some(Duration::from_secs(5)));
+
+        let err = settings
+            .check_ack_timeout(sent_at + Duration::from_secs(5))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::GoAway(_, Reason::SETTINGS_TIMEOUT, Initiator::Library)
+        ));
+    }
+
+    #[test]
+    fn ack_timeout_ignored_once_synced_or_unset() {
+        let (settings, sent_at) = waiting_ack(None);
+        assert!(settings
+            .check_ack_timeout(sent_at + Duration::from_secs(3_600))
+            .is_ok());
+
+        let (mut settings, sent_at) = waiting_ack(Some(Duration::from_secs(5)));
+        settings.local = Local::Synced;
+        assert!(settings
+            .check_ack_timeout(sent_at + Duration::from_secs(3_600))
+            .is_ok());
+    }
 }
@@ -1,5 +1,6 @@
 use super::*;
 
+use std::time::Instant;
 use std::usize;
 
 #[derive(Debug)]
@@ -20,6 +21,10 @@ pub(super) struct Counts {
     /// Current number of locally initiated streams
     num_recv_streams: usize,
 
+    /// Number of remote initiated streams opened over the connection's
+    /// lifetime
+    total_recv_streams: usize,
+
     /// Maximum number of pending locally reset streams
     max_local_reset_streams: usize,
 
@@ -31,6 +36,38 @@ pub(super) struct Counts {
 
     /// Current number of "pending accept" streams that were remotely reset
     num_remote_reset_streams: usize,
+
+    /// Max number of streams reset because of errors caused by the peer
+    max_local_error_reset_streams: Option<usize>,
+
+    /// Number of streams reset because of errors caused by the peer over the
+    /// connection's lifetime
+    num_local_error_reset_streams: usize,
+
+    /// Notified of every stream state transition
+    observer: Option<Arc<dyn StateObserver>>,
+
+    /// Queues lifecycle events on each stream
+    events: StreamEvents,
+}
+
+/// The side effects of moving a stream to a new state.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct TransitionOutcome {
+    /// The observed states before and after, if the state changed.
+    pub state_change: Option<(StreamState, StreamState)>,
+
+    /// Connection receive capacity reclaimed from data that can no longer be
+    /// read.
+    pub reclaimed_window: WindowSize,
+
+    /// Lifecycle events caused by the transition, whether or not the stream
+    /// queues them.
+    pub events: Vec<StreamEvent>,
+
+    /// The stream WINDOW_UPDATE increment to send right away, if received
+    /// data made capacity released earlier worth advertising.
+    pub emit_update: Option<WindowSize>,
 }
 
 impl Counts {
@@ -42,10 +79,15 @@ impl Counts {
             num_send_streams: 0,
             max_recv_streams: config.remote_max_initiated.unwrap_or(usize::MAX),
             num_recv_streams: 0,
+            total_recv_streams: 0,
             max_local_reset_streams: config.local_reset_max,
             num_local_reset_streams: 0,
             max_remote_reset_streams: config.remote_reset_max,
             num_remote_reset_streams: 0,
+            max_local_error_reset_streams: config.local_max_error_reset_streams,
+            num_local_error_reset_streams: 0,
+            observer: config.state_observer.clone(),
+            events: StreamEvents::new(config.stream_event_capacity),
         }
     }
 
@@ -82,9 +124,15 @@ impl Counts {
 
         // Increment the number of remote initiated streams
         self.num_recv_streams += 1;
+        self.total_recv_streams += 1;
         stream.is_counted = true;
     }
 
+    /// Returns the number of remote initiated streams opened so far.
+    pub fn total_recv_streams(&self) -> usize {
+        self.total_recv_streams
+    }
+
     /// Returns true if the send stream concurrency can be incremented
     pub fn can_inc_num_send_streams(&self) -> bool {
         self.max_send_streams > self.num_send_streams
@@ -147,6 +195,29 @@ impl Counts {
         self.num_remote_reset_streams -= 1;
     }
 
+    pub(crate) fn max_local_error_reset_streams(&self) -> Option<usize> {
+        self.max_local_error_reset_streams
+    }
+
+    /// Returns true if another stream may be reset because of an error
+    /// caused by the peer.
+    pub(crate) fn can_inc_num_local_error_reset_streams(&self) -> bool {
+        self.max_local_error_reset_streams
+            .map_or(true, |max| max > self.num_local_error_reset_streams)
+    }
+
+    /// Increments the number of streams reset because of errors caused by
+    /// the peer.
+    ///
+    /// # Panics
+    ///
+    /// Panics on failure as this should have been validated before hand.
+    pub(crate) fn inc_num_local_error_reset_streams(&mut self) {
+        assert!(self.can_inc_num_local_error_reset_streams());
+
+        self.num_local_error_reset_streams += 1;
+    }
+
     pub fn apply_remote_settings(&mut self, settings: &frame::Settings) {
         if let Some(val) = settings.max_concurrent_streams() {
             self.max_send_streams = val as usize;
@@ -174,8 +245,53 @@ impl Counts {
         ret
     }
 
+    /// Reports the stream's state to the observer and the stream's event
+    /// queue if it changed since the last report.
+    pub fn observe(&mut self, stream: &mut store::Ptr) -> TransitionOutcome {
+        let mut outcome = TransitionOutcome::default();
+
+        let to = stream.state.observed();
+        if to == stream.observed_state {
+            return outcome;
+        }
+
+        let from = std::mem::replace(&mut stream.observed_state, to);
+        stream.state_durations.transition(to, Instant::now());
+        let cause = stream.state.transition_cause(from);
+
+        tracing::trace!(
+            "observe; stream={:?}; from={:?}; to={:?}; cause={:?}",
+            stream.id,
+            from,
+            to,
+            cause
+        );
+
+        outcome.state_change = Some((from, to));
+
+        if let TransitionCause::Reset(reason) = cause {
+            outcome.events.push(StreamEvent::Reset(reason));
+        }
+
+        if to == StreamState::Closed {
+            outcome.events.push(StreamEvent::Closed);
+        }
+
+        for &event in &outcome.events {
+            self.events.push(stream, event);
+        }
+
+        if let Some(ref observer) = self.observer {
+            observer.on_transition(crate::StreamId::from_internal(stream.id), from, to, cause);
+        }
+
+        outcome
+    }
+
     // TODO: move this to macro?
     pub fn transition_after(&mut self, mut stream: store::Ptr, is_reset_counted: bool) {
+        self.observe(&mut stream);
+
         tracing::trace!(
             "transition_after; stream={:?}; state={:?}; is_closed={:?}; \
              pending_send_empty={:?}; buffered_send_data={}; \
@@ -222,6 +338,11 @@ impl Counts {
         self.max_recv_streams
     }
 
+    /// Returns the number of active streams initiated by the remote peer.
+    pub(crate) fn num_recv_streams(&self) -> usize {
+        self.num_recv_streams
+    }
+
     fn dec_num_streams(&mut self, stream: &mut store::Ptr) {
         assert!(stream.is_counted);
 
@@ -0,0 +1,45 @@
+use super::Stream;
+use crate::observer::StreamEvent;
+
+/// Queues lifecycle events on each stream for the application to await.
+///
+/// A capacity of zero disables the queue entirely. Once a stream's queue is
+/// full, new events are dropped until the application catches up, so an
+/// application that never reads events never holds back the peer.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct StreamEvents {
+    capacity: usize,
+}
+
+impl StreamEvents {
+    pub fn new(capacity: usize) -> StreamEvents {
+        StreamEvents { capacity }
+    }
+
+    /// Returns true if events are queued at all.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Queues `event` on `stream` and wakes the task awaiting it.
+    pub fn push(&self, stream: &mut Stream, event: StreamEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        if stream.events.len() >= self.capacity {
+            tracing::trace!(
+                "dropping stream event; stream={:?}; event={:?}",
+                stream.id,
+                event
+            );
+            return;
+        }
+
+        stream.events.push_back(event);
+
+        if let Some(task) = stream.event_task.take() {
+            task.wake();
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use crate::frame::Reason;
 use crate::proto::{WindowSize, MAX_WINDOW_SIZE};
 
-use std::fmt;
+use std::time::{Duration, Instant};
+use std::{cmp, fmt};
 
 // We don't want to send WINDOW_UPDATE frames for tiny changes, but instead
 // aggregate them when the changes are significant. Many implementations do
@@ -26,6 +27,163 @@ fn sanity_unclaimed_ratio() {
     assert!(UNCLAIMED_DENOMINATOR > 0);
 }
 
+/// Decides when consumed receive capacity is advertised to the peer.
+pub trait WindowUpdatePolicy: fmt::Debug + Send + Sync {
+    /// Returns the WINDOW_UPDATE increment to send, if any.
+    ///
+    /// `consumed` is the number of bytes released since the last update and
+    /// `window` is the window size the peer currently knows about.
+    fn window_update(&self, consumed: WindowSize, window: WindowSize) -> Option<WindowSize>;
+}
+
+/// Advertises capacity once it reaches a fixed ratio of the peer's window.
+///
+/// This is the default policy.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThresholdPolicy;
+
+impl WindowUpdatePolicy for ThresholdPolicy {
+    fn window_update(&self, consumed: WindowSize, window: WindowSize) -> Option<WindowSize> {
+        let threshold = window as i32 / UNCLAIMED_DENOMINATOR * UNCLAIMED_NUMERATOR;
+
+        if (consumed as i32) < threshold {
+            None
+        } else {
+            Some(consumed)
+        }
+    }
+}
+
+/// Advertises any consumed capacity immediately.
+#[cfg(feature = "unstable")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EagerPolicy;
+
+#[cfg(feature = "unstable")]
+impl WindowUpdatePolicy for EagerPolicy {
+    fn window_update(&self, consumed: WindowSize, _window: WindowSize) -> Option<WindowSize> {
+        if consumed > 0 {
+            Some(consumed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Advertises capacity once the peer's window falls below an estimated
+/// bandwidth-delay product, so the sender never stalls waiting on an update.
+#[cfg(feature = "unstable")]
+#[derive(Clone, Copy, Debug)]
+pub struct BdpPolicy {
+    bdp: WindowSize,
+}
+
+#[cfg(feature = "unstable")]
+impl BdpPolicy {
+    pub fn new(bdp: WindowSize) -> BdpPolicy {
+        BdpPolicy { bdp }
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl WindowUpdatePolicy for BdpPolicy {
+    fn window_update(&self, consumed: WindowSize, window: WindowSize) -> Option<WindowSize> {
+        if consumed > 0 && window < self.bdp {
+            Some(consumed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decides when WINDOW_UPDATE frames held back by the policy are sent anyway.
+///
+/// Released capacity below the policy's threshold is batched while other
+/// frames are queued. Once nothing else is queued, the connection is about to
+/// go idle, and holding the capacity back any longer could stall the peer.
+#[derive(Copy, Clone, Debug)]
+pub struct WindowUpdateDebouncer {
+    flush_when_idle: bool,
+}
+
+impl WindowUpdateDebouncer {
+    pub fn new(flush_when_idle: bool) -> WindowUpdateDebouncer {
+        WindowUpdateDebouncer { flush_when_idle }
+    }
+
+    /// Returns true if flushing when idle is enabled at all.
+    pub fn is_flushing_when_idle(&self) -> bool {
+        self.flush_when_idle
+    }
+
+    /// Returns true if all released capacity should be advertised now, given
+    /// whether the queue of other frames to send is empty.
+    pub fn should_flush_updates(&self, queue_empty: bool) -> bool {
+        self.flush_when_idle && queue_empty
+    }
+}
+
+/// Measures the rate at which DATA is received on the connection, and sizes
+/// the connection window so the peer is never held back at that rate.
+///
+/// The rate is sampled once per round trip, starting with the first DATA
+/// received, so that a connection idling before its first request does not
+/// drag the first sample down. The window it asks for is twice
+/// the bandwidth-delay product, leaving the peer room to speed up before the
+/// next sample.
+#[derive(Debug)]
+pub struct RecvRate {
+    /// Round-trip time the connection window has to cover.
+    rtt: Duration,
+
+    /// When the current sample started, once any DATA was received.
+    sample_start: Option<Instant>,
+
+    /// Bytes received since the current sample started.
+    sample_bytes: u64,
+
+    /// Bytes per second received over the last complete sample.
+    rate: u64,
+}
+
+impl RecvRate {
+    pub fn new(rtt: Duration) -> RecvRate {
+        RecvRate {
+            rtt,
+            sample_start: None,
+            sample_bytes: 0,
+            rate: 0,
+        }
+    }
+
+    /// Returns the bytes per second received over the last complete sample.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Records `sz` bytes received at `now`.
+    ///
+    /// Once a round trip has passed since the sample started, the sample is
+    /// completed and the connection window it calls for is returned.
+    pub fn record(&mut self, sz: WindowSize, now: Instant) -> Option<WindowSize> {
+        self.sample_bytes += u64::from(sz);
+
+        let start = *self.sample_start.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed.is_zero() || elapsed < self.rtt {
+            return None;
+        }
+
+        let rate = u128::from(self.sample_bytes) * 1_000_000_000 / elapsed.as_nanos();
+        self.rate = cmp::min(rate, u128::from(u64::MAX)) as u64;
+        self.sample_start = Some(now);
+        self.sample_bytes = 0;
+
+        let bdp = FlowControl::pipe_fill_target(self.rtt, self.rate);
+        Some(cmp::min(bdp.saturating_mul(2), MAX_WINDOW_SIZE))
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct FlowControl {
     /// Window the peer knows about.
@@ -46,6 +204,15 @@ pub struct FlowControl {
     /// This can go negative if a user declares a smaller target window than
     /// the peer knows about.
     available: Window,
+
+    /// The initial window size last applied with `set_initial_window`.
+    initial_window: WindowSize,
+
+    /// Capacity granted by the application on top of the initial window.
+    granted: WindowSize,
+
+    /// When sent data last exhausted the window, unless it has grown since.
+    exhausted_at: Option<tokio::time::Instant>,
 }
 
 impl FlowControl {
@@ -53,14 +220,35 @@ impl FlowControl {
         FlowControl {
             window_size: Window(0),
             available: Window(0),
+            initial_window: 0,
+            granted: 0,
+            exhausted_at: None,
         }
     }
 
+    /// Returns the window that keeps a link with the given round-trip time
+    /// and `bandwidth`, in bytes per second, full.
+    ///
+    /// This is the bandwidth-delay product, rounded down and clamped to the
+    /// max window size.
+    pub fn pipe_fill_target(rtt: Duration, bandwidth: u64) -> WindowSize {
+        let bdp = u128::from(bandwidth).saturating_mul(rtt.as_nanos()) / 1_000_000_000;
+        cmp::min(bdp, u128::from(MAX_WINDOW_SIZE)) as WindowSize
+    }
+
     /// Returns the window size as known by the peer
     pub fn window_size(&self) -> WindowSize {
         self.window_size.as_size()
     }
 
+    /// Returns how many bytes can be sent in a single DATA frame right now.
+    ///
+    /// This is bound by this (stream) window as the peer knows it, the
+    /// connection window, and the maximum frame size.
+    pub fn effective_send_capacity(&self, conn_window: WindowSize, max_frame: u32) -> WindowSize {
+        cmp::min(self.window_size(), cmp::min(conn_window, max_frame))
+    }
+
     /// Returns the window size available to the consumer
     pub fn available(&self) -> Window {
         self.available
@@ -83,30 +271,64 @@ impl FlowControl {
         self.available.increase_by(capacity)
     }
 
+    /// Grows the capacity available to the consumer by up to `capacity`
+    /// beyond the initial window, returning the amount actually granted.
+    ///
+    /// The grant is capped so that the window advertised to the peer never
+    /// exceeds `MAX_WINDOW_SIZE`.
+    #[cfg(feature = "unstable")]
+    pub fn grant_capacity(&mut self, capacity: WindowSize) -> WindowSize {
+        let headroom = cmp::max(MAX_WINDOW_SIZE as i32 - self.available.0, 0) as WindowSize;
+        let granted = cmp::min(capacity, headroom);
+
+        self.available.0 += granted as i32;
+        self.granted += granted;
+
+        granted
+    }
+
     /// If a WINDOW_UPDATE frame should be sent, returns a positive number
     /// representing the increment to be used.
     ///
-    /// If there is no available bytes to be reclaimed, or the number of
-    /// available bytes does not reach the threshold, this returns `None`.
+    /// The bytes consumed since the last update are handed to `policy`, which
+    /// decides whether they are worth advertising yet.
     ///
     /// This represents pending outbound WINDOW_UPDATE frames.
-    pub fn unclaimed_capacity(&self) -> Option<WindowSize> {
-        let available = self.available;
+    pub fn window_update_for(&self, policy: &dyn WindowUpdatePolicy) -> Option<WindowSize> {
+        self.unadvertised_capacity()
+            .and_then(|consumed| policy.window_update(consumed, self.window_size()))
+    }
 
-        if self.window_size >= available {
-            return None;
-        }
+    /// Returns true if the window was exhausted by sent data and has not grown
+    /// for at least `timeout` as of `now`.
+    pub fn is_stalled(&self, now: tokio::time::Instant, timeout: Duration) -> bool {
+        self.stall_deadline(timeout)
+            .map_or(false, |deadline| now >= deadline)
+    }
 
-        let unclaimed = available.0 - self.window_size.0;
-        let threshold = self.window_size.0 / UNCLAIMED_DENOMINATOR * UNCLAIMED_NUMERATOR;
+    /// Returns when the window will have been exhausted for `timeout`, if it
+    /// is exhausted at all.
+    pub fn stall_deadline(&self, timeout: Duration) -> Option<tokio::time::Instant> {
+        self.exhausted_at.map(|exhausted_at| exhausted_at + timeout)
+    }
 
-        if unclaimed < threshold {
-            None
-        } else {
-            Some(unclaimed as WindowSize)
+    /// Records the window as exhausted, if it is and was not already.
+    fn check_exhausted(&mut self) {
+        if self.window_size <= 0 && self.exhausted_at.is_none() {
+            self.exhausted_at = Some(tokio::time::Instant::now());
         }
     }
 
+    /// Returns the capacity released since the last WINDOW_UPDATE, whether or
+    /// not it is worth advertising yet.
+    pub fn unadvertised_capacity(&self) -> Option<WindowSize> {
+        if self.window_size >= self.available {
+            return None;
+        }
+
+        Some((self.available.0 - self.window_size.0) as WindowSize)
+    }
+
     /// Increase the window size.
     ///
     /// This is called after receiving a WINDOW_UPDATE frame
@@ -129,6 +351,11 @@ impl FlowControl {
         );
 
         self.window_size = Window(val);
+
+        if self.window_size > 0 {
+            self.exhausted_at = None;
+        }
+
         Ok(())
     }
 
@@ -145,24 +372,69 @@ impl FlowControl {
         );
         // ~~This should not be able to overflow `window_size` from the bottom.~~ wrong. it can.
         self.window_size.decrease_by(sz)?;
+        self.check_exhausted();
         Ok(())
     }
 
-    /// Decrement the recv-side window size.
+    /// Applies a new initial window size to a recv-side window.
     ///
-    /// This is called after receiving a SETTINGS ACK frame with a lower
-    /// INITIAL_WINDOW_SIZE value.
-    pub fn dec_recv_window(&mut self, sz: WindowSize) -> Result<(), Reason> {
+    /// This is called after receiving a SETTINGS ACK frame with a new
+    /// INITIAL_WINDOW_SIZE value. Both the window and the available capacity
+    /// move by the difference from the previous initial size, so capacity
+    /// already consumed stays consumed. Returns that difference, which is
+    /// negative if the window shrank.
+    pub fn set_initial_window(&mut self, new: WindowSize) -> Result<i32, Reason> {
+        // Both sizes are at most `MAX_WINDOW_SIZE`, so this cannot overflow.
+        let delta = new as i32 - self.initial_window as i32;
+
         tracing::trace!(
-            "dec_recv_window; sz={}; window={}, available={}",
-            sz,
+            "set_initial_window; new={}; old={}; window={}; available={}",
+            new,
+            self.initial_window,
             self.window_size,
             self.available
         );
-        // This should not be able to overflow `window_size` from the bottom.
-        self.window_size.decrease_by(sz)?;
-        self.available.decrease_by(sz)?;
-        Ok(())
+
+        let window_size = self
+            .window_size
+            .0
+            .checked_add(delta)
+            .filter(|&val| val <= MAX_WINDOW_SIZE as i32)
+            .ok_or(Reason::FLOW_CONTROL_ERROR)?;
+        let available = self
+            .available
+            .0
+            .checked_add(delta)
+            .ok_or(Reason::FLOW_CONTROL_ERROR)?;
+
+        self.window_size = Window(window_size);
+        self.available = Window(available);
+        self.initial_window = new;
+
+        Ok(delta)
+    }
+
+    /// Asserts, in debug builds, that the capacity available to the consumer
+    /// and the `consumed` bytes not yet released add up to the initial
+    /// window plus any granted capacity.
+    ///
+    /// This only holds for recv-side windows, which are only adjusted by
+    /// `set_initial_window`, received data, released and granted capacity.
+    pub fn debug_assert_recv_window(&self, consumed: WindowSize) {
+        debug_assert_eq!(
+            self.available.0 as i64 + consumed as i64,
+            self.initial_window as i64 + self.granted as i64,
+            "recv window accounting broken; available={}; consumed={}; initial={}; granted={}",
+            self.available,
+            consumed,
+            self.initial_window,
+            self.granted
+        );
+    }
+
+    #[cfg(test)]
+    fn set_available(&mut self, available: i32) {
+        self.available = Window(available);
     }
 
     /// Decrements the window reflecting data has actually been sent. The caller
@@ -183,6 +455,7 @@ impl FlowControl {
             // Update values
             self.window_size.decrease_by(sz)?;
             self.available.decrease_by(sz)?;
+            self.check_exhausted();
         }
         Ok(())
     }
@@ -267,3 +540,379 @@ impl From<Window> for isize {
         w.0 as isize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Returns a scripted increment each time it is consulted, so that tests
+    /// can drive a window without depending on how updates are computed.
+    ///
+    /// `None` entries hold the update back. Increments are capped by the
+    /// capacity actually released, and an exhausted script holds back every
+    /// further update.
+    #[derive(Debug)]
+    struct ScriptedPolicy {
+        script: Mutex<VecDeque<Option<WindowSize>>>,
+    }
+
+    impl ScriptedPolicy {
+        fn new<I>(script: I) -> ScriptedPolicy
+        where
+            I: IntoIterator<Item = Option<WindowSize>>,
+        {
+            ScriptedPolicy {
+                script: Mutex::new(script.into_iter().collect()),
+            }
+        }
+    }
+
+    impl WindowUpdatePolicy for ScriptedPolicy {
+        fn window_update(&self, consumed: WindowSize, _window: WindowSize) -> Option<WindowSize> {
+            let incr = self.script.lock().unwrap().pop_front().flatten()?;
+            Some(incr.min(consumed))
+        }
+    }
+
+    /// Receives and releases each chunk in turn against a window of 100,
+    /// returning the WINDOW_UPDATE increments the policy emits.
+    fn emitted(policy: &dyn WindowUpdatePolicy) -> Vec<Option<WindowSize>> {
+        let mut flow = FlowControl::new();
+        flow.inc_window(100).unwrap();
+        flow.assign_capacity(100).unwrap();
+
+        [10, 20, 30, 40]
+            .iter()
+            .map(|&sz| {
+                flow.send_data(sz).unwrap();
+                flow.assign_capacity(sz).unwrap();
+
+                let incr = flow.window_update_for(policy);
+                if let Some(incr) = incr {
+                    flow.inc_window(incr).unwrap();
+                }
+                incr
+            })
+            .collect()
+    }
+
+    #[test]
+    fn threshold_policy_waits_for_half_window() {
+        assert_eq!(emitted(&ThresholdPolicy), [None, None, Some(60), Some(40)]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn eager_policy_emits_every_release() {
+        assert_eq!(
+            emitted(&EagerPolicy),
+            [Some(10), Some(20), Some(30), Some(40)]
+        );
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn bdp_policy_emits_below_estimate() {
+        assert_eq!(emitted(&BdpPolicy::new(50)), [None, None, Some(60), None]);
+    }
+
+    #[test]
+    fn pipe_fill_target_is_bandwidth_delay_product() {
+        let target = FlowControl::pipe_fill_target;
+
+        assert_eq!(target(Duration::from_millis(100), 10_000_000), 1_000_000);
+        assert_eq!(target(Duration::from_millis(1), 1_000_000), 1_000);
+        assert_eq!(target(Duration::from_micros(250), 12_500_000), 3_125);
+    }
+
+    #[test]
+    fn pipe_fill_target_rounds_down() {
+        let target = FlowControl::pipe_fill_target;
+
+        assert_eq!(target(Duration::ZERO, 10_000_000), 0);
+        assert_eq!(target(Duration::from_millis(10), 0), 0);
+        assert_eq!(target(Duration::from_micros(1), 999_999), 0);
+        assert_eq!(target(Duration::from_millis(3), 1_000), 3);
+    }
+
+    #[test]
+    fn pipe_fill_target_clamped_to_max_window_size() {
+        let target = FlowControl::pipe_fill_target;
+
+        assert_eq!(
+            target(Duration::from_secs(1), 10_000_000_000),
+            MAX_WINDOW_SIZE
+        );
+        assert_eq!(target(Duration::MAX, u64::MAX), MAX_WINDOW_SIZE);
+    }
+
+    #[test]
+    fn recv_rate_targets_twice_bandwidth_delay_product() {
+        let start = Instant::now();
+        let mut rate = RecvRate::new(Duration::from_millis(100));
+
+        // 1MB over the first 100ms: 10MB/s, which puts 1MB in flight.
+        assert_eq!(rate.record(500_000, start), None);
+        assert_eq!(
+            rate.record(500_000, start + Duration::from_millis(100)),
+            Some(2_000_000)
+        );
+        assert_eq!(rate.rate(), 10_000_000);
+
+        // 250KB over the next 200ms: 1.25MB/s.
+        assert_eq!(
+            rate.record(250_000, start + Duration::from_millis(300)),
+            Some(250_000)
+        );
+        assert_eq!(rate.rate(), 1_250_000);
+    }
+
+    #[test]
+    fn recv_rate_target_clamped_to_max_window_size() {
+        let start = Instant::now();
+        let mut rate = RecvRate::new(Duration::from_secs(1));
+
+        assert_eq!(rate.record(MAX_WINDOW_SIZE, start), None);
+        assert_eq!(
+            rate.record(MAX_WINDOW_SIZE, start + Duration::from_millis(1500)),
+            Some(MAX_WINDOW_SIZE)
+        );
+    }
+
+    #[test]
+    fn scripted_policy_drives_window_through_sequence() {
+        let policy = ScriptedPolicy::new([None, Some(15), None, Some(100)]);
+
+        assert_eq!(emitted(&policy), [None, Some(15), None, Some(85)]);
+        assert!(policy.script.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn scripted_policy_holds_back_once_exhausted() {
+        let policy = ScriptedPolicy::new([Some(10)]);
+
+        assert_eq!(emitted(&policy), [Some(10), None, None, None]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn grant_capacity_is_advertised_in_next_update() {
+        let mut flow = FlowControl::new();
+        flow.set_initial_window(65_535).unwrap();
+
+        assert_eq!(flow.grant_capacity(1_000_000), 1_000_000);
+        assert_eq!(flow.unadvertised_capacity(), Some(1_000_000));
+        assert_eq!(flow.window_update_for(&ThresholdPolicy), Some(1_000_000));
+        flow.debug_assert_recv_window(0);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn grant_capacity_capped_at_max_window_size() {
+        let mut flow = FlowControl::new();
+        flow.set_initial_window(65_535).unwrap();
+
+        assert_eq!(
+            flow.grant_capacity(MAX_WINDOW_SIZE),
+            MAX_WINDOW_SIZE - 65_535
+        );
+        assert_eq!(flow.available(), MAX_WINDOW_SIZE as usize);
+        assert_eq!(flow.grant_capacity(1), 0);
+        flow.debug_assert_recv_window(0);
+    }
+
+    /// Returns a flow with a send window of `window`.
+    fn send_window(window: WindowSize) -> FlowControl {
+        let mut flow = FlowControl::new();
+        flow.inc_window(window).unwrap();
+        flow
+    }
+
+    #[test]
+    fn effective_send_capacity_bound_by_stream_window() {
+        let flow = send_window(100);
+        assert_eq!(flow.effective_send_capacity(1_000, 16_384), 100);
+    }
+
+    #[test]
+    fn effective_send_capacity_bound_by_connection_window() {
+        let flow = send_window(1_000);
+        assert_eq!(flow.effective_send_capacity(100, 16_384), 100);
+    }
+
+    #[test]
+    fn effective_send_capacity_bound_by_max_frame_size() {
+        let flow = send_window(65_535);
+        assert_eq!(flow.effective_send_capacity(65_535, 16_384), 16_384);
+    }
+
+    #[test]
+    fn effective_send_capacity_of_negative_stream_window() {
+        let mut flow = send_window(100);
+        flow.dec_send_window(150).unwrap();
+        assert_eq!(flow.effective_send_capacity(1_000, 16_384), 0);
+    }
+
+    const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Returns a flow whose window of 100 has just been used up by sent data.
+    fn exhausted() -> FlowControl {
+        let mut flow = FlowControl::new();
+        flow.inc_window(100).unwrap();
+        flow.assign_capacity(100).unwrap();
+        flow.send_data(100).unwrap();
+        flow
+    }
+
+    #[test]
+    fn exhausted_window_stalls_after_timeout() {
+        let flow = exhausted();
+        let now = tokio::time::Instant::now();
+
+        assert!(!flow.is_stalled(now, STALL_TIMEOUT));
+        assert!(!flow.is_stalled(now + STALL_TIMEOUT / 2, STALL_TIMEOUT));
+        assert!(flow.is_stalled(now + STALL_TIMEOUT, STALL_TIMEOUT));
+    }
+
+    #[test]
+    fn window_with_room_never_stalls() {
+        let mut flow = FlowControl::new();
+        flow.inc_window(100).unwrap();
+        flow.assign_capacity(100).unwrap();
+        flow.send_data(99).unwrap();
+
+        assert!(!flow.is_stalled(tokio::time::Instant::now() + STALL_TIMEOUT, STALL_TIMEOUT));
+    }
+
+    #[test]
+    fn grow_resets_stall() {
+        let mut flow = exhausted();
+        flow.inc_window(10).unwrap();
+
+        let later = tokio::time::Instant::now() + STALL_TIMEOUT * 2;
+        assert!(!flow.is_stalled(later, STALL_TIMEOUT));
+
+        // Exhausting the window again restarts the clock.
+        flow.assign_capacity(10).unwrap();
+        flow.send_data(10).unwrap();
+        let now = tokio::time::Instant::now();
+        assert!(!flow.is_stalled(now, STALL_TIMEOUT));
+        assert!(flow.is_stalled(now + STALL_TIMEOUT, STALL_TIMEOUT));
+    }
+
+    #[test]
+    fn shrinking_window_counts_as_exhausted() {
+        let mut flow = FlowControl::new();
+        flow.inc_window(100).unwrap();
+        flow.dec_send_window(150).unwrap();
+        let later = tokio::time::Instant::now() + STALL_TIMEOUT;
+        assert!(flow.is_stalled(later, STALL_TIMEOUT));
+
+        flow.inc_window(40).unwrap();
+        assert!(flow.is_stalled(later, STALL_TIMEOUT));
+
+        flow.inc_window(20).unwrap();
+        assert!(!flow.is_stalled(later, STALL_TIMEOUT));
+    }
+
+    /// Returns a recv flow with an initial window of 100, 30 bytes of which
+    /// have been received and not yet released.
+    fn partly_consumed() -> FlowControl {
+        let mut flow = FlowControl::new();
+        flow.set_initial_window(100).unwrap();
+        flow.send_data(30).unwrap();
+        flow
+    }
+
+    #[test]
+    fn increasing_initial_window_keeps_consumed_bytes() {
+        let mut flow = partly_consumed();
+
+        assert_eq!(flow.set_initial_window(150), Ok(50));
+        assert_eq!(flow.window_size(), 120);
+        assert_eq!(flow.available(), Window(120));
+    }
+
+    #[test]
+    fn decreasing_initial_window_keeps_consumed_bytes() {
+        let mut flow = partly_consumed();
+
+        assert_eq!(flow.set_initial_window(20), Ok(-80));
+        assert_eq!(flow.window_size(), 0);
+        assert_eq!(flow.available(), Window(-10));
+
+        // Releasing the consumed bytes brings the window back to the new
+        // initial size.
+        flow.assign_capacity(30).unwrap();
+        assert_eq!(flow.available(), Window(20));
+    }
+
+    #[test]
+    fn initial_window_beyond_max_is_rejected() {
+        let mut flow = partly_consumed();
+        flow.inc_window(MAX_WINDOW_SIZE - 100).unwrap();
+        assert_eq!(
+            flow.set_initial_window(200),
+            Err(Reason::FLOW_CONTROL_ERROR)
+        );
+    }
+
+    #[test]
+    fn recv_window_accounting_holds_through_settings_changes() {
+        let mut flow = partly_consumed();
+        flow.debug_assert_recv_window(30);
+
+        flow.set_initial_window(20).unwrap();
+        flow.debug_assert_recv_window(30);
+
+        flow.assign_capacity(10).unwrap();
+        flow.debug_assert_recv_window(20);
+
+        flow.set_initial_window(200).unwrap();
+        flow.send_data(50).unwrap();
+        flow.debug_assert_recv_window(70);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "recv window accounting broken")]
+    fn recv_window_accounting_catches_lost_capacity() {
+        let mut flow = partly_consumed();
+        flow.set_available(60);
+        flow.debug_assert_recv_window(30);
+    }
+
+    #[test]
+    fn data_exactly_filling_recv_window_then_window_update() {
+        let mut flow = partly_consumed();
+        flow.send_data(70).unwrap();
+        assert_eq!(flow.window_size(), 0);
+        flow.debug_assert_recv_window(100);
+
+        // Releasing the data and advertising it reopens the whole window.
+        flow.assign_capacity(100).unwrap();
+        assert_eq!(flow.unadvertised_capacity(), Some(100));
+        flow.inc_window(100).unwrap();
+        assert_eq!(flow.window_size(), 100);
+        flow.debug_assert_recv_window(0);
+    }
+
+    #[test]
+    fn debouncer_flushes_when_queue_is_idle() {
+        let debouncer = WindowUpdateDebouncer::new(true);
+        assert!(debouncer.should_flush_updates(true));
+    }
+
+    #[test]
+    fn debouncer_holds_batch_while_frames_are_queued() {
+        let debouncer = WindowUpdateDebouncer::new(true);
+        assert!(!debouncer.should_flush_updates(false));
+
+        let disabled = WindowUpdateDebouncer::new(false);
+        assert!(!disabled.should_flush_updates(true));
+        assert!(!disabled.should_flush_updates(false));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::frame::StreamId;
+
+use std::fmt;
+
+/// Picks the identifier of each stream the local endpoint opens.
+///
+/// HTTP/2 requires every new stream to use an identifier greater than all
+/// those the endpoint opened before, and of the endpoint's parity: odd for a
+/// client, even for a server. Within these rules, an allocator may skip
+/// identifiers, for example to leave a range unused.
+///
+/// The first identifier comes from the builder, and the allocator is asked
+/// for each following one.
+pub trait StreamIdAllocator: fmt::Debug + Send + Sync {
+    /// Returns the identifier to use after `prev`, or `None` if there is none
+    /// left.
+    ///
+    /// An identifier that does not follow `prev` with the same parity is
+    /// treated as if none was left.
+    fn next_id(&self, prev: StreamId) -> Option<StreamId>;
+}
+
+/// Uses every identifier in turn, each 2 above the one before.
+///
+/// This is the default allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicIds;
+
+impl StreamIdAllocator for MonotonicIds {
+    fn next_id(&self, prev: StreamId) -> Option<StreamId> {
+        prev.next_id().ok()
+    }
+}
+
+/// Asks `allocator` for the identifier following `prev`, checking that it is
+/// one the endpoint may still open.
+pub(super) fn allocate_after(
+    allocator: &dyn StreamIdAllocator,
+    prev: StreamId,
+) -> Option<StreamId> {
+    let next = allocator.next_id(prev)?;
+
+    if next <= prev || next.is_server_initiated() != prev.is_server_initiated() {
+        tracing::warn!(?prev, ?next, "stream id allocator returned an invalid id");
+        return None;
+    }
+
+    Some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic_ids_step_by_two_until_exhausted() {
+        let mut id = StreamId::from(1);
+        let mut ids = vec![u32::from(id)];
+
+        for _ in 0..2 {
+            id = allocate_after(&MonotonicIds, id).unwrap();
+            ids.push(id.into());
+        }
+        assert_eq!(ids, [1, 3, 5]);
+
+        assert!(allocate_after(&MonotonicIds, StreamId::MAX).is_none());
+        assert_eq!(
+            allocate_after(&MonotonicIds, StreamId::from((u32::MAX >> 1) - 2)),
+            Some(StreamId::MAX)
+        );
+    }
+
+    #[test]
+    fn allocator_ids_must_increase_with_same_parity() {
+        #[derive(Debug)]
+        struct Fixed(u32);
+
+        impl StreamIdAllocator for Fixed {
+            fn next_id(&self, _: StreamId) -> Option<StreamId> {
+                Some(self.0.into())
+            }
+        }
+
+        let prev = StreamId::from(5);
+        assert_eq!(allocate_after(&Fixed(9), prev), Some(9.into()));
+        assert_eq!(allocate_after(&Fixed(5), prev), None);
+        assert_eq!(allocate_after(&Fixed(3), prev), None);
+        assert_eq!(allocate_after(&Fixed(8), prev), None);
+    }
+}
@@ -1,6 +1,8 @@
 mod buffer;
 mod counts;
+mod events;
 mod flow_control;
+mod id_allocator;
 mod prioritize;
 mod recv;
 mod send;
@@ -9,33 +11,40 @@ mod store;
 mod stream;
 #[allow(clippy::module_inception)]
 mod streams;
+mod window_pool;
 
 pub(crate) use self::prioritize::Prioritized;
 pub(crate) use self::recv::Open;
 pub(crate) use self::send::PollReset;
 pub(crate) use self::streams::{DynStreams, OpaqueStreamRef, StreamRef, Streams};
 
+#[cfg(feature = "unstable")]
+pub use self::flow_control::{BdpPolicy, EagerPolicy};
+pub use self::flow_control::{ThresholdPolicy, WindowUpdatePolicy};
+pub use self::id_allocator::{MonotonicIds, StreamIdAllocator};
+
 use self::buffer::Buffer;
 use self::counts::Counts;
-use self::flow_control::FlowControl;
+use self::events::StreamEvents;
+use self::flow_control::{FlowControl, RecvRate, WindowUpdateDebouncer};
 use self::prioritize::Prioritize;
 use self::recv::Recv;
 use self::send::Send;
 use self::state::State;
 use self::store::Store;
 use self::stream::Stream;
+use self::window_pool::WindowPool;
 
 use crate::frame::{StreamId, StreamIdOverflow};
+use crate::observer::{StateDurations, StateObserver, StreamEvent, StreamState, TransitionCause};
 use crate::proto::*;
 
 use bytes::Bytes;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Config {
-    /// Initial window size of locally initiated streams
-    pub local_init_window_sz: WindowSize,
-
     /// Initial maximum number of locally initiated streams.
     /// After receiving a Settings frame from the remote peer,
     /// the connection will overwrite this value with the
@@ -45,9 +54,20 @@ pub struct Config {
     /// Max amount of DATA bytes to buffer per stream.
     pub local_max_buffer_size: usize,
 
+    /// Max amount of unread received DATA bytes to buffer per stream before
+    /// holding back its WINDOW_UPDATE frames.
+    pub local_max_recv_buffer_size: usize,
+
+    /// If WINDOW_UPDATE frames are balanced between the connection and
+    /// stream windows.
+    pub local_balance_window_updates: bool,
+
     /// The stream ID to start the next local stream with
     pub local_next_stream_id: StreamId,
 
+    /// Picks the ID of each locally initiated stream after the first
+    pub local_stream_id_allocator: Arc<dyn StreamIdAllocator>,
+
     /// If the local peer is willing to receive push promises
     pub local_push_enabled: bool,
 
@@ -64,9 +84,41 @@ pub struct Config {
     /// time. Going over this number results in a connection error.
     pub remote_reset_max: usize,
 
+    /// Maximum number of streams reset because of errors caused by the peer
+    /// over the connection's lifetime. Going over this number results in a
+    /// connection error.
+    pub local_max_error_reset_streams: Option<usize>,
+
     /// Initial window size of remote initiated streams
     pub remote_init_window_sz: WindowSize,
 
     /// Maximum number of remote initiated streams
     pub remote_max_initiated: Option<usize>,
+
+    /// Decides when released capacity is advertised with WINDOW_UPDATE
+    pub window_update_policy: Arc<dyn WindowUpdatePolicy>,
+
+    /// If released capacity held back by the policy is advertised once the
+    /// connection has nothing else to send
+    pub local_flush_idle_window_updates: bool,
+
+    /// Round-trip time used to grow the connection window with the rate at
+    /// which DATA is received, if enabled
+    pub local_adaptive_connection_window: Option<Duration>,
+
+    /// If a stream whose receive window is overrun is reset, rather than the
+    /// connection closed
+    pub local_reset_overrun_streams: bool,
+
+    /// Notified of every stream state transition
+    pub state_observer: Option<Arc<dyn StateObserver>>,
+
+    /// Max number of lifecycle events to queue per stream, or 0 to disable
+    pub stream_event_capacity: usize,
+
+    /// How long a send window may stay exhausted before the stream is reset
+    pub local_stalled_stream_timeout: Option<Duration>,
+
+    /// The scheme received requests must carry, if validated
+    pub expected_scheme: Option<http::uri::Scheme>,
 }
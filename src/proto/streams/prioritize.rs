@@ -56,6 +56,9 @@ pub(super) struct Prioritize {
 
     /// The maximum amount of bytes a stream should buffer.
     max_buffer_size: usize,
+
+    /// Splits connection capacity between streams pending capacity.
+    pool: WindowPool,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -101,6 +104,7 @@ impl Prioritize {
             last_opened_id: StreamId::ZERO,
             in_flight_data_frame: InFlightData::Nothing,
             max_buffer_size: config.local_max_buffer_size,
+            pool: WindowPool::new(),
         }
     }
 
@@ -383,45 +387,65 @@ impl Prioritize {
         let _res = self.flow.assign_capacity(inc);
         debug_assert!(_res.is_ok());
 
-        // Assign newly acquired capacity to streams pending capacity.
-        while self.flow.available() > 0 {
-            let stream = match self.pending_capacity.pop(store) {
-                Some(stream) => stream,
-                None => return,
-            };
+        if self.flow.available() == 0 {
+            return;
+        }
+
+        // Assign newly acquired capacity to streams pending capacity, each
+        // receiving a share in proportion to its weight.
+        let mut pending = Vec::new();
 
+        while let Some(stream) = self.pending_capacity.pop(store) {
             // Streams pending capacity may have been reset before capacity
             // became available. In that case, the stream won't want any
             // capacity, and so we shouldn't "transition" on it, but just evict
             // it and continue the loop.
-            if !(stream.state.is_send_streaming() || stream.buffered_send_data > 0) {
+            if !(stream.state.priority_eligible() || stream.buffered_send_data > 0) {
                 continue;
             }
 
-            counts.transition(stream, |_, stream| {
+            self.pool
+                .request(stream.send_weight, Self::additional_capacity(&stream));
+            pending.push(stream.key());
+        }
+
+        let shares = self.pool.distribute(self.flow.available().as_size());
+
+        for (key, share) in pending.into_iter().zip(shares) {
+            counts.transition(store.resolve(key), |_, stream| {
                 // Try to assign capacity to the stream. This will also re-queue the
-                // stream if there isn't enough connection level capacity to fulfill
-                // the capacity request.
-                self.try_assign_capacity(stream);
+                // stream if its share did not fulfill the capacity request.
+                self.try_assign_capacity_up_to(stream, share);
             })
         }
     }
 
+    /// The amount of additional capacity that the stream requests.
+    fn additional_capacity(stream: &Stream) -> WindowSize {
+        cmp::min(
+            stream.requested_send_capacity - stream.send_flow.available().as_size(),
+            // Can't assign more than what is available
+            stream.send_flow.window_size() - stream.send_flow.available().as_size(),
+        )
+    }
+
     /// Request capacity to send data
     fn try_assign_capacity(&mut self, stream: &mut store::Ptr) {
+        let conn_available = self.flow.available().as_size();
+        self.try_assign_capacity_up_to(stream, conn_available);
+    }
+
+    /// Request capacity to send data, assigning at most `limit` bytes of the
+    /// connection's capacity
+    fn try_assign_capacity_up_to(&mut self, stream: &mut store::Ptr, limit: WindowSize) {
         let total_requested = stream.requested_send_capacity;
 
         // Total requested should never go below actual assigned
         // (Note: the window size can go lower than assigned)
         debug_assert!(stream.send_flow.available() <= total_requested as usize);
 
-        // The amount of additional capacity that the stream requests.
         // Don't assign more than the window has available!
-        let additional = cmp::min(
-            total_requested - stream.send_flow.available().as_size(),
-            // Can't assign more than what is available
-            stream.send_flow.window_size() - stream.send_flow.available().as_size(),
-        );
+        let additional = Self::additional_capacity(stream);
         let span = tracing::trace_span!("try_assign_capacity", ?stream.id);
         let _e = span.enter();
         tracing::trace!(
@@ -447,12 +471,11 @@ impl Prioritize {
         );
 
         // The amount of currently available capacity on the connection
-        let conn_available = self.flow.available().as_size();
+        let conn_available = cmp::min(self.flow.available().as_size(), limit);
 
         // First check if capacity is immediately available
         if conn_available > 0 {
             // The amount of capacity to assign to the stream
-            // TODO: Should prioritization factor into this?
             let assign = cmp::min(conn_available, additional);
 
             tracing::trace!(capacity = assign, "assigning");
@@ -504,6 +527,11 @@ impl Prioritize {
         }
     }
 
+    /// Returns true if no stream has frames ready to be sent.
+    pub fn is_queue_empty(&self) -> bool {
+        self.pending_send.is_empty()
+    }
+
     pub fn poll_complete<T, B>(
         &mut self,
         cx: &mut Context,
@@ -738,6 +766,15 @@ impl Prioritize {
                                 "data frame"
                             );
 
+                            // A paused stream keeps its DATA, and everything
+                            // queued behind it, until sending is resumed.
+                            if stream.is_send_paused {
+                                tracing::trace!("stream is paused");
+                                stream.pending_send.push_front(buffer, frame.into());
+
+                                continue;
+                            }
+
                             // Zero length data frames always have capacity to
                             // be sent.
                             if sz > 0 && stream_capacity == 0 {
@@ -772,7 +809,10 @@ impl Prioritize {
                             // Check if the stream level window the peer knows is available. In some
                             // scenarios, maybe the window we know is available but the window which
                             // peer knows is not.
-                            if len > 0 && len > stream.send_flow.window_size() {
+                            let peer_capacity = stream
+                                .send_flow
+                                .effective_send_capacity(self.flow.window_size(), max_len as u32);
+                            if len > 0 && len > peer_capacity {
                                 stream.pending_send.push_front(buffer, frame.into());
                                 continue;
                             }
@@ -1,3 +1,4 @@
+use super::counts::TransitionOutcome;
 use super::*;
 use crate::codec::UserError;
 use crate::frame::{self, PushPromiseHeaderError, Reason, DEFAULT_INITIAL_WINDOW_SIZE};
@@ -5,11 +6,19 @@ use crate::proto::{self, Error};
 
 use http::{HeaderMap, Request, Response};
 
-use std::cmp::Ordering;
+use std::cmp;
+use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
 use std::time::{Duration, Instant};
 
+/// Max number of idle streams whose priority is remembered until they open.
+///
+/// Declaring a priority does not count against the concurrency limit, so the
+/// oldest declarations are forgotten once this many are held.
+pub(super) const MAX_IDLE_PRIORITIES: usize = 32;
+
 #[derive(Debug)]
 pub(super) struct Recv {
     /// Initial window size of remote initiated streams
@@ -39,6 +48,9 @@ pub(super) struct Recv {
     /// Streams that have pending window updates
     pending_window_updates: store::Queue<stream::NextWindowUpdate>,
 
+    /// Streams whose released capacity the policy has not advertised yet
+    held_window_updates: store::Queue<stream::NextHeldWindowUpdate>,
+
     /// New streams to be accepted
     pending_accept: store::Queue<stream::NextAccept>,
 
@@ -59,6 +71,38 @@ pub(super) struct Recv {
 
     /// If extended connect protocol is enabled.
     is_extended_connect_protocol_enabled: bool,
+
+    /// The scheme received requests must carry, if validated.
+    expected_scheme: Option<http::uri::Scheme>,
+
+    /// Decides when released capacity is advertised with WINDOW_UPDATE
+    window_update_policy: Arc<dyn WindowUpdatePolicy>,
+
+    /// Max amount of unread DATA bytes to buffer per stream before its
+    /// WINDOW_UPDATE frames are held back
+    max_buffer_size: usize,
+
+    /// If released capacity is advertised early on whichever of the
+    /// connection or stream window is holding the peer back.
+    is_balancing_windows: bool,
+
+    /// Decides when capacity held back by the policy is advertised anyway
+    debouncer: WindowUpdateDebouncer,
+
+    /// If a stream whose receive window is overrun is reset, rather than the
+    /// whole connection closed.
+    is_resetting_overrun_streams: bool,
+
+    /// Measures the rate at which DATA is received, if the connection window
+    /// grows with it.
+    recv_rate: Option<RecvRate>,
+
+    /// Queues lifecycle events on each stream
+    events: StreamEvents,
+
+    /// Priorities declared for idle streams the peer has yet to open, oldest
+    /// first
+    idle_priorities: VecDeque<(StreamId, frame::StreamDependency)>,
 }
 
 #[derive(Debug)]
@@ -93,11 +137,15 @@ impl Recv {
         flow.assign_capacity(DEFAULT_INITIAL_WINDOW_SIZE).unwrap();
 
         Recv {
-            init_window_sz: config.local_init_window_sz,
+            // The remote assumes the default until it acknowledges our
+            // SETTINGS, at which point streams opened in the meantime are
+            // adjusted to the configured size.
+            init_window_sz: DEFAULT_INITIAL_WINDOW_SIZE,
             flow,
             in_flight_data: 0 as WindowSize,
             next_stream_id: Ok(next_stream_id.into()),
             pending_window_updates: store::Queue::new(),
+            held_window_updates: store::Queue::new(),
             last_processed_id: StreamId::ZERO,
             max_stream_id: StreamId::MAX,
             pending_accept: store::Queue::new(),
@@ -107,6 +155,15 @@ impl Recv {
             refused: None,
             is_push_enabled: config.local_push_enabled,
             is_extended_connect_protocol_enabled: config.extended_connect_protocol_enabled,
+            expected_scheme: config.expected_scheme.clone(),
+            window_update_policy: config.window_update_policy.clone(),
+            max_buffer_size: config.local_max_recv_buffer_size,
+            is_balancing_windows: config.local_balance_window_updates,
+            debouncer: WindowUpdateDebouncer::new(config.local_flush_idle_window_updates),
+            is_resetting_overrun_streams: config.local_reset_overrun_streams,
+            recv_rate: config.local_adaptive_connection_window.map(RecvRate::new),
+            events: StreamEvents::new(config.stream_event_capacity),
+            idle_priorities: VecDeque::new(),
         }
     }
 
@@ -133,6 +190,10 @@ impl Recv {
 
         counts.peer().ensure_can_open(id, mode)?;
 
+        // Stream ids are never reused. Closed streams are no longer
+        // reachable by id, so a HEADERS frame for any id below the next
+        // expected one, including the most recently used, reopens a closed
+        // stream.
         let next_id = self.next_stream_id()?;
         if id < next_id {
             proto_err!(conn: "id ({:?}) < next_id ({:?})", id, next_id);
@@ -158,7 +219,11 @@ impl Recv {
         stream: &mut store::Ptr,
         counts: &mut Counts,
     ) -> Result<(), RecvHeaderBlockError<Option<frame::Headers>>> {
-        tracing::trace!("opening stream; init_window={}", self.init_window_sz);
+        tracing::trace!(
+            "opening stream; init_window={}; stream={:?}",
+            self.init_window_sz,
+            stream.id
+        );
         let is_initial = stream.state.recv_open(&frame)?;
 
         if is_initial {
@@ -218,6 +283,8 @@ impl Recv {
             };
         }
 
+        validate_message(&frame, !frame.is_end_stream(), false, counts.peer())?;
+
         let stream_id = frame.stream_id();
         let (pseudo, fields) = frame.into_parts();
 
@@ -229,12 +296,18 @@ impl Recv {
             return Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR).into());
         }
 
-        if pseudo.status.is_some() && counts.peer().is_server() {
-            proto_err!(stream: "cannot use :status header for requests; stream={:?}", stream.id);
-            return Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR).into());
+        // CONNECT requests carry no :scheme, which is checked when the
+        // request is built.
+        if let (Some(expected), Some(scheme)) = (&self.expected_scheme, &pseudo.scheme) {
+            if counts.peer().is_server() && !scheme.eq_ignore_ascii_case(expected.as_str()) {
+                proto_err!(stream: "scheme {:?} does not match connection ({:?}); stream={:?}", scheme, expected, stream.id);
+                return Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR).into());
+            }
         }
 
         if !pseudo.is_informational() {
+            stream.recv_header_blocks += 1;
+
             let message = counts
                 .peer()
                 .convert_poll_message(pseudo, fields, stream_id)?;
@@ -244,6 +317,7 @@ impl Recv {
                 .pending_recv
                 .push_back(&mut self.buffer, Event::Headers(message));
             stream.notify_recv();
+            self.events.push(stream, StreamEvent::HeadersReceived);
 
             // Only servers can receive a headers frame that initiates the stream.
             // This is verified in `Streams` before calling this function.
@@ -340,6 +414,7 @@ impl Recv {
     ) -> Result<(), Error> {
         // Transition the state
         stream.state.recv_close()?;
+        stream.recv_header_blocks += 1;
 
         if stream.ensure_content_length_zero().is_err() {
             proto_err!(stream: "recv_trailers: content-length is not zero; stream={:?};",  stream.id);
@@ -353,6 +428,7 @@ impl Recv {
             .pending_recv
             .push_back(&mut self.buffer, Event::Trailers(trailers));
         stream.notify_recv();
+        self.events.push(stream, StreamEvent::HeadersReceived);
 
         Ok(())
     }
@@ -373,7 +449,12 @@ impl Recv {
         let _res = self.flow.assign_capacity(capacity);
         debug_assert!(_res.is_ok());
 
-        if self.flow.unclaimed_capacity().is_some() {
+        // When flushing on idle, any released capacity is worth a poll of
+        // the connection, which decides whether it can be sent yet.
+        let is_flush_pending =
+            self.debouncer.is_flushing_when_idle() && self.flow.unadvertised_capacity().is_some();
+
+        if self.connection_window_update().is_some() || is_flush_pending {
             if let Some(task) = task.take() {
                 task.wake();
             }
@@ -387,7 +468,11 @@ impl Recv {
         stream: &mut store::Ptr,
         task: &mut Option<Waker>,
     ) -> Result<(), UserError> {
-        tracing::trace!("release_capacity; size={}", capacity);
+        tracing::trace!(
+            "release_capacity; size={}; stream={:?}",
+            capacity,
+            stream.id
+        );
 
         if capacity > stream.in_flight_recv_data {
             return Err(UserError::ReleaseCapacityTooBig);
@@ -402,17 +487,90 @@ impl Recv {
         // TODO: proper error handling
         let _res = stream.recv_flow.assign_capacity(capacity);
         debug_assert!(_res.is_ok());
+        stream.debug_assert_recv_window();
+
+        self.queue_window_update(stream, task);
 
-        if stream.recv_flow.unclaimed_capacity().is_some() {
+        Ok(())
+    }
+
+    /// Grants the stream receive capacity beyond its initial window,
+    /// returning the amount granted.
+    ///
+    /// Nothing is granted once the stream no longer receives data.
+    #[cfg(feature = "unstable")]
+    pub fn grant_capacity(
+        &mut self,
+        capacity: WindowSize,
+        stream: &mut store::Ptr,
+        task: &mut Option<Waker>,
+    ) -> WindowSize {
+        if !stream.state.is_recv_streaming() {
+            return 0;
+        }
+
+        let granted = stream.recv_flow.grant_capacity(capacity);
+        stream.debug_assert_recv_window();
+
+        tracing::trace!(
+            "grant_capacity; size={}; granted={}; stream={:?}",
+            capacity,
+            granted,
+            stream.id
+        );
+
+        self.queue_window_update(stream, task);
+
+        granted
+    }
+
+    /// Starts deferring stream WINDOW_UPDATE frames while the application
+    /// reads a burst of DATA.
+    #[cfg(feature = "unstable")]
+    pub fn begin_batch(&mut self, stream: &mut store::Ptr) {
+        tracing::trace!("begin_batch; stream={:?}", stream.id);
+        stream.is_recv_batching = true;
+    }
+
+    /// Stops deferring stream WINDOW_UPDATE frames, queueing a single update
+    /// for all capacity released during the batch.
+    #[cfg(feature = "unstable")]
+    pub fn end_batch(&mut self, stream: &mut store::Ptr, task: &mut Option<Waker>) {
+        tracing::trace!("end_batch; stream={:?}", stream.id);
+
+        if !stream.is_recv_batching {
+            return;
+        }
+
+        stream.is_recv_batching = false;
+
+        self.queue_window_update(stream, task);
+    }
+
+    /// Queues a WINDOW_UPDATE frame for `stream` if the policy wants its
+    /// released capacity advertised now and updates are not held back.
+    ///
+    /// Capacity the policy keeps back is remembered instead, so that
+    /// `flush_window_updates` only has to visit the streams that have some.
+    fn queue_window_update(&mut self, stream: &mut store::Ptr, task: &mut Option<Waker>) {
+        if self.is_window_update_held(stream) {
+            // Queued again once the application has read enough of the
+            // buffered data or ends its batch.
+            return;
+        }
+
+        if self.stream_window_update(stream).is_some() {
             // Queue the stream for sending the WINDOW_UPDATE frame.
             self.pending_window_updates.push(stream);
 
             if let Some(task) = task.take() {
                 task.wake();
             }
+        } else if self.debouncer.is_flushing_when_idle()
+            && stream.recv_flow.unadvertised_capacity().is_some()
+        {
+            self.held_window_updates.push(stream);
         }
-
-        Ok(())
     }
 
     /// Release any unclaimed capacity for a closed stream.
@@ -435,6 +593,46 @@ impl Recv {
         self.clear_recv_buffer(stream);
     }
 
+    /// Discard data the user can no longer receive, returning the capacity it
+    /// holds to the connection.
+    ///
+    /// Capacity for data that was already delivered stays in flight until the
+    /// user releases it.
+    pub fn discard_recv_buffer(&mut self, stream: &mut store::Ptr, task: &mut Option<Waker>) {
+        let undelivered = cmp::min(
+            stream.buffered_recv_data as WindowSize,
+            stream.in_flight_recv_data,
+        );
+
+        self.clear_recv_buffer(stream);
+
+        if undelivered > 0 {
+            tracing::trace!(
+                "auto-release discarded stream ({:?}) capacity: {:?}",
+                stream.id,
+                undelivered,
+            );
+
+            self.release_connection_capacity(undelivered, task);
+            stream.in_flight_recv_data -= undelivered;
+
+            // The capacity is only advertised for the stream if it drains.
+            let _res = stream.recv_flow.assign_capacity(undelivered);
+            debug_assert!(_res.is_ok());
+            stream.debug_assert_recv_window();
+        }
+
+        self.release_draining_capacity(stream, task);
+    }
+
+    /// Advertises the released capacity of a draining stream that is no
+    /// longer read, so the peer can keep sending until it ends the stream.
+    pub fn release_draining_capacity(&mut self, stream: &mut store::Ptr, task: &mut Option<Waker>) {
+        if stream.is_draining && !stream.is_recv {
+            self.queue_window_update(stream, task);
+        }
+    }
+
     /// Set the "target" connection window size.
     ///
     /// By default, all new connections start with 64kb of window size. As
@@ -478,7 +676,7 @@ impl Recv {
         // If changing the target capacity means we gained a bunch of capacity,
         // enough that we went over the update threshold, then schedule sending
         // a connection WINDOW_UPDATE.
-        if self.flow.unclaimed_capacity().is_some() {
+        if self.connection_window_update().is_some() {
             if let Some(task) = task.take() {
                 task.wake();
             }
@@ -517,39 +715,23 @@ impl Recv {
             // flow-controlled frames until it receives WINDOW_UPDATE frames that
             // cause the flow-control window to become positive.
 
-            match target.cmp(&old_sz) {
-                Ordering::Less => {
-                    // We must decrease the (local) window on every open stream.
-                    let dec = old_sz - target;
-                    tracing::trace!("decrementing all windows; dec={}", dec);
-
-                    store.try_for_each(|mut stream| {
-                        stream
-                            .recv_flow
-                            .dec_recv_window(dec)
-                            .map_err(proto::Error::library_go_away)?;
-                        Ok::<_, proto::Error>(())
-                    })?;
-                }
-                Ordering::Greater => {
-                    // We must increase the (local) window on every open stream.
-                    let inc = target - old_sz;
-                    tracing::trace!("incrementing all windows; inc={}", inc);
-                    store.try_for_each(|mut stream| {
-                        // XXX: Shouldn't the peer have already noticed our
-                        // overflow and sent us a GOAWAY?
-                        stream
-                            .recv_flow
-                            .inc_window(inc)
-                            .map_err(proto::Error::library_go_away)?;
-                        stream
-                            .recv_flow
-                            .assign_capacity(inc)
-                            .map_err(proto::Error::library_go_away)?;
-                        Ok::<_, proto::Error>(())
-                    })?;
-                }
-                Ordering::Equal => (),
+            if target != old_sz {
+                // We must adjust the (local) window on every open stream.
+                store.try_for_each(|mut stream| {
+                    // XXX: Shouldn't the peer have already noticed our
+                    // overflow and sent us a GOAWAY?
+                    let delta = stream
+                        .recv_flow
+                        .set_initial_window(target)
+                        .map_err(proto::Error::library_go_away)?;
+                    stream.debug_assert_recv_window();
+                    tracing::trace!(
+                        "adjusted stream window; id={:?}; delta={}",
+                        stream.id,
+                        delta
+                    );
+                    Ok::<_, proto::Error>(())
+                })?;
             }
         }
 
@@ -564,17 +746,41 @@ impl Recv {
         stream.pending_recv.is_empty()
     }
 
-    pub fn recv_data(&mut self, frame: frame::Data, stream: &mut store::Ptr) -> Result<(), Error> {
-        let sz = frame.payload().len();
+    /// Receives a DATA frame on `stream`.
+    ///
+    /// The outcome reports whether a WINDOW_UPDATE for the stream is now due,
+    /// in which case it has been queued and should be written promptly.
+    pub fn recv_data(
+        &mut self,
+        frame: frame::Data,
+        stream: &mut store::Ptr,
+    ) -> Result<TransitionOutcome, Error> {
+        // Padding is flow controlled along with the payload.
+        let sz = frame.flow_len();
 
         // This should have been enforced at the codec::FramedRead layer, so
         // this is just a sanity check.
         assert!(sz <= MAX_WINDOW_SIZE as usize);
 
         let sz = sz as WindowSize;
+        let padding = sz - frame.payload().len() as WindowSize;
 
+        // After we send RST_STREAM, the peer may keep sending DATA until it
+        // sees the reset. For the reset duration, such frames still count
+        // against the connection window, but are dropped and their capacity
+        // released so the connection window stays in sync with the peer's.
         let is_ignoring_frame = stream.state.is_local_error();
 
+        if !is_ignoring_frame && stream.state.is_recv_awaiting_final_headers() {
+            // Only informational responses (if anything) have been received,
+            // so DATA makes the message malformed. The frame still counts
+            // against the connection window, and is released once the stream
+            // is reset.
+            proto_err!(stream: "recv_data: DATA before final headers; stream={:?}", stream.id);
+            self.consume_connection_window(sz)?;
+            return Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR));
+        }
+
         if !is_ignoring_frame && !stream.state.is_recv_streaming() {
             // TODO: There are cases where this can be a stream error of
             // STREAM_CLOSED instead...
@@ -586,18 +792,31 @@ impl Recv {
         }
 
         tracing::trace!(
-            "recv_data; size={}; connection={}; stream={}",
+            "recv_data; size={}; connection={}; stream={}; id={:?}",
             sz,
             self.flow.window_size(),
-            stream.recv_flow.window_size()
+            stream.recv_flow.window_size(),
+            stream.id
         );
 
+        if is_ignoring_frame && !stream.is_reset_resent && !self.is_within_reset_grace(stream) {
+            // The peer has had the reset duration to see our RST_STREAM, so
+            // the stream is now treated as closed. The frame still counts
+            // against the connection window, and is released once the
+            // stream error is handled. The reset is only sent again once;
+            // later frames are ignored.
+            proto_err!(stream: "recv_data: DATA after reset grace period; stream={:?}", stream.id);
+            self.consume_connection_window(sz)?;
+            return Err(Error::library_reset(stream.id, Reason::STREAM_CLOSED));
+        }
+
         if is_ignoring_frame {
             tracing::trace!(
                 "recv_data; frame ignored on locally reset {:?} for some time",
                 stream.id,
             );
-            return self.ignore_data(sz);
+            self.ignore_data(sz)?;
+            return Ok(TransitionOutcome::default());
         }
 
         // Ensure that there is enough capacity on the connection before acting
@@ -605,15 +824,7 @@ impl Recv {
         self.consume_connection_window(sz)?;
 
         if stream.recv_flow.window_size() < sz {
-            // http://httpwg.org/specs/rfc7540.html#WINDOW_UPDATE
-            // > A receiver MAY respond with a stream error (Section 5.4.2) or
-            // > connection error (Section 5.4.1) of type FLOW_CONTROL_ERROR if
-            // > it is unable to accept a frame.
-            //
-            // So, for violating the **stream** window, we can send either a
-            // stream or connection error. We've opted to send a stream
-            // error.
-            return Err(Error::library_reset(stream.id, Reason::FLOW_CONTROL_ERROR));
+            return Err(self.stream_window_overrun(stream, sz));
         }
 
         if stream.dec_content_length(frame.payload().len()).is_err() {
@@ -647,8 +858,32 @@ impl Recv {
                 "recv_data; frame ignored on stream release {:?} for some time",
                 stream.id,
             );
+            stream.discarded_recv_data += frame.payload().len() as u64;
             self.release_connection_capacity(sz, &mut None);
-            return Ok(());
+
+            if !stream.is_draining {
+                return Ok(TransitionOutcome::default());
+            }
+
+            // A draining stream waits for the peer to end it, so its window
+            // has to keep moving even though nobody reads the data.
+            stream
+                .recv_flow
+                .send_data(sz)
+                .map_err(proto::Error::library_go_away)?;
+            let _res = stream.recv_flow.assign_capacity(sz);
+            debug_assert!(_res.is_ok());
+            stream.debug_assert_recv_window();
+
+            let emit_update = self.stream_window_update(stream);
+            if emit_update.is_some() {
+                self.pending_window_updates.push(stream);
+            }
+
+            return Ok(TransitionOutcome {
+                emit_update,
+                ..TransitionOutcome::default()
+            });
         }
 
         // Update stream level flow control
@@ -659,14 +894,61 @@ impl Recv {
 
         // Track the data as in-flight
         stream.in_flight_recv_data += sz;
+        stream.debug_assert_recv_window();
+
+        // The user never sees the padding, so release its capacity right away.
+        if padding > 0 {
+            self.release_capacity(padding, stream, &mut None)
+                .expect("padding is in flight");
+        }
 
+        stream.buffered_recv_data += frame.payload().len();
+
+        let is_empty = frame.payload().is_empty();
         let event = Event::Data(frame.into_payload());
 
         // Push the frame onto the recv buffer
         stream.pending_recv.push_back(&mut self.buffer, event);
         stream.notify_recv();
 
-        Ok(())
+        if !is_empty {
+            self.events.push(stream, StreamEvent::DataAvailable);
+        }
+
+        // Receiving data shrinks the window the peer knows about, which can
+        // make capacity released earlier worth advertising right away.
+        let emit_update = self
+            .stream_window_update(stream)
+            .filter(|_| !self.is_window_update_held(stream));
+
+        if emit_update.is_some() {
+            self.pending_window_updates.push(stream);
+        }
+
+        Ok(TransitionOutcome {
+            emit_update,
+            ..TransitionOutcome::default()
+        })
+    }
+
+    /// Returns the error for a DATA frame of `sz` bytes that fit in the
+    /// connection window but overran the receive window of `stream`.
+    fn stream_window_overrun(&self, stream: &Stream, sz: WindowSize) -> Error {
+        // http://httpwg.org/specs/rfc7540.html#WINDOW_UPDATE
+        // > A receiver MAY respond with a stream error (Section 5.4.2) or
+        // > connection error (Section 5.4.1) of type FLOW_CONTROL_ERROR if
+        // > it is unable to accept a frame.
+        //
+        // So, for violating the **stream** window, we can send either a
+        // stream or connection error. Unless configured otherwise, we send
+        // a stream error, so other streams are unaffected.
+        if self.is_resetting_overrun_streams {
+            proto_err!(stream: "recv_data: window exceeded; stream={:?}; size={:?}", stream.id, sz);
+            Error::library_reset(stream.id, Reason::FLOW_CONTROL_ERROR)
+        } else {
+            proto_err!(conn: "recv_data: window exceeded; stream={:?}; size={:?}", stream.id, sz);
+            Error::library_go_away(Reason::FLOW_CONTROL_ERROR)
+        }
     }
 
     pub fn ignore_data(&mut self, sz: WindowSize) -> Result<(), Error> {
@@ -700,9 +982,43 @@ impl Recv {
 
         // Track the data as in-flight
         self.in_flight_data += sz;
+
+        self.record_recv_rate(sz, Instant::now());
         Ok(())
     }
 
+    /// Records `sz` bytes of DATA received at `now`, growing the target
+    /// connection window if the rate they were received at calls for more.
+    ///
+    /// The target is never shrunk here, so a window set by the application
+    /// is kept until the peer sends fast enough to need a larger one.
+    pub(super) fn record_recv_rate(&mut self, sz: WindowSize, now: Instant) {
+        let target = match self
+            .recv_rate
+            .as_mut()
+            .and_then(|rate| rate.record(sz, now))
+        {
+            Some(target) => target,
+            None => return,
+        };
+
+        // As in `set_target_connection_window`, the current target is what
+        // is available plus what streams hold in flight.
+        let current = isize::from(self.flow.available()) + self.in_flight_data as isize;
+
+        if target as isize > current {
+            tracing::trace!(
+                "record_recv_rate; growing connection window; rate={}; target={}; current={}",
+                self.recv_rate.as_ref().map_or(0, RecvRate::rate),
+                target,
+                current,
+            );
+            let _res = self
+                .flow
+                .assign_capacity((target as isize - current) as WindowSize);
+        }
+    }
+
     pub fn recv_push_promise(
         &mut self,
         frame: frame::PushPromise,
@@ -760,6 +1076,7 @@ impl Recv {
             .pending_recv
             .push_back(&mut self.buffer, Event::Headers(Server(req)));
         stream.notify_recv();
+        self.events.push(stream, StreamEvent::HeadersReceived);
         Ok(())
     }
 
@@ -809,8 +1126,20 @@ impl Recv {
             }
         }
 
+        // A reset racing our queued END_STREAM is only ignored for the reset
+        // duration after the stream closed; later it is reported as usual.
+        let closed_for =
+            Instant::now().saturating_duration_since(stream.state_durations.entered_current_at());
+        let is_within_grace = closed_for <= self.reset_duration;
+
         // Notify the stream
-        stream.state.recv_reset(frame, stream.is_pending_send);
+        if let Some(reason) =
+            stream
+                .state
+                .recv_reset(frame, stream.is_pending_send, is_within_grace)
+        {
+            stream.raced_reset = Some(reason);
+        }
 
         stream.notify_send();
         stream.notify_recv();
@@ -840,9 +1169,118 @@ impl Recv {
     }
 
     pub(super) fn clear_recv_buffer(&mut self, stream: &mut Stream) {
-        while stream.pending_recv.pop_front(&mut self.buffer).is_some() {
-            // drop it
+        while let Some(event) = stream.pending_recv.pop_front(&mut self.buffer) {
+            if let Event::Data(payload) = event {
+                stream.discarded_recv_data += payload.len() as u64;
+            }
+        }
+
+        stream.buffered_recv_data = 0;
+    }
+
+    /// Returns the connection level WINDOW_UPDATE increment to send, if any.
+    ///
+    /// When windows are balanced, released capacity is also advertised as
+    /// soon as the connection window drops below the initial stream window,
+    /// so the connection does not hold back streams that still have room.
+    fn connection_window_update(&self) -> Option<WindowSize> {
+        self.flow
+            .window_update_for(&*self.window_update_policy)
+            .or_else(|| {
+                if self.is_balancing_windows && self.flow.window_size() < self.init_window_sz {
+                    self.flow.unadvertised_capacity()
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns the stream level WINDOW_UPDATE increment to send, if any.
+    ///
+    /// There is none once the remote has finished sending, as the stream's
+    /// receive window no longer matters.
+    ///
+    /// When windows are balanced, released capacity is also advertised as
+    /// soon as the stream window drops below half of the connection window,
+    /// so a plentiful connection window is not wasted on a starved stream.
+    fn stream_window_update(&self, stream: &Stream) -> Option<WindowSize> {
+        if !stream.state.is_recv_streaming() {
+            return None;
         }
+
+        stream
+            .recv_flow
+            .window_update_for(&*self.window_update_policy)
+            .or_else(|| {
+                if self.is_balancing_windows
+                    && stream.recv_flow.window_size() < self.flow.window_size() / 2
+                {
+                    stream.recv_flow.unadvertised_capacity()
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns the connection level WINDOW_UPDATE frame to send, if any.
+    ///
+    /// If `flush` is set, all released capacity is advertised, whether or not
+    /// the policy would send it yet.
+    pub(super) fn connection_window_update_frame(
+        &self,
+        flush: bool,
+    ) -> Option<frame::WindowUpdate> {
+        let incr = if flush {
+            self.flow.unadvertised_capacity()
+        } else {
+            self.connection_window_update()
+        };
+
+        incr.map(|incr| frame::WindowUpdate::new(StreamId::zero(), incr))
+    }
+
+    /// Returns the stream level WINDOW_UPDATE frame to send for `stream`, if
+    /// any.
+    ///
+    /// If `flush` is set, all released capacity is advertised, whether or not
+    /// the policy would send it yet.
+    pub(super) fn stream_window_update_frame(
+        &self,
+        stream: &Stream,
+        flush: bool,
+    ) -> Option<frame::WindowUpdate> {
+        let incr = if flush {
+            stream
+                .recv_flow
+                .unadvertised_capacity()
+                .filter(|_| stream.state.is_recv_streaming())
+        } else {
+            self.stream_window_update(stream)
+        };
+
+        incr.map(|incr| frame::WindowUpdate::new(stream.id, incr))
+    }
+
+    /// Returns true if frames received on the locally reset `stream` should
+    /// still be ignored, as the peer may not have seen the reset yet.
+    fn is_within_reset_grace(&self, stream: &Stream) -> bool {
+        stream.closed_at.map_or(true, |closed_at| {
+            Instant::now().saturating_duration_since(closed_at) <= self.reset_duration
+        })
+    }
+
+    /// Returns true if `stream` holds more unread DATA than allowed.
+    ///
+    /// While this is the case, WINDOW_UPDATE frames for the stream are held
+    /// back so that the peer stops sending until the application catches up.
+    fn is_recv_buffer_full(&self, stream: &Stream) -> bool {
+        stream.buffered_recv_data > self.max_buffer_size
+    }
+
+    /// Returns true if stream WINDOW_UPDATE frames must not be sent yet,
+    /// either because the receive buffer is full or a batch is in progress.
+    fn is_window_update_held(&self, stream: &Stream) -> bool {
+        stream.is_recv_batching || self.is_recv_buffer_full(stream)
     }
 
     /// Get the max ID of streams we can receive.
@@ -860,6 +1298,37 @@ impl Recv {
         }
     }
 
+    /// Remembers the priority declared by a PRIORITY frame for a stream that
+    /// is not in the store, so it applies once the peer opens the stream.
+    ///
+    /// Priorities for streams that are already closed, or that only this
+    /// endpoint can open, are ignored.
+    pub fn recv_idle_priority(&mut self, frame: frame::Priority, counts: &Counts) {
+        let id = frame.stream_id();
+
+        if counts.peer().is_local_init(id) || self.may_have_created_stream(id) {
+            tracing::trace!("ignoring PRIORITY for non-idle stream; stream={:?}", id);
+            return;
+        }
+
+        self.idle_priorities.retain(|&(idle_id, _)| idle_id != id);
+
+        if self.idle_priorities.len() == MAX_IDLE_PRIORITIES {
+            self.idle_priorities.pop_front();
+        }
+
+        self.idle_priorities.push_back((id, frame.dependency()));
+    }
+
+    /// Takes the priority declared for `id` while it was idle, if any.
+    pub fn take_idle_priority(&mut self, id: StreamId) -> Option<frame::StreamDependency> {
+        let pos = self
+            .idle_priorities
+            .iter()
+            .position(|&(idle_id, _)| idle_id == id)?;
+        self.idle_priorities.remove(pos).map(|(_, dep)| dep)
+    }
+
     pub fn may_have_created_stream(&self, id: StreamId) -> bool {
         if let Ok(next_id) = self.next_stream_id {
             // Peer::is_local_init should have been called beforehand
@@ -965,6 +1434,15 @@ impl Recv {
                 tracing::trace!("clear_stream_window_update_queue; stream={:?}", stream.id);
             })
         }
+
+        while let Some(stream) = self.held_window_updates.pop(store) {
+            counts.transition(stream, |_, stream| {
+                tracing::trace!(
+                    "clear_stream_window_update_queue; held; stream={:?}",
+                    stream.id
+                );
+            })
+        }
     }
 
     /// Called on EOF
@@ -992,27 +1470,57 @@ impl Recv {
         B: Buf,
     {
         // Send any pending connection level window updates
-        ready!(self.send_connection_window_update(cx, dst))?;
+        ready!(self.send_connection_window_update(cx, dst, false))?;
 
         // Send any pending stream level window updates
-        ready!(self.send_stream_window_updates(cx, store, counts, dst))?;
+        ready!(self.send_stream_window_updates(cx, store, counts, dst, false))?;
 
         Poll::Ready(Ok(()))
     }
 
+    /// Returns true if all released capacity should be advertised now rather
+    /// than batched, given whether any other frames are queued to be sent.
+    pub fn should_flush_updates(&self, queue_empty: bool) -> bool {
+        self.debouncer.should_flush_updates(queue_empty)
+    }
+
+    /// Advertises all released capacity that the policy is still holding
+    /// back, on the connection and on the streams that have some.
+    pub fn flush_window_updates<T, B>(
+        &mut self,
+        cx: &mut Context,
+        store: &mut Store,
+        counts: &mut Counts,
+        dst: &mut Codec<T, Prioritized<B>>,
+    ) -> Poll<io::Result<()>>
+    where
+        T: AsyncWrite + Unpin,
+        B: Buf,
+    {
+        ready!(self.send_connection_window_update(cx, dst, true))?;
+
+        while let Some(mut stream) = self.held_window_updates.pop(store) {
+            self.pending_window_updates.push(&mut stream);
+        }
+
+        self.send_stream_window_updates(cx, store, counts, dst, true)
+    }
+
     /// Send connection level window update
+    ///
+    /// If `flush` is set, all released capacity is advertised, whether or not
+    /// the policy would send it yet.
     fn send_connection_window_update<T, B>(
         &mut self,
         cx: &mut Context,
         dst: &mut Codec<T, Prioritized<B>>,
+        flush: bool,
     ) -> Poll<io::Result<()>>
     where
         T: AsyncWrite + Unpin,
         B: Buf,
     {
-        if let Some(incr) = self.flow.unclaimed_capacity() {
-            let frame = frame::WindowUpdate::new(StreamId::zero(), incr);
-
+        if let Some(frame) = self.connection_window_update_frame(flush) {
             // Ensure the codec has capacity
             ready!(dst.poll_ready(cx))?;
 
@@ -1022,7 +1530,7 @@ impl Recv {
 
             // Update flow control
             self.flow
-                .inc_window(incr)
+                .inc_window(frame.size_increment())
                 .expect("unexpected flow control state");
         }
 
@@ -1030,12 +1538,16 @@ impl Recv {
     }
 
     /// Send stream level window update
-    pub fn send_stream_window_updates<T, B>(
+    ///
+    /// If `flush` is set, all released capacity is advertised, whether or not
+    /// the policy would send it yet.
+    fn send_stream_window_updates<T, B>(
         &mut self,
         cx: &mut Context,
         store: &mut Store,
         counts: &mut Counts,
         dst: &mut Codec<T, Prioritized<B>>,
+        flush: bool,
     ) -> Poll<io::Result<()>>
     where
         T: AsyncWrite + Unpin,
@@ -1065,11 +1577,14 @@ impl Recv {
                     return;
                 }
 
-                // TODO: de-dup
-                if let Some(incr) = stream.recv_flow.unclaimed_capacity() {
-                    // Create the WINDOW_UPDATE frame
-                    let frame = frame::WindowUpdate::new(stream.id, incr);
+                if self.is_window_update_held(stream) {
+                    // The stream is queued again once the application has
+                    // read enough of the buffered data or ends its batch.
+                    tracing::trace!("holding WINDOW_UPDATE; stream={:?}", stream.id);
+                    return;
+                }
 
+                if let Some(frame) = self.stream_window_update_frame(stream, flush) {
                     // Buffer it
                     dst.buffer(frame.into())
                         .expect("invalid WINDOW_UPDATE frame");
@@ -1077,7 +1592,7 @@ impl Recv {
                     // Update flow control
                     stream
                         .recv_flow
-                        .inc_window(incr)
+                        .inc_window(frame.size_increment())
                         .expect("unexpected flow control state");
                 }
             })
@@ -1091,10 +1606,22 @@ impl Recv {
     pub fn poll_data(
         &mut self,
         cx: &Context,
-        stream: &mut Stream,
+        stream: &mut store::Ptr,
+        task: &mut Option<Waker>,
     ) -> Poll<Option<Result<Bytes, proto::Error>>> {
         match stream.pending_recv.pop_front(&mut self.buffer) {
-            Some(Event::Data(payload)) => Poll::Ready(Some(Ok(payload))),
+            Some(Event::Data(payload)) => {
+                let was_full = self.is_recv_buffer_full(stream);
+                stream.buffered_recv_data -= payload.len();
+                stream.delivered_recv_data += payload.len() as u64;
+
+                // Resume any WINDOW_UPDATE held back while the buffer was full.
+                if was_full {
+                    self.queue_window_update(stream, task);
+                }
+
+                Poll::Ready(Some(Ok(payload)))
+            }
             Some(event) => {
                 // Frame is trailer
                 stream.pending_recv.push_front(&mut self.buffer, event);
@@ -1133,6 +1660,22 @@ impl Recv {
         }
     }
 
+    #[cfg(feature = "unstable")]
+    pub fn poll_event(
+        &mut self,
+        cx: &Context,
+        stream: &mut store::Ptr,
+    ) -> Poll<Option<StreamEvent>> {
+        match stream.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if stream.state.is_closed() || !self.events.is_enabled() => Poll::Ready(None),
+            None => {
+                stream.event_task = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
     fn schedule_recv<T>(
         &mut self,
         cx: &Context,
@@ -1149,6 +1692,52 @@ impl Recv {
     }
 }
 
+/// Checks a received header block against the HTTP/2 message rules that do
+/// not depend on the stream's state.
+///
+/// `has_body` is true when the block does not end the stream, and `peer` is
+/// the local peer. Malformed messages are a stream error (section 8.1.1).
+/// The request pseudo-headers themselves are checked when the request is
+/// built, see `server::Peer::convert_poll_message`, and connection-specific
+/// fields are rejected while the header block is decoded.
+pub(super) fn validate_message(
+    headers: &frame::Headers,
+    has_body: bool,
+    is_trailers: bool,
+    peer: peer::Dyn,
+) -> Result<(), Error> {
+    let id = headers.stream_id();
+
+    if is_trailers {
+        // > Pseudo-header fields MUST NOT appear in trailers.
+        if headers.has_pseudo() {
+            proto_err!(stream: "trailers contain pseudo-headers; stream={:?}", id);
+            return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
+        }
+
+        if has_body {
+            proto_err!(stream: "trailers frame was not EOS; stream={:?}", id);
+            return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
+        }
+
+        return Ok(());
+    }
+
+    let status = headers.pseudo().status;
+
+    if status.is_some() && peer.is_server() {
+        proto_err!(stream: "cannot use :status header for requests; stream={:?}", id);
+        return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
+    }
+
+    if status.is_none() && !peer.is_server() {
+        proto_err!(stream: "response is missing :status header; stream={:?}", id);
+        return Err(Error::library_reset(id, Reason::PROTOCOL_ERROR));
+    }
+
+    Ok(())
+}
+
 // ===== impl Open =====
 
 impl Open {
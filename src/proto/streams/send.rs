@@ -1,6 +1,6 @@
 use super::{
-    store, Buffer, Codec, Config, Counts, Frame, Prioritize, Prioritized, Store, Stream, StreamId,
-    StreamIdOverflow, WindowSize,
+    id_allocator, store, Buffer, Codec, Config, Counts, Frame, Prioritize, Prioritized, Store,
+    Stream, StreamId, StreamIdAllocator, StreamIdOverflow, WindowSize,
 };
 use crate::codec::UserError;
 use crate::frame::{self, Reason};
@@ -8,10 +8,15 @@ use crate::proto::{self, Error, Initiator};
 
 use bytes::Buf;
 use tokio::io::AsyncWrite;
+use tokio::time::Sleep;
 
 use std::cmp::Ordering;
+use std::future::Future;
 use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
 /// Manages state transitions related to outbound frames.
 #[derive(Debug)]
@@ -19,6 +24,9 @@ pub(super) struct Send {
     /// Stream identifier to use for next initialized stream.
     next_stream_id: Result<StreamId, StreamIdOverflow>,
 
+    /// Picks the stream identifier following the one just used.
+    id_allocator: Arc<dyn StreamIdAllocator>,
+
     /// Any streams with a higher ID are ignored.
     ///
     /// This starts as MAX, but is lowered when a GOAWAY is received.
@@ -38,6 +46,15 @@ pub(super) struct Send {
 
     /// If extended connect protocol is enabled.
     is_extended_connect_protocol_enabled: bool,
+
+    /// How long a send window may stay exhausted before the stream is reset
+    stalled_timeout: Option<Duration>,
+
+    /// Wakes the connection when the next stream would stall
+    stall_timer: Option<Pin<Box<Sleep>>>,
+
+    /// Distinct authorities of the requests sent, in first-use order
+    authorities: Vec<http::uri::Authority>,
 }
 
 /// A value to detect which public API has called `poll_reset`.
@@ -54,9 +71,13 @@ impl Send {
             init_window_sz: config.remote_init_window_sz,
             max_stream_id: StreamId::MAX,
             next_stream_id: Ok(config.local_next_stream_id),
+            id_allocator: config.local_stream_id_allocator.clone(),
             prioritize: Prioritize::new(config),
             is_push_enabled: true,
             is_extended_connect_protocol_enabled: false,
+            stalled_timeout: config.local_stalled_stream_timeout,
+            stall_timer: None,
+            authorities: Vec::new(),
         }
     }
 
@@ -65,18 +86,42 @@ impl Send {
         self.init_window_sz
     }
 
+    /// Remembers that a request was sent to `authority`.
+    ///
+    /// Only the first `MAX_AUTHORITIES` distinct authorities are kept.
+    pub fn record_authority(&mut self, authority: http::uri::Authority) {
+        if self.authorities.contains(&authority) {
+            return;
+        }
+
+        if self.authorities.len() == proto::MAX_AUTHORITIES {
+            tracing::trace!(%authority, "authority not recorded; limit reached");
+            return;
+        }
+
+        self.authorities.push(authority);
+    }
+
+    pub fn authorities(&self) -> &[http::uri::Authority] {
+        &self.authorities
+    }
+
     pub fn open(&mut self) -> Result<StreamId, UserError> {
         let stream_id = self.ensure_next_stream_id()?;
-        self.next_stream_id = stream_id.next_id();
+        self.next_stream_id = self.allocate_after(stream_id);
         Ok(stream_id)
     }
 
     pub fn reserve_local(&mut self) -> Result<StreamId, UserError> {
         let stream_id = self.ensure_next_stream_id()?;
-        self.next_stream_id = stream_id.next_id();
+        self.next_stream_id = self.allocate_after(stream_id);
         Ok(stream_id)
     }
 
+    fn allocate_after(&self, id: StreamId) -> Result<StreamId, StreamIdOverflow> {
+        id_allocator::allocate_after(&*self.id_allocator, id).ok_or(StreamIdOverflow)
+    }
+
     fn check_headers(fields: &http::HeaderMap) -> Result<(), UserError> {
         // 8.1.2.2. Connection-Specific Header Fields
         if fields.contains_key(http::header::CONNECTION)
@@ -88,8 +133,8 @@ impl Send {
             tracing::debug!("illegal connection-specific headers found");
             return Err(UserError::MalformedHeaders);
         } else if let Some(te) = fields.get(http::header::TE) {
-            if te != "trailers" {
-                tracing::debug!("illegal connection-specific headers found");
+            if !frame::is_te_trailers(te) {
+                tracing::debug!("illegal TE header found; val={:?}", te);
                 return Err(UserError::MalformedHeaders);
             }
         }
@@ -207,6 +252,7 @@ impl Send {
 
         // Transition the state to reset no matter what.
         stream.state.set_reset(stream_id, reason, initiator);
+        stream.closed_at = Some(Instant::now());
 
         // If closed AND the send queue is flushed, then the stream cannot be
         // reset explicitly, either. Implicit resets can still be queued.
@@ -233,6 +279,29 @@ impl Send {
         self.prioritize.reclaim_all_capacity(stream, counts);
     }
 
+    /// Answer a frame the peer sent on a stream we have already reset with
+    /// another RST_STREAM, leaving the stream's state as it is.
+    ///
+    /// This is only done once per stream; further frames are dropped.
+    pub fn resend_reset<B>(
+        &mut self,
+        reason: Reason,
+        buffer: &mut Buffer<Frame<B>>,
+        stream: &mut store::Ptr,
+        task: &mut Option<Waker>,
+    ) {
+        debug_assert!(stream.state.is_reset());
+        debug_assert!(!stream.is_reset_resent);
+
+        stream.is_reset_resent = true;
+
+        let frame = frame::Reset::new(stream.id, reason);
+
+        tracing::trace!("resend_reset -- queueing; frame={:?}", frame);
+        self.prioritize
+            .queue_frame(frame.into(), buffer, stream, task);
+    }
+
     pub fn schedule_implicit_reset(
         &mut self,
         stream: &mut store::Ptr,
@@ -246,6 +315,7 @@ impl Send {
         }
 
         stream.state.set_scheduled_reset(reason);
+        stream.closed_at = Some(Instant::now());
 
         self.prioritize.reclaim_reserved_capacity(stream, counts);
         self.prioritize.schedule_send(stream, task);
@@ -307,6 +377,11 @@ impl Send {
             .poll_complete(cx, buffer, store, counts, dst)
     }
 
+    /// Returns true if no stream has frames ready to be sent.
+    pub fn is_queue_empty(&self) -> bool {
+        self.prioritize.is_queue_empty()
+    }
+
     /// Request capacity to send data
     pub fn reserve_capacity(
         &mut self,
@@ -317,6 +392,28 @@ impl Send {
         self.prioritize.reserve_capacity(capacity, stream, counts)
     }
 
+    /// Hold back the stream's DATA frames until `resume_send` is called.
+    #[cfg(feature = "unstable")]
+    pub fn pause_send(&mut self, stream: &mut store::Ptr) {
+        tracing::trace!(?stream.id, "pause_send");
+        stream.is_send_paused = true;
+    }
+
+    /// Reschedule a paused stream that still has frames to send.
+    #[cfg(feature = "unstable")]
+    pub fn resume_send(&mut self, stream: &mut store::Ptr, task: &mut Option<Waker>) {
+        if !stream.is_send_paused {
+            return;
+        }
+
+        tracing::trace!(?stream.id, "resume_send");
+        stream.is_send_paused = false;
+
+        if !stream.pending_send.is_empty() {
+            self.prioritize.schedule_send(stream, task);
+        }
+    }
+
     pub fn poll_capacity(
         &mut self,
         cx: &Context,
@@ -347,6 +444,12 @@ impl Send {
         stream: &mut Stream,
         mode: PollReset,
     ) -> Poll<Result<Reason, crate::Error>> {
+        // The peer's reason is still reported when its reset was ignored
+        // because it raced our END_STREAM.
+        if let Some(reason) = stream.raced_reset {
+            return Poll::Ready(Ok(reason));
+        }
+
         match stream.state.ensure_reason(mode)? {
             Some(reason) => Poll::Ready(Ok(reason)),
             None => {
@@ -543,6 +646,61 @@ impl Send {
         self.prioritize.clear_pending_open(store, counts);
     }
 
+    /// Returns true if the stream has data waiting on a send window that has
+    /// stayed exhausted for longer than the stall timeout.
+    pub fn is_stalled(&self, stream: &Stream, now: tokio::time::Instant) -> bool {
+        match self.stalled_timeout {
+            Some(timeout) => {
+                stream.buffered_send_data > 0 && stream.send_flow.is_stalled(now, timeout)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns when `stream` will be stalled, if it has data waiting on an
+    /// exhausted send window.
+    pub fn stall_deadline(&self, stream: &Stream) -> Option<tokio::time::Instant> {
+        match self.stalled_timeout {
+            Some(timeout) if stream.buffered_send_data > 0 => {
+                stream.send_flow.stall_deadline(timeout)
+            }
+            _ => None,
+        }
+    }
+
+    /// Arms the stall timer for `deadline`, so the connection is woken once
+    /// the next stream stalls even if the peer sends nothing until then.
+    pub(super) fn poll_stall_timer(
+        &mut self,
+        cx: &mut Context,
+        deadline: Option<tokio::time::Instant>,
+    ) {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.stall_timer = None;
+                return;
+            }
+        };
+
+        let timer = self
+            .stall_timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+
+        if timer.deadline() != deadline {
+            timer.as_mut().reset(deadline);
+        }
+
+        // The stalled streams are reset on the next poll of the connection.
+        if timer.as_mut().poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+    }
+
+    pub(super) fn has_stalled_timeout(&self) -> bool {
+        self.stalled_timeout.is_some()
+    }
+
     pub fn ensure_not_idle(&self, id: StreamId) -> Result<(), Reason> {
         if let Ok(next) = self.next_stream_id {
             if id >= next {
@@ -574,7 +732,7 @@ impl Send {
             // Peer::is_local_init should have been called beforehand
             debug_assert_eq!(id.is_server_initiated(), next_id.is_server_initiated());
             if id >= next_id {
-                self.next_stream_id = id.next_id();
+                self.next_stream_id = self.allocate_after(id);
             }
         }
     }
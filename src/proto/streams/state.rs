@@ -2,6 +2,7 @@ use std::io;
 
 use crate::codec::UserError;
 use crate::frame::{self, Reason, StreamId};
+use crate::observer::{StreamState, TransitionCause};
 use crate::proto::{self, Error, Initiator, PollReset};
 
 use self::Inner::*;
@@ -47,12 +48,12 @@ use self::Peer::*;
 ///        ES: END_STREAM flag
 ///        R:  RST_STREAM frame
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     inner: Inner,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Inner {
     Idle,
     // TODO: these states shouldn't count against concurrency limits:
@@ -64,14 +65,14 @@ enum Inner {
     Closed(Cause),
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 enum Peer {
     #[default]
     AwaitingHeaders,
     Streaming,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Cause {
     EndStream,
     Error(Error),
@@ -87,11 +88,24 @@ enum Cause {
 }
 
 impl State {
+    /// Moves the stream to the given state.
+    ///
+    /// `Idle` is only ever the initial state of a stream; no transition may
+    /// return to it.
+    fn transition_to(&mut self, inner: Inner) {
+        debug_assert!(
+            !matches!(inner, Idle),
+            "illegal transition from {:?} to Idle",
+            self.inner
+        );
+        self.inner = inner;
+    }
+
     /// Opens the send-half of a stream if it is not already open.
     pub fn send_open(&mut self, eos: bool) -> Result<(), UserError> {
         let local = Streaming;
 
-        self.inner = match self.inner {
+        let inner = match self.inner {
             Idle => {
                 if eos {
                     HalfClosedLocal(AwaitingHeaders)
@@ -125,6 +139,8 @@ impl State {
             }
         };
 
+        self.transition_to(inner);
+
         Ok(())
     }
 
@@ -135,7 +151,7 @@ impl State {
         let mut initial = false;
         let eos = frame.is_end_stream();
 
-        self.inner = match self.inner {
+        let inner = match self.inner {
             Idle => {
                 initial = true;
 
@@ -185,6 +201,10 @@ impl State {
             }
             HalfClosedLocal(AwaitingHeaders) => {
                 if eos {
+                    // A bodiless message closes the stream with a single
+                    // frame; no DATA will follow, so nothing is left to
+                    // flow control on the receive side.
+                    tracing::trace!("recv_open: HalfClosedLocal => Closed");
                     Closed(Cause::EndStream)
                 } else if frame.is_informational() {
                     tracing::trace!("skipping 1xx response headers");
@@ -200,6 +220,8 @@ impl State {
             }
         };
 
+        self.transition_to(inner);
+
         Ok(initial)
     }
 
@@ -207,7 +229,7 @@ impl State {
     pub fn reserve_remote(&mut self) -> Result<(), Error> {
         match self.inner {
             Idle => {
-                self.inner = ReservedRemote;
+                self.transition_to(ReservedRemote);
                 Ok(())
             }
             ref state => {
@@ -221,7 +243,7 @@ impl State {
     pub fn reserve_local(&mut self) -> Result<(), UserError> {
         match self.inner {
             Idle => {
-                self.inner = ReservedLocal;
+                self.transition_to(ReservedLocal);
                 Ok(())
             }
             _ => Err(UserError::UnexpectedFrameType),
@@ -234,12 +256,12 @@ impl State {
             Open { local, .. } => {
                 // The remote side will continue to receive data.
                 tracing::trace!("recv_close: Open => HalfClosedRemote({:?})", local);
-                self.inner = HalfClosedRemote(local);
+                self.transition_to(HalfClosedRemote(local));
                 Ok(())
             }
             HalfClosedLocal(..) => {
                 tracing::trace!("recv_close: HalfClosedLocal => Closed");
-                self.inner = Closed(Cause::EndStream);
+                self.transition_to(Closed(Cause::EndStream));
                 Ok(())
             }
             ref state => {
@@ -254,25 +276,40 @@ impl State {
     /// # Arguments
     /// - `frame`: the received RST_STREAM frame.
     /// - `queued`: true if this stream has frames in the pending send queue.
-    pub fn recv_reset(&mut self, frame: frame::Reset, queued: bool) {
+    /// - `is_within_grace`: true if a reset racing our END_STREAM may still
+    ///   be ignored.
+    ///
+    /// Returns the reason of a racing reset that was ignored.
+    pub fn recv_reset(
+        &mut self,
+        frame: frame::Reset,
+        queued: bool,
+        is_within_grace: bool,
+    ) -> Option<Reason> {
         match self.inner {
             // If the stream is already in a `Closed` state, do nothing,
             // provided that there are no frames still in the send queue.
-            Closed(..) if !queued => {}
-            // A notionally `Closed` stream may still have queued frames in
-            // the following cases:
-            //
-            // - if the cause is `Cause::Scheduled(..)` (i.e. we have not
-            //   actually closed the stream yet).
-            // - if the cause is `Cause::EndStream`: we transition to this
-            //   state when an EOS frame is *enqueued* (so that it's invalid
-            //   to enqueue more frames), not when the EOS frame is *sent*;
-            //   therefore, there may still be frames ahead of the EOS frame
-            //   in the send queue.
+            Closed(..) if !queued => None,
+            // Both sides have sent END_STREAM, but ours may still be queued.
+            // The peer could not have known that when it decided to reset
+            // the stream, so the two frames raced (RFC 7540, Section 5.1).
+            // The queue is cleared by the caller, and the stream stays
+            // cleanly closed rather than reporting the reset as an error.
+            // The reason is still handed back so it can be surfaced.
+            Closed(Cause::EndStream) if is_within_grace => {
+                tracing::trace!(
+                    "recv_reset; ignoring reset racing with END_STREAM; frame={:?}",
+                    frame
+                );
+                Some(frame.reason())
+            }
+            // A notionally `Closed` stream may still have queued frames if
+            // the cause is `Cause::Scheduled(..)` (i.e. we have not actually
+            // closed the stream yet).
             //
-            // In either of these cases, we want to overwrite the stream's
-            // previous state with the received RST_STREAM, so that the queue
-            // will be cleared by `Prioritize::pop_frame`.
+            // In this case, we want to overwrite the stream's previous state
+            // with the received RST_STREAM, so that the queue will be cleared
+            // by `Prioritize::pop_frame`.
             ref state => {
                 tracing::trace!(
                     "recv_reset; frame={:?}; state={:?}; queued={:?}",
@@ -280,10 +317,11 @@ impl State {
                     state,
                     queued
                 );
-                self.inner = Closed(Cause::Error(Error::remote_reset(
+                self.transition_to(Closed(Cause::Error(Error::remote_reset(
                     frame.stream_id(),
                     frame.reason(),
-                )));
+                ))));
+                None
             }
         }
     }
@@ -294,7 +332,7 @@ impl State {
             Closed(..) => {}
             _ => {
                 tracing::trace!("handle_error; err={:?}", err);
-                self.inner = Closed(Cause::Error(err.clone()));
+                self.transition_to(Closed(Cause::Error(err.clone())));
             }
         }
     }
@@ -304,13 +342,13 @@ impl State {
             Closed(..) => {}
             ref state => {
                 tracing::trace!("recv_eof; state={:?}", state);
-                self.inner = Closed(Cause::Error(
+                self.transition_to(Closed(Cause::Error(
                     io::Error::new(
                         io::ErrorKind::BrokenPipe,
                         "stream closed because of a broken pipe",
                     )
                     .into(),
-                ));
+                )));
             }
         }
     }
@@ -321,11 +359,11 @@ impl State {
             Open { remote, .. } => {
                 // The remote side will continue to receive data.
                 tracing::trace!("send_close: Open => HalfClosedLocal({:?})", remote);
-                self.inner = HalfClosedLocal(remote);
+                self.transition_to(HalfClosedLocal(remote));
             }
             HalfClosedRemote(..) => {
                 tracing::trace!("send_close: HalfClosedRemote => Closed");
-                self.inner = Closed(Cause::EndStream);
+                self.transition_to(Closed(Cause::EndStream));
             }
             ref state => panic!("send_close: unexpected state {:?}", state),
         }
@@ -333,13 +371,15 @@ impl State {
 
     /// Set the stream state to reset locally.
     pub fn set_reset(&mut self, stream_id: StreamId, reason: Reason, initiator: Initiator) {
-        self.inner = Closed(Cause::Error(Error::Reset(stream_id, reason, initiator)));
+        self.transition_to(Closed(Cause::Error(Error::Reset(
+            stream_id, reason, initiator,
+        ))));
     }
 
     /// Set the stream state to a scheduled reset.
     pub fn set_scheduled_reset(&mut self, reason: Reason) {
         debug_assert!(!self.is_closed());
-        self.inner = Closed(Cause::ScheduledLibraryReset(reason));
+        self.transition_to(Closed(Cause::ScheduledLibraryReset(reason)));
     }
 
     pub fn get_scheduled_reset(&self) -> Option<Reason> {
@@ -387,6 +427,14 @@ impl State {
         )
     }
 
+    /// Returns true if the stream may still produce DATA worth scheduling.
+    ///
+    /// The local side must have sent its headers without ending the stream,
+    /// and no reset may be waiting to go out.
+    pub fn priority_eligible(&self) -> bool {
+        self.is_send_streaming() && !self.is_scheduled_reset()
+    }
+
     /// Returns true when the stream is in a state to receive headers
     pub fn is_recv_headers(&self) -> bool {
         matches!(
@@ -399,6 +447,18 @@ impl State {
         )
     }
 
+    /// Returns true when the stream is open, but the peer has not yet sent
+    /// its final (non-informational) headers
+    pub fn is_recv_awaiting_final_headers(&self) -> bool {
+        matches!(
+            self.inner,
+            Open {
+                remote: AwaitingHeaders,
+                ..
+            } | HalfClosedLocal(AwaitingHeaders)
+        )
+    }
+
     pub fn is_recv_streaming(&self) -> bool {
         matches!(
             self.inner,
@@ -460,6 +520,41 @@ impl State {
             _ => Ok(None),
         }
     }
+
+    /// Returns the state as reported to a `StateObserver`.
+    pub fn observed(&self) -> StreamState {
+        match self.inner {
+            Idle => StreamState::Idle,
+            ReservedLocal => StreamState::ReservedLocal,
+            ReservedRemote => StreamState::ReservedRemote,
+            Open { .. } => StreamState::Open,
+            HalfClosedLocal(..) => StreamState::HalfClosedLocal,
+            HalfClosedRemote(..) => StreamState::HalfClosedRemote,
+            Closed(..) => StreamState::Closed,
+        }
+    }
+
+    /// Returns true if the state is `tag`, whatever the state of each half
+    /// or the cause of closing.
+    #[cfg(test)]
+    pub fn matches_variant(&self, tag: StreamState) -> bool {
+        self.observed() == tag
+    }
+
+    /// Returns why the stream reached its current state from `from`.
+    pub fn transition_cause(&self, from: StreamState) -> TransitionCause {
+        match self.inner {
+            Closed(Cause::Error(Error::Reset(_, reason, _)))
+            | Closed(Cause::ScheduledLibraryReset(reason)) => TransitionCause::Reset(reason),
+            Closed(Cause::Error(Error::GoAway(_, reason, _))) => TransitionCause::GoAway(reason),
+            Closed(Cause::Error(Error::Io(..))) => TransitionCause::Io,
+            Closed(Cause::EndStream) => TransitionCause::EndStream,
+            HalfClosedLocal(..) | HalfClosedRemote(..) if from == StreamState::Open => {
+                TransitionCause::EndStream
+            }
+            _ => TransitionCause::Headers,
+        }
+    }
 }
 
 impl Default for State {
@@ -467,3 +562,283 @@ impl Default for State {
         State { inner: Inner::Idle }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> State {
+        let mut state = State::default();
+        state.send_open(false).unwrap();
+        state
+    }
+
+    fn headers(eos: bool) -> frame::Headers {
+        let mut frame = frame::Headers::new(
+            StreamId::from(1),
+            frame::Pseudo::response(http::StatusCode::OK),
+            http::HeaderMap::new(),
+        );
+        if eos {
+            frame.set_end_stream();
+        }
+        frame
+    }
+
+    /// Every state reachable from `Idle` through a single transition.
+    fn reachable() -> Vec<State> {
+        let mut states = Vec::new();
+
+        for eos in [false, true] {
+            let mut state = State::default();
+            state.send_open(eos).unwrap();
+            states.push(state);
+
+            let mut state = State::default();
+            state.recv_open(&headers(eos)).unwrap();
+            states.push(state);
+        }
+
+        let mut state = State::default();
+        state.reserve_local().unwrap();
+        states.push(state);
+
+        let mut state = State::default();
+        state.reserve_remote().unwrap();
+        states.push(state);
+
+        states
+    }
+
+    #[test]
+    fn no_transition_returns_to_idle() {
+        let reset = frame::Reset::new(StreamId::from(1), Reason::CANCEL);
+        let err = Error::library_go_away(Reason::PROTOCOL_ERROR);
+
+        for state in reachable() {
+            let transitions: Vec<Box<dyn Fn(&mut State)>> = vec![
+                Box::new(|s| drop(s.send_open(false))),
+                Box::new(|s| drop(s.send_open(true))),
+                Box::new(|s| drop(s.recv_open(&headers(false)))),
+                Box::new(|s| drop(s.recv_open(&headers(true)))),
+                Box::new(|s| drop(s.reserve_local())),
+                Box::new(|s| drop(s.reserve_remote())),
+                Box::new(|s| drop(s.recv_close())),
+                Box::new(|s| {
+                    let _ = s.recv_reset(reset, false, true);
+                }),
+                Box::new(|s| s.handle_error(&err)),
+                Box::new(|s| s.recv_eof()),
+                Box::new(|s| s.set_reset(StreamId::from(1), Reason::CANCEL, Initiator::User)),
+            ];
+
+            for transition in transitions {
+                let mut next = state.clone();
+                transition(&mut next);
+                assert!(!next.is_idle(), "{:?} returned to Idle", state);
+            }
+        }
+    }
+
+    #[test]
+    fn equality_compares_halves_and_cause() {
+        let mut remote_opened = State::default();
+        remote_opened.recv_open(&headers(false)).unwrap();
+        assert_ne!(remote_opened, open());
+        assert!(remote_opened.matches_variant(StreamState::Open));
+
+        // Once both halves stream, how the stream opened no longer matters.
+        let mut local_opened = open();
+        local_opened.recv_open(&headers(false)).unwrap();
+        remote_opened.send_open(false).unwrap();
+        assert_eq!(local_opened, remote_opened);
+
+        let mut reset = open();
+        reset.set_reset(StreamId::from(1), Reason::CANCEL, Initiator::User);
+        let mut ended = open();
+        ended.send_close();
+        ended.recv_close().unwrap();
+        assert_ne!(reset, ended);
+        assert!(reset.matches_variant(StreamState::Closed));
+        assert!(ended.matches_variant(StreamState::Closed));
+
+        let mut other_reason = open();
+        other_reason.set_reset(StreamId::from(1), Reason::PROTOCOL_ERROR, Initiator::User);
+        assert_ne!(reset, other_reason);
+        assert_eq!(reset, reset.clone());
+    }
+
+    #[test]
+    fn matches_variant_ignores_halves() {
+        assert!(State::default().matches_variant(StreamState::Idle));
+        assert!(!open().matches_variant(StreamState::Idle));
+
+        let mut half_closed_local = open();
+        half_closed_local.send_close();
+        assert!(half_closed_local.matches_variant(StreamState::HalfClosedLocal));
+
+        for state in reachable() {
+            assert!(state.matches_variant(state.observed()), "{:?}", state);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "to Idle")]
+    fn transition_to_idle_panics() {
+        let mut state = open();
+        state.transition_to(Idle);
+    }
+
+    #[test]
+    fn priority_eligible_only_while_send_streaming() {
+        assert!(open().priority_eligible());
+
+        let mut half_closed_remote = open();
+        half_closed_remote.recv_close().unwrap();
+        assert!(half_closed_remote.priority_eligible());
+
+        let mut remote_opened = State::default();
+        remote_opened.recv_open(&headers(false)).unwrap();
+        assert!(!remote_opened.priority_eligible());
+        remote_opened.send_open(false).unwrap();
+        assert!(remote_opened.priority_eligible());
+
+        for state in reachable() {
+            if !state.is_send_streaming() {
+                assert!(!state.priority_eligible(), "{:?}", state);
+            }
+        }
+    }
+
+    #[test]
+    fn priority_eligible_excludes_closed_and_resetting() {
+        assert!(!State::default().priority_eligible());
+
+        let mut half_closed_local = State::default();
+        half_closed_local.send_open(true).unwrap();
+        assert!(!half_closed_local.priority_eligible());
+
+        let mut ended = open();
+        ended.send_close();
+        assert!(!ended.priority_eligible());
+
+        let mut reset = open();
+        reset.set_reset(StreamId::from(1), Reason::CANCEL, Initiator::User);
+        assert!(!reset.priority_eligible());
+
+        let mut scheduled = open();
+        scheduled.set_scheduled_reset(Reason::CANCEL);
+        assert!(!scheduled.priority_eligible());
+    }
+
+    #[test]
+    fn awaiting_final_headers_until_non_informational() {
+        let informational = frame::Headers::new(
+            StreamId::from(1),
+            frame::Pseudo::response(http::StatusCode::CONTINUE),
+            http::HeaderMap::new(),
+        );
+        assert!(informational.is_informational());
+
+        for eos in [false, true] {
+            let mut state = State::default();
+            state.send_open(eos).unwrap();
+            assert!(state.is_recv_awaiting_final_headers());
+
+            state.recv_open(&informational).unwrap();
+            assert!(state.is_recv_awaiting_final_headers());
+
+            state.recv_open(&headers(false)).unwrap();
+            assert!(!state.is_recv_awaiting_final_headers());
+            assert!(state.is_recv_streaming());
+        }
+
+        assert!(!State::default().is_recv_awaiting_final_headers());
+    }
+
+    #[test]
+    fn empty_eos_data_after_headers_half_closes_remote() {
+        let mut state = State::default();
+        state.recv_open(&headers(false)).unwrap();
+        assert!(state.is_recv_streaming());
+
+        // An empty DATA frame with END_STREAM is all the body there is.
+        state.recv_close().unwrap();
+        assert!(state.is_recv_closed());
+        assert!(!state.is_closed());
+        assert!(!state.ensure_recv_open().unwrap());
+
+        state.send_open(true).unwrap();
+        assert!(state.is_closed());
+    }
+
+    #[test]
+    fn closed_once_both_halves_end() {
+        let mut state = open();
+        assert!(!state.is_closed());
+
+        state.send_close();
+        assert!(state.is_send_closed());
+        assert!(!state.is_closed());
+
+        state.recv_open(&headers(false)).unwrap();
+        assert!(!state.is_closed());
+
+        state.recv_close().unwrap();
+        assert!(state.is_closed());
+    }
+
+    #[test]
+    fn closed_after_recv_close_then_send_close() {
+        let mut state = open();
+        state.recv_open(&headers(true)).unwrap();
+        assert!(state.is_recv_closed());
+        assert!(!state.is_closed());
+
+        state.send_close();
+        assert!(state.is_closed());
+    }
+
+    #[test]
+    fn closed_once_reset() {
+        let mut local = open();
+        local.set_reset(StreamId::from(1), Reason::CANCEL, Initiator::User);
+        assert!(local.is_closed());
+
+        let mut scheduled = open();
+        scheduled.set_scheduled_reset(Reason::CANCEL);
+        assert!(scheduled.is_closed());
+
+        let mut remote = open();
+        remote.recv_reset(
+            frame::Reset::new(StreamId::from(1), Reason::CANCEL),
+            false,
+            true,
+        );
+        assert!(remote.is_closed());
+    }
+
+    #[test]
+    fn closed_once_refused_by_go_away() {
+        let mut state = open();
+        state.handle_error(&Error::remote_go_away(
+            bytes::Bytes::new(),
+            Reason::NO_ERROR,
+        ));
+        assert!(state.is_closed());
+
+        let mut state = open();
+        state.recv_eof();
+        assert!(state.is_closed());
+    }
+
+    #[test]
+    fn not_closed_while_either_half_can_progress() {
+        assert!(!State::default().is_closed());
+
+        for state in reachable() {
+            assert!(!state.is_closed(), "{:?}", state);
+        }
+    }
+}
@@ -214,6 +214,17 @@ impl Store {
     pub fn num_wired_streams(&self) -> usize {
         self.slab.len()
     }
+
+    /// Returns the streams that are able to send DATA.
+    ///
+    /// Streams that are send-open but stalled on a zero window are skipped.
+    #[cfg(feature = "unstable")]
+    pub fn ready_to_send(&self) -> impl Iterator<Item = StreamId> + '_ {
+        self.ids
+            .iter()
+            .filter(move |&(_, index)| self.slab[index.0 as usize].can_send_data())
+            .map(|(id, _)| *id)
+    }
 }
 
 // While running h2 unit/integration tests, enable this debug assertion.
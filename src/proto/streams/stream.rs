@@ -1,5 +1,6 @@
 use super::*;
 
+use std::collections::VecDeque;
 use std::task::{Context, Waker};
 use std::time::Instant;
 use std::usize;
@@ -23,6 +24,12 @@ pub(super) struct Stream {
     /// Current state of the stream
     pub state: State,
 
+    /// State last reported to the connection's `StateObserver`
+    pub observed_state: StreamState,
+
+    /// Time spent in each observed state
+    pub state_durations: StateDurations,
+
     /// Set to `true` when the stream is counted against the connection's max
     /// concurrent streams.
     pub is_counted: bool,
@@ -43,6 +50,9 @@ pub(super) struct Stream {
     /// Amount of send capacity that has been requested, but not yet allocated.
     pub requested_send_capacity: WindowSize,
 
+    /// Weight of the stream's share of the connection's send capacity.
+    pub send_weight: u16,
+
     /// Amount of data buffered at the prioritization layer.
     /// TODO: Technically this could be greater than the window size...
     pub buffered_send_data: usize,
@@ -72,6 +82,16 @@ pub(super) struct Stream {
     /// Set to true when a push is pending for this stream
     pub is_pending_push: bool,
 
+    /// Set to true while the user has paused sending DATA on this stream
+    pub is_send_paused: bool,
+
+    /// Set to true once the user finished sending and asked for the stream
+    /// to close with the peer's END_STREAM instead of being reset.
+    pub is_draining: bool,
+
+    /// Number of DATA bytes written for this stream
+    pub sent_data: u64,
+
     // ===== Fields related to receiving =====
     /// Next node in the accept linked list
     pub next_pending_accept: Option<store::Key>,
@@ -79,6 +99,9 @@ pub(super) struct Stream {
     /// Set to true when the stream is pending accept
     pub is_pending_accept: bool,
 
+    /// The stream's position in the dependency tree, if the peer declared one
+    pub dependency: Option<frame::StreamDependency>,
+
     /// Receive data flow control
     pub recv_flow: FlowControl,
 
@@ -90,15 +113,51 @@ pub(super) struct Stream {
     /// True if the stream is waiting to send a window update
     pub is_pending_window_update: bool,
 
+    /// Next node in the linked list of streams with released capacity that
+    /// the window update policy has not advertised yet.
+    pub next_held_window_update: Option<store::Key>,
+
+    /// True if the stream has released capacity held back by the policy
+    pub is_held_window_update: bool,
+
     /// The time when this stream may have been locally reset.
     pub reset_at: Option<Instant>,
 
+    /// The time when this stream was locally reset, kept after the stream
+    /// leaves the reset expiration queue.
+    pub closed_at: Option<Instant>,
+
+    /// True once DATA received after the reset grace period has been answered
+    /// with another RST_STREAM.
+    pub is_reset_resent: bool,
+
+    /// Number of final header blocks received: the message headers and, once
+    /// the body ended, the trailers. Informational responses are not counted.
+    pub recv_header_blocks: u8,
+
+    /// The reason of a reset that raced our END_STREAM and was ignored.
+    pub raced_reset: Option<frame::Reason>,
+
     /// Next node in list of reset streams that should expire eventually
     pub next_reset_expire: Option<store::Key>,
 
     /// Frames pending for this stream to read
     pub pending_recv: buffer::Deque,
 
+    /// Number of DATA bytes received but not yet read by the application
+    pub buffered_recv_data: usize,
+
+    /// Number of DATA bytes read by the application
+    pub delivered_recv_data: u64,
+
+    /// Number of DATA bytes received but dropped before the application
+    /// read them
+    pub discarded_recv_data: u64,
+
+    /// True while the application is reading a burst of DATA and stream
+    /// WINDOW_UPDATE frames are deferred until the burst ends.
+    pub is_recv_batching: bool,
+
     /// When the RecvStream drop occurs, no data should be received.
     pub is_recv: bool,
 
@@ -108,8 +167,18 @@ pub(super) struct Stream {
     /// The stream's pending push promises
     pub pending_push_promises: store::Queue<NextAccept>,
 
+    /// Lifecycle events not yet seen by the application
+    pub events: VecDeque<StreamEvent>,
+
+    /// Task awaiting the next lifecycle event
+    pub event_task: Option<Waker>,
+
     /// Validate content-length headers
     pub content_length: ContentLength,
+
+    /// Values attached to the stream by the application
+    #[cfg(feature = "unstable")]
+    pub context: http::Extensions,
 }
 
 /// State related to validating a stream's content-length
@@ -132,6 +201,9 @@ pub(super) struct NextSendCapacity;
 #[derive(Debug)]
 pub(super) struct NextWindowUpdate;
 
+#[derive(Debug)]
+pub(super) struct NextHeldWindowUpdate;
+
 #[derive(Debug)]
 pub(super) struct NextOpen;
 
@@ -144,11 +216,8 @@ impl Stream {
         let mut recv_flow = FlowControl::new();
 
         recv_flow
-            .inc_window(init_recv_window)
+            .set_initial_window(init_recv_window)
             .expect("invalid initial receive window");
-        // TODO: proper error handling?
-        let _res = recv_flow.assign_capacity(init_recv_window);
-        debug_assert!(_res.is_ok());
 
         send_flow
             .inc_window(init_send_window)
@@ -157,6 +226,8 @@ impl Stream {
         Stream {
             id,
             state: State::default(),
+            observed_state: StreamState::Idle,
+            state_durations: StateDurations::new(Instant::now()),
             ref_count: 0,
             is_counted: false,
 
@@ -165,6 +236,7 @@ impl Stream {
             is_pending_send: false,
             send_flow,
             requested_send_capacity: 0,
+            send_weight: window_pool::DEFAULT_WEIGHT,
             buffered_send_data: 0,
             send_task: None,
             pending_send: buffer::Deque::new(),
@@ -174,21 +246,39 @@ impl Stream {
             is_pending_open: false,
             next_open: None,
             is_pending_push: false,
+            is_send_paused: false,
+            is_draining: false,
+            sent_data: 0,
 
             // ===== Fields related to receiving =====
             next_pending_accept: None,
             is_pending_accept: false,
+            dependency: None,
             recv_flow,
             in_flight_recv_data: 0,
             next_window_update: None,
             is_pending_window_update: false,
+            next_held_window_update: None,
+            is_held_window_update: false,
             reset_at: None,
+            closed_at: None,
+            is_reset_resent: false,
+            recv_header_blocks: 0,
+            raced_reset: None,
             next_reset_expire: None,
             pending_recv: buffer::Deque::new(),
+            buffered_recv_data: 0,
+            delivered_recv_data: 0,
+            discarded_recv_data: 0,
+            is_recv_batching: false,
             is_recv: true,
             recv_task: None,
             pending_push_promises: store::Queue::new(),
+            events: VecDeque::new(),
+            event_task: None,
             content_length: ContentLength::Omitted,
+            #[cfg(feature = "unstable")]
+            context: http::Extensions::new(),
         }
     }
 
@@ -254,6 +344,7 @@ impl Stream {
             // The stream is not in any queue
             !self.is_pending_send && !self.is_pending_send_capacity &&
             !self.is_pending_accept && !self.is_pending_window_update &&
+            !self.is_held_window_update &&
             !self.is_pending_open && self.reset_at.is_none()
     }
 
@@ -261,9 +352,34 @@ impl Stream {
     /// (indicating no further interest in the stream) and the stream state is
     /// not actually closed.
     ///
-    /// In this case, a reset should be sent.
+    /// In this case, a reset should be sent, unless the stream is draining and
+    /// waits for the peer to end it.
     pub fn is_canceled_interest(&self) -> bool {
-        self.ref_count == 0 && !self.state.is_closed()
+        self.ref_count == 0 && !self.state.is_closed() && !self.is_draining
+    }
+
+    /// Returns true if the stream may currently send DATA: the send half is
+    /// open, sending is not paused and the peer has left some room in the
+    /// stream window.
+    #[cfg(feature = "unstable")]
+    pub fn can_send_data(&self) -> bool {
+        self.state.is_send_streaming() && !self.is_send_paused && self.send_flow.window_size() > 0
+    }
+
+    /// Returns the share of the send window granted by the peer so far that
+    /// was used by DATA written for this stream.
+    ///
+    /// The window granted is the DATA written plus what is left of the send
+    /// window, so it includes the initial window and every increment since.
+    #[cfg(feature = "unstable")]
+    pub fn send_window_utilization(&self) -> f32 {
+        let granted = self.sent_data + u64::from(self.send_flow.window_size());
+
+        if granted == 0 {
+            return 0.0;
+        }
+
+        self.sent_data as f32 / granted as f32
     }
 
     /// Current available stream send capacity
@@ -306,6 +422,7 @@ impl Stream {
         debug_assert!(self.buffered_send_data >= len as usize);
         self.buffered_send_data -= len as usize;
         self.requested_send_capacity -= len;
+        self.sent_data += u64::from(len);
 
         tracing::trace!(
             "  sent stream data; available={}; buffered={}; id={:?}; max_buffer_size={} prev={}",
@@ -370,6 +487,18 @@ impl Stream {
             task.wake();
         }
     }
+
+    /// Asserts, in debug builds, that the receive window accounts for every
+    /// byte received and not yet released.
+    ///
+    /// Once the user stops receiving, buffered data is discarded without
+    /// going through the window, so only receiving streams are checked.
+    pub fn debug_assert_recv_window(&self) {
+        if self.is_recv {
+            self.recv_flow
+                .debug_assert_recv_window(self.in_flight_recv_data);
+        }
+    }
 }
 
 impl store::Next for NextAccept {
@@ -465,6 +594,28 @@ impl store::Next for NextWindowUpdate {
     }
 }
 
+impl store::Next for NextHeldWindowUpdate {
+    fn next(stream: &Stream) -> Option<store::Key> {
+        stream.next_held_window_update
+    }
+
+    fn set_next(stream: &mut Stream, key: Option<store::Key>) {
+        stream.next_held_window_update = key;
+    }
+
+    fn take_next(stream: &mut Stream) -> Option<store::Key> {
+        stream.next_held_window_update.take()
+    }
+
+    fn is_queued(stream: &Stream) -> bool {
+        stream.is_held_window_update
+    }
+
+    fn set_queued(stream: &mut Stream, val: bool) {
+        stream.is_held_window_update = val;
+    }
+}
+
 impl store::Next for NextOpen {
     fn next(stream: &Stream) -> Option<store::Key> {
         stream.next_open
@@ -1,4 +1,5 @@
-use super::recv::RecvHeaderBlockError;
+use super::counts::TransitionOutcome;
+use super::recv::{validate_message, RecvHeaderBlockError};
 use super::store::{self, Entry, Resolve, Store};
 use super::{Buffer, Config, Counts, Prioritized, Recv, Send, Stream, StreamId};
 use crate::codec::{Codec, SendError, UserError};
@@ -13,7 +14,7 @@ use std::task::{Context, Poll, Waker};
 use tokio::io::AsyncWrite;
 
 use std::sync::{Arc, Mutex};
-use std::{fmt, io};
+use std::{cmp, fmt, io};
 
 #[derive(Debug)]
 pub(crate) struct Streams<B, P>
@@ -93,6 +94,10 @@ struct Actions {
 
     /// If the connection errors, a copy is kept for any StreamRefs.
     conn_error: Option<proto::Error>,
+
+    /// The stream reset whose error escalated to a connection error, to be
+    /// sent ahead of the GOAWAY.
+    escalated_reset: Option<frame::Reset>,
 }
 
 /// Contains the buffer of frames to be written to the wire.
@@ -174,6 +179,24 @@ where
             .clear_expired_reset_streams(&mut me.store, &mut me.counts);
     }
 
+    pub fn reset_stalled_streams(&mut self) {
+        let mut me = self.inner.lock().unwrap();
+        me.reset_stalled_streams(&self.send_buffer);
+    }
+
+    /// Arms a timer for the earliest time a stream may stall.
+    pub fn poll_stall_timer(&mut self, cx: &mut Context) {
+        let mut me = self.inner.lock().unwrap();
+        me.poll_stall_timer(cx);
+    }
+
+    /// Resets up to `n` active streams, lowest priority first, returning how
+    /// many were reset.
+    pub fn reset_lowest_priority(&mut self, n: usize, reason: Reason) -> usize {
+        let mut me = self.inner.lock().unwrap();
+        me.reset_lowest_priority(&self.send_buffer, n, reason)
+    }
+
     pub fn poll_complete<T>(
         &mut self,
         cx: &mut Context,
@@ -256,6 +279,12 @@ where
             return Err(UserError::UnexpectedFrameType.into());
         }
 
+        // The `:protocol` pseudo-header may only be sent once the server has
+        // advertised SETTINGS_ENABLE_CONNECT_PROTOCOL (RFC 8441, Section 4).
+        if protocol.is_some() && !me.actions.send.is_extended_connect_protocol_enabled() {
+            return Err(UserError::PeerDisabledExtendedConnect.into());
+        }
+
         let stream_id = me.actions.send.open()?;
 
         let mut stream = Stream::new(
@@ -268,6 +297,8 @@ where
             stream.content_length = ContentLength::Head;
         }
 
+        let authority = request.uri().authority().cloned();
+
         // Convert the message
         let headers =
             client::Peer::convert_send_message(stream_id, request, protocol, end_of_stream)?;
@@ -294,6 +325,12 @@ where
         // closed state.
         debug_assert!(!stream.state.is_closed());
 
+        if let Some(authority) = authority {
+            me.actions.send.record_authority(authority);
+        }
+
+        me.counts.observe(&mut stream);
+
         // TODO: ideally, OpaqueStreamRefs::new would do this, but we're holding
         // the lock, so it can't.
         me.refs += 1;
@@ -316,6 +353,16 @@ where
             .send
             .is_extended_connect_protocol_enabled()
     }
+
+    pub(crate) fn authorities(&self) -> Vec<http::uri::Authority> {
+        self.inner
+            .lock()
+            .unwrap()
+            .actions
+            .send
+            .authorities()
+            .to_vec()
+    }
 }
 
 impl<B> DynStreams<'_, B> {
@@ -361,16 +408,30 @@ impl<B> DynStreams<'_, B> {
         me.recv_push_promise(self.send_buffer, frame)
     }
 
+    pub fn recv_priority(&mut self, frame: frame::Priority) {
+        let mut me = self.inner.lock().unwrap();
+        me.recv_priority(frame)
+    }
+
     pub fn recv_eof(&mut self, clear_pending_accept: bool) -> Result<(), ()> {
         let mut me = self.inner.lock().map_err(|_| ())?;
         me.recv_eof(self.send_buffer, clear_pending_accept)
     }
 
-    pub fn send_reset(&mut self, id: StreamId, reason: Reason) {
+    /// Resets a stream because of an error caused by the peer.
+    ///
+    /// Returns the connection error to raise if there have been too many.
+    pub fn send_reset(&mut self, id: StreamId, reason: Reason) -> Result<(), Error> {
         let mut me = self.inner.lock().unwrap();
         me.send_reset(self.send_buffer, id, reason)
     }
 
+    /// Takes the stream reset that escalated to the current connection error,
+    /// if any.
+    pub fn take_escalated_reset(&mut self) -> Option<frame::Reset> {
+        self.inner.lock().unwrap().actions.escalated_reset.take()
+    }
+
     pub fn send_go_away(&mut self, last_processed_id: StreamId) {
         let mut me = self.inner.lock().unwrap();
         me.actions.recv.go_away(last_processed_id);
@@ -386,6 +447,7 @@ impl Inner {
                 send: Send::new(&config),
                 task: None,
                 conn_error: None,
+                escalated_reset: None,
             },
             store: Store::new(),
             refs: 1,
@@ -437,12 +499,17 @@ impl Inner {
                     .open(id, Open::Headers, &mut self.counts)?
                 {
                     Some(stream_id) => {
-                        let stream = Stream::new(
+                        let mut stream = Stream::new(
                             stream_id,
                             self.actions.send.init_window_sz(),
                             self.actions.recv.init_window_sz(),
                         );
 
+                        // A priority in the HEADERS replaces any declared
+                        // while the stream was idle.
+                        let idle_dep = self.actions.recv.take_idle_priority(stream_id);
+                        stream.dependency = frame.stream_dep().or(idle_dep);
+
                         e.insert(stream)
                     }
                     None => return Ok(()),
@@ -495,15 +562,26 @@ impl Inner {
                     },
                     Err(RecvHeaderBlockError::State(err)) => Err(err),
                 }
-            } else {
-                if !frame.is_end_stream() {
-                    // Receiving trailers that don't set EOS is a "malformed"
-                    // message. Malformed messages are a stream error.
-                    proto_err!(stream: "recv_headers: trailers frame was not EOS; stream={:?}", stream.id);
-                    return Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR));
+            } else if stream.recv_header_blocks >= 2 || !stream.state.is_recv_streaming() {
+                // The message already ended, either with trailers or with a
+                // request that opened the stream with END_STREAM.
+                if stream.state.is_closed() && !stream.state.is_reset() {
+                    // Frames received after both sides ended the stream are a
+                    // connection error, unlike those after a RST_STREAM
+                    // (RFC 9113, Section 5.1).
+                    proto_err!(conn: "recv_headers: HEADERS on closed stream; stream={:?}", stream.id);
+                    Err(Error::library_go_away(Reason::STREAM_CLOSED))
+                } else {
+                    proto_err!(stream: "recv_headers: HEADERS on half-closed (remote) stream; stream={:?}", stream.id);
+                    Err(Error::library_reset(stream.id, Reason::STREAM_CLOSED))
                 }
-
-                actions.recv.recv_trailers(frame, stream)
+            } else if peer.is_server() && frame.pseudo().method.is_some() {
+                // A second request for a stream the peer already opened.
+                proto_err!(stream: "recv_headers: request HEADERS for open stream; stream={:?}", stream.id);
+                Err(Error::library_reset(stream.id, Reason::PROTOCOL_ERROR))
+            } else {
+                validate_message(&frame, !frame.is_end_stream(), true, peer)
+                    .and_then(|()| actions.recv.recv_trailers(frame, stream))
             };
 
             actions.reset_on_recv_stream_err(send_buffer, stream, counts, res)
@@ -535,7 +613,8 @@ impl Inner {
                 if self.actions.may_have_forgotten_stream(peer, id) {
                     tracing::debug!("recv_data for old stream={:?}, sending STREAM_CLOSED", id,);
 
-                    let sz = frame.payload().len();
+                    // Padding is flow controlled along with the payload.
+                    let sz = frame.flow_len();
                     // This should have been enforced at the codec::FramedRead layer, so
                     // this is just a sanity check.
                     assert!(sz <= super::MAX_WINDOW_SIZE as usize);
@@ -555,16 +634,31 @@ impl Inner {
         let send_buffer = &mut *send_buffer;
 
         self.counts.transition(stream, |counts, stream| {
-            let sz = frame.payload().len();
-            let res = actions.recv.recv_data(frame, stream);
+            let sz = frame.flow_len();
+            let res = actions.recv.recv_data(frame, stream).map(|outcome| {
+                if outcome.emit_update.is_some() {
+                    if let Some(task) = actions.task.take() {
+                        task.wake();
+                    }
+                }
+            });
 
             // Any stream error after receiving a DATA frame means
             // we won't give the data to the user, and so they can't
             // release the capacity. We do it automatically.
-            if let Err(Error::Reset(..)) = res {
+            if let Err(Error::Reset(_, reason, _)) = res {
                 actions
                     .recv
                     .release_connection_capacity(sz as WindowSize, &mut None);
+
+                // The stream was reset by us a while ago and the peer is still
+                // sending on it.
+                if stream.state.is_local_error() {
+                    actions
+                        .send
+                        .resend_reset(reason, send_buffer, stream, &mut actions.task);
+                    return Ok(());
+                }
             }
             actions.reset_on_recv_stream_err(send_buffer, stream, counts, res)
         })
@@ -618,6 +712,15 @@ impl Inner {
         })
     }
 
+    fn recv_priority(&mut self, frame: frame::Priority) {
+        let id = frame.stream_id();
+
+        match self.store.find_mut(&id) {
+            Some(mut stream) => stream.dependency = Some(frame.dependency()),
+            None => self.actions.recv.recv_idle_priority(frame, &self.counts),
+        }
+    }
+
     fn recv_window_update<B>(
         &mut self,
         send_buffer: &SendBuffer<B>,
@@ -665,12 +768,15 @@ impl Inner {
 
         let last_processed_id = actions.recv.last_processed_id();
 
+        let mut reclaimed = 0;
         self.store.for_each(|stream| {
             counts.transition(stream, |counts, stream| {
-                actions.recv.handle_error(&err, &mut *stream);
-                actions.send.handle_error(send_buffer, stream, counts);
+                reclaimed += actions
+                    .force_close(send_buffer, stream, counts, Some(&err))
+                    .reclaimed_window;
             })
         });
+        tracing::trace!("handle_error; reclaimed connection capacity={}", reclaimed);
 
         actions.conn_error = Some(err);
 
@@ -693,20 +799,102 @@ impl Inner {
 
         let err = Error::remote_go_away(frame.debug_data().clone(), frame.reason());
 
+        let mut reclaimed = 0;
         self.store.for_each(|stream| {
             if stream.id > last_stream_id {
                 counts.transition(stream, |counts, stream| {
-                    actions.recv.handle_error(&err, &mut *stream);
-                    actions.send.handle_error(send_buffer, stream, counts);
+                    reclaimed += actions
+                        .force_close(send_buffer, stream, counts, Some(&err))
+                        .reclaimed_window;
                 })
             }
         });
+        tracing::trace!("recv_go_away; reclaimed connection capacity={}", reclaimed);
 
         actions.conn_error = Some(err);
 
         Ok(())
     }
 
+    fn reset_stalled_streams<B>(&mut self, send_buffer: &SendBuffer<B>) {
+        if !self.actions.send.has_stalled_timeout() {
+            return;
+        }
+
+        let actions = &mut self.actions;
+        let counts = &mut self.counts;
+        let mut send_buffer = send_buffer.inner.lock().unwrap();
+        let send_buffer = &mut *send_buffer;
+        let now = tokio::time::Instant::now();
+
+        self.store.for_each(|stream| {
+            if actions.send.is_stalled(&stream, now) {
+                tracing::debug!("send window stalled; resetting stream={:?}", stream.id);
+                actions.send_reset(
+                    stream,
+                    Reason::CANCEL,
+                    Initiator::Library,
+                    counts,
+                    send_buffer,
+                );
+            }
+        });
+    }
+
+    fn poll_stall_timer(&mut self, cx: &mut Context) {
+        if !self.actions.send.has_stalled_timeout() {
+            return;
+        }
+
+        let send = &self.actions.send;
+        let mut deadline = None;
+
+        self.store.for_each(|stream| {
+            if let Some(at) = send.stall_deadline(&stream) {
+                deadline = Some(deadline.map_or(at, |earliest| cmp::min(earliest, at)));
+            }
+        });
+
+        self.actions.send.poll_stall_timer(cx, deadline);
+    }
+
+    fn reset_lowest_priority<B>(
+        &mut self,
+        send_buffer: &SendBuffer<B>,
+        n: usize,
+        reason: Reason,
+    ) -> usize {
+        let mut active = Vec::new();
+
+        self.store.for_each(|stream| {
+            if stream.is_counted && !stream.state.is_closed() {
+                active.push((stream.send_weight, stream.id, stream.key()));
+            }
+        });
+
+        // Lowest weight first; among equal weights, the most recently opened
+        // stream has the least work to lose.
+        active.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        active.truncate(n);
+
+        let mut send_buffer = send_buffer.inner.lock().unwrap();
+        let send_buffer = &mut *send_buffer;
+
+        for &(_, id, key) in &active {
+            tracing::debug!("shedding load; resetting stream={:?}", id);
+            let stream = self.store.resolve(key);
+            self.actions.send_reset(
+                stream,
+                reason,
+                Initiator::User,
+                &mut self.counts,
+                send_buffer,
+            );
+        }
+
+        active.len()
+    }
+
     fn recv_push_promise<B>(
         &mut self,
         send_buffer: &SendBuffer<B>,
@@ -831,15 +1019,15 @@ impl Inner {
 
         tracing::trace!("Streams::recv_eof");
 
+        let mut reclaimed = 0;
         self.store.for_each(|stream| {
             counts.transition(stream, |counts, stream| {
-                actions.recv.recv_eof(stream);
-
-                // This handles resetting send state associated with the
-                // stream
-                actions.send.handle_error(send_buffer, stream, counts);
+                reclaimed += actions
+                    .force_close(send_buffer, stream, counts, None)
+                    .reclaimed_window;
             })
         });
+        tracing::trace!("recv_eof; reclaimed connection capacity={}", reclaimed);
 
         actions.clear_queues(clear_pending_accept, &mut self.store, counts);
         Ok(())
@@ -876,13 +1064,34 @@ impl Inner {
             dst
         ))?;
 
+        // The connection is about to go idle, so advertise any capacity that
+        // was batched while other frames were being sent.
+        if self
+            .actions
+            .recv
+            .should_flush_updates(self.actions.send.is_queue_empty())
+        {
+            ready!(self.actions.recv.flush_window_updates(
+                cx,
+                &mut self.store,
+                &mut self.counts,
+                dst
+            ))?;
+            ready!(dst.flush(cx))?;
+        }
+
         // Nothing else to do, track the task
         self.actions.task = Some(cx.waker().clone());
 
         Poll::Ready(Ok(()))
     }
 
-    fn send_reset<B>(&mut self, send_buffer: &SendBuffer<B>, id: StreamId, reason: Reason) {
+    fn send_reset<B>(
+        &mut self,
+        send_buffer: &SendBuffer<B>,
+        id: StreamId,
+        reason: Reason,
+    ) -> Result<(), Error> {
         let key = match self.store.find_entry(id) {
             Entry::Occupied(e) => e.key(),
             Entry::Vacant(e) => {
@@ -917,6 +1126,7 @@ impl Inner {
         let stream = self.store.resolve(key);
         let mut send_buffer = send_buffer.inner.lock().unwrap();
         let send_buffer = &mut *send_buffer;
+        let res = self.actions.count_error_reset(id, reason, &mut self.counts);
         self.actions.send_reset(
             stream,
             reason,
@@ -924,6 +1134,7 @@ impl Inner {
             &mut self.counts,
             send_buffer,
         );
+        res
     }
 }
 
@@ -986,6 +1197,10 @@ where
         self.inner.lock().unwrap().counts.max_recv_streams()
     }
 
+    pub(crate) fn num_recv_streams(&self) -> usize {
+        self.inner.lock().unwrap().counts.num_recv_streams()
+    }
+
     #[cfg(feature = "unstable")]
     pub fn num_active_streams(&self) -> usize {
         let me = self.inner.lock().unwrap();
@@ -997,6 +1212,12 @@ where
         me.counts.has_streams()
     }
 
+    /// Returns the number of remote initiated streams opened so far.
+    pub fn total_recv_streams(&self) -> usize {
+        let me = self.inner.lock().unwrap();
+        me.counts.total_recv_streams()
+    }
+
     pub fn has_streams_or_other_references(&self) -> bool {
         let me = self.inner.lock().unwrap();
         me.counts.has_streams() || me.refs > 1
@@ -1007,6 +1228,12 @@ where
         let me = self.inner.lock().unwrap();
         me.store.num_wired_streams()
     }
+
+    #[cfg(feature = "unstable")]
+    pub fn ready_to_send(&self) -> Vec<StreamId> {
+        let me = self.inner.lock().unwrap();
+        me.store.ready_to_send().collect()
+    }
 }
 
 // no derive because we don't need B and P to be Clone.
@@ -1067,6 +1294,36 @@ impl<B> StreamRef<B> {
         })
     }
 
+    pub fn drain(&mut self) -> Result<(), UserError>
+    where
+        B: Buf + From<Bytes>,
+    {
+        let mut me = self.opaque.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let stream = me.store.resolve(self.opaque.key);
+        let actions = &mut me.actions;
+        let mut send_buffer = self.send_buffer.inner.lock().unwrap();
+        let send_buffer = &mut *send_buffer;
+
+        me.counts.transition(stream, |counts, stream| {
+            if stream.state.is_send_streaming() {
+                let mut frame = frame::Data::new(stream.id, Bytes::new().into());
+                frame.set_end_stream(true);
+
+                actions
+                    .send
+                    .send_data(frame, send_buffer, stream, counts, &mut actions.task)?;
+            }
+
+            stream.is_draining = true;
+            actions
+                .recv
+                .release_draining_capacity(stream, &mut actions.task);
+            Ok(())
+        })
+    }
+
     pub fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), UserError> {
         let mut me = self.opaque.inner.lock().unwrap();
         let me = &mut *me;
@@ -1129,6 +1386,10 @@ impl<B> StreamRef<B> {
     ) -> Result<StreamRef<B>, UserError> {
         // Clear before taking lock, incase extensions contain a StreamRef.
         request.extensions_mut().clear();
+
+        // Reject the request before a promised stream ID is used up for it.
+        crate::server::Peer::validate_push_message(&request)?;
+
         let mut me = self.opaque.inner.lock().unwrap();
         let me = &mut *me;
 
@@ -1155,7 +1416,7 @@ impl<B> StreamRef<B> {
         let pushed = {
             let mut stream = me.store.resolve(self.opaque.key);
 
-            let frame = crate::server::Peer::convert_push_message(stream.id, promised_id, request)?;
+            let frame = crate::server::Peer::convert_push_message(stream.id, promised_id, request);
 
             actions
                 .send
@@ -1169,9 +1430,11 @@ impl<B> StreamRef<B> {
             return Err(err);
         }
 
+        let mut child_stream = me.store.resolve(child_key);
+        me.counts.observe(&mut child_stream);
+
         me.refs += 1;
-        let opaque =
-            OpaqueStreamRef::new(self.opaque.inner.clone(), &mut me.store.resolve(child_key));
+        let opaque = OpaqueStreamRef::new(self.opaque.inner.clone(), &mut child_stream);
 
         Ok(StreamRef {
             opaque,
@@ -1212,6 +1475,42 @@ impl<B> StreamRef<B> {
             .reserve_capacity(capacity, &mut stream, &mut me.counts)
     }
 
+    /// Sets the weight of the stream's share of the connection's send
+    /// capacity.
+    #[cfg(feature = "unstable")]
+    pub fn set_send_weight(&mut self, weight: u16) {
+        let mut me = self.opaque.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.opaque.key);
+
+        stream.send_weight = weight.clamp(1, super::window_pool::MAX_WEIGHT);
+    }
+
+    /// Holds back the stream's DATA until `resume_send` is called.
+    #[cfg(feature = "unstable")]
+    pub fn pause_send(&mut self) {
+        let mut me = self.opaque.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.opaque.key);
+
+        me.actions.send.pause_send(&mut stream)
+    }
+
+    /// Lets the stream's buffered DATA be sent again.
+    #[cfg(feature = "unstable")]
+    pub fn resume_send(&mut self) {
+        let mut me = self.opaque.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.opaque.key);
+
+        me.actions
+            .send
+            .resume_send(&mut stream, &mut me.actions.task)
+    }
+
     /// Returns the stream's current send capacity.
     pub fn capacity(&self) -> WindowSize {
         let mut me = self.opaque.inner.lock().unwrap();
@@ -1256,6 +1555,38 @@ impl<B> StreamRef<B> {
     pub fn stream_id(&self) -> StreamId {
         self.opaque.stream_id()
     }
+
+    #[cfg(feature = "unstable")]
+    pub fn state_durations(&self) -> crate::observer::StateDurations {
+        self.opaque.state_durations()
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn summary_metrics(&self) -> crate::StreamMetrics {
+        self.opaque.summary_metrics()
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn window_utilization(&self) -> f32 {
+        let me = self.opaque.inner.lock().unwrap();
+        me.store[self.opaque.key].send_window_utilization()
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn insert_context<T>(&mut self, val: T) -> Option<T>
+    where
+        T: Clone + std::marker::Send + Sync + 'static,
+    {
+        self.opaque.insert_context(val)
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn context<T>(&self) -> Option<T>
+    where
+        T: Clone + std::marker::Send + Sync + 'static,
+    {
+        self.opaque.context()
+    }
 }
 
 impl<B> Clone for StreamRef<B> {
@@ -1321,7 +1652,19 @@ impl OpaqueStreamRef {
 
         let mut stream = me.store.resolve(self.key);
 
-        me.actions.recv.poll_data(cx, &mut stream)
+        me.actions
+            .recv
+            .poll_data(cx, &mut stream, &mut me.actions.task)
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn poll_event(&mut self, cx: &Context) -> Poll<Option<crate::StreamEvent>> {
+        let mut me = self.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.key);
+
+        me.actions.recv.poll_event(cx, &mut stream)
     }
 
     pub fn poll_trailers(&mut self, cx: &Context) -> Poll<Option<Result<HeaderMap, proto::Error>>> {
@@ -1349,6 +1692,62 @@ impl OpaqueStreamRef {
         stream.in_flight_recv_data
     }
 
+    #[cfg(feature = "unstable")]
+    pub(crate) fn delivered_recv_data(&self) -> u64 {
+        let me = self.inner.lock().unwrap();
+        me.store[self.key].delivered_recv_data
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(crate) fn discarded_recv_data(&self) -> u64 {
+        let me = self.inner.lock().unwrap();
+        me.store[self.key].discarded_recv_data
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(crate) fn state_durations(&self) -> crate::observer::StateDurations {
+        let me = self.inner.lock().unwrap();
+        me.store[self.key]
+            .state_durations
+            .snapshot(std::time::Instant::now())
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(crate) fn summary_metrics(&self) -> crate::StreamMetrics {
+        let me = self.inner.lock().unwrap();
+        let stream = &me.store[self.key];
+        let durations = stream.state_durations.snapshot(std::time::Instant::now());
+
+        crate::StreamMetrics {
+            state: durations.current(),
+            send_window: stream.send_flow.window_size(),
+            recv_window: stream.recv_flow.window_size(),
+            bytes_sent: stream.sent_data,
+            bytes_received: stream.delivered_recv_data
+                + stream.discarded_recv_data
+                + stream.buffered_recv_data as u64,
+            time_in_state: durations.get(durations.current()),
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(crate) fn insert_context<T>(&mut self, val: T) -> Option<T>
+    where
+        T: Clone + std::marker::Send + Sync + 'static,
+    {
+        let mut me = self.inner.lock().unwrap();
+        me.store[self.key].context.insert(val)
+    }
+
+    #[cfg(feature = "unstable")]
+    pub(crate) fn context<T>(&self) -> Option<T>
+    where
+        T: Clone + std::marker::Send + Sync + 'static,
+    {
+        let me = self.inner.lock().unwrap();
+        me.store[self.key].context.get::<T>().cloned()
+    }
+
     /// Releases recv capacity back to the peer. This may result in sending
     /// WINDOW_UPDATE frames on both the stream and connection.
     pub fn release_capacity(&mut self, capacity: WindowSize) -> Result<(), UserError> {
@@ -1362,14 +1761,52 @@ impl OpaqueStreamRef {
             .release_capacity(capacity, &mut stream, &mut me.actions.task)
     }
 
-    /// Clear the receive queue and set the status to no longer receive data frames.
+    #[cfg(feature = "unstable")]
+    pub fn grant_recv_capacity(&mut self, capacity: WindowSize) -> WindowSize {
+        let mut me = self.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.key);
+
+        me.actions
+            .recv
+            .grant_capacity(capacity, &mut stream, &mut me.actions.task)
+    }
+
+    /// Defers stream WINDOW_UPDATE frames until `end_recv_batch` is called.
+    #[cfg(feature = "unstable")]
+    pub fn begin_recv_batch(&mut self) {
+        let mut me = self.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.key);
+
+        me.actions.recv.begin_batch(&mut stream);
+    }
+
+    /// Sends a single WINDOW_UPDATE for the capacity released since
+    /// `begin_recv_batch`.
+    #[cfg(feature = "unstable")]
+    pub fn end_recv_batch(&mut self) {
+        let mut me = self.inner.lock().unwrap();
+        let me = &mut *me;
+
+        let mut stream = me.store.resolve(self.key);
+
+        me.actions.recv.end_batch(&mut stream, &mut me.actions.task);
+    }
+
+    /// Clear the receive queue and set the status to no longer receive data frames,
+    /// returning capacity held by undelivered data to the connection.
     pub(crate) fn clear_recv_buffer(&mut self) {
         let mut me = self.inner.lock().unwrap();
         let me = &mut *me;
 
         let mut stream = me.store.resolve(self.key);
         stream.is_recv = false;
-        me.actions.recv.clear_recv_buffer(&mut stream);
+        me.actions
+            .recv
+            .discard_recv_buffer(&mut stream, &mut me.actions.task);
     }
 
     pub fn stream_id(&self) -> StreamId {
@@ -1533,6 +1970,40 @@ impl Actions {
         });
     }
 
+    /// Closes `stream` because the connection failed, went away (`err`) or
+    /// reached EOF (`None`), whatever state the stream is in.
+    ///
+    /// The state change and the reclaimed connection capacity are reported
+    /// together in the outcome. Capacity is only reclaimed from received data
+    /// that can no longer be read: data still reachable by the user, through
+    /// a handle or the accept queue, stays in flight until it is released or
+    /// dropped.
+    fn force_close<B>(
+        &mut self,
+        buffer: &mut Buffer<Frame<B>>,
+        stream: &mut store::Ptr,
+        counts: &mut Counts,
+        err: Option<&proto::Error>,
+    ) -> TransitionOutcome {
+        match err {
+            Some(err) => self.recv.handle_error(err, stream),
+            None => self.recv.recv_eof(stream),
+        }
+
+        // This handles resetting send state associated with the stream
+        self.send.handle_error(buffer, stream, counts);
+
+        let mut outcome = counts.observe(stream);
+
+        // Streams waiting to be accepted can still be read once they are.
+        if stream.ref_count == 0 && !stream.is_pending_accept {
+            outcome.reclaimed_window = stream.in_flight_recv_data;
+            self.recv.release_closed_capacity(stream, &mut self.task);
+        }
+
+        outcome
+    }
+
     fn reset_on_recv_stream_err<B>(
         &mut self,
         buffer: &mut Buffer<Frame<B>>,
@@ -1542,15 +2013,53 @@ impl Actions {
     ) -> Result<(), Error> {
         if let Err(Error::Reset(stream_id, reason, initiator)) = res {
             debug_assert_eq!(stream_id, stream.id);
+
+            let res = if initiator == Initiator::Library {
+                self.count_error_reset(stream_id, reason, counts)
+            } else {
+                Ok(())
+            };
+
             // Reset the stream.
             self.send
                 .send_reset(reason, initiator, buffer, stream, counts, &mut self.task);
-            Ok(())
+            res
         } else {
             res
         }
     }
 
+    /// Counts a stream reset because of an error caused by the peer.
+    ///
+    /// Once too many streams have been reset this way, the error escalates
+    /// to a connection error. The stream is still reset: the RST_STREAM is
+    /// kept to be sent ahead of the GOAWAY, as resetting the other streams
+    /// drops any frames they have queued.
+    fn count_error_reset(
+        &mut self,
+        id: StreamId,
+        reason: Reason,
+        counts: &mut Counts,
+    ) -> Result<(), Error> {
+        if counts.can_inc_num_local_error_reset_streams() {
+            counts.inc_num_local_error_reset_streams();
+            return Ok(());
+        }
+
+        tracing::warn!(
+            "locally-reset streams reached limit ({:?}); stream={:?}",
+            counts.max_local_error_reset_streams(),
+            id,
+        );
+
+        self.escalated_reset = Some(frame::Reset::new(id, reason));
+
+        Err(Error::library_go_away_data(
+            Reason::ENHANCE_YOUR_CALM,
+            "too_many_internal_resets",
+        ))
+    }
+
     fn ensure_not_idle(&mut self, peer: peer::Dyn, id: StreamId) -> Result<(), Reason> {
         if peer.is_local_init(id) {
             self.send.ensure_not_idle(id)
@@ -1592,3 +2101,408 @@ impl Actions {
         self.send.clear_queues(store, counts);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::recv::MAX_IDLE_PRIORITIES;
+    use super::*;
+    use crate::frame::{Pseudo, DEFAULT_INITIAL_WINDOW_SIZE};
+    use crate::observer::{StreamEvent, StreamState};
+    use crate::proto::ThresholdPolicy;
+    use std::time::{Duration, Instant};
+
+    fn streams() -> Streams<Bytes, server::Peer> {
+        Streams::new(config())
+    }
+
+    fn config() -> Config {
+        Config {
+            initial_max_send_streams: usize::MAX,
+            local_max_buffer_size: proto::DEFAULT_MAX_SEND_BUFFER_SIZE,
+            local_max_recv_buffer_size: usize::MAX,
+            local_balance_window_updates: false,
+            local_next_stream_id: 2.into(),
+            local_stream_id_allocator: Arc::new(proto::MonotonicIds),
+            local_push_enabled: false,
+            extended_connect_protocol_enabled: false,
+            local_reset_duration: Duration::from_secs(proto::DEFAULT_RESET_STREAM_SECS),
+            local_reset_max: proto::DEFAULT_RESET_STREAM_MAX,
+            remote_reset_max: proto::DEFAULT_REMOTE_RESET_STREAM_MAX,
+            local_max_error_reset_streams: Some(proto::DEFAULT_LOCAL_RESET_COUNT_MAX),
+            remote_init_window_sz: DEFAULT_INITIAL_WINDOW_SIZE,
+            remote_max_initiated: None,
+            window_update_policy: Arc::new(ThresholdPolicy),
+            local_flush_idle_window_updates: false,
+            local_adaptive_connection_window: None,
+            local_reset_overrun_streams: true,
+            state_observer: None,
+            stream_event_capacity: 0,
+            local_stalled_stream_timeout: None,
+            expected_scheme: None,
+        }
+    }
+
+    fn request(id: u32) -> frame::Headers {
+        let pseudo = Pseudo::request(
+            http::Method::POST,
+            "https://example.com/".parse().unwrap(),
+            None,
+        );
+        frame::Headers::new(id.into(), pseudo, HeaderMap::new())
+    }
+
+    fn priority(id: u32, dependency_id: u32, weight: u8) -> frame::Priority {
+        let head = frame::Head::new(frame::Kind::Priority, 0, id.into());
+        let mut payload = dependency_id.to_be_bytes().to_vec();
+        payload.push(weight);
+        frame::Priority::load(head, &payload).unwrap()
+    }
+
+    fn dependency(
+        streams: &Streams<Bytes, server::Peer>,
+        id: u32,
+    ) -> Option<frame::StreamDependency> {
+        let mut me = streams.inner.lock().unwrap();
+        me.store
+            .find_mut(&id.into())
+            .and_then(|stream| stream.dependency)
+    }
+
+    /// Opens stream 1 with a request carrying `len` bytes of unread data.
+    fn open_with_data(streams: &Streams<Bytes, server::Peer>, len: usize) {
+        let data = frame::Data::new(1.into(), Bytes::from(vec![0; len]));
+
+        let mut streams = streams.as_dyn();
+        streams.recv_headers(request(1)).unwrap();
+        streams.recv_data(data).unwrap();
+    }
+
+    fn force_close(streams: &Streams<Bytes, server::Peer>, accept: bool) -> TransitionOutcome {
+        let mut buffer = streams.send_buffer.inner.lock().unwrap();
+        let mut me = streams.inner.lock().unwrap();
+        let me = &mut *me;
+
+        if accept {
+            me.actions.recv.next_incoming(&mut me.store).unwrap();
+        }
+
+        let err = Error::library_go_away(Reason::PROTOCOL_ERROR);
+        let stream = me.store.find_mut(&1.into()).unwrap();
+        let actions = &mut me.actions;
+        me.counts.transition(stream, |counts, stream| {
+            actions.force_close(&mut buffer, stream, counts, Some(&err))
+        })
+    }
+
+    fn is_malformed(res: Result<(), Error>) -> bool {
+        matches!(res, Err(Error::Reset(_, Reason::PROTOCOL_ERROR, _)))
+    }
+
+    #[test]
+    fn validate_message_checks_status_and_trailers() {
+        let frame = request(1);
+        assert!(validate_message(&frame, false, false, peer::Dyn::Server).is_ok());
+        assert!(is_malformed(validate_message(
+            &frame,
+            false,
+            false,
+            peer::Dyn::Client
+        )));
+
+        let frame = frame::Headers::trailers(1.into(), HeaderMap::new());
+        assert!(validate_message(&frame, false, true, peer::Dyn::Server).is_ok());
+
+        // trailers must end the stream
+        assert!(is_malformed(validate_message(
+            &frame,
+            true,
+            true,
+            peer::Dyn::Server
+        )));
+
+        // pseudo-headers are not allowed in trailers
+        assert!(is_malformed(validate_message(
+            &request(1),
+            false,
+            true,
+            peer::Dyn::Server
+        )));
+    }
+
+    #[test]
+    fn recorded_authorities_are_bounded() {
+        let mut send = Send::new(&config());
+
+        for i in 0..proto::MAX_AUTHORITIES + 4 {
+            let authority = format!("host{}.example.com", i).parse().unwrap();
+            send.record_authority(authority);
+        }
+        send.record_authority("host0.example.com".parse().unwrap());
+
+        let authorities = send.authorities();
+        assert_eq!(authorities.len(), proto::MAX_AUTHORITIES);
+        assert_eq!(authorities[0], "host0.example.com");
+        assert_eq!(
+            authorities[proto::MAX_AUTHORITIES - 1],
+            format!("host{}.example.com", proto::MAX_AUTHORITIES - 1).as_str()
+        );
+    }
+
+    #[test]
+    fn reset_lowest_priority_picks_lowest_weights_then_newest() {
+        let mut streams = streams();
+
+        for (id, weight) in [(1, 16), (3, 4), (5, 16), (7, 32)] {
+            streams.as_dyn().recv_headers(request(id)).unwrap();
+            let mut me = streams.inner.lock().unwrap();
+            me.store.find_mut(&id.into()).unwrap().send_weight = weight;
+        }
+
+        assert_eq!(
+            streams.reset_lowest_priority(2, Reason::ENHANCE_YOUR_CALM),
+            2
+        );
+
+        let mut me = streams.inner.lock().unwrap();
+        for (id, reset) in [(1, false), (3, true), (5, true), (7, false)] {
+            let stream = me.store.find_mut(&id.into()).unwrap();
+            assert_eq!(stream.state.is_reset(), reset, "stream {}", id);
+        }
+        drop(me);
+
+        // Reset streams are no longer active, so only two remain.
+        assert_eq!(
+            streams.reset_lowest_priority(5, Reason::ENHANCE_YOUR_CALM),
+            2
+        );
+
+        streams.as_dyn().recv_eof(true).unwrap();
+    }
+
+    #[test]
+    fn force_close_reclaims_unreachable_window() {
+        let streams = streams();
+        open_with_data(&streams, 100);
+
+        let outcome = force_close(&streams, true);
+
+        assert_eq!(
+            outcome,
+            TransitionOutcome {
+                state_change: Some((StreamState::Open, StreamState::Closed)),
+                reclaimed_window: 100,
+                events: vec![StreamEvent::Closed],
+                emit_update: None,
+            }
+        );
+    }
+
+    #[test]
+    fn force_close_keeps_window_of_pending_accept() {
+        let streams = streams();
+        open_with_data(&streams, 100);
+
+        let outcome = force_close(&streams, false);
+
+        assert_eq!(outcome.reclaimed_window, 0);
+        assert_eq!(
+            outcome.state_change,
+            Some((StreamState::Open, StreamState::Closed))
+        );
+
+        streams.as_dyn().recv_eof(true).unwrap();
+    }
+
+    /// Opens stream 1 with `released` bytes received and released, then
+    /// receives `len` more bytes on it.
+    fn recv_data_after_release(released: usize, len: usize) -> TransitionOutcome {
+        let streams = streams();
+        open_with_data(&streams, released);
+
+        let outcome = {
+            let mut me = streams.inner.lock().unwrap();
+            let me = &mut *me;
+
+            let mut stream = me.store.find_mut(&1.into()).unwrap();
+            me.actions
+                .recv
+                .release_capacity(released as WindowSize, &mut stream, &mut None)
+                .unwrap();
+
+            let data = frame::Data::new(1.into(), Bytes::from(vec![0; len]));
+            me.actions.recv.recv_data(data, &mut stream).unwrap()
+        };
+
+        streams.as_dyn().recv_eof(true).unwrap();
+        outcome
+    }
+
+    #[test]
+    fn recv_data_signals_update_once_window_shrinks_past_threshold() {
+        // 10_000 released is below half of the 55_535 window, but not of the
+        // 5_535 left after the second frame.
+        let outcome = recv_data_after_release(10_000, 50_000);
+
+        assert_eq!(outcome.emit_update, Some(10_000));
+        assert_eq!(outcome.state_change, None);
+    }
+
+    #[test]
+    fn recv_data_without_update_due() {
+        let outcome = recv_data_after_release(10_000, 1_000);
+
+        assert_eq!(outcome.emit_update, None);
+    }
+
+    #[test]
+    fn idle_priority_applies_when_stream_opens() {
+        let streams = streams();
+        let mut dyn_streams = streams.as_dyn();
+
+        dyn_streams.recv_priority(priority(3, 1, 200));
+        assert!(streams
+            .inner
+            .lock()
+            .unwrap()
+            .store
+            .find_mut(&3.into())
+            .is_none());
+        assert!(!streams.has_streams());
+
+        dyn_streams.recv_headers(request(3)).unwrap();
+        assert_eq!(
+            dependency(&streams, 3),
+            Some(frame::StreamDependency::new(1.into(), 200, false))
+        );
+
+        // Once open, the stream's priority is updated in place.
+        dyn_streams.recv_priority(priority(3, 0, 16));
+        assert_eq!(
+            dependency(&streams, 3),
+            Some(frame::StreamDependency::new(0.into(), 16, false))
+        );
+
+        dyn_streams.recv_eof(true).unwrap();
+    }
+
+    #[test]
+    fn idle_priorities_are_bounded() {
+        let streams = streams();
+        let mut dyn_streams = streams.as_dyn();
+
+        let ids: Vec<u32> = (0..=MAX_IDLE_PRIORITIES as u32)
+            .map(|i| 2 * i + 1)
+            .collect();
+        for &id in &ids {
+            dyn_streams.recv_priority(priority(id, 0, 42));
+        }
+
+        let oldest = ids[0];
+        let newest = ids[ids.len() - 1];
+        dyn_streams.recv_headers(request(oldest)).unwrap();
+        dyn_streams.recv_headers(request(newest)).unwrap();
+
+        assert_eq!(dependency(&streams, oldest), None);
+        assert_eq!(
+            dependency(&streams, newest),
+            Some(frame::StreamDependency::new(0.into(), 42, false))
+        );
+
+        dyn_streams.recv_eof(true).unwrap();
+    }
+
+    #[test]
+    fn window_update_frames_carry_stream_id_and_increment() {
+        let streams = streams();
+        open_with_data(&streams, 100);
+
+        {
+            let mut me = streams.inner.lock().unwrap();
+            let me = &mut *me;
+            let mut stream = me.store.find_mut(&1.into()).unwrap();
+
+            // Too little was released for the policy to advertise it yet.
+            me.actions
+                .recv
+                .release_capacity(100, &mut stream, &mut me.actions.task)
+                .unwrap();
+            assert_eq!(me.actions.recv.connection_window_update_frame(false), None);
+            assert_eq!(
+                me.actions.recv.stream_window_update_frame(&stream, false),
+                None
+            );
+
+            assert_eq!(
+                me.actions.recv.connection_window_update_frame(true),
+                Some(frame::WindowUpdate::new(StreamId::zero(), 100))
+            );
+            assert_eq!(
+                me.actions.recv.stream_window_update_frame(&stream, true),
+                Some(frame::WindowUpdate::new(1.into(), 100))
+            );
+        }
+
+        streams.as_dyn().recv_eof(true).unwrap();
+    }
+
+    /// Feeds `rates`, in bytes received per 100ms round trip, to a connection
+    /// growing its window with the receive rate, returning the connection
+    /// capacity not yet advertised to the peer after each.
+    fn adaptive_window_updates(rates: &[WindowSize]) -> Vec<Option<WindowSize>> {
+        let mut config = config();
+        config.local_adaptive_connection_window = Some(Duration::from_millis(100));
+        let streams = Streams::<Bytes, server::Peer>::new(config);
+
+        let mut me = streams.inner.lock().unwrap();
+        let recv = &mut me.actions.recv;
+        let mut now = Instant::now();
+        recv.record_recv_rate(0, now);
+
+        rates
+            .iter()
+            .map(|&sz| {
+                now += Duration::from_millis(100);
+                recv.record_recv_rate(sz, now);
+
+                recv.connection_window_update_frame(true)
+                    .map(|update| update.size_increment())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adaptive_connection_window_grows_with_recv_rate() {
+        // 1MB per round trip asks for a 2MB window, up from the default 64KB.
+        // Doubling the rate doubles the window again.
+        assert_eq!(
+            adaptive_window_updates(&[1_000_000, 2_000_000]),
+            [
+                Some(2_000_000 - DEFAULT_INITIAL_WINDOW_SIZE),
+                Some(4_000_000 - DEFAULT_INITIAL_WINDOW_SIZE)
+            ]
+        );
+    }
+
+    #[test]
+    fn adaptive_connection_window_never_shrinks() {
+        assert_eq!(
+            adaptive_window_updates(&[1_000_000, 100_000, 1_000_000]),
+            [Some(2_000_000 - DEFAULT_INITIAL_WINDOW_SIZE); 3]
+        );
+
+        // Rates the initial window already covers leave it alone.
+        assert_eq!(adaptive_window_updates(&[10_000, 30_000]), [None, None]);
+    }
+
+    #[test]
+    fn connection_window_fixed_without_adaptive_window() {
+        let streams = streams();
+        let mut me = streams.inner.lock().unwrap();
+        let recv = &mut me.actions.recv;
+
+        let now = Instant::now();
+        recv.record_recv_rate(1_000_000, now);
+        recv.record_recv_rate(1_000_000, now + Duration::from_millis(100));
+        assert_eq!(recv.connection_window_update_frame(true), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::proto::WindowSize;
+
+/// The weight a stream has unless one is set, as defined by RFC 7540.
+pub(crate) const DEFAULT_WEIGHT: u16 = 16;
+
+/// The largest weight a stream can have.
+pub(crate) const MAX_WEIGHT: u16 = 256;
+
+/// Splits connection-level send capacity between streams waiting for it.
+///
+/// Each stream asks for an amount of capacity along with its weight. When
+/// capacity is distributed, every stream receives a share proportional to its
+/// weight, so that no single stream monopolizes the connection window. Any
+/// share a stream does not need is handed out again to the others.
+#[derive(Debug, Default)]
+pub(super) struct WindowPool {
+    /// The weight and wanted capacity of each request, in request order.
+    requests: Vec<(u16, WindowSize)>,
+}
+
+impl WindowPool {
+    pub fn new() -> WindowPool {
+        WindowPool::default()
+    }
+
+    /// Adds a request for `wanted` bytes of capacity by a stream of `weight`.
+    pub fn request(&mut self, weight: u16, wanted: WindowSize) {
+        debug_assert!(weight > 0 && weight <= MAX_WEIGHT);
+        self.requests.push((weight, wanted));
+    }
+
+    /// Distributes `available` capacity between all pending requests.
+    ///
+    /// Returns the amount allocated to each request, in request order. No
+    /// request is allocated more than it asked for. Capacity that cannot be
+    /// split proportionally goes to the earliest requests.
+    pub fn distribute(&mut self, available: WindowSize) -> Vec<WindowSize> {
+        let mut allocated = vec![0; self.requests.len()];
+        let mut remaining = available;
+
+        let mut active: Vec<usize> = (0..self.requests.len())
+            .filter(|&i| self.requests[i].1 > 0)
+            .collect();
+
+        while remaining > 0 && !active.is_empty() {
+            let total_weight: u64 = active.iter().map(|&i| self.requests[i].0 as u64).sum();
+            let mut given = 0;
+
+            for &i in &active {
+                let (weight, wanted) = self.requests[i];
+                let share = (remaining as u64 * weight as u64 / total_weight) as WindowSize;
+                let grant = share.min(wanted - allocated[i]);
+
+                allocated[i] += grant;
+                given += grant;
+            }
+
+            remaining -= given;
+            active.retain(|&i| allocated[i] < self.requests[i].1);
+
+            if given == 0 {
+                // Fewer bytes remain than there is weight to split them by.
+                for &i in &active {
+                    let grant = remaining.min(self.requests[i].1 - allocated[i]);
+
+                    allocated[i] += grant;
+                    remaining -= grant;
+                }
+                break;
+            }
+        }
+
+        self.requests.clear();
+        allocated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distribute(available: WindowSize, requests: &[(u16, WindowSize)]) -> Vec<WindowSize> {
+        let mut pool = WindowPool::new();
+        for &(weight, wanted) in requests {
+            pool.request(weight, wanted);
+        }
+        pool.distribute(available)
+    }
+
+    #[test]
+    fn splits_proportionally_to_weight() {
+        assert_eq!(distribute(1_000, &[(16, 5_000), (16, 5_000)]), [500, 500]);
+        assert_eq!(distribute(1_000, &[(48, 5_000), (16, 5_000)]), [750, 250]);
+    }
+
+    #[test]
+    fn redistributes_unneeded_share() {
+        assert_eq!(distribute(1_000, &[(16, 100), (16, 5_000)]), [100, 900]);
+    }
+
+    #[test]
+    fn never_allocates_more_than_wanted() {
+        assert_eq!(distribute(1_000, &[(16, 100), (16, 200)]), [100, 200]);
+        assert_eq!(distribute(1_000, &[(16, 0), (16, 5_000)]), [0, 1_000]);
+    }
+
+    #[test]
+    fn leftover_goes_to_earliest_request() {
+        assert_eq!(distribute(1, &[(16, 100), (16, 100)]), [1, 0]);
+        assert_eq!(distribute(10, &[(1, 100), (1, 100), (1, 100)]), [4, 3, 3]);
+    }
+}
@@ -117,13 +117,15 @@
 
 use crate::codec::{Codec, UserError};
 use crate::frame::{self, Pseudo, PushPromiseHeaderError, Reason, Settings, StreamId};
+use crate::observer::StateObserver;
 use crate::proto::{self, Config, Error, Prioritized};
-use crate::{FlowControl, PingPong, RecvStream, SendStream};
+use crate::{EffectiveSettings, FlowControl, PeerSettings, PingPong, RecvStream, SendStream};
 
 use bytes::{Buf, Bytes};
-use http::{HeaderMap, Method, Request, Response};
+use http::{HeaderMap, Method, Request, Response, StatusCode};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use std::{fmt, io};
@@ -244,6 +246,9 @@ pub struct Builder {
     /// accept queue.
     pending_accept_reset_stream_max: usize,
 
+    /// Maximum number of streams reset because of errors caused by the peer.
+    local_max_error_reset_streams: Option<usize>,
+
     /// Initial `Settings` frame to send as part of the handshake.
     settings: Settings,
 
@@ -252,6 +257,59 @@ pub struct Builder {
 
     /// Maximum amount of bytes to "buffer" for writing per stream.
     max_send_buffer_size: usize,
+
+    /// Maximum amount of unread received bytes to buffer per stream.
+    max_recv_buffer_size: usize,
+
+    /// Maximum encoded size of a received header block.
+    max_header_block_size: Option<usize>,
+
+    /// Whether WINDOW_UPDATE frames are balanced between the connection and
+    /// stream windows.
+    balance_window_updates: bool,
+
+    /// Whether WINDOW_UPDATE frames held back by the policy are sent once the
+    /// connection has nothing else to send.
+    flush_idle_window_updates: bool,
+
+    /// Round-trip time used to grow the connection window with the rate at
+    /// which DATA is received, if enabled.
+    adaptive_connection_window: Option<Duration>,
+
+    /// Whether a stream whose receive window is overrun is reset, rather
+    /// than the connection closed.
+    reset_overrun_streams: bool,
+
+    /// Decides when released receive capacity is advertised to the peer.
+    window_update_policy: Arc<dyn proto::WindowUpdatePolicy>,
+
+    /// Notified of every stream state transition.
+    state_observer: Option<Arc<dyn StateObserver>>,
+
+    /// Max number of lifecycle events queued per stream, or 0 if disabled.
+    stream_event_capacity: usize,
+
+    /// How long a stream's send window may stay exhausted before it is reset.
+    stalled_stream_timeout: Option<Duration>,
+
+    /// Max number of each rate-limited frame type accepted per interval.
+    frame_rate_limit: Option<(usize, Duration)>,
+
+    /// Max number of empty DATA frames accepted per interval.
+    empty_data_rate_limit: Option<(usize, Duration)>,
+
+    /// Number of streams the client may open before the connection is
+    /// gracefully shut down.
+    max_lifetime_streams: Option<usize>,
+
+    /// The scheme requests must carry in `:scheme`, if validated.
+    expected_scheme: Option<http::uri::Scheme>,
+
+    /// Max number of frames read each time the connection is polled.
+    max_frames_per_poll: Option<usize>,
+
+    /// How long the peer may take to acknowledge our SETTINGS.
+    settings_ack_timeout: Option<Duration>,
 }
 
 /// Send a response back to the client
@@ -385,9 +443,13 @@ where
             codec.set_max_recv_header_list_size(max as usize);
         }
 
+        if let Some(max) = builder.max_header_block_size {
+            codec.set_max_recv_header_block_size(max);
+        }
+
         // Send initial settings frame.
         codec
-            .buffer(builder.settings.clone().into())
+            .buffer(builder.settings.to_frame())
             .expect("invalid SETTINGS frame");
 
         // Create the handshake future.
@@ -468,6 +530,14 @@ where
     /// This can be used to increase or decrease the window size for existing
     /// streams.
     ///
+    /// Decreasing it applies the difference to every open stream, so a stream
+    /// that already received more than the new size is left with a negative
+    /// window. The remote may not send DATA on such a stream until enough
+    /// capacity has been released with [`FlowControl::release_capacity`]
+    /// to bring the window back above zero.
+    ///
+    /// [`FlowControl::release_capacity`]: crate::FlowControl::release_capacity
+    ///
     /// # Errors
     ///
     /// Returns an error if a previous call is still pending acknowledgement
@@ -538,6 +608,22 @@ where
         self.connection.go_away_gracefully();
     }
 
+    /// Resets up to `n` active streams, starting with the lowest priority
+    /// ones, and returns how many were reset.
+    ///
+    /// This sheds load when the connection is under pressure. Streams are
+    /// ordered by their send weight, which is set with
+    /// `SendStream::set_weight` under the `unstable` feature; among streams
+    /// of equal weight, the most recently opened ones are reset first.
+    /// `reason` is usually [`Reason::ENHANCE_YOUR_CALM`], or
+    /// [`Reason::REFUSED_STREAM`] for streams that have not been processed
+    /// yet.
+    ///
+    /// The connection must continue being polled to send the resets.
+    pub fn reset_lowest_priority_streams(&mut self, n: usize, reason: Reason) -> usize {
+        self.connection.reset_lowest_priority_streams(n, reason)
+    }
+
     /// Takes a `PingPong` instance from the connection.
     ///
     /// # Note
@@ -575,6 +661,28 @@ where
         self.connection.max_recv_streams()
     }
 
+    /// Returns the number of active streams initiated by the client on this
+    /// connection.
+    ///
+    /// These are the streams counted against
+    /// [`max_concurrent_recv_streams`][1]; once the two are equal, further
+    /// streams opened by the client are refused with `REFUSED_STREAM`.
+    ///
+    /// [1]: #method.max_concurrent_recv_streams
+    pub fn num_active_recv_streams(&self) -> usize {
+        self.connection.num_recv_streams()
+    }
+
+    /// Returns the settings in effect on this connection.
+    ///
+    /// Local settings are included once the remote peer has acknowledged
+    /// them, and remote settings once they have been acknowledged and
+    /// applied. Until then, the previous values (or the protocol defaults)
+    /// are returned.
+    pub fn effective_settings(&self) -> EffectiveSettings {
+        self.connection.effective_settings()
+    }
+
     // Could disappear at anytime.
     #[doc(hidden)]
     #[cfg(feature = "unstable")]
@@ -641,9 +749,26 @@ impl Builder {
             reset_stream_duration: Duration::from_secs(proto::DEFAULT_RESET_STREAM_SECS),
             reset_stream_max: proto::DEFAULT_RESET_STREAM_MAX,
             pending_accept_reset_stream_max: proto::DEFAULT_REMOTE_RESET_STREAM_MAX,
+            local_max_error_reset_streams: Some(proto::DEFAULT_LOCAL_RESET_COUNT_MAX),
             settings: Settings::default(),
             initial_target_connection_window_size: None,
             max_send_buffer_size: proto::DEFAULT_MAX_SEND_BUFFER_SIZE,
+            max_recv_buffer_size: usize::MAX,
+            max_header_block_size: None,
+            balance_window_updates: false,
+            flush_idle_window_updates: false,
+            adaptive_connection_window: None,
+            reset_overrun_streams: true,
+            window_update_policy: Arc::new(proto::ThresholdPolicy),
+            state_observer: None,
+            stream_event_capacity: 0,
+            stalled_stream_timeout: None,
+            frame_rate_limit: None,
+            empty_data_rate_limit: None,
+            max_lifetime_streams: None,
+            expected_scheme: None,
+            max_frames_per_poll: None,
+            settings_ack_timeout: None,
         }
     }
 
@@ -653,6 +778,10 @@ impl Builder {
     /// The initial window of a stream is used as part of flow control. For more
     /// details, see [`FlowControl`].
     ///
+    /// The size only takes effect once the peer has acknowledged it. Streams
+    /// opened before then start with the default window, which is shrunk or
+    /// grown to the configured size on acknowledgement.
+    ///
     /// The default value is 65,535.
     ///
     /// [`FlowControl`]: ../struct.FlowControl.html
@@ -787,6 +916,22 @@ impl Builder {
         self
     }
 
+    /// Sets the max encoded size of a received header block.
+    ///
+    /// A header block is carried by a HEADERS or PUSH_PROMISE frame followed
+    /// by any number of CONTINUATION frames. Unlike [`max_header_list_size`],
+    /// this limit applies to the compressed bytes received, so that a peer
+    /// cannot keep sending CONTINUATION frames indefinitely. A peer exceeding
+    /// it is treated as a connection error of type `ENHANCE_YOUR_CALM`.
+    ///
+    /// The default is currently 16MB, but may change.
+    ///
+    /// [`max_header_list_size`]: #method.max_header_list_size
+    pub fn max_header_block_size(&mut self, max: usize) -> &mut Self {
+        self.max_header_block_size = Some(max);
+        self
+    }
+
     /// Sets the maximum number of concurrent streams.
     ///
     /// The maximum concurrent streams setting only controls the maximum number
@@ -835,6 +980,38 @@ impl Builder {
         self
     }
 
+    /// Sets the maximum number of frames read per poll of the connection.
+    ///
+    /// Once `max` frames have been processed, the connection flushes what it
+    /// has to write and yields back to the runtime, scheduling itself to be
+    /// polled again. This keeps a peer that sends frames faster than they can
+    /// be handled from monopolizing the task running the connection.
+    ///
+    /// By default, frames are read for as long as they are available.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `max` is 0.
+    pub fn max_frames_per_poll(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_frames_per_poll must be greater than 0");
+        self.max_frames_per_poll = Some(max);
+        self
+    }
+
+    /// Sets how long the peer may take to acknowledge our SETTINGS.
+    ///
+    /// If a SETTINGS frame we sent, including the one in the connection
+    /// preface, is not acknowledged within `dur`, the connection is closed
+    /// with a GOAWAY carrying `SETTINGS_TIMEOUT`. A timer wakes the
+    /// connection when the timeout elapses, so this must be used within a
+    /// Tokio runtime with the time driver enabled.
+    ///
+    /// By default, the peer may take as long as it likes.
+    pub fn settings_ack_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.settings_ack_timeout = Some(dur);
+        self
+    }
+
     /// Sets the maximum number of concurrent locally reset streams.
     ///
     /// When a stream is explicitly reset by either calling
@@ -924,6 +1101,23 @@ impl Builder {
         self
     }
 
+    /// Sets the maximum number of streams reset because of errors caused by
+    /// the peer.
+    ///
+    /// A peer that keeps sending malformed or otherwise invalid frames on its
+    /// streams only gets those streams reset, at little cost to itself. Once
+    /// more than `max` streams have been reset this way over the lifetime of
+    /// the connection, the next one is reset as usual and the connection is
+    /// then closed with a GOAWAY carrying `ENHANCE_YOUR_CALM`.
+    ///
+    /// Passing `None` disables the limit.
+    ///
+    /// The default value is currently 1024, but could change.
+    pub fn max_local_error_reset_streams(&mut self, max: Option<usize>) -> &mut Self {
+        self.local_max_error_reset_streams = max;
+        self
+    }
+
     /// Sets the maximum send buffer size per stream.
     ///
     /// Once a stream has buffered up to (or over) the maximum, the stream's
@@ -931,6 +1125,11 @@ impl Builder {
     /// stream have been written to the connection, the send buffer capacity
     /// will be freed up again.
     ///
+    /// This bounds memory rather than protocol-level flow: the limit applies
+    /// even while the peer's flow control window still has room, so
+    /// `poll_capacity` will wait for buffered data to be flushed before
+    /// yielding more capacity.
+    ///
     /// The default is currently ~400KB, but may change.
     ///
     /// # Panics
@@ -942,6 +1141,196 @@ impl Builder {
         self
     }
 
+    /// Sets the maximum receive buffer size per stream.
+    ///
+    /// Once a stream has buffered more received data than the maximum, the
+    /// stream's WINDOW_UPDATE frames are held back, even if capacity has been
+    /// released, until the application reads enough of the buffered data.
+    /// This applies backpressure to the peer.
+    ///
+    /// By default, there is no limit.
+    pub fn max_recv_buffer_size(&mut self, max: usize) -> &mut Self {
+        self.max_recv_buffer_size = max;
+        self
+    }
+
+    /// Enables or disables balancing WINDOW_UPDATE frames between the
+    /// connection and stream windows.
+    ///
+    /// Normally, released capacity is only advertised once enough of a
+    /// window has been consumed. When balancing is enabled, it is advertised
+    /// right away on whichever window is holding the peer back: a stream
+    /// whose window is small compared to the connection window, or the
+    /// connection when its window is smaller than the initial stream window.
+    ///
+    /// This is disabled by default.
+    pub fn balance_window_updates(&mut self, enabled: bool) -> &mut Self {
+        self.balance_window_updates = enabled;
+        self
+    }
+
+    /// Enables or disables flushing WINDOW_UPDATE frames when the connection
+    /// goes idle.
+    ///
+    /// Released capacity that the window update policy holds back is
+    /// normally batched until enough has accumulated. When this is enabled,
+    /// it is still batched while other frames are queued, but advertised as
+    /// soon as the connection has nothing else to send, so the peer is never
+    /// left waiting on capacity that was already released.
+    ///
+    /// This is disabled by default.
+    pub fn flush_window_updates_when_idle(&mut self, enabled: bool) -> &mut Self {
+        self.flush_idle_window_updates = enabled;
+        self
+    }
+
+    /// Grows the connection window with the rate at which DATA is received.
+    ///
+    /// The rate is measured over each `rtt`, the expected round-trip time to
+    /// the peer. Whenever the peer sends fast enough that the connection
+    /// window no longer covers twice the data in flight over one round trip,
+    /// the window is grown to match, so a sustained high throughput is not
+    /// held back by flow control. The window is never shrunk.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "unstable")]
+    pub fn adaptive_connection_window(&mut self, rtt: Duration) -> &mut Self {
+        self.adaptive_connection_window = Some(rtt);
+        self
+    }
+
+    /// Sets whether a peer sending more DATA than a stream's receive window
+    /// allows only resets that stream.
+    ///
+    /// When enabled, the stream is reset with `FLOW_CONTROL_ERROR`, and other
+    /// streams on the connection carry on. When disabled, the connection is
+    /// closed with a GOAWAY carrying `FLOW_CONTROL_ERROR` instead. Overrunning
+    /// the connection window always closes the connection.
+    ///
+    /// Either way, the resulting error reports
+    /// [`Error::is_flow_control_violation`].
+    ///
+    /// This is enabled by default.
+    ///
+    /// [`Error::is_flow_control_violation`]: ../struct.Error.html#method.is_flow_control_violation
+    pub fn reset_stream_on_window_overrun(&mut self, enabled: bool) -> &mut Self {
+        self.reset_overrun_streams = enabled;
+        self
+    }
+
+    /// Sets an observer notified each time a stream changes state, or a
+    /// frame of an unknown type is received and discarded.
+    ///
+    /// This can be used to export stream lifecycle events to a metrics or
+    /// tracing system. See [`StateObserver`] for details.
+    ///
+    /// By default, no observer is set.
+    ///
+    /// [`StateObserver`]: ../trait.StateObserver.html
+    #[cfg(feature = "unstable")]
+    pub fn state_observer<O>(&mut self, observer: O) -> &mut Self
+    where
+        O: StateObserver + 'static,
+    {
+        self.state_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Enables a queue of lifecycle events on each stream.
+    ///
+    /// Up to `capacity` events are queued per stream and can be awaited with
+    /// [`RecvStream::event`]. Once a stream's queue is full, new events are
+    /// dropped until the application catches up.
+    ///
+    /// By default, no events are queued.
+    ///
+    /// [`RecvStream::event`]: ../struct.RecvStream.html#method.event
+    #[cfg(feature = "unstable")]
+    pub fn stream_event_queue(&mut self, capacity: usize) -> &mut Self {
+        self.stream_event_capacity = capacity;
+        self
+    }
+
+    /// Sets how long a stream may wait on an exhausted send window.
+    ///
+    /// Once sent data has used up a stream's send window, the peer is expected
+    /// to grow it again with WINDOW_UPDATE. If a stream with data still
+    /// buffered sees no window growth for `dur`, it is reset with `CANCEL`
+    /// instead of waiting forever. A timer wakes the connection when a stream
+    /// stalls, so this must be used within a Tokio runtime with the time
+    /// driver enabled.
+    ///
+    /// By default, stalled streams are never reset.
+    pub fn stalled_stream_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.stalled_stream_timeout = Some(dur);
+        self
+    }
+
+    /// Limits how often the peer may send frames that carry no stream
+    /// progress.
+    ///
+    /// PING, SETTINGS and PRIORITY frames, as well as empty DATA frames that
+    /// do not end their stream, are each counted separately. If the peer
+    /// sends more than `max` frames of any one of these types within
+    /// `interval`, the connection is closed with a GOAWAY carrying
+    /// `ENHANCE_YOUR_CALM`. Acknowledgements are not counted.
+    ///
+    /// By default, these frames are not rate limited.
+    pub fn frame_rate_limit(&mut self, max: usize, interval: Duration) -> &mut Self {
+        self.frame_rate_limit = Some((max, interval));
+        self
+    }
+
+    /// Limits how often the peer may send empty DATA frames.
+    ///
+    /// An empty DATA frame that does not end its stream consumes no flow
+    /// control window, so a peer can send them without limit. If the peer
+    /// sends more than `max` of them within `interval`, the connection is
+    /// closed with a GOAWAY carrying `ENHANCE_YOUR_CALM`.
+    ///
+    /// This replaces the limit set with [`frame_rate_limit`] for empty DATA
+    /// frames only, and applies even if no general limit is set.
+    ///
+    /// By default, empty DATA frames are subject to [`frame_rate_limit`].
+    ///
+    /// [`frame_rate_limit`]: #method.frame_rate_limit
+    pub fn empty_data_rate_limit(&mut self, max: usize, interval: Duration) -> &mut Self {
+        self.empty_data_rate_limit = Some((max, interval));
+        self
+    }
+
+    /// Sets the number of streams the client may open over the lifetime of
+    /// the connection.
+    ///
+    /// Once the client has opened `max` streams, the connection starts a
+    /// graceful shutdown, as with [`Connection::graceful_shutdown`]. Streams
+    /// already open are served to completion, and the client is expected to
+    /// open further streams on a new connection. This bounds how long a
+    /// single connection lives, and with it the state it accumulates.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// [`Connection::graceful_shutdown`]: struct.Connection.html#method.graceful_shutdown
+    pub fn max_streams_per_connection(&mut self, max: usize) -> &mut Self {
+        self.max_lifetime_streams = Some(max);
+        self
+    }
+
+    /// Sets the scheme of the connection, which requests must match.
+    ///
+    /// The `:scheme` of a request names the scheme of its target URI. A
+    /// request carrying a different scheme than the one the connection was
+    /// established with, such as `http` over a TLS connection, is refused
+    /// by resetting its stream with `PROTOCOL_ERROR`. CONNECT requests carry
+    /// no `:scheme` and are not affected, while extended CONNECT requests
+    /// are checked like any other.
+    ///
+    /// By default, any scheme is accepted.
+    pub fn expected_scheme(&mut self, scheme: http::uri::Scheme) -> &mut Self {
+        self.expected_scheme = Some(scheme);
+        self
+    }
+
     /// Sets the maximum number of concurrent locally reset streams.
     ///
     /// When a stream is explicitly reset by either calling
@@ -958,6 +1347,10 @@ impl Builder {
     /// this state will be maintained in memory. Once the duration elapses, the
     /// stream state is purged from memory.
     ///
+    /// The duration is also the grace period for the peer to notice the
+    /// reset. DATA received on the stream after it elapses is answered with a
+    /// `STREAM_CLOSED` reset, even if the stream is still in use locally.
+    ///
     /// Once the stream has been fully purged from memory, any additional frames
     /// received for that stream will result in a connection level protocol
     /// error, forcing the connection to terminate.
@@ -997,6 +1390,19 @@ impl Builder {
         self
     }
 
+    /// Sets the policy deciding when released receive capacity is sent to
+    /// the peer in a WINDOW_UPDATE frame.
+    ///
+    /// By default, an update is sent once half of the window is released.
+    #[cfg(feature = "unstable")]
+    pub fn window_update_policy<P>(&mut self, policy: P) -> &mut Self
+    where
+        P: proto::WindowUpdatePolicy + 'static,
+    {
+        self.window_update_policy = Arc::new(policy);
+        self
+    }
+
     /// Creates a new configured HTTP/2 server backed by `io`.
     ///
     /// It is expected that `io` already be in an appropriate state to commence
@@ -1098,6 +1504,24 @@ impl<B: Buf> SendResponse<B> {
             .map_err(Into::into)
     }
 
+    /// Respond with `421 Misdirected Request` and close the stream.
+    ///
+    /// A server sends this when the request is for an authority it cannot
+    /// serve on this connection. The client may then retry the request on
+    /// a different connection. The response has no body.
+    ///
+    /// Like [`send_response`], this may only be called once per instance.
+    ///
+    /// [`send_response`]: #method.send_response
+    pub fn send_misdirected_request(&mut self) -> Result<(), crate::Error> {
+        let response = Response::builder()
+            .status(StatusCode::MISDIRECTED_REQUEST)
+            .body(())
+            .unwrap();
+
+        self.send_response(response, true).map(|_| ())
+    }
+
     /// Push a request and response to the client
     ///
     /// On success, a [`SendResponse`] instance is returned.
@@ -1134,6 +1558,28 @@ impl<B: Buf> SendResponse<B> {
         self.inner.send_reset(reason)
     }
 
+    /// Resets the stream because the application failed with `err` while
+    /// handling the request.
+    ///
+    /// The stream is reset with the reason of the first [`Error`] made from a
+    /// [`Reason`] found in `err` or its sources, or with `INTERNAL_ERROR` if
+    /// there is none. See [`SendStream::send_error`] for details. The reason
+    /// sent is returned.
+    ///
+    /// As with [`send_reset`], a later call to [`send_response`] will result
+    /// in an error.
+    ///
+    /// [`Error`]: ../struct.Error.html
+    /// [`Reason`]: ../struct.Reason.html
+    /// [`SendStream::send_error`]: ../struct.SendStream.html#method.send_error
+    /// [`send_reset`]: #method.send_reset
+    /// [`send_response`]: #method.send_response
+    pub fn send_error(&mut self, err: &(dyn std::error::Error + 'static)) -> Reason {
+        let reason = crate::error::reset_reason(err);
+        self.inner.send_reset(reason);
+        reason
+    }
+
     /// Polls to be notified when the client resets this stream.
     ///
     /// If stream is still open, this returns `Poll::Pending`, and
@@ -1293,9 +1739,11 @@ where
             }
 
             if &PREFACE[self.pos..self.pos + n] != buf.filled() {
-                proto_err!(conn: "read_preface: invalid preface");
+                let mut received = PREFACE[..self.pos].to_vec();
+                received.extend_from_slice(buf.filled());
+                proto_err!(conn: "read_preface: invalid preface; received={:?}", received);
                 // TODO: Should this just write the GO_AWAY frame directly?
-                return Poll::Ready(Err(Error::library_go_away(Reason::PROTOCOL_ERROR).into()));
+                return Poll::Ready(Err(crate::Error::preface(received.into())));
             }
 
             self.pos += n;
@@ -1349,13 +1797,32 @@ where
                         codec,
                         Config {
                             next_stream_id: 2.into(),
+                            stream_id_allocator: Arc::new(proto::MonotonicIds),
                             // Server does not need to locally initiate any streams
                             initial_max_send_streams: 0,
                             max_send_buffer_size: self.builder.max_send_buffer_size,
+                            max_recv_buffer_size: self.builder.max_recv_buffer_size,
+                            balance_window_updates: self.builder.balance_window_updates,
+                            flush_idle_window_updates: self.builder.flush_idle_window_updates,
+                            adaptive_connection_window: self.builder.adaptive_connection_window,
+                            reset_overrun_streams: self.builder.reset_overrun_streams,
                             reset_stream_duration: self.builder.reset_stream_duration,
                             reset_stream_max: self.builder.reset_stream_max,
                             remote_reset_stream_max: self.builder.pending_accept_reset_stream_max,
+                            local_error_reset_streams_max: self
+                                .builder
+                                .local_max_error_reset_streams,
                             settings: self.builder.settings.clone(),
+                            window_update_policy: self.builder.window_update_policy.clone(),
+                            state_observer: self.builder.state_observer.clone(),
+                            stream_event_capacity: self.builder.stream_event_capacity,
+                            stalled_stream_timeout: self.builder.stalled_stream_timeout,
+                            frame_rate_limit: self.builder.frame_rate_limit,
+                            empty_data_rate_limit: self.builder.empty_data_rate_limit,
+                            max_lifetime_streams: self.builder.max_lifetime_streams,
+                            expected_scheme: self.builder.expected_scheme.clone(),
+                            max_frames_per_poll: self.builder.max_frames_per_poll,
+                            settings_ack_timeout: self.builder.settings_ack_timeout,
                         },
                     );
 
@@ -1415,30 +1882,34 @@ impl Peer {
         frame
     }
 
-    pub fn convert_push_message(
-        stream_id: StreamId,
-        promised_id: StreamId,
-        request: Request<()>,
-    ) -> Result<frame::PushPromise, UserError> {
-        use http::request::Parts;
-
-        if let Err(e) = frame::PushPromise::validate_request(&request) {
+    /// Checks that `request` may be promised, before a stream is reserved
+    /// for it.
+    pub fn validate_push_message(request: &Request<()>) -> Result<(), UserError> {
+        if let Err(e) = frame::PushPromise::validate_request(request) {
             use PushPromiseHeaderError::*;
             match e {
                 NotSafeAndCacheable => tracing::debug!(
-                    ?promised_id,
-                    "convert_push_message: method {} is not safe and cacheable",
+                    "validate_push_message: method {} is not safe and cacheable",
                     request.method(),
                 ),
                 InvalidContentLength(e) => tracing::debug!(
-                    ?promised_id,
-                    "convert_push_message; promised request has invalid content-length {:?}",
+                    "validate_push_message; promised request has invalid content-length {:?}",
                     e,
                 ),
             }
             return Err(UserError::MalformedHeaders);
         }
 
+        Ok(())
+    }
+
+    pub fn convert_push_message(
+        stream_id: StreamId,
+        promised_id: StreamId,
+        request: Request<()>,
+    ) -> frame::PushPromise {
+        use http::request::Parts;
+
         // Extract the components of the HTTP request
         let (
             Parts {
@@ -1452,12 +1923,7 @@ impl Peer {
 
         let pseudo = Pseudo::request(method, uri, None);
 
-        Ok(frame::PushPromise::new(
-            stream_id,
-            promised_id,
-            pseudo,
-            headers,
-        ))
+        frame::PushPromise::new(stream_id, promised_id, pseudo, headers)
     }
 }
 
@@ -1474,12 +1940,20 @@ impl proto::Peer for Peer {
         proto::DynPeer::Server
     }
 
+    fn default_settings() -> PeerSettings {
+        // A server never accepts PUSH_PROMISE frames, whether or not it
+        // advertises SETTINGS_ENABLE_PUSH.
+        let mut settings = PeerSettings::default();
+        settings.set_enable_push(false);
+        settings
+    }
+
     fn convert_poll_message(
         pseudo: Pseudo,
-        fields: HeaderMap,
+        mut fields: HeaderMap,
         stream_id: StreamId,
     ) -> Result<Self::Poll, Error> {
-        use http::{uri, Version};
+        use http::{header, uri, Version};
 
         let mut b = Request::builder();
 
@@ -1528,6 +2002,19 @@ impl proto::Peer for Peer {
                     why,
                 )
             })?);
+
+            // A Host header must identify the same entity as :authority, which
+            // takes precedence, so a matching Host is redundant and dropped.
+            for host in fields.get_all(header::HOST) {
+                if !host.as_bytes().eq_ignore_ascii_case(authority.as_bytes()) {
+                    malformed!(
+                        "malformed headers: host ({:?}) does not match authority ({:?})",
+                        host,
+                        authority,
+                    );
+                }
+            }
+            fields.remove(header::HOST);
         }
 
         // A :scheme is required, except CONNECT.
@@ -1,6 +1,8 @@
 use crate::codec::UserError;
 use crate::frame::Reason;
 use crate::proto::{self, WindowSize};
+#[cfg(feature = "unstable")]
+use crate::{StateDurations, StreamEvent};
 
 use bytes::{Buf, Bytes};
 use http::HeaderMap;
@@ -287,6 +289,46 @@ impl<B: Buf> SendStream<B> {
         self.inner.reserve_capacity(capacity as WindowSize)
     }
 
+    /// Sets the stream's weight when sharing connection capacity.
+    ///
+    /// When several streams are waiting for capacity and the connection's
+    /// window is limited, newly available capacity is split between them in
+    /// proportion to their weights. Weights range from 1 to 256, and values
+    /// outside that range are clamped. The default weight is 16.
+    ///
+    /// See [Flow control](struct.SendStream.html#flow-control) for an overview
+    /// of how send flow control works.
+    #[cfg(feature = "unstable")]
+    pub fn set_weight(&mut self, weight: u16) {
+        self.inner.set_send_weight(weight)
+    }
+
+    /// Pauses sending DATA on the stream without resetting it.
+    ///
+    /// Data passed to `send_data` while paused is buffered as usual but not
+    /// written to the connection, and neither is anything sent after it,
+    /// such as trailers. Sending a reset is not affected. The stream keeps
+    /// its place in flow control, so capacity assigned to it is not handed
+    /// to other streams.
+    ///
+    /// Call [`resume_send`] to let the buffered data go out.
+    ///
+    /// [`resume_send`]: #method.resume_send
+    #[cfg(feature = "unstable")]
+    pub fn pause_send(&mut self) {
+        self.inner.pause_send()
+    }
+
+    /// Resumes sending DATA on a stream paused with [`pause_send`].
+    ///
+    /// Does nothing if the stream is not paused.
+    ///
+    /// [`pause_send`]: #method.pause_send
+    #[cfg(feature = "unstable")]
+    pub fn resume_send(&mut self) {
+        self.inner.resume_send()
+    }
+
     /// Returns the stream's current send capacity.
     ///
     /// This allows the caller to check the current amount of available capacity
@@ -356,6 +398,26 @@ impl<B: Buf> SendStream<B> {
         self.inner.send_reset(reason)
     }
 
+    /// Resets the stream because the application failed with `err`.
+    ///
+    /// The stream is reset with the reason of the first [`Error`] made from a
+    /// [`Reason`] found in `err` or its sources, so a specific code can be
+    /// sent by failing with such an [`Error`]. Any other error, including one
+    /// received from the peer, resets the stream with `INTERNAL_ERROR`, as
+    /// does `NO_ERROR`. The stream is closed either way, and the reason sent
+    /// is returned.
+    ///
+    /// To reset the stream with a given reason directly, use [`send_reset`].
+    ///
+    /// [`Error`]: struct.Error.html
+    /// [`Reason`]: struct.Reason.html
+    /// [`send_reset`]: #method.send_reset
+    pub fn send_error(&mut self, err: &(dyn std::error::Error + 'static)) -> Reason {
+        let reason = crate::error::reset_reason(err);
+        self.inner.send_reset(reason);
+        reason
+    }
+
     /// Polls to be notified when the client resets this stream.
     ///
     /// If stream is still open, this returns `Poll::Pending`, and
@@ -380,6 +442,90 @@ impl<B: Buf> SendStream<B> {
     pub fn stream_id(&self) -> StreamId {
         StreamId::from_internal(self.inner.stream_id())
     }
+
+    /// Returns the time this stream has spent in each state so far.
+    ///
+    /// See [`StateDurations`] for details.
+    ///
+    /// [`StateDurations`]: struct.StateDurations.html
+    #[cfg(feature = "unstable")]
+    pub fn state_durations(&self) -> StateDurations {
+        self.inner.state_durations()
+    }
+
+    /// Returns the stream's state, windows and byte counts in one call.
+    ///
+    /// See [`StreamMetrics`] for details.
+    ///
+    /// [`StreamMetrics`]: struct.StreamMetrics.html
+    #[cfg(feature = "unstable")]
+    pub fn summary_metrics(&self) -> crate::StreamMetrics {
+        self.inner.summary_metrics()
+    }
+
+    /// Returns the share of the stream's send window that has been used.
+    ///
+    /// This is the number of DATA bytes sent on the stream, divided by the
+    /// flow control window the peer granted it so far: the initial window
+    /// and every increment since. The value is between `0.0` and `1.0`.
+    ///
+    /// A stream that ends with a low utilization was granted far more window
+    /// than it needed, and the peer buffers it committed to were wasted. A
+    /// utilization close to `1.0` on a stream that had more to send means
+    /// the peer's window, rather than the data, set the pace. A stream that
+    /// was never granted any window reports `0.0`.
+    #[cfg(feature = "unstable")]
+    pub fn window_utilization(&self) -> f32 {
+        self.inner.window_utilization()
+    }
+
+    /// Attaches a value of type `T` to the stream.
+    ///
+    /// The value is shared by every handle to the stream, such as the
+    /// matching [`RecvStream`], and stays available for as long as one of
+    /// them is alive, even once the stream has closed. If a value of this
+    /// type was already attached, it is replaced and returned.
+    ///
+    /// [`RecvStream`]: struct.RecvStream.html
+    #[cfg(feature = "unstable")]
+    pub fn insert_context<T>(&mut self, val: T) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.inner.insert_context(val)
+    }
+
+    /// Returns a copy of the value of type `T` attached to the stream, if any.
+    ///
+    /// See [`insert_context`] for details.
+    ///
+    /// [`insert_context`]: #method.insert_context
+    #[cfg(feature = "unstable")]
+    pub fn context<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.inner.context()
+    }
+}
+
+impl SendStream<Bytes> {
+    /// Finishes sending and lets the stream close once the peer finishes.
+    ///
+    /// Unless the send side is already finished, this sends an empty DATA
+    /// frame with the end stream flag, moving the stream to half-closed
+    /// (local). From then on, dropping every handle to the stream no longer
+    /// resets it with `CANCEL` or `NO_ERROR`. Instead, the stream stays open
+    /// until the peer sends its own END_STREAM, and any DATA that arrives
+    /// once the [`RecvStream`] is dropped is discarded.
+    ///
+    /// A draining stream still counts against the peer's concurrent stream
+    /// limit until the peer ends or resets it.
+    ///
+    /// [`RecvStream`]: struct.RecvStream.html
+    pub fn drain(&mut self) -> Result<(), crate::Error> {
+        self.inner.drain().map_err(Into::into)
+    }
 }
 
 // ===== impl StreamId =====
@@ -409,6 +555,12 @@ impl RecvStream {
     }
 
     /// Get the next data frame.
+    ///
+    /// Reading a data frame does not give its capacity back to the peer.
+    /// Once the data has been consumed, the caller must hand it back with
+    /// [`FlowControl::release_capacity`].
+    ///
+    /// [`FlowControl::release_capacity`]: struct.FlowControl.html#method.release_capacity
     pub async fn data(&mut self) -> Option<Result<Bytes, crate::Error>> {
         futures_util::future::poll_fn(move |cx| self.poll_data(cx)).await
     }
@@ -423,6 +575,25 @@ impl RecvStream {
         self.inner.inner.poll_data(cx).map_err(Into::into)
     }
 
+    /// Get the next lifecycle event of this stream.
+    ///
+    /// Events are only queued when enabled on the connection's builder, for
+    /// example with [`client::Builder::stream_event_queue`]. Returns `None`
+    /// once the stream is closed and all of its events have been seen, or
+    /// right away if events are not enabled.
+    ///
+    /// [`client::Builder::stream_event_queue`]: client/struct.Builder.html#method.stream_event_queue
+    #[cfg(feature = "unstable")]
+    pub async fn event(&mut self) -> Option<StreamEvent> {
+        futures_util::future::poll_fn(move |cx| self.poll_event(cx)).await
+    }
+
+    /// Poll for the next lifecycle event of this stream.
+    #[cfg(feature = "unstable")]
+    pub fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<StreamEvent>> {
+        self.inner.inner.poll_event(cx)
+    }
+
     #[doc(hidden)]
     pub fn poll_trailers(
         &mut self,
@@ -458,6 +629,55 @@ impl RecvStream {
     pub fn stream_id(&self) -> StreamId {
         self.inner.stream_id()
     }
+
+    /// Returns the time this stream has spent in each state so far.
+    ///
+    /// See [`StateDurations`] for details.
+    ///
+    /// [`StateDurations`]: struct.StateDurations.html
+    #[cfg(feature = "unstable")]
+    pub fn state_durations(&self) -> StateDurations {
+        self.inner.inner.state_durations()
+    }
+
+    /// Returns the stream's state, windows and byte counts in one call.
+    ///
+    /// See [`StreamMetrics`] for details.
+    ///
+    /// [`StreamMetrics`]: struct.StreamMetrics.html
+    #[cfg(feature = "unstable")]
+    pub fn summary_metrics(&self) -> crate::StreamMetrics {
+        self.inner.inner.summary_metrics()
+    }
+
+    /// Attaches a value of type `T` to the stream.
+    ///
+    /// The value is shared by every handle to the stream, such as the
+    /// matching [`SendStream`], and stays available for as long as one of
+    /// them is alive, even once the stream has closed. If a value of this
+    /// type was already attached, it is replaced and returned.
+    ///
+    /// [`SendStream`]: struct.SendStream.html
+    #[cfg(feature = "unstable")]
+    pub fn insert_context<T>(&mut self, val: T) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.inner.inner.insert_context(val)
+    }
+
+    /// Returns a copy of the value of type `T` attached to the stream, if any.
+    ///
+    /// See [`insert_context`] for details.
+    ///
+    /// [`insert_context`]: #method.insert_context
+    #[cfg(feature = "unstable")]
+    pub fn context<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.inner.inner.context()
+    }
 }
 
 #[cfg(feature = "stream")]
@@ -513,6 +733,31 @@ impl FlowControl {
         self.inner.used_recv_capacity() as usize
     }
 
+    /// Returns the number of DATA bytes of this stream read by the
+    /// application so far.
+    ///
+    /// Unlike the received byte count, this leaves out any data that was
+    /// dropped before it could be read, as counted by [`discarded_bytes`].
+    ///
+    /// [`discarded_bytes`]: #method.discarded_bytes
+    #[cfg(feature = "unstable")]
+    pub fn goodput_bytes(&self) -> u64 {
+        self.inner.delivered_recv_data()
+    }
+
+    /// Returns the number of DATA bytes of this stream that were received
+    /// but dropped without being read.
+    ///
+    /// This happens when the [`RecvStream`] is dropped with data still
+    /// buffered, typically because the stream was reset mid-body, or when
+    /// data arrives after it was dropped.
+    ///
+    /// [`RecvStream`]: struct.RecvStream.html
+    #[cfg(feature = "unstable")]
+    pub fn discarded_bytes(&self) -> u64 {
+        self.inner.discarded_recv_data()
+    }
+
     /// Release window capacity back to remote stream.
     ///
     /// This releases capacity back to the stream level and the connection level
@@ -537,6 +782,48 @@ impl FlowControl {
             .release_capacity(sz as proto::WindowSize)
             .map_err(Into::into)
     }
+
+    /// Grant the remote extra capacity to send on this stream, beyond the
+    /// initial window size.
+    ///
+    /// This lets a receiver expecting a large body open a large window up
+    /// front rather than waiting for consumed data to be released. The
+    /// stream window grows by the returned increment, which is advertised in
+    /// the next WINDOW_UPDATE frame for the stream. It is less than `sz` if
+    /// the window would otherwise exceed the maximum window size, and zero
+    /// once the stream no longer receives data.
+    ///
+    /// Only the stream window grows. The connection window can be raised
+    /// with `set_target_window_size` on the connection.
+    #[cfg(feature = "unstable")]
+    pub fn grant_recv_window(&mut self, sz: usize) -> usize {
+        let sz = std::cmp::min(sz, proto::MAX_WINDOW_SIZE as usize);
+        self.inner.grant_recv_capacity(sz as proto::WindowSize) as usize
+    }
+
+    /// Start deferring stream-level WINDOW_UPDATE frames.
+    ///
+    /// Capacity released with [`release_capacity`] while a batch is in
+    /// progress is accumulated rather than advertised, so that reading a
+    /// burst of DATA frames does not produce a WINDOW_UPDATE for each one.
+    /// Connection-level updates are not affected.
+    ///
+    /// [`release_capacity`]: #method.release_capacity
+    #[cfg(feature = "unstable")]
+    pub fn begin_batch(&mut self) {
+        self.inner.begin_recv_batch();
+    }
+
+    /// Stop deferring stream-level WINDOW_UPDATE frames.
+    ///
+    /// Capacity released since [`begin_batch`] is advertised to the peer in a
+    /// single WINDOW_UPDATE frame, if it is large enough to be worth sending.
+    ///
+    /// [`begin_batch`]: #method.begin_batch
+    #[cfg(feature = "unstable")]
+    pub fn end_batch(&mut self) {
+        self.inner.end_recv_batch();
+    }
 }
 
 // ===== impl PingPong =====
@@ -54,6 +54,13 @@ where
     frame::WindowUpdate::new(id.into(), sz)
 }
 
+pub fn altsvc<T>(id: T, origin: &'static str, value: &'static str) -> frame::AltSvc
+where
+    T: Into<StreamId>,
+{
+    frame::AltSvc::new(id.into(), Bytes::from(origin), Bytes::from(value))
+}
+
 pub fn go_away<T>(id: T) -> Mock<frame::GoAway>
 where
     T: Into<StreamId>,
@@ -396,6 +403,11 @@ impl Mock<frame::Settings> {
         self.0.set_header_table_size(Some(val));
         self
     }
+
+    pub fn max_frame_size(mut self, val: u32) -> Self {
+        self.0.set_max_frame_size(Some(val));
+        self
+    }
 }
 
 impl From<Mock<frame::Settings>> for frame::Settings {
@@ -10,6 +10,9 @@ pub use h2::*;
 // Re-export mock
 pub use super::mock::{self, idle_ms};
 
+// Re-export the network simulator
+pub use super::sim::{self, Conditions, SimConnection};
+
 // Re-export frames helpers
 pub use super::frames;
 
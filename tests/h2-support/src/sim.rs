@@ -0,0 +1,363 @@
+//! An in-memory connection between a client and a server that injects
+//! latency, reordering and loss on the frames sent in each direction.
+//!
+//! Unlike `mock`, both ends are driven by real `h2` connections, so a test
+//! exercises the client and server state machines against each other.
+
+use h2::frame::{Head, Kind, HEADER_LEN};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use std::{cmp, fmt, io, mem};
+
+const PREFACE_LEN: usize = 24;
+
+/// The conditions applied to the frames sent in one direction.
+#[derive(Clone, Default)]
+pub struct Conditions {
+    latency: Duration,
+    reorder: bool,
+    loss: Option<Arc<dyn Fn(&Head) -> bool + Send + Sync>>,
+}
+
+/// What the conditions did to the frames sent in one direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Number of times two frames were swapped.
+    pub reordered: usize,
+
+    /// Number of frames that were dropped.
+    pub dropped: usize,
+}
+
+/// A pair of links carrying frames between a client and a server.
+#[derive(Debug)]
+pub struct SimConnection {
+    to_server: Link,
+    to_client: Link,
+}
+
+/// One end of a `SimConnection`.
+///
+/// Dropping it closes the connection for the other end.
+#[derive(Debug)]
+pub struct SimIo {
+    tx: Link,
+    rx: Link,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+#[derive(Clone, Debug)]
+struct Link {
+    inner: Arc<Mutex<LinkInner>>,
+}
+
+#[derive(Debug)]
+struct LinkInner {
+    conditions: Conditions,
+
+    /// Bytes still to pass through before frames start, i.e. the preface.
+    unframed: usize,
+
+    /// Written bytes that do not form a whole frame yet.
+    partial: Vec<u8>,
+
+    /// Frames written since the last flush.
+    batch: Vec<(Head, Vec<u8>)>,
+
+    /// Flushed bytes and when they reach the other end.
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+
+    /// Bytes that reached the other end and have yet to be read.
+    readable: Vec<u8>,
+
+    read_task: Option<Waker>,
+
+    stats: LinkStats,
+
+    closed: bool,
+}
+
+// ===== impl Conditions =====
+
+impl Conditions {
+    /// Frames are delivered immediately, in order.
+    pub fn new() -> Conditions {
+        Conditions::default()
+    }
+
+    /// Delays every flushed frame by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Conditions {
+        self.latency = latency;
+        self
+    }
+
+    /// Swaps adjacent frames in a flush whenever HTTP/2 does not depend on
+    /// their relative order.
+    ///
+    /// Only DATA, PRIORITY, RST_STREAM, PING and WINDOW_UPDATE frames on
+    /// different streams are swapped. Header blocks share HPACK state and
+    /// SETTINGS must be acknowledged in order, so they are never moved.
+    pub fn reorder(mut self) -> Conditions {
+        self.reorder = true;
+        self
+    }
+
+    /// Drops every frame for which `f` returns true.
+    pub fn drop_frames<F>(mut self, f: F) -> Conditions
+    where
+        F: Fn(&Head) -> bool + Send + Sync + 'static,
+    {
+        self.loss = Some(Arc::new(f));
+        self
+    }
+
+    fn is_lost(&self, head: &Head) -> bool {
+        self.loss.as_ref().map_or(false, |f| f(head))
+    }
+}
+
+impl fmt::Debug for Conditions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Conditions")
+            .field("latency", &self.latency)
+            .field("reorder", &self.reorder)
+            .field("loss", &self.loss.is_some())
+            .finish()
+    }
+}
+
+fn is_reorderable(head: &Head) -> bool {
+    matches!(
+        head.kind(),
+        Kind::Data | Kind::Priority | Kind::Reset | Kind::Ping | Kind::WindowUpdate
+    )
+}
+
+// ===== impl SimConnection =====
+
+impl SimConnection {
+    /// Creates a connection applying `to_server` to the frames sent by the
+    /// client and `to_client` to the frames sent by the server.
+    pub fn new(to_server: Conditions, to_client: Conditions) -> SimConnection {
+        SimConnection {
+            to_server: Link::new(to_server, PREFACE_LEN),
+            to_client: Link::new(to_client, 0),
+        }
+    }
+
+    /// Returns the I/O to hand to `client::handshake`.
+    pub fn client_io(&self) -> SimIo {
+        SimIo::new(self.to_server.clone(), self.to_client.clone())
+    }
+
+    /// Returns the I/O to hand to `server::handshake`.
+    pub fn server_io(&self) -> SimIo {
+        SimIo::new(self.to_client.clone(), self.to_server.clone())
+    }
+
+    /// Returns what happened to the frames sent by the client so far.
+    pub fn to_server_stats(&self) -> LinkStats {
+        self.to_server.inner.lock().unwrap().stats
+    }
+
+    /// Returns what happened to the frames sent by the server so far.
+    pub fn to_client_stats(&self) -> LinkStats {
+        self.to_client.inner.lock().unwrap().stats
+    }
+}
+
+// ===== impl Link =====
+
+impl Link {
+    fn new(conditions: Conditions, unframed: usize) -> Link {
+        Link {
+            inner: Arc::new(Mutex::new(LinkInner {
+                conditions,
+                unframed,
+                partial: vec![],
+                batch: vec![],
+                in_flight: VecDeque::new(),
+                readable: vec![],
+                read_task: None,
+                stats: LinkStats::default(),
+                closed: false,
+            })),
+        }
+    }
+
+    fn close(&self) {
+        let mut me = self.inner.lock().unwrap();
+        me.flush();
+        me.closed = true;
+
+        if let Some(task) = me.read_task.take() {
+            task.wake();
+        }
+    }
+}
+
+impl LinkInner {
+    fn write(&mut self, buf: &[u8]) {
+        self.partial.extend_from_slice(buf);
+
+        if self.unframed > 0 {
+            let n = cmp::min(self.unframed, self.partial.len());
+            let preface = self.partial.drain(..n).collect();
+            self.unframed -= n;
+            self.in_flight.push_back((Instant::now(), preface));
+        }
+
+        while self.partial.len() >= HEADER_LEN {
+            let len = (self.partial[0] as usize) << 16
+                | (self.partial[1] as usize) << 8
+                | self.partial[2] as usize;
+
+            if self.partial.len() < HEADER_LEN + len {
+                break;
+            }
+
+            let frame: Vec<u8> = self.partial.drain(..HEADER_LEN + len).collect();
+            let head = Head::parse(&frame);
+
+            if self.conditions.is_lost(&head) {
+                tracing::trace!(?head, "sim; dropping frame");
+                self.stats.dropped += 1;
+                continue;
+            }
+
+            self.batch.push((head, frame));
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut batch = mem::take(&mut self.batch);
+
+        if self.conditions.reorder {
+            let mut i = 0;
+
+            while i + 1 < batch.len() {
+                let (a, b) = (&batch[i].0, &batch[i + 1].0);
+
+                if is_reorderable(a) && is_reorderable(b) && a.stream_id() != b.stream_id() {
+                    tracing::trace!(?a, ?b, "sim; swapping frames");
+                    batch.swap(i, i + 1);
+                    self.stats.reordered += 1;
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        let deliver_at = Instant::now() + self.conditions.latency;
+
+        for (_, frame) in batch {
+            self.in_flight.push_back((deliver_at, frame));
+        }
+
+        if let Some(task) = self.read_task.take() {
+            task.wake();
+        }
+    }
+
+    /// Moves the bytes that have arrived by `now` to the readable buffer.
+    fn deliver(&mut self, now: Instant) {
+        while let Some(&(deliver_at, _)) = self.in_flight.front() {
+            if deliver_at > now {
+                break;
+            }
+
+            let (_, bytes) = self.in_flight.pop_front().unwrap();
+            self.readable.extend(bytes);
+        }
+    }
+}
+
+// ===== impl SimIo =====
+
+impl SimIo {
+    fn new(tx: Link, rx: Link) -> SimIo {
+        SimIo {
+            tx,
+            rx,
+            sleep: None,
+        }
+    }
+}
+
+impl AsyncRead for SimIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut me = this.rx.inner.lock().unwrap();
+
+        me.deliver(Instant::now());
+
+        if !me.readable.is_empty() {
+            let n = cmp::min(buf.remaining(), me.readable.len());
+            buf.put_slice(&me.readable[..n]);
+            me.readable.drain(..n);
+            return Poll::Ready(Ok(()));
+        }
+
+        if let Some(&(deliver_at, _)) = me.in_flight.front() {
+            let sleep = this
+                .sleep
+                .get_or_insert_with(|| Box::pin(sleep_until(deliver_at)));
+            sleep.as_mut().reset(deliver_at);
+
+            if sleep.as_mut().poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        } else if me.closed {
+            return Poll::Ready(Ok(()));
+        }
+
+        me.read_task = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for SimIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut me = self.tx.inner.lock().unwrap();
+
+        if !me.closed {
+            me.write(buf);
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.tx.inner.lock().unwrap().flush();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for SimIo {
+    fn drop(&mut self) {
+        self.tx.close();
+        self.rx.close();
+    }
+}
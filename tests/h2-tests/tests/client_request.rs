@@ -147,6 +147,70 @@ async fn request_stream_id_overflows() {
     join(srv, h2).await;
 }
 
+#[tokio::test]
+async fn request_stream_ids_from_allocator() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    /// Leaves every other odd ID unused, up to stream 9.
+    #[derive(Debug)]
+    struct EveryOther;
+
+    impl h2::proto::StreamIdAllocator for EveryOther {
+        fn next_id(&self, prev: frame::StreamId) -> Option<frame::StreamId> {
+            let next = u32::from(prev) + 4;
+            if next > 9 {
+                None
+            } else {
+                Some(next.into())
+            }
+        }
+    }
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .stream_id_allocator(EveryOther)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+
+        for id in [1, 5, 9] {
+            let request = Request::get("https://example.com/").body(()).unwrap();
+            let (response, _) = client.send_request(request, true).unwrap();
+            let response = h2.drive(response).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "stream {}", id);
+        }
+
+        // the allocator has no ID left
+        let poll_err = poll_fn(|cx| client.poll_ready(cx)).await.unwrap_err();
+        assert_eq!(poll_err.to_string(), "user error: stream ID overflowed");
+
+        let request = Request::get("https://example.com/").body(()).unwrap();
+        let err = client.send_request(request, true).unwrap_err();
+        assert_eq!(err.to_string(), "user error: stream ID overflowed");
+
+        h2.await.unwrap();
+    };
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        for id in [1, 5, 9] {
+            srv.recv_frame(
+                frames::headers(id)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+            srv.send_frame(frames::headers(id).response(200).eos())
+                .await;
+        }
+        idle_ms(10).await;
+    };
+
+    join(srv, h2).await;
+}
+
 #[tokio::test]
 async fn client_builder_max_concurrent_streams() {
     h2_support::trace_init!();
@@ -370,6 +434,141 @@ async fn recv_decrement_max_concurrent_streams_when_requests_queued() {
     join(srv, h2).await;
 }
 
+#[tokio::test]
+async fn recv_max_concurrent_streams_zero_then_raised() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().max_concurrent_streams(0))
+            .await;
+        assert_default_settings!(settings);
+        tx.send(()).unwrap();
+        // the queued request must not be opened while the limit is 0
+        srv.ping_pong([1; 8]).await;
+        srv.send_frame(frames::settings().max_concurrent_streams(10))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.recv_frame(
+            frames::headers(5)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200).eos()).await;
+        srv.send_frame(frames::headers(5).response(200).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.expect("handshake");
+        h2.drive(rx).await.unwrap();
+
+        let request = || {
+            Request::builder()
+                .method(Method::GET)
+                .uri("https://example.com/")
+                .body(())
+                .unwrap()
+        };
+
+        // the request is queued rather than opened
+        let (resp1, _) = client.send_request(request(), true).unwrap();
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(!client.poll_ready(&mut cx).is_ready());
+
+        let err = client.send_request(request(), true).unwrap_err();
+        assert_eq!(err.to_string(), "user error: rejected");
+
+        let resp = h2.drive(resp1).await.expect("resp1");
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // once raised, new streams open right away
+        client = h2.drive(client.ready()).await.unwrap();
+        let (resp3, _) = client.send_request(request(), true).unwrap();
+        client = h2.drive(client.ready()).await.unwrap();
+        let (resp5, _) = client.send_request(request(), true).unwrap();
+
+        join(async move { h2.await.unwrap() }, async move {
+            resp3.await.unwrap();
+            resp5.await.unwrap();
+            drop(client);
+        })
+        .await;
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn recv_max_concurrent_streams_zero_keeps_open_streams() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        srv.send_frame(frames::settings().max_concurrent_streams(0))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, "hello").eos()).await;
+        srv.send_frame(frames::data(1, "world").eos()).await;
+        // the second request stays queued while the limit is 0
+        srv.ping_pong([1; 8]).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.expect("handshake");
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("https://example.com/")
+                .body(())
+                .unwrap()
+        };
+
+        let (resp1, mut stream1) = client.send_request(request(), false).unwrap();
+        let resp = h2.drive(resp1).await.expect("resp1");
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        client = h2.drive(client.ready()).await.unwrap();
+        let (resp2, _) = client.send_request(request(), true).unwrap();
+
+        stream1.send_data("hello".into(), true).unwrap();
+        let body = h2.drive(util::concat(resp.into_body())).await.unwrap();
+        assert_eq!(body, "world");
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(!client.poll_ready(&mut cx).is_ready());
+
+        h2.await.unwrap();
+        let err = resp2.await.unwrap_err();
+        assert_eq!(err.get_io().unwrap().kind(), std::io::ErrorKind::BrokenPipe);
+    };
+
+    join(srv, h2).await;
+}
+
 #[tokio::test]
 async fn send_request_poll_ready_when_connection_error() {
     h2_support::trace_init!();
@@ -631,6 +830,123 @@ async fn request_with_connection_headers() {
     join(srv, client).await;
 }
 
+#[tokio::test]
+async fn request_with_te_trailers() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        // The rejected request used up stream 1.
+        srv.recv_frame(
+            frames::headers(3)
+                .request("POST", "https://http2.akamai.com/")
+                .field("te", "trailers")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200).eos()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.expect("handshake");
+
+        let req = Request::builder()
+            .uri("https://http2.akamai.com/")
+            .header("te", "gzip")
+            .body(())
+            .unwrap();
+        let err = client.send_request(req, true).unwrap_err();
+        assert_eq!(err.to_string(), "user error: malformed headers");
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .header("te", "trailers")
+            .body(())
+            .unwrap();
+        let rsp = client.send_request(req, true).unwrap().0;
+        let rsp = conn.drive(rsp).await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+
+        drop(client);
+        conn.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn authorities_of_sent_requests() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://EXAMPLE.com/a")
+                .eos(),
+        )
+        .await;
+        // Stream 5 was used up by the rejected request.
+        srv.recv_frame(
+            frames::headers(7)
+                .request("GET", "https://foo.example.com/")
+                .eos(),
+        )
+        .await;
+        for id in [1, 3, 7] {
+            srv.send_frame(frames::headers(id).response(200).eos())
+                .await;
+        }
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.expect("handshake");
+        assert!(client.authorities().is_empty());
+
+        let uris = [
+            "https://example.com/",
+            "https://EXAMPLE.com/a",
+            "https://rejected.example.com/",
+            "https://foo.example.com/",
+        ];
+
+        let mut responses = vec![];
+        for uri in uris {
+            let mut req = Request::builder().uri(uri);
+            if uri.contains("rejected") {
+                req = req.header("connection", "close");
+            }
+            match client.send_request(req.body(()).unwrap(), true) {
+                Ok((rsp, _)) => responses.push(rsp),
+                Err(err) => assert_eq!(err.to_string(), "user error: malformed headers"),
+            }
+        }
+
+        assert_eq!(client.authorities(), ["example.com", "foo.example.com"]);
+
+        for rsp in responses {
+            let rsp = conn.drive(rsp).await.unwrap();
+            assert_eq!(rsp.status(), StatusCode::OK);
+        }
+
+        drop(client);
+        conn.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
 #[tokio::test]
 async fn connection_close_notifies_response_future() {
     h2_support::trace_init!();
@@ -1395,6 +1711,45 @@ async fn informational_while_local_streaming() {
     join(srv, h2).await;
 }
 
+#[tokio::test]
+async fn recv_data_after_informational_before_final_response() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(100)).await;
+        srv.send_frame(frames::data(1, "hello")).await;
+        srv.recv_frame(frames::reset(1).protocol_error()).await;
+        // only the stream is reset, the connection is still usable
+        srv.ping_pong([1; 8]).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://example.com/")
+            .body(())
+            .unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let err = h2.drive(response).await.unwrap_err();
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+        assert!(!err.is_go_away());
+
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
 #[tokio::test]
 async fn extended_connect_protocol_disabled_by_default() {
     h2_support::trace_init!();
@@ -1507,30 +1862,192 @@ async fn invalid_connect_protocol_enabled_setting() {
 }
 
 #[tokio::test]
-async fn extended_connect_request() {
+async fn default_settings_enable_push() {
     h2_support::trace_init!();
 
     let (io, mut srv) = mock::new();
 
     let srv = async move {
-        let settings = srv
-            .assert_client_handshake_with_settings(frames::settings().enable_connect_protocol(1))
-            .await;
+        let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
+    };
 
-        srv.recv_frame(
-            frames::headers(1)
-                .request("CONNECT", "http://bread/baguette")
-                .protocol("the-bread-protocol")
+    let h2 = async move {
+        let (_client, conn) = client::handshake(io).await.unwrap();
+        assert!(conn.effective_settings().local().is_push_enabled());
+        assert!(conn.effective_settings().remote().is_push_enabled());
+        conn.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn effective_settings_reflect_acked_values() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+    let (remote_tx, remote_rx) = futures::channel::oneshot::channel();
+    let (local_tx, local_rx) = futures::channel::oneshot::channel();
+
+    let srv = async move {
+        srv.send(
+            frames::settings()
+                .max_concurrent_streams(10)
+                .initial_window_size(2_000)
+                .into(),
+        )
+        .await
+        .unwrap();
+        srv.read_preface().await.unwrap();
+
+        let settings = assert_settings!(srv.next().await.expect("unexpected EOF").unwrap());
+        assert_eq!(settings.initial_window_size(), Some(1_000));
+
+        // the client ACKs our settings, but we hold back the ACK of its own
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.ping_pong([1; 8]).await;
+        remote_tx.send(()).unwrap();
+
+        srv.send_frame(frames::settings_ack()).await;
+        srv.ping_pong([2; 8]).await;
+        local_tx.send(()).unwrap();
+    };
+
+    let h2 = async move {
+        let (_client, mut conn) = client::Builder::new()
+            .initial_window_size(1_000)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+
+        let settings = conn.effective_settings();
+        assert_eq!(settings.local().initial_window_size(), 65_535);
+        assert_eq!(settings.remote().initial_window_size(), 65_535);
+        assert_eq!(settings.remote().max_concurrent_streams(), None);
+
+        conn.drive(remote_rx).await.unwrap();
+
+        let settings = conn.effective_settings();
+        assert_eq!(settings.local().initial_window_size(), 65_535);
+        assert_eq!(settings.remote().initial_window_size(), 2_000);
+        assert_eq!(settings.remote().max_concurrent_streams(), Some(10));
+        assert_eq!(settings.remote().max_frame_size(), 16_384);
+
+        conn.drive(local_rx).await.unwrap();
+
+        let settings = conn.effective_settings();
+        assert_eq!(settings.local().initial_window_size(), 1_000);
+        assert_eq!(settings.remote().initial_window_size(), 2_000);
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn settings_not_acked_in_time_is_settings_timeout() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        srv.send_frame(frames::settings()).await;
+        srv.read_preface().await.unwrap();
+        srv.recv_frame(frames::settings()).await;
+        srv.recv_frame(frames::settings_ack()).await;
+
+        // never ACK the client's SETTINGS, then poke the connection once the
+        // timeout has passed
+        idle_ms(20).await;
+        srv.send_frame(frames::ping([1; 8])).await;
+        srv.recv_frame(frames::go_away(0).reason(Reason::SETTINGS_TIMEOUT))
+            .await;
+        srv.recv_eof().await;
+    };
+
+    let h2 = async move {
+        let (_client, conn) = client::Builder::new()
+            .settings_ack_timeout(Duration::from_millis(10))
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+
+        let err = conn.await.expect_err("connection");
+        assert_eq!(err.reason(), Some(Reason::SETTINGS_TIMEOUT));
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn settings_not_acked_by_silent_peer_is_settings_timeout() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        srv.send_frame(frames::settings()).await;
+        srv.read_preface().await.unwrap();
+        srv.recv_frame(frames::settings()).await;
+        srv.recv_frame(frames::settings_ack()).await;
+
+        // never ACK the client's SETTINGS, and send nothing else
+        srv.recv_frame(frames::go_away(0).reason(Reason::SETTINGS_TIMEOUT))
+            .await;
+        srv.recv_eof().await;
+    };
+
+    let h2 = async move {
+        let (_client, conn) = client::Builder::new()
+            .settings_ack_timeout(Duration::from_secs(5))
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+
+        let err = conn.await.expect_err("connection");
+        assert_eq!(err.reason(), Some(Reason::SETTINGS_TIMEOUT));
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn extended_connect_request() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().enable_connect_protocol(1))
+            .await;
+        assert_default_settings!(settings);
+
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "http://bread/baguette")
                 .eos(),
         )
         .await;
         srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("CONNECT", "http://bread/baguette")
+                .protocol("the-bread-protocol")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200).eos()).await;
     };
 
     let h2 = async move {
         let (mut client, mut h2) = client::handshake(io).await.unwrap();
 
+        // :protocol may only be sent once the server settings are received
+        let request = Request::get("http://bread/baguette").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        h2.drive(response).await.unwrap();
+
         let request = Request::connect("http://bread/baguette")
             .extension(Protocol::from("the-bread-protocol"))
             .body(())
@@ -1542,6 +2059,60 @@ async fn extended_connect_request() {
     join(srv, h2).await;
 }
 
+#[tokio::test]
+async fn extended_connect_request_without_setting_is_rejected() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "http://bread/baguette")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "http://bread/baguette")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+
+        // drive the connection so we receive the server settings
+        let request = Request::get("http://bread/baguette").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        h2.drive(response).await.unwrap();
+        assert!(!client.is_extended_connect_protocol_enabled());
+
+        let request = Request::connect("http://bread/baguette")
+            .extension(Protocol::from("the-bread-protocol"))
+            .body(())
+            .unwrap();
+        let err = client.send_request(request, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "user error: sending :protocol to peer who has not enabled extended CONNECT"
+        );
+
+        // no stream was opened, so the next request uses stream 3
+        let request = Request::get("http://bread/baguette").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        h2.drive(response).await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
 #[tokio::test]
 async fn rogue_server_odd_headers() {
     h2_support::trace_init!();
@@ -1661,6 +2232,93 @@ async fn client_builder_header_table_size() {
     join(srv, h2).await;
 }
 
+#[tokio::test]
+async fn recv_response_without_status() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        // the initial response HEADERS lacks `:status`
+        srv.send_frame(frames::headers(1).field("content-length", "0"))
+            .await;
+        srv.recv_frame(frames::reset(1).protocol_error()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+
+        let (response, _) = client.send_request(request, true).unwrap();
+        let err = conn.drive(response).await.unwrap_err();
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+
+        drop(client);
+        conn.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn recv_altsvc_frames() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::altsvc(0, "https://example.com", "h2=\":8000\""))
+            .await;
+        srv.send_frame(frames::altsvc(1, "", "h2=\"alt.example.com:443\""))
+            .await;
+        // stream 0 requires an origin, other streams must not carry one;
+        // invalid frames are ignored
+        srv.send_frame(frames::altsvc(0, "", "clear")).await;
+        srv.send_frame(frames::altsvc(1, "https://example.com", "clear"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::get("https://example.com/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let resp = h2.drive(response).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let alt_svc = h2.alt_svc().expect("connection alt-svc");
+        assert_eq!(alt_svc.origin(), Some(&b"https://example.com"[..]));
+        assert_eq!(alt_svc.stream_id(), None);
+        assert_eq!(alt_svc.value(), b"h2=\":8000\"");
+
+        let alt_svc = h2.alt_svc().expect("stream alt-svc");
+        assert_eq!(alt_svc.origin(), None);
+        assert_eq!(alt_svc.stream_id().map(|id| id.as_u32()), Some(1));
+        assert_eq!(alt_svc.value(), b"h2=\"alt.example.com:443\"");
+
+        assert!(h2.alt_svc().is_none());
+    };
+
+    join(srv, h2).await;
+}
+
 const SETTINGS: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
 const SETTINGS_ACK: &[u8] = &[0, 0, 0, 4, 1, 0, 0, 0, 0];
 
@@ -1677,3 +2335,130 @@ impl MockH2 for mock_io::Builder {
             .read(SETTINGS_ACK)
     }
 }
+
+/// Resets a request with `reason`, returning the error the client surfaces.
+async fn recv_reset_error(reason: Reason) -> h2::Error {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::reset(1).reason(reason)).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut conn) = client::handshake(io).await.expect("handshake");
+        let request = Request::builder()
+            .uri("https://example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, _) = client.send_request(request, true).unwrap();
+        let err = conn.drive(response).await.expect_err("response");
+        drop(client);
+        conn.await.expect("client");
+        err
+    };
+
+    join(srv, h2).await.1
+}
+
+#[tokio::test]
+async fn recv_reset_surfaces_stream_error() {
+    for reason in [
+        Reason::PROTOCOL_ERROR,
+        Reason::INTERNAL_ERROR,
+        Reason::FLOW_CONTROL_ERROR,
+        Reason::STREAM_CLOSED,
+        Reason::REFUSED_STREAM,
+        Reason::CANCEL,
+        Reason::ENHANCE_YOUR_CALM,
+        Reason::INADEQUATE_SECURITY,
+        Reason::HTTP_1_1_REQUIRED,
+        Reason::from(0xff),
+    ] {
+        let err = recv_reset_error(reason).await;
+        let stream_error = err.stream_error().expect("stream error");
+        assert_eq!(stream_error.reason(), reason);
+        assert_eq!(stream_error.stream_id().as_u32(), 1);
+        assert_eq!(stream_error.to_string(), err.to_string());
+    }
+}
+
+#[tokio::test]
+async fn recv_reset_surfaces_stream_error_in_body() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::reset(1).cancel()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, conn) = client::handshake(io).await.expect("handshake");
+        let request = Request::builder()
+            .uri("https://example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, _) = client.send_request(request, true).unwrap();
+        let body = async move {
+            let mut body = response.await.unwrap().into_body();
+            let err = body.data().await.unwrap().expect_err("data");
+            assert_eq!(err.stream_error().unwrap().reason(), Reason::CANCEL);
+        };
+        join(async move { conn.await.expect("client") }, body).await;
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn send_reset_has_no_stream_error() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        // The reset is sent in place of the request headers.
+        srv.recv_frame(frames::reset(1).cancel()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut conn) = client::handshake(io).await.expect("handshake");
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+        stream.send_reset(Reason::CANCEL);
+
+        let err = conn.drive(response).await.expect_err("response");
+        assert_eq!(err.reason(), Some(Reason::CANCEL));
+        assert!(err.stream_error().is_none());
+
+        drop(client);
+        conn.await.expect("client");
+    };
+
+    join(srv, h2).await;
+}
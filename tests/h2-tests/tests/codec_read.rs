@@ -1,5 +1,7 @@
 use futures::future::join;
+use h2::StateObserver;
 use h2_support::prelude::*;
+use std::sync::{Arc, Mutex};
 
 #[tokio::test]
 async fn read_none() {
@@ -82,6 +84,39 @@ async fn read_data_padding() {
     assert_closed!(codec);
 }
 
+#[tokio::test]
+async fn read_data_padding_fills_payload() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 6, 0, 0x8, 0, 0, 0, 1,
+            5,            // Pad length, equal to the remaining payload
+            "\0\0\0\0\0", // Padding
+        ];
+    };
+
+    let data = poll_frame!(Data, codec);
+    assert_eq!(data.stream_id(), 1);
+    assert_eq!(data.payload(), &b""[..]);
+
+    assert_closed!(codec);
+}
+
+#[tokio::test]
+async fn read_data_padding_too_long() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 6, 0, 0x8, 0, 0, 0, 1,
+            6,            // Pad length, longer than the remaining payload
+            "\0\0\0\0\0", // Padding
+        ];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::PROTOCOL_ERROR),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
 #[tokio::test]
 async fn read_push_promise() {
     let mut codec = raw_codec! {
@@ -114,6 +149,206 @@ async fn read_data_stream_id_zero() {
     poll_err!(codec);
 }
 
+// ===== STREAM 0 =====
+
+async fn read_invalid_stream_id(frame: &[u8]) {
+    let mut codec = raw_codec! {
+        read => [frame,];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::PROTOCOL_ERROR),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn read_headers_stream_id_zero() {
+    read_invalid_stream_id(&[0, 0, 1, 1, 0x5, 0, 0, 0, 0, 0x82]).await;
+}
+
+#[tokio::test]
+async fn read_priority_stream_id_zero() {
+    read_invalid_stream_id(&[0, 0, 5, 2, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]).await;
+}
+
+#[tokio::test]
+async fn read_reset_stream_id_zero() {
+    read_invalid_stream_id(&[0, 0, 4, 3, 0, 0, 0, 0, 0, 0, 0, 0, 8]).await;
+}
+
+#[tokio::test]
+async fn read_goaway_stream_id_non_zero() {
+    read_invalid_stream_id(&[0, 0, 8, 7, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]).await;
+}
+
+#[tokio::test]
+async fn read_window_update_stream_id_zero() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 4, 8, 0, 0, 0, 0, 0,
+            0, 0, 0x40, 0,
+        ];
+    };
+
+    let frame = poll_frame!(WindowUpdate, codec);
+    assert!(frame.stream_id().is_zero());
+    assert_eq!(frame.size_increment(), 16_384);
+
+    assert_closed!(codec);
+}
+
+// ===== UNKNOWN =====
+
+#[derive(Debug, Default)]
+struct UnknownFrames(Mutex<Vec<(u8, u32, usize)>>);
+
+impl StateObserver for UnknownFrames {
+    fn on_unknown_frame(&self, frame_type: u8, stream_id: h2::StreamId, len: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((frame_type, stream_id.as_u32(), len));
+    }
+}
+
+#[tokio::test]
+async fn read_unknown_frames_skipped_and_observed() {
+    let mut codec = raw_codec! {
+        read => [
+            // unknown type on the connection stream
+            0, 0, 3, 0xB, 0, 0, 0, 0, 0,
+            1, 2, 3,
+            // unknown type with flags on a stream that was never opened
+            0, 0, 0, 0xFF, 0xFF, 0, 0, 0, 7,
+            0, 0, 4, 8, 0, 0, 0, 0, 0,
+            0, 0, 0x40, 0,
+        ];
+    };
+
+    let observer = Arc::new(UnknownFrames::default());
+    codec.set_unknown_frame_observer(Some(observer.clone()));
+
+    let frame = poll_frame!(WindowUpdate, codec);
+    assert_eq!(frame.size_increment(), 16_384);
+
+    assert_closed!(codec);
+
+    assert_eq!(*observer.0.lock().unwrap(), [(0xB, 0, 3), (0xFF, 7, 0)]);
+}
+
+// ===== SETTINGS =====
+
+#[tokio::test]
+async fn read_settings_unknown_identifier() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 12, 4, 0, 0, 0, 0, 0,
+            // unknown identifier
+            0, 0xFF, 0, 0, 0, 7,
+            // SETTINGS_ENABLE_PUSH
+            0, 2, 0, 0, 0, 0,
+        ];
+    };
+
+    let settings = poll_frame!(Settings, codec);
+    assert_eq!(settings.is_push_enabled(), Some(false));
+
+    assert_closed!(codec);
+}
+
+#[tokio::test]
+async fn read_settings_enable_push_out_of_range() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 6, 4, 0, 0, 0, 0, 0,
+            0, 2, 0, 0, 0, 2,
+        ];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::PROTOCOL_ERROR),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn read_settings_initial_window_size_out_of_range() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 6, 4, 0, 0, 0, 0, 0,
+            0, 4, 0x80, 0, 0, 0,
+        ];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => {
+            assert_eq!(reason, Reason::FLOW_CONTROL_ERROR)
+        }
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
+fn settings_max_frame_size(val: u32) -> Codec<mock_io::Mock> {
+    let [b0, b1, b2, b3] = val.to_be_bytes();
+    raw_codec! {
+        read => [
+            0, 0, 6, 4, 0, 0, 0, 0, 0,
+            0, 5, b0, b1, b2, b3,
+        ];
+    }
+}
+
+async fn read_settings_max_frame_size_out_of_range(val: u32) {
+    let mut codec = settings_max_frame_size(val);
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::PROTOCOL_ERROR),
+        e => panic!("unexpected error; val={}; err={:?}", val, e),
+    }
+}
+
+#[tokio::test]
+async fn read_settings_max_frame_size_below_range() {
+    read_settings_max_frame_size_out_of_range(0).await;
+    read_settings_max_frame_size_out_of_range(16_383).await;
+}
+
+#[tokio::test]
+async fn read_settings_max_frame_size_above_range() {
+    read_settings_max_frame_size_out_of_range(1 << 24).await;
+    read_settings_max_frame_size_out_of_range(u32::MAX).await;
+}
+
+#[tokio::test]
+async fn read_settings_max_frame_size_within_range() {
+    for val in [16_384, 65_535, (1 << 24) - 1] {
+        let mut codec = settings_max_frame_size(val);
+
+        let settings = poll_frame!(Settings, codec);
+        assert_eq!(settings.max_frame_size(), Some(val));
+
+        assert_closed!(codec);
+    }
+}
+
+// ===== PING =====
+
+#[tokio::test]
+async fn read_ping_stream_id_non_zero() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 8, 6, 0, 0, 0, 0, 3,
+            0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::PROTOCOL_ERROR),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
 // ===== HEADERS =====
 
 #[test]
@@ -128,6 +363,117 @@ fn read_headers_with_pseudo() {}
 #[ignore]
 fn read_headers_empty_payload() {}
 
+#[tokio::test]
+async fn read_continuation_frames_within_header_block_size() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 40, 1, 0, 0, 0, 0, 1,
+            vec![0x90; 40], // HEADERS, accept-encoding
+            0, 0, 20, 9, 4, 0, 0, 0, 1,
+            vec![0x90; 20], // CONTINUATION, END_HEADERS
+        ];
+    };
+
+    codec.set_max_recv_header_block_size(64);
+
+    let headers = poll_frame!(Headers, codec);
+    assert_eq!(
+        headers.fields().get_all("accept-encoding").iter().count(),
+        60
+    );
+
+    assert_closed!(codec);
+}
+
+#[tokio::test]
+async fn read_continuation_frames_keep_end_stream_of_headers() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 2, 1, 1, 0, 0, 0, 1,
+            0x82, 0x84, // HEADERS, END_STREAM
+            0, 0, 2, 9, 4, 0, 0, 0, 1,
+            0x87, 0x90, // CONTINUATION, END_HEADERS
+        ];
+    };
+
+    let headers = poll_frame!(Headers, codec);
+    assert!(headers.is_end_headers());
+    assert!(headers.is_end_stream());
+    assert_eq!(
+        headers.fields().get("accept-encoding").unwrap(),
+        "gzip, deflate"
+    );
+
+    assert_closed!(codec);
+}
+
+#[tokio::test]
+async fn read_headers_size_update_at_start_of_block() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 3, 1, 5, 0, 0, 0, 1,
+            0x20, 0x82, 0x84, // HEADERS, size update to 0, then headers
+        ];
+    };
+
+    let headers = poll_frame!(Headers, codec);
+    assert!(headers.is_end_headers());
+
+    assert_closed!(codec);
+}
+
+async fn read_misplaced_size_update(frames: &[u8]) {
+    let mut codec = raw_codec! {
+        read => [frames,];
+    };
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::COMPRESSION_ERROR),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn read_headers_size_update_after_header() {
+    read_misplaced_size_update(&[
+        0, 0, 2, 1, 5, 0, 0, 0, 1, //
+        0x82, 0x20, // HEADERS, size update after :method
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn read_continuation_size_update_after_header() {
+    read_misplaced_size_update(&[
+        0, 0, 1, 1, 1, 0, 0, 0, 1,    //
+        0x82, // HEADERS, END_STREAM
+        0, 0, 1, 9, 4, 0, 0, 0, 1,    //
+        0x20, // CONTINUATION, END_HEADERS, size update
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn read_continuation_frames_over_header_block_size() {
+    let mut codec = raw_codec! {
+        read => [
+            0, 0, 40, 1, 0, 0, 0, 0, 1,
+            vec![0x90; 40], // HEADERS, accept-encoding
+            0, 0, 20, 9, 0, 0, 0, 0, 1,
+            vec![0x90; 20], // CONTINUATION
+            0, 0, 20, 9, 4, 0, 0, 0, 1,
+            vec![0x90; 20], // CONTINUATION, END_HEADERS
+        ];
+    };
+
+    codec.set_max_recv_header_block_size(64);
+
+    match poll_err!(codec) {
+        h2::proto::Error::GoAway(_, reason, _) => assert_eq!(reason, Reason::ENHANCE_YOUR_CALM),
+        e => panic!("unexpected error; err={:?}", e),
+    }
+}
+
 #[tokio::test]
 async fn read_continuation_frames() {
     h2_support::trace_init!();
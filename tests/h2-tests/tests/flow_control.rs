@@ -2,6 +2,7 @@ use futures::future::{join, join4};
 use futures::{StreamExt, TryStreamExt};
 use h2_support::prelude::*;
 use h2_support::util::yield_once;
+use std::cmp;
 
 // In this case, the stream & connection both have capacity, but capacity is not
 // explicitly requested.
@@ -219,6 +220,7 @@ async fn recv_data_overflows_connection_window() {
                 err.to_string(),
                 "connection error detected: flow-control protocol violated"
             );
+            assert_eq!(err.stream_id(), None);
         };
 
         // client should see a flow control error
@@ -280,6 +282,7 @@ async fn recv_data_overflows_stream_window() {
                 err.to_string(),
                 "stream error detected: flow-control protocol violated"
             );
+            assert_eq!(err.stream_id().map(|id| id.as_u32()), Some(1));
         };
 
         join(async move { conn.await.unwrap() }, req).await;
@@ -287,242 +290,368 @@ async fn recv_data_overflows_stream_window() {
     join(mock, h2).await;
 }
 
-#[test]
-#[ignore]
-fn recv_window_update_causes_overflow() {
-    // A received window update causes the window to overflow.
-}
-
 #[tokio::test]
-async fn stream_error_release_connection_capacity() {
+async fn recv_data_exactly_filling_stream_window() {
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
         srv.recv_frame(
             frames::headers(1)
                 .request("GET", "https://http2.akamai.com/")
                 .eos(),
         )
         .await;
-        // we're sending the wrong content-length
-        srv.send_frame(
-            frames::headers(1)
-                .response(200)
-                .field("content-length", &*(16_384 * 3).to_string()),
-        )
-        .await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 10]).eos()).await;
-        // mismatched content-length is a protocol error
-        srv.recv_frame(frames::reset(1).protocol_error()).await;
-        // but then the capacity should be released automatically
-        srv.recv_frame(frames::window_update(0, 16_384 * 2 + 10))
-            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        // exactly the whole window is accepted
+        srv.send_frame(frames::data(1, vec![0u8; 16_384])).await;
+        // and once released, it is reopened in full
+        srv.recv_frame(frames::window_update(1, 16_384)).await;
+        srv.send_frame(frames::data(1, vec![0u8; 16_384])).await;
+        // but a single byte more overflows it
+        srv.send_frame(frames::data(1, &[0; 1][..]).eos()).await;
+        // the rejected byte and the released data are returned to the connection
+        srv.recv_frame(frames::window_update(0, 16_385)).await;
+        srv.recv_frame(frames::reset(1).flow_control()).await;
     };
 
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(16_384)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
         let request = Request::builder()
+            .method(Method::GET)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let req = async {
-            let resp = client
-                .send_request(request, true)
-                .unwrap()
-                .0
-                .await
-                .expect("response");
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
             assert_eq!(resp.status(), StatusCode::OK);
             let mut body = resp.into_parts().1;
-            let mut cap = body.flow_control().clone();
-            let to_release = 16_384 * 2;
-            let mut should_recv_bytes = to_release;
-            let mut should_recv_frames = 2usize;
 
-            let err = body
-                .try_for_each(|bytes| async move {
-                    should_recv_bytes -= bytes.len();
-                    should_recv_frames -= 1;
-                    if should_recv_bytes == 0 {
-                        assert_eq!(should_recv_frames, 0);
-                    }
-                    Ok(())
-                })
-                .await
-                .expect_err("body");
-            assert_eq!(
-                err.to_string(),
-                "stream error detected: unspecific protocol error detected"
-            );
-            cap.release_capacity(to_release).expect("release_capacity");
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 16_384);
+            body.flow_control().release_capacity(buf.len()).unwrap();
+
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 16_384);
+
+            let err = body.data().await.unwrap().unwrap_err();
+            assert_eq!(err.reason(), Some(Reason::FLOW_CONTROL_ERROR));
         };
-        conn.drive(req).await;
-        conn.await.expect("client");
-    };
 
-    join(srv, client).await;
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn stream_close_by_data_frame_releases_capacity() {
+async fn recv_data_error_carries_stream_id() {
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
 
-    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE as usize;
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::headers(3).response(200)).await;
+        srv.send_frame(frames::data(1, vec![0u8; 1_024]).eos())
+            .await;
+        // only stream 3 overflows its window
+        srv.send_frame(frames::data(3, vec![0u8; 1_025]).eos())
+            .await;
+        srv.recv_frame(frames::reset(3).flow_control()).await;
+    };
 
     let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://http2.akamai.com/")
-            .body(())
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(1_024)
+            .handshake::<_, Bytes>(io)
+            .await
             .unwrap();
+        let request = || {
+            Request::builder()
+                .method(Method::GET)
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap()
+        };
 
-        // Send request
-        let (resp1, mut s1) = client.send_request(request, false).unwrap();
+        let resp1 = client.send_request(request(), true).unwrap().0;
+        let resp3 = client.send_request(request(), true).unwrap().0;
 
-        // This effectively reserves the entire connection window
-        s1.reserve_capacity(window_size);
+        let req = async move {
+            let body = resp1.await.unwrap().into_body();
+            util::concat(body).await.unwrap();
 
-        // The capacity should be immediately available as nothing else is
-        // happening on the stream.
-        assert_eq!(s1.capacity(), window_size);
+            let body = resp3.await.unwrap().into_body();
+            let err = util::concat(body).await.unwrap_err();
+            assert_eq!(err.reason(), Some(Reason::FLOW_CONTROL_ERROR));
+            assert_eq!(err.stream_id().map(|id| id.as_u32()), Some(3));
+        };
 
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://http2.akamai.com/")
-            .body(())
-            .unwrap();
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
+}
 
-        // Create a second stream
-        let (resp2, mut s2) = client.send_request(request, false).unwrap();
+#[tokio::test]
+async fn stream_window_overrun_resets_only_that_stream() {
+    h2_support::trace_init!();
 
-        // Request capacity
-        s2.reserve_capacity(5);
+    let (io, mut srv) = mock::new();
 
-        // There should be no available capacity (as it is being held up by
-        // the previous stream
-        assert_eq!(s2.capacity(), 0);
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::headers(3).response(200)).await;
+        srv.send_frame(frames::data(3, vec![0u8; 1_025]).eos())
+            .await;
+        srv.recv_frame(frames::reset(3).flow_control()).await;
+        // the connection survives, and stream 1 still completes
+        srv.send_frame(frames::data(1, vec![0u8; 1_024]).eos())
+            .await;
+        srv.recv_frame(frames::go_away(0)).await;
+        srv.recv_eof().await;
+    };
 
-        // Closing the previous stream by sending an empty data frame will
-        // release the capacity to s2
-        s1.send_data("".into(), true).unwrap();
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(1_024)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = || {
+            Request::builder()
+                .method(Method::GET)
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap()
+        };
 
-        // The capacity should be available
-        assert_eq!(s2.capacity(), 5);
+        let resp1 = client.send_request(request(), true).unwrap().0;
+        let resp3 = client.send_request(request(), true).unwrap().0;
 
-        // Send the frame
-        s2.send_data("hello".into(), true).unwrap();
+        let req = async move {
+            let body = resp3.await.unwrap().into_body();
+            let err = util::concat(body).await.unwrap_err();
+            assert!(err.is_flow_control_violation());
+            assert_eq!(err.scope(), Some(h2::ErrorScope::Stream));
 
-        // Drive both streams to prevent the handles from being dropped
-        // (which will send a RST_STREAM) before the connection is closed.
-        h2.drive(resp1).await.unwrap();
-        h2.drive(resp2).await.unwrap();
-    };
+            let body = resp1.await.unwrap().into_body();
+            assert_eq!(util::concat(body).await.unwrap().len(), 1_024);
+            drop(client);
+        };
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
-            .await;
-        srv.send_frame(frames::headers(3).response(200)).await;
-        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
-        srv.recv_frame(frames::data(3, &b"hello"[..]).eos()).await;
+        join(async move { conn.await.unwrap() }, req).await;
     };
-    join(srv, h2).await;
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn stream_close_by_trailers_frame_releases_capacity() {
+async fn stream_window_overrun_closes_connection_when_configured() {
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
 
-    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE as usize;
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![0u8; 1_025]).eos())
+            .await;
+        srv.recv_frame(frames::go_away(0).flow_control()).await;
+        srv.recv_eof().await;
+    };
 
     let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(1_024)
+            .reset_stream_on_window_overrun(false)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
         let request = Request::builder()
-            .method(Method::POST)
+            .method(Method::GET)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        // Send request
-        let (resp1, mut s1) = client.send_request(request, false).unwrap();
-
-        // This effectively reserves the entire connection window
-        s1.reserve_capacity(window_size);
+        let resp = client.send_request(request, true).unwrap().0;
 
-        // The capacity should be immediately available as nothing else is
-        // happening on the stream.
-        assert_eq!(s1.capacity(), window_size);
+        let conn = async move {
+            let err = conn.await.unwrap_err();
+            assert!(err.is_flow_control_violation());
+            assert_eq!(err.scope(), Some(h2::ErrorScope::Connection));
+        };
+        let req = async move {
+            let body = resp.await.unwrap().into_body();
+            let err = util::concat(body).await.unwrap_err();
+            assert!(err.is_flow_control_violation());
+        };
 
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://http2.akamai.com/")
-            .body(())
-            .unwrap();
+        join(conn, req).await;
+    };
+    join(mock, h2).await;
+}
 
-        // Create a second stream
-        let (resp2, mut s2) = client.send_request(request, false).unwrap();
+#[tokio::test]
+async fn recv_data_pauses_until_capacity_released() {
+    // a constrained stream window means the peer must stop sending until the
+    // application hands capacity back
+    h2_support::trace_init!();
 
-        // Request capacity
-        s2.reserve_capacity(5);
+    let (io, mut srv) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
 
-        // There should be no available capacity (as it is being held up by
-        // the previous stream
-        assert_eq!(s2.capacity(), 0);
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        // fill the whole window
+        srv.send_frame(frames::data(1, &[0; 16][..])).await;
+        // the window stays shut while the data is held by the application
+        srv.ping_pong([1; 8]).await;
+        tx.send(()).unwrap();
+        srv.recv_frame(frames::window_update(1, 16)).await;
+        srv.send_frame(frames::data(1, &[0; 16][..]).eos()).await;
+    };
 
-        // Closing the previous stream by sending a trailers frame will
-        // release the capacity to s2
-        s1.send_trailers(Default::default()).unwrap();
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(16)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-        // The capacity should be available
-        assert_eq!(s2.capacity(), 5);
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let mut body = resp.into_parts().1;
 
-        // Send the frame
-        s2.send_data("hello".into(), true).unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 16);
+            assert_eq!(body.flow_control().available_capacity(), 0);
 
-        // Drive both streams to prevent the handles from being dropped
-        // (which will send a RST_STREAM) before the connection is closed.
-        h2.drive(resp1).await.unwrap();
-        h2.drive(resp2).await.unwrap();
+            rx.await.unwrap();
+            body.flow_control().release_capacity(buf.len()).unwrap();
+
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 16);
+            assert!(body.data().await.is_none());
+        };
+
+        join(async move { conn.await.unwrap() }, req).await;
     };
+    join(mock, h2).await;
+}
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        // Get the first frame
+#[tokio::test]
+async fn reading_data_without_release_sends_no_window_update() {
+    h2_support::trace_init!();
+
+    let payload = vec![0u8; 16_384];
+    let (io, mut client) = mock::new();
+    let (read_tx, read_rx) = futures::channel::oneshot::channel();
+    let (release_tx, release_rx) = futures::channel::oneshot::channel();
+
+    let mock = async move {
+        let settings = client.assert_server_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
             .await;
-        srv.send_frame(frames::headers(3).response(200)).await;
-        srv.recv_frame(frames::headers(1).eos()).await;
-        srv.recv_frame(frames::data(3, &b"hello"[..]).eos()).await;
+        client.send_frame(frames::data(1, &payload[..])).await;
+        client.send_frame(frames::data(1, &payload[..])).await;
+        // Both frames have been read, but nothing has been released yet.
+        read_rx.await.unwrap();
+        client.ping_pong([1; 8]).await;
+        release_tx.send(()).unwrap();
+        client.recv_frame(frames::window_update(0, 32_768)).await;
+        client.recv_frame(frames::window_update(1, 32_768)).await;
     };
-    join(srv, h2).await;
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, _respond) = srv.next().await.unwrap().unwrap();
+        let mut body = req.into_body();
+
+        let read = async move {
+            for _ in 0..2 {
+                let buf = body.data().await.unwrap().unwrap();
+                assert_eq!(buf.len(), 16_384);
+            }
+            assert_eq!(body.flow_control().used_capacity(), 32_768);
+            read_tx.send(()).unwrap();
+
+            release_rx.await.unwrap();
+            body.flow_control().release_capacity(32_768).unwrap();
+            assert_eq!(body.flow_control().used_capacity(), 0);
+        };
+
+        join(async move { assert!(srv.next().await.is_none()) }, read).await;
+    };
+
+    join(mock, srv).await;
 }
 
 #[tokio::test]
-async fn stream_close_by_send_reset_frame_releases_capacity() {
+async fn recv_buffer_full_holds_window_update() {
+    // released capacity is not advertised while the application has more
+    // unread data buffered than allowed
     h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
+    let (io, mut srv) = mock::new();
+    let (buffered_tx, buffered_rx) = futures::channel::oneshot::channel();
+    let (released_tx, released_rx) = futures::channel::oneshot::channel();
+    let (read_tx, read_rx) = futures::channel::oneshot::channel();
 
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
         srv.recv_frame(
             frames::headers(1)
                 .request("GET", "https://http2.akamai.com/")
@@ -530,548 +659,687 @@ async fn stream_close_by_send_reset_frame_releases_capacity() {
         )
         .await;
         srv.send_frame(frames::headers(1).response(200)).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384]).eos()).await;
-        srv.recv_frame(frames::window_update(0, 16_384 * 2)).await;
-        srv.recv_frame(
-            frames::headers(3)
-                .request("GET", "https://http2.akamai.com/")
-                .eos(),
-        )
-        .await;
-        srv.send_frame(frames::headers(3).response(200).eos()).await;
+        srv.send_frame(frames::data(1, &[0; 20][..])).await;
+        srv.ping_pong([1; 8]).await;
+        buffered_tx.send(()).unwrap();
+
+        // the capacity is released, but nothing is sent while it is buffered
+        released_rx.await.unwrap();
+        srv.ping_pong([2; 8]).await;
+        srv.ping_pong([3; 8]).await;
+        read_tx.send(()).unwrap();
+
+        // reading the data resumes the update
+        srv.recv_frame(frames::window_update(1, 20)).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
     };
 
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.expect("client handshake");
-        {
-            let request = Request::builder()
-                .uri("https://http2.akamai.com/")
-                .body(())
-                .unwrap();
-            let (resp, _) = client.send_request(request, true).unwrap();
-            let _res = conn.drive(resp).await;
-            //    ^-- ignore the response body
-        }
-        let resp = {
-            let request = Request::builder()
-                .uri("https://http2.akamai.com/")
-                .body(())
-                .unwrap();
-            let (resp, _) = client.send_request(request, true).unwrap();
-            drop(client);
-            resp
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(32)
+            .max_recv_buffer_size(16)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let mut body = resp.into_parts().1;
+
+            buffered_rx.await.unwrap();
+            body.flow_control().release_capacity(20).unwrap();
+            released_tx.send(()).unwrap();
+
+            read_rx.await.unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 20);
+
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
         };
-        let _res = conn.drive(resp).await;
-        conn.await.expect("client conn");
-    };
 
-    join(srv, client).await;
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
 }
 
-#[test]
-#[ignore]
-fn stream_close_by_recv_reset_frame_releases_capacity() {}
-
 #[tokio::test]
-async fn recv_window_update_on_stream_closed_by_data_frame() {
+async fn balanced_window_updates_favor_starved_stream() {
+    // the connection window is plentiful, so capacity released on a small
+    // stream window is advertised right away
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
 
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, &[0; 60][..])).await;
+        // only the stream is updated, well before half its window is used
+        srv.recv_frame(frames::window_update(1, 10)).await;
+        srv.ping_pong([1; 8]).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
+    };
+
     let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(100)
+            .balance_window_updates(true)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
         let request = Request::builder()
-            .method(Method::POST)
+            .method(Method::GET)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
-
-        // Wait for the response
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
 
-        // Send a data frame, this will also close the connection
-        stream.send_data("hello".into(), true).unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 60);
+            body.flow_control().release_capacity(10).unwrap();
 
-        // keep `stream` from being dropped in order to prevent
-        // it from sending an RST_STREAM frame.
-        //
-        // i know this is kind of evil, but it's necessary to
-        // ensure that the stream is closed by the EOS frame,
-        // and not by the RST_STREAM.
-        std::mem::forget(stream);
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
+        };
 
-        // Wait for the connection to close
-        h2.await.unwrap();
-    };
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::data(1, "hello").eos()).await;
-        srv.send_frame(frames::window_update(1, 5)).await;
+        join(async move { conn.await.unwrap() }, req).await;
     };
-    join(srv, h2).await;
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn reserved_capacity_assigned_in_multi_window_updates() {
+async fn balanced_window_updates_favor_starved_connection() {
+    // the stream window is larger than the connection window, so capacity
+    // released on the connection is advertised right away
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
 
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, &[0; 1_000][..])).await;
+        // only the connection is updated
+        srv.recv_frame(frames::window_update(0, 1_000)).await;
+        srv.ping_pong([1; 8]).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
+    };
+
     let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(1_000_000)
+            .balance_window_updates(true)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
         let request = Request::builder()
-            .method(Method::POST)
+            .method(Method::GET)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
 
-        // Consume the capacity
-        let payload = vec![0; frame::DEFAULT_INITIAL_WINDOW_SIZE as usize];
-        stream.send_data(payload.into(), false).unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 1_000);
+            body.flow_control().release_capacity(1_000).unwrap();
 
-        // Reserve more data than we want
-        stream.reserve_capacity(10);
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
+        };
 
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
-        stream.send_data("hello".into(), false).unwrap();
-        stream.send_data("world".into(), true).unwrap();
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
+}
 
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+#[tokio::test]
+async fn idle_connection_flushes_window_updates_below_threshold() {
+    h2_support::trace_init!();
 
-        // Wait for the connection to close
-        h2.await.unwrap();
+    let (io, mut srv) = mock::new();
+
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, &[0; 100][..])).await;
+        // far below half of either window, but nothing else is queued
+        srv.recv_frame(frames::window_update(0, 100)).await;
+        srv.recv_frame(frames::window_update(1, 100)).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
     };
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0u8; 16_383])).await;
-        idle_ms(100).await;
-        // Increase the connection window
-        srv.send_frame(frames::window_update(0, 10)).await;
-        // Incrementally increase the stream window
-        srv.send_frame(frames::window_update(1, 4)).await;
-        idle_ms(50).await;
-        srv.send_frame(frames::window_update(1, 1)).await;
-        // Receive first chunk
-        srv.recv_frame(frames::data(1, "hello")).await;
-        srv.send_frame(frames::window_update(1, 5)).await;
-        // Receive second chunk
-        srv.recv_frame(frames::data(1, "world").eos()).await;
-        srv.send_frame(frames::headers(1).response(204).eos()).await;
-        /*
-        .recv_frame(frames::data(1, "hello").eos())
-        .send_frame(frames::window_update(1, 5))
-        */
-    };
-    join(srv, h2).await;
-}
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .flush_window_updates_when_idle(true)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-#[tokio::test]
-async fn connection_notified_on_released_capacity() {
-    use tokio::sync::{mpsc, oneshot};
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
 
-    h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 100);
+            body.flow_control().release_capacity(100).unwrap();
 
-    // We're going to run the connection on a thread in order to isolate task
-    // notifications. This test is here, in part, to ensure that the connection
-    // receives the appropriate notifications to send out window updates.
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
+        };
 
-    let (tx, mut rx) = mpsc::unbounded_channel();
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
+}
 
-    // Because threading is fun
-    let (settings_tx, settings_rx) = oneshot::channel();
+#[tokio::test]
+async fn goodput_excludes_data_discarded_on_reset() {
+    h2_support::trace_init!();
 
-    let (th1_tx, th1_rx) = oneshot::channel();
+    let (io, mut srv) = mock::new();
+    let (reset_tx, reset_rx) = futures::channel::oneshot::channel();
 
-    tokio::spawn(async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        settings_tx.send(()).unwrap();
-        // Get the first request
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
         srv.recv_frame(
             frames::headers(1)
-                .request("GET", "https://example.com/a")
-                .eos(),
-        )
-        .await;
-        // Get the second request
-        srv.recv_frame(
-            frames::headers(3)
-                .request("GET", "https://example.com/b")
+                .request("GET", "https://http2.akamai.com/")
                 .eos(),
         )
         .await;
-        // Send the first response
         srv.send_frame(frames::headers(1).response(200)).await;
-        // Send the second response
-        srv.send_frame(frames::headers(3).response(200)).await;
-
-        // Fill the connection window
-        srv.send_frame(frames::data(1, vec![0u8; 16_384]).eos())
-            .await;
-        idle_ms(100).await;
-        srv.send_frame(frames::data(3, vec![0u8; 16_384]).eos())
-            .await;
-
-        // The window update is sent
-        srv.recv_frame(frames::window_update(0, 16_384)).await;
-
-        th1_tx.send(()).unwrap();
-    });
-
-    let (th2_tx, th2_rx) = oneshot::channel();
+        srv.send_frame(frames::data(1, "hello")).await;
+        srv.send_frame(frames::data(1, "world")).await;
+        srv.send_frame(frames::reset(1).cancel()).await;
+        srv.ping_pong([1; 8]).await;
+        reset_tx.send(()).unwrap();
+    };
 
-    let (mut client, mut h2) = client::handshake(io).await.unwrap();
+    let h2 = async move {
+        let (mut client, conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-    h2.drive(settings_rx).await.unwrap();
-    let request = Request::get("https://example.com/a").body(()).unwrap();
-    tx.send(client.send_request(request, true).unwrap().0)
-        .unwrap();
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
+            let flow = body.flow_control().clone();
 
-    let request = Request::get("https://example.com/b").body(()).unwrap();
-    tx.send(client.send_request(request, true).unwrap().0)
-        .unwrap();
+            assert_eq!(body.data().await.unwrap().unwrap(), "hello");
 
-    tokio::spawn(async move {
-        // Run the connection to completion
-        h2.await.unwrap();
+            // "world" was received before the reset, but is never read.
+            reset_rx.await.unwrap();
+            assert_eq!(flow.goodput_bytes(), 5);
+            assert_eq!(flow.discarded_bytes(), 0);
 
-        th2_tx.send(()).unwrap();
-        drop(client);
-    });
+            drop(body);
+            assert_eq!(flow.goodput_bytes(), 5);
+            assert_eq!(flow.discarded_bytes(), 5);
+        };
 
-    // Get the two requests
-    let a = rx.recv().await.unwrap();
-    let b = rx.recv().await.unwrap();
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
+}
 
-    // Get the first response
-    let response = a.await.unwrap();
-    assert_eq!(response.status(), StatusCode::OK);
-    let (_, mut a) = response.into_parts();
+#[tokio::test]
+async fn recv_burst_sends_window_update_per_release() {
+    h2_support::trace_init!();
 
-    // Get the next chunk
-    let chunk = a.data().await.unwrap();
-    assert_eq!(16_384, chunk.unwrap().len());
+    let (io, mut srv) = mock::new();
 
-    // Get the second response
-    let response = b.await.unwrap();
-    assert_eq!(response.status(), StatusCode::OK);
-    let (_, mut b) = response.into_parts();
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, &[0; 100][..])).await;
+        srv.recv_frame(frames::window_update(1, 100)).await;
+        srv.send_frame(frames::data(1, &[0; 100][..])).await;
+        srv.recv_frame(frames::window_update(1, 100)).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
+    };
 
-    // Get the next chunk
-    let chunk = b.data().await.unwrap();
-    assert_eq!(16_384, chunk.unwrap().len());
+    let h2 = async move {
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(200)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-    // Wait a bit
-    idle_ms(100).await;
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
 
-    // Release the capacity
-    a.flow_control().release_capacity(16_384).unwrap();
+            for _ in 0..2 {
+                let buf = body.data().await.unwrap().unwrap();
+                assert_eq!(buf.len(), 100);
+                body.flow_control().release_capacity(100).unwrap();
+            }
 
-    th1_rx.await.unwrap();
-    th2_rx.await.unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
+        };
 
-    // Explicitly drop this after the joins so that the capacity doesn't get
-    // implicitly released before.
-    drop(b);
+        join(async move { conn.await.unwrap() }, req).await;
+    };
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn recv_settings_removes_available_capacity() {
+async fn recv_burst_in_batch_sends_single_window_update() {
     h2_support::trace_init!();
+
     let (io, mut srv) = mock::new();
+    let (released_tx, released_rx) = futures::channel::oneshot::channel();
 
-    let mut settings = frame::Settings::default();
-    settings.set_initial_window_size(Some(0));
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, &[0; 100][..])).await;
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake_with_settings(settings).await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        idle_ms(100).await;
-        srv.send_frame(frames::window_update(0, 11)).await;
-        srv.send_frame(frames::window_update(1, 11)).await;
-        srv.recv_frame(frames::data(1, "hello world").eos()).await;
-        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        // the first release is held back by the batch
+        released_rx.await.unwrap();
+        srv.ping_pong([1; 8]).await;
+
+        srv.send_frame(frames::data(1, &[0; 100][..])).await;
+        srv.recv_frame(frames::window_update(1, 200)).await;
+        srv.send_frame(frames::data(1, &[0; 8][..]).eos()).await;
     };
 
     let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let (mut client, conn) = client::Builder::new()
+            .initial_window_size(200)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
         let request = Request::builder()
-            .method(Method::POST)
+            .method(Method::GET)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            let mut body = resp.into_parts().1;
 
-        stream.reserve_capacity(11);
+            body.flow_control().begin_batch();
 
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 11)).await;
-        assert_eq!(stream.capacity(), 11);
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 100);
+            body.flow_control().release_capacity(100).unwrap();
+            released_tx.send(()).unwrap();
 
-        stream.send_data("hello world".into(), true).unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 100);
+            body.flow_control().release_capacity(100).unwrap();
 
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            body.flow_control().end_batch();
 
-        // Wait for the connection to close
-        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
-        h2.await.unwrap();
+            let buf = body.data().await.unwrap().unwrap();
+            assert_eq!(buf.len(), 8);
+            assert!(body.data().await.is_none());
+        };
+
+        join(async move { conn.await.unwrap() }, req).await;
     };
-    join(srv, h2).await;
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn recv_settings_keeps_assigned_capacity() {
+async fn connection_capacity_is_shared_by_weight() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
+    let (drained_tx, drained_rx) = futures::channel::oneshot::channel();
+    let (updated_tx, updated_rx) = futures::channel::oneshot::channel();
 
-    let (sent_settings, sent_settings_rx) = futures::channel::oneshot::channel();
+    let mock = async move {
+        let _ = srv.assert_client_handshake().await;
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        // exhaust the connection window
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_383]).eos()).await;
+        drained_tx.send(()).unwrap();
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+        srv.recv_frame(frames::headers(3).request("POST", "https://www.example.com/"))
             .await;
-        srv.send_frame(frames::settings().initial_window_size(64))
+        srv.recv_frame(frames::headers(5).request("POST", "https://www.example.com/"))
             .await;
-        srv.recv_frame(frames::settings_ack()).await;
-        sent_settings.send(()).unwrap();
-        srv.recv_frame(frames::data(1, "hello world").eos()).await;
-        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        srv.send_frame(frames::window_update(0, 1_000)).await;
+        srv.ping_pong([1; 8]).await;
+        updated_tx.send(()).unwrap();
     };
 
     let h2 = async move {
-        let (mut client, h2) = client::handshake(io).await.unwrap();
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://http2.akamai.com/")
-            .body(())
-            .unwrap();
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("https://www.example.com/")
+                .body(())
+                .unwrap()
+        };
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+        let (_resp1, mut stream1) = client.send_request(request(), false).unwrap();
+        stream1.send_data(vec![0; 65_535].into(), true).unwrap();
+        conn.drive(drained_rx).await.unwrap();
 
-        stream.reserve_capacity(11);
+        let (_resp3, mut stream3) = client.send_request(request(), false).unwrap();
+        let (_resp5, mut stream5) = client.send_request(request(), false).unwrap();
+        stream3.set_weight(48);
+        stream5.set_weight(16);
+        stream3.reserve_capacity(10_000);
+        stream5.reserve_capacity(10_000);
+        assert_eq!(stream3.capacity(), 0);
+        assert_eq!(stream5.capacity(), 0);
 
-        let f = async move {
-            let mut stream = util::wait_for_capacity(stream, 11).await;
-            sent_settings_rx.await.expect("rx");
-            stream.send_data("hello world".into(), true).unwrap();
-            let resp = response.await.expect("response");
-            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
-        };
-        join(async move { h2.await.expect("h2") }, f).await;
+        conn.drive(updated_rx).await.unwrap();
+
+        assert_eq!(stream3.capacity(), 750);
+        assert_eq!(stream5.capacity(), 250);
     };
 
-    join(srv, h2).await;
+    join(mock, h2).await;
+}
+
+#[test]
+#[ignore]
+fn recv_window_update_causes_overflow() {
+    // A received window update causes the window to overflow.
 }
 
 #[tokio::test]
-async fn recv_no_init_window_then_receive_some_init_window() {
+async fn stream_error_release_connection_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
-    let mut settings = frame::Settings::default();
-    settings.set_initial_window_size(Some(0));
-
     let srv = async move {
-        let settings = srv.assert_client_handshake_with_settings(settings).await;
+        let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
-            .await;
-        idle_ms(100).await;
-        srv.send_frame(frames::settings().initial_window_size(10))
-            .await;
-        srv.recv_frame(frames::settings_ack()).await;
-        srv.recv_frame(frames::data(1, "hello worl")).await;
-        idle_ms(100).await;
-        srv.send_frame(frames::settings().initial_window_size(11))
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        // we're sending the wrong content-length
+        srv.send_frame(
+            frames::headers(1)
+                .response(200)
+                .field("content-length", &*(16_384 * 3).to_string()),
+        )
+        .await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 10]).eos()).await;
+        // mismatched content-length is a protocol error
+        srv.recv_frame(frames::reset(1).protocol_error()).await;
+        // but then the capacity should be released automatically
+        srv.recv_frame(frames::window_update(0, 16_384 * 2 + 10))
             .await;
-        srv.recv_frame(frames::settings_ack()).await;
-        srv.recv_frame(frames::data(1, "d").eos()).await;
-        srv.send_frame(frames::headers(1).response(204).eos()).await;
     };
 
-    let h2 = async move {
-        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method(Method::POST)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
-
-        stream.reserve_capacity(11);
-
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 11)).await;
-        assert_eq!(stream.capacity(), 11);
-
-        stream.send_data("hello world".into(), true).unwrap();
-
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let req = async {
+            let resp = client
+                .send_request(request, true)
+                .unwrap()
+                .0
+                .await
+                .expect("response");
+            assert_eq!(resp.status(), StatusCode::OK);
+            let mut body = resp.into_parts().1;
+            let mut cap = body.flow_control().clone();
+            let to_release = 16_384 * 2;
+            let mut should_recv_bytes = to_release;
+            let mut should_recv_frames = 2usize;
 
-        // Wait for the connection to close
-        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
-        h2.await.unwrap();
+            let err = body
+                .try_for_each(|bytes| async move {
+                    should_recv_bytes -= bytes.len();
+                    should_recv_frames -= 1;
+                    if should_recv_bytes == 0 {
+                        assert_eq!(should_recv_frames, 0);
+                    }
+                    Ok(())
+                })
+                .await
+                .expect_err("body");
+            assert_eq!(
+                err.to_string(),
+                "stream error detected: unspecific protocol error detected"
+            );
+            cap.release_capacity(to_release).expect("release_capacity");
+        };
+        conn.drive(req).await;
+        conn.await.expect("client");
     };
-    join(srv, h2).await;
+
+    join(srv, client).await;
 }
 
 #[tokio::test]
-async fn settings_lowered_capacity_returns_capacity_to_connection() {
-    use futures::channel::oneshot;
-
+async fn stream_close_by_data_frame_releases_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
-    let (tx1, rx1) = oneshot::channel();
-    let (tx2, rx2) = oneshot::channel();
 
     let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE as usize;
 
-    let (th1_tx, th1_rx) = oneshot::channel();
-    // Spawn the server on a thread
-    tokio::spawn(async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        tx1.send(()).unwrap();
-        srv.recv_frame(frames::headers(1).request("POST", "https://example.com/one"))
-            .await;
-        srv.recv_frame(frames::headers(3).request("POST", "https://example.com/two"))
-            .await;
-        idle_ms(200).await;
-        // Remove all capacity from streams
-        srv.send_frame(frames::settings().initial_window_size(0))
-            .await;
-        srv.recv_frame(frames::settings_ack()).await;
-
-        // Let stream 3 make progress
-        srv.send_frame(frames::window_update(3, 11)).await;
-        srv.recv_frame(frames::data(3, "hello world").eos()).await;
-        // Wait to get notified
-        //
-        // A timeout is used here to avoid blocking forever if there is a
-        // failure
-        let _ = tokio::time::timeout(Duration::from_secs(5), rx2)
-            .await
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .body(())
             .unwrap();
 
-        idle_ms(500).await;
+        // Send request
+        let (resp1, mut s1) = client.send_request(request, false).unwrap();
 
-        // Reset initial window size
-        srv.send_frame(frames::settings().initial_window_size(window_size as u32))
-            .await;
-        srv.recv_frame(frames::settings_ack()).await;
+        // This effectively reserves the entire connection window
+        s1.reserve_capacity(window_size);
 
-        // Get data from first stream
-        srv.recv_frame(frames::data(1, "hello world").eos()).await;
+        // The capacity should be immediately available as nothing else is
+        // happening on the stream.
+        assert_eq!(s1.capacity(), window_size);
 
-        // Send responses
-        srv.send_frame(frames::headers(1).response(204).eos()).await;
-        srv.send_frame(frames::headers(3).response(204).eos()).await;
-        drop(srv);
-        th1_tx.send(()).unwrap();
-    });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-    let (mut client, h2) = client::handshake(io).await.unwrap();
+        // Create a second stream
+        let (resp2, mut s2) = client.send_request(request, false).unwrap();
 
-    let (th2_tx, th2_rx) = oneshot::channel();
-    // Drive client connection
-    tokio::spawn(async move {
-        h2.await.unwrap();
-        th2_tx.send(()).unwrap();
-    });
+        // Request capacity
+        s2.reserve_capacity(5);
 
-    // Wait for server handshake to complete.
-    let _ = tokio::time::timeout(Duration::from_secs(5), rx1)
-        .await
-        .unwrap();
+        // There should be no available capacity (as it is being held up by
+        // the previous stream
+        assert_eq!(s2.capacity(), 0);
 
-    let request = Request::post("https://example.com/one").body(()).unwrap();
+        // Closing the previous stream by sending an empty data frame will
+        // release the capacity to s2
+        s1.send_data("".into(), true).unwrap();
 
-    let (resp1, mut stream1) = client.send_request(request, false).unwrap();
+        // The capacity should be available
+        assert_eq!(s2.capacity(), 5);
 
-    let request = Request::post("https://example.com/two").body(()).unwrap();
+        // Send the frame
+        s2.send_data("hello".into(), true).unwrap();
 
-    let (resp2, mut stream2) = client.send_request(request, false).unwrap();
+        // Drive both streams to prevent the handles from being dropped
+        // (which will send a RST_STREAM) before the connection is closed.
+        h2.drive(resp1).await.unwrap();
+        h2.drive(resp2).await.unwrap();
+    };
 
-    // Reserve capacity for stream one, this will consume all connection level
-    // capacity
-    stream1.reserve_capacity(window_size);
-    let stream1 = util::wait_for_capacity(stream1, window_size).await;
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.send_frame(frames::headers(3).response(200)).await;
+        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+        srv.recv_frame(frames::data(3, &b"hello"[..]).eos()).await;
+    };
+    join(srv, h2).await;
+}
 
-    // Now, wait for capacity on the other stream
-    stream2.reserve_capacity(11);
-    let mut stream2 = util::wait_for_capacity(stream2, 11).await;
+#[tokio::test]
+async fn stream_close_by_trailers_frame_releases_capacity() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
 
-    // Send data on stream 2
-    stream2.send_data("hello world".into(), true).unwrap();
+    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE as usize;
 
-    tx2.send(()).unwrap();
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-    // Wait for capacity on stream 1
-    let mut stream1 = util::wait_for_capacity(stream1, 11).await;
+        // Send request
+        let (resp1, mut s1) = client.send_request(request, false).unwrap();
 
-    stream1.send_data("hello world".into(), true).unwrap();
+        // This effectively reserves the entire connection window
+        s1.reserve_capacity(window_size);
 
-    // Wait for responses..
-    let resp = resp1.await.unwrap();
-    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        // The capacity should be immediately available as nothing else is
+        // happening on the stream.
+        assert_eq!(s1.capacity(), window_size);
 
-    let resp = resp2.await.unwrap();
-    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
 
-    th1_rx.await.unwrap();
-    th2_rx.await.unwrap();
-}
+        // Create a second stream
+        let (resp2, mut s2) = client.send_request(request, false).unwrap();
 
-#[tokio::test]
-async fn client_increase_target_window_size() {
-    h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
+        // Request capacity
+        s2.reserve_capacity(5);
+
+        // There should be no available capacity (as it is being held up by
+        // the previous stream
+        assert_eq!(s2.capacity(), 0);
+
+        // Closing the previous stream by sending a trailers frame will
+        // release the capacity to s2
+        s1.send_trailers(Default::default()).unwrap();
+
+        // The capacity should be available
+        assert_eq!(s2.capacity(), 5);
+
+        // Send the frame
+        s2.send_data("hello".into(), true).unwrap();
+
+        // Drive both streams to prevent the handles from being dropped
+        // (which will send a RST_STREAM) before the connection is closed.
+        h2.drive(resp1).await.unwrap();
+        h2.drive(resp2).await.unwrap();
+    };
 
     let srv = async move {
         let settings = srv.assert_client_handshake().await;
+        // Get the first frame
         assert_default_settings!(settings);
-        srv.recv_frame(frames::window_update(0, (2 << 20) - 65_535))
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
             .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.send_frame(frames::headers(3).response(200)).await;
+        srv.recv_frame(frames::headers(1).eos()).await;
+        srv.recv_frame(frames::data(3, &b"hello"[..]).eos()).await;
     };
-
-    let client = async move {
-        let (_client, mut conn) = client::handshake(io).await.unwrap();
-        conn.set_target_window_size(2 << 20);
-        conn.await.unwrap();
-    };
-    join(srv, client).await;
+    join(srv, h2).await;
 }
 
 #[tokio::test]
-async fn increase_target_window_size_after_using_some() {
+async fn stream_close_by_send_reset_frame_releases_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
     let srv = async move {
         let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
+
         srv.recv_frame(
             frames::headers(1)
                 .request("GET", "https://http2.akamai.com/")
@@ -1079,509 +1347,1986 @@ async fn increase_target_window_size_after_using_some() {
         )
         .await;
         srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
         srv.send_frame(frames::data(1, vec![0; 16_384]).eos()).await;
-        srv.recv_frame(frames::window_update(0, (2 << 20) - 65_535))
-            .await;
+        srv.recv_frame(frames::window_update(0, 16_384 * 2)).await;
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200).eos()).await;
     };
 
     let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
-        let request = Request::builder()
-            .uri("https://http2.akamai.com/")
-            .body(())
-            .unwrap();
-
-        let res = client.send_request(request, true).unwrap().0;
-
-        let res = conn.drive(res).await.unwrap();
-        conn.set_target_window_size(2 << 20);
-        // drive an empty future to allow the WINDOW_UPDATE
-        // to go out while the response capacity is still in use.
-        conn.drive(yield_once()).await;
-        let _res = conn.drive(util::concat(res.into_body())).await;
-        conn.await.expect("client");
+        let (mut client, mut conn) = client::handshake(io).await.expect("client handshake");
+        {
+            let request = Request::builder()
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap();
+            let (resp, _) = client.send_request(request, true).unwrap();
+            let _res = conn.drive(resp).await;
+            //    ^-- ignore the response body
+        }
+        let resp = {
+            let request = Request::builder()
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap();
+            let (resp, _) = client.send_request(request, true).unwrap();
+            drop(client);
+            resp
+        };
+        let _res = conn.drive(resp).await;
+        conn.await.expect("client conn");
     };
 
     join(srv, client).await;
 }
 
+#[test]
+#[ignore]
+fn stream_close_by_recv_reset_frame_releases_capacity() {}
+
 #[tokio::test]
-async fn decrease_target_window_size() {
+async fn recv_window_update_on_stream_closed_by_data_frame() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        // Wait for the response
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Send a data frame, this will also close the connection
+        stream.send_data("hello".into(), true).unwrap();
+
+        // keep `stream` from being dropped in order to prevent
+        // it from sending an RST_STREAM frame.
+        //
+        // i know this is kind of evil, but it's necessary to
+        // ensure that the stream is closed by the EOS frame,
+        // and not by the RST_STREAM.
+        std::mem::forget(stream);
+
+        // Wait for the connection to close
+        h2.await.unwrap();
+    };
     let srv = async move {
         let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(
-            frames::headers(1)
-                .request("GET", "https://http2.akamai.com/")
-                .eos(),
-        )
-        .await;
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
         srv.send_frame(frames::headers(1).response(200)).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![0; 16_383]).eos()).await;
-        srv.recv_frame(frames::window_update(0, 16_384)).await;
+        srv.recv_frame(frames::data(1, "hello").eos()).await;
+        srv.send_frame(frames::window_update(1, 5)).await;
     };
+    join(srv, h2).await;
+}
 
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
-        conn.set_target_window_size(16_384 * 2);
+#[tokio::test]
+async fn reserved_capacity_assigned_in_multi_window_updates() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
 
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
         let request = Request::builder()
+            .method(Method::POST)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
-        let (resp, _) = client.send_request(request, true).unwrap();
-        let res = conn.drive(resp).await.expect("response");
-        conn.set_target_window_size(16_384);
-        let mut body = res.into_parts().1;
-        let mut cap = body.flow_control().clone();
 
-        let bytes = conn.drive(util::concat(body)).await.expect("concat");
-        assert_eq!(bytes.len(), 65_535);
-        cap.release_capacity(bytes.len()).unwrap();
-        conn.await.expect("conn");
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        // Consume the capacity
+        let payload = vec![0; frame::DEFAULT_INITIAL_WINDOW_SIZE as usize];
+        stream.send_data(payload.into(), false).unwrap();
+
+        // Reserve more data than we want
+        stream.reserve_capacity(10);
+
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+        stream.send_data("hello".into(), false).unwrap();
+        stream.send_data("world".into(), true).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Wait for the connection to close
+        h2.await.unwrap();
     };
 
-    join(srv, client).await;
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0u8; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0u8; 16_383])).await;
+        idle_ms(100).await;
+        // Increase the connection window
+        srv.send_frame(frames::window_update(0, 10)).await;
+        // Incrementally increase the stream window
+        srv.send_frame(frames::window_update(1, 4)).await;
+        idle_ms(50).await;
+        srv.send_frame(frames::window_update(1, 1)).await;
+        // Receive first chunk
+        srv.recv_frame(frames::data(1, "hello")).await;
+        srv.send_frame(frames::window_update(1, 5)).await;
+        // Receive second chunk
+        srv.recv_frame(frames::data(1, "world").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        /*
+        .recv_frame(frames::data(1, "hello").eos())
+        .send_frame(frames::window_update(1, 5))
+        */
+    };
+    join(srv, h2).await;
 }
 
 #[tokio::test]
-async fn client_update_initial_window_size() {
+async fn connection_notified_on_released_capacity() {
+    use tokio::sync::{mpsc, oneshot};
+
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
-    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE * 2;
+    // We're going to run the connection on a thread in order to isolate task
+    // notifications. This test is here, in part, to ensure that the connection
+    // receives the appropriate notifications to send out window updates.
 
-    let srv = async move {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    // Because threading is fun
+    let (settings_tx, settings_rx) = oneshot::channel();
+
+    let (th1_tx, th1_rx) = oneshot::channel();
+
+    tokio::spawn(async move {
         let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::window_update(0, window_size - 65_535))
-            .await;
+        settings_tx.send(()).unwrap();
+        // Get the first request
         srv.recv_frame(
             frames::headers(1)
-                .request("GET", "https://http2.akamai.com/")
+                .request("GET", "https://example.com/a")
                 .eos(),
         )
         .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.send_frame(frames::data(1, vec![b'a'; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![b'b'; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![b'c'; 16_384])).await;
-        srv.recv_frame(frames::settings().initial_window_size(window_size))
-            .await;
-        srv.send_frame(frames::settings_ack()).await;
-        // we never got a WINDOW_UPDATE, but initial update allows more
-        srv.send_frame(frames::data(1, vec![b'd'; 16_384])).await;
-        srv.send_frame(frames::data(1, vec![b'e'; 16_384]).eos())
-            .await;
-    };
-
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
-        conn.set_target_window_size(window_size);
-
-        // We'll never release_capacity back...
-        async fn data(body: &mut h2::RecvStream, expect: &str) {
-            let buf = body.data().await.expect(expect).expect(expect);
-            assert_eq!(buf.len(), 16_384, "{}", expect);
-        }
-
-        let res_fut = client.get("https://http2.akamai.com/");
-
-        // Receive most of the stream's window...
-        let body = conn
-            .drive(async move {
-                let resp = res_fut.await.expect("response");
-                let mut body = resp.into_body();
-
-                data(&mut body, "data1").await;
-                data(&mut body, "data2").await;
-                data(&mut body, "data3").await;
-
-                body
-            })
-            .await;
-
-        // Update the initial window size to double
-        conn.set_initial_window_size(window_size).expect("update");
-
-        // And then ensure we got the data normally "over" the smaller
-        // initial_window_size...
-        let f = async move {
-            let mut body = body;
-            data(&mut body, "data4").await;
-            data(&mut body, "data5").await;
-            assert!(body.data().await.is_none(), "eos");
-        };
-
-        join(async move { conn.await.expect("client") }, f).await;
-    };
-
-    join(srv, client).await;
-}
-
-#[tokio::test]
-async fn client_decrease_initial_window_size() {
-    h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
-
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-
-        srv.recv_frame(
-            frames::headers(1)
-                .request("GET", "https://http2.akamai.com/")
-                .eos(),
-        )
-        .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.send_frame(frames::data(1, vec![b'a'; 100])).await;
-
+        // Get the second request
         srv.recv_frame(
             frames::headers(3)
-                .request("GET", "https://http2.akamai.com/")
+                .request("GET", "https://example.com/b")
                 .eos(),
         )
         .await;
+        // Send the first response
+        srv.send_frame(frames::headers(1).response(200)).await;
+        // Send the second response
         srv.send_frame(frames::headers(3).response(200)).await;
-        srv.send_frame(frames::data(3, vec![b'a'; 100])).await;
-
-        srv.recv_frame(
-            frames::headers(5)
-                .request("GET", "https://http2.akamai.com/")
-                .eos(),
-        )
-        .await;
-        srv.send_frame(frames::headers(5).response(200)).await;
-        srv.send_frame(frames::data(5, vec![b'a'; 100])).await;
 
-        srv.recv_frame(frames::settings().initial_window_size(0))
+        // Fill the connection window
+        srv.send_frame(frames::data(1, vec![0u8; 16_384]).eos())
             .await;
-        // check settings haven't applied before ACK
-        srv.send_frame(frames::data(1, vec![b'a'; 100]).eos()).await;
-        srv.send_frame(frames::settings_ack()).await;
-
-        // check stream 3 has no window
-        srv.send_frame(frames::data(3, vec![b'a'; 1])).await;
-        srv.recv_frame(frames::reset(3).flow_control()).await;
-
-        // check stream 5 can release capacity
-        srv.recv_frame(frames::window_update(5, 100)).await;
-
-        srv.recv_frame(frames::settings().initial_window_size(16_384))
+        idle_ms(100).await;
+        srv.send_frame(frames::data(3, vec![0u8; 16_384]).eos())
             .await;
-        srv.send_frame(frames::settings_ack()).await;
-
-        srv.send_frame(frames::data(5, vec![b'a'; 100])).await;
-        srv.send_frame(frames::data(5, vec![b'a'; 100]).eos()).await;
-    };
-
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
 
-        async fn req(client: &mut client::SendRequest<Bytes>) -> h2::RecvStream {
-            let res_fut = client.get("https://http2.akamai.com/");
+        // The window update is sent
+        srv.recv_frame(frames::window_update(0, 16_384)).await;
 
-            // Use some of the recv window
-            let resp = res_fut.await.expect("response");
-            let mut body = resp.into_body();
+        th1_tx.send(()).unwrap();
+    });
 
-            data(&mut body, "data1").await;
+    let (th2_tx, th2_rx) = oneshot::channel();
 
-            body
-        }
+    let (mut client, mut h2) = client::handshake(io).await.unwrap();
 
-        async fn data(body: &mut h2::RecvStream, expect: &str) {
-            let buf = body.data().await.expect(expect).expect(expect);
-            assert_eq!(buf.len(), 100, "{}", expect);
-        }
+    h2.drive(settings_rx).await.unwrap();
+    let request = Request::get("https://example.com/a").body(()).unwrap();
+    tx.send(client.send_request(request, true).unwrap().0)
+        .unwrap();
 
-        let mut body1 = conn.drive(req(&mut client)).await;
-        let mut body3 = conn.drive(req(&mut client)).await;
-        let mut body5 = conn.drive(req(&mut client)).await;
+    let request = Request::get("https://example.com/b").body(()).unwrap();
+    tx.send(client.send_request(request, true).unwrap().0)
+        .unwrap();
 
-        // Remove *all* window size of streams
-        conn.set_initial_window_size(0).expect("update0");
-        conn.drive(yield_once()).await;
+    tokio::spawn(async move {
+        // Run the connection to completion
+        h2.await.unwrap();
 
-        // stream 1 received before settings ACK
-        conn.drive(async {
-            data(&mut body1, "body1 data2").await;
-            assert!(body1.is_end_stream());
-        })
-        .await;
+        th2_tx.send(()).unwrap();
+        drop(client);
+    });
 
-        // stream 3 received after ACK, which is stream error
-        conn.drive(async {
-            body3.data().await.expect("body3").expect_err("data2");
-        })
-        .await;
+    // Get the two requests
+    let a = rx.recv().await.unwrap();
+    let b = rx.recv().await.unwrap();
 
-        // stream 5 went negative, so release back to 0
-        assert_eq!(body5.flow_control().available_capacity(), -100);
-        assert_eq!(body5.flow_control().used_capacity(), 100);
-        body5
-            .flow_control()
-            .release_capacity(100)
-            .expect("release_capacity");
-        conn.drive(yield_once()).await;
+    // Get the first response
+    let response = a.await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let (_, mut a) = response.into_parts();
 
-        // open up again
-        conn.set_initial_window_size(16_384).expect("update16");
-        conn.drive(yield_once()).await;
+    // Get the next chunk
+    let chunk = a.data().await.unwrap();
+    assert_eq!(16_384, chunk.unwrap().len());
 
-        // get stream 5 data after opening up
-        conn.drive(async {
-            data(&mut body5, "body5 data2").await;
-            data(&mut body5, "body5 data3").await;
-            assert!(body3.is_end_stream());
-        })
-        .await;
+    // Get the second response
+    let response = b.await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let (_, mut b) = response.into_parts();
 
-        conn.await.expect("client")
-    };
+    // Get the next chunk
+    let chunk = b.data().await.unwrap();
+    assert_eq!(16_384, chunk.unwrap().len());
 
-    join(srv, client).await;
-}
+    // Wait a bit
+    idle_ms(100).await;
 
-#[tokio::test]
-async fn server_target_window_size() {
-    h2_support::trace_init!();
-    let (io, mut client) = mock::new();
+    // Release the capacity
+    a.flow_control().release_capacity(16_384).unwrap();
 
-    let client = async move {
-        let settings = client.assert_server_handshake().await;
-        assert_default_settings!(settings);
-        client
-            .recv_frame(frames::window_update(0, (2 << 20) - 65_535))
-            .await;
-    };
-    let srv = async move {
-        let mut conn = server::handshake(io).await.unwrap();
-        conn.set_target_window_size(2 << 20);
-        conn.next().await;
-    };
+    th1_rx.await.unwrap();
+    th2_rx.await.unwrap();
 
-    join(srv, client).await;
+    // Explicitly drop this after the joins so that the capacity doesn't get
+    // implicitly released before.
+    drop(b);
 }
 
 #[tokio::test]
-async fn recv_settings_increase_window_size_after_using_some() {
-    // See https://github.com/hyperium/h2/issues/208
+async fn recv_settings_removes_available_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
-    let new_win_size = 16_384 * 4; // 1 bigger than default
+    let mut settings = frame::Settings::default();
+    settings.set_initial_window_size(Some(0));
+
     let srv = async move {
-        let settings = srv.assert_client_handshake().await;
+        let settings = srv.assert_client_handshake_with_settings(settings).await;
         assert_default_settings!(settings);
         srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
             .await;
-        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16_383])).await;
-        srv.send_frame(frames::settings().initial_window_size(new_win_size as u32))
-            .await;
-        srv.recv_frame(frames::settings_ack()).await;
-        srv.send_frame(frames::window_update(0, 1)).await;
-        srv.recv_frame(frames::data(1, vec![0; 1]).eos()).await;
-        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        idle_ms(100).await;
+        srv.send_frame(frames::window_update(0, 11)).await;
+        srv.send_frame(frames::window_update(1, 11)).await;
+        srv.recv_frame(frames::data(1, "hello world").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
     };
 
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method("POST")
+            .method(Method::POST)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
-        let (resp, mut req_body) = client.send_request(request, false).unwrap();
-        req_body
-            .send_data(vec![0; new_win_size].into(), true)
-            .unwrap();
-        let _res = conn.drive(resp).await.expect("response");
-        conn.await.expect("client");
-    };
 
-    join(srv, client).await;
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        stream.reserve_capacity(11);
+
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 11)).await;
+        assert_eq!(stream.capacity(), 11);
+
+        stream.send_data("hello world".into(), true).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Wait for the connection to close
+        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
+        h2.await.unwrap();
+    };
+    join(srv, h2).await;
 }
 
 #[tokio::test]
-async fn reserve_capacity_after_peer_closes() {
-    // See https://github.com/hyperium/h2/issues/300
+async fn recv_settings_keeps_assigned_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
+    let (sent_settings, sent_settings_rx) = futures::channel::oneshot::channel();
+
     let srv = async move {
         let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
         srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
             .await;
-        // close connection suddenly
+        srv.send_frame(frames::settings().initial_window_size(64))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+        sent_settings.send(()).unwrap();
+        srv.recv_frame(frames::data(1, "hello world").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
     };
 
-    let client = async move {
-        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+    let h2 = async move {
+        let (mut client, h2) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method("POST")
+            .method(Method::POST)
             .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
-        let (resp, mut req_body) = client.send_request(request, false).unwrap();
-        conn.drive(async move {
-            let result = resp.await;
-            assert!(result.is_err());
-        })
-        .await;
-        // As stated in #300, this would panic because the connection
-        // had already been closed.
-        req_body.reserve_capacity(1);
-        conn.await.expect("client");
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        stream.reserve_capacity(11);
+
+        let f = async move {
+            let mut stream = util::wait_for_capacity(stream, 11).await;
+            sent_settings_rx.await.expect("rx");
+            stream.send_data("hello world".into(), true).unwrap();
+            let resp = response.await.expect("response");
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        };
+        join(async move { h2.await.expect("h2") }, f).await;
     };
 
-    join(srv, client).await;
+    join(srv, h2).await;
 }
 
 #[tokio::test]
-async fn reset_stream_waiting_for_capacity() {
-    // This tests that receiving a reset on a stream that has some available
-    // connection-level window reassigns that window to another stream.
+async fn recv_no_init_window_then_receive_some_init_window() {
     h2_support::trace_init!();
-
     let (io, mut srv) = mock::new();
 
+    let mut settings = frame::Settings::default();
+    settings.set_initial_window_size(Some(0));
+
     let srv = async move {
-        let settings = srv.assert_client_handshake().await;
+        let settings = srv.assert_client_handshake_with_settings(settings).await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("GET", "http://example.com/"))
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
             .await;
-        srv.recv_frame(frames::headers(3).request("GET", "http://example.com/"))
+        idle_ms(100).await;
+        srv.send_frame(frames::settings().initial_window_size(10))
             .await;
-        srv.recv_frame(frames::headers(5).request("GET", "http://example.com/"))
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.recv_frame(frames::data(1, "hello worl")).await;
+        idle_ms(100).await;
+        srv.send_frame(frames::settings().initial_window_size(11))
             .await;
-        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
-        srv.recv_frame(frames::data(1, vec![0; 16383]).eos()).await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        // Assign enough connection window for stream 3...
-        srv.send_frame(frames::window_update(0, 1)).await;
-        // but then reset it.
-        srv.send_frame(frames::reset(3)).await;
-        // 5 should use that window instead.
-        srv.recv_frame(frames::data(5, vec![0; 1]).eos()).await;
-        srv.send_frame(frames::headers(5).response(200)).await;
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.recv_frame(frames::data(1, "d").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
     };
-    fn request() -> Request<()> {
-        Request::builder()
-            .uri("http://example.com/")
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
             .body(())
-            .unwrap()
-    }
+            .unwrap();
 
-    let client = async move {
-        let (mut client, conn) = client::Builder::new()
-            .handshake::<_, Bytes>(io)
-            .await
-            .expect("handshake");
-        let (req1, mut send1) = client.send_request(request(), false).unwrap();
-        let (req2, mut send2) = client.send_request(request(), false).unwrap();
-        let (req3, mut send3) = client.send_request(request(), false).unwrap();
-        // Use up the connection window.
-        send1.send_data(vec![0; 65535].into(), true).unwrap();
-        // Queue up for more connection window.
-        send2.send_data(vec![0; 1].into(), true).unwrap();
-        // .. and even more.
-        send3.send_data(vec![0; 1].into(), true).unwrap();
-        join4(
-            async move { conn.await.expect("h2") },
-            async move { req1.await.expect("req1") },
-            async move { req2.await.unwrap_err() },
-            async move { req3.await.expect("req3") },
-        )
-        .await;
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        stream.reserve_capacity(11);
+
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 11)).await;
+        assert_eq!(stream.capacity(), 11);
+
+        stream.send_data("hello world".into(), true).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Wait for the connection to close
+        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
+        h2.await.unwrap();
     };
+    join(srv, h2).await;
+}
 
-    join(srv, client).await;
+#[tokio::test]
+async fn streams_ready_to_send_skips_window_stalled() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let mut settings = frame::Settings::default();
+    settings.set_initial_window_size(Some(0));
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake_with_settings(settings).await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
+            .await;
+        // only stream 3 gets any window
+        srv.send_frame(frames::window_update(3, 10)).await;
+        srv.ping_pong([1; 8]).await;
+        tx.send(()).unwrap();
+        srv.recv_frame(frames::data(1, "").eos()).await;
+        srv.recv_frame(frames::data(3, "").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        srv.send_frame(frames::headers(3).response(204).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap()
+        };
+
+        let (response1, mut stream1) = client.send_request(request(), false).unwrap();
+        let (response3, mut stream3) = client.send_request(request(), false).unwrap();
+
+        h2.drive(rx).await.unwrap();
+
+        // both streams are send-open, but stream 1 is stalled on its window
+        assert_eq!(client.streams_ready_to_send(), [frame::StreamId::from(3)]);
+
+        stream1.send_data("".into(), true).unwrap();
+        stream3.send_data("".into(), true).unwrap();
+        assert!(client.streams_ready_to_send().is_empty());
+
+        let response = h2.drive(response1).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = h2.drive(response3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
+        h2.await.unwrap();
+    };
+    join(srv, h2).await;
 }
 
 #[tokio::test]
-async fn data_padding() {
+async fn window_utilization_tracks_sent_over_granted() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
+    let (tx1, rx1) = futures::channel::oneshot::channel();
+    let (tx2, rx2) = futures::channel::oneshot::channel();
 
-    let mut body = Vec::new();
-    body.push(5);
-    body.extend_from_slice(&[b'z'; 100][..]);
-    body.extend_from_slice(&[b'0'; 5][..]);
+    let mut settings = frame::Settings::default();
+    settings.set_initial_window_size(Some(10));
 
     let srv = async move {
-        let settings = srv.assert_client_handshake().await;
+        let settings = srv.assert_client_handshake_with_settings(settings).await;
         assert_default_settings!(settings);
-        srv.recv_frame(
-            frames::headers(1)
-                .request("GET", "http://example.com/")
-                .eos(),
-        )
-        .await;
-        srv.send_frame(
-            frames::headers(1)
-                .response(200)
-                .field("content-length", 100),
-        )
-        .await;
-        srv.send_frame(frames::data(1, body).padded().eos()).await;
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.recv_frame(frames::data(1, "hello")).await;
+        srv.send_frame(frames::window_update(1, 10)).await;
+        srv.ping_pong([1; 8]).await;
+        tx1.send(()).unwrap();
+        srv.recv_frame(frames::data(1, "0123456789")).await;
+        tx2.send(()).unwrap();
+        srv.recv_frame(frames::data(1, "").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
     };
+
     let h2 = async move {
-        let (mut client, conn) = client::handshake(io).await.expect("handshake");
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method(Method::GET)
-            .uri("http://example.com/")
+            .method(Method::POST)
+            .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        // first request is allowed
-        let (response, _) = client.send_request(request, true).unwrap();
-        let fut = async move {
-            let resp = response.await.unwrap();
-            assert_eq!(resp.status(), StatusCode::OK);
-            let body = resp.into_body();
-            let bytes = util::concat(body).await.unwrap();
-            assert_eq!(bytes.len(), 100);
-        };
-        join(async move { conn.await.expect("client") }, fut).await;
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+        assert_eq!(stream.window_utilization(), 0.0);
+
+        // 5 of the initial 10 bytes, then 10 more once the window grew by 10
+        stream.send_data("hello".into(), false).unwrap();
+        h2.drive(rx1).await.unwrap();
+        assert_eq!(stream.window_utilization(), 0.25);
+
+        stream.send_data("0123456789".into(), false).unwrap();
+        h2.drive(rx2).await.unwrap();
+        assert_eq!(stream.window_utilization(), 0.75);
+
+        stream.send_data("".into(), true).unwrap();
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(stream.window_utilization(), 0.75);
+
+        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
+        h2.await.unwrap();
+    };
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn paused_stream_holds_data_until_resumed() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.recv_frame(frames::headers(3).request("POST", "https://http2.akamai.com/"))
+            .await;
+        // stream 1 is paused, so only stream 3's data is written
+        srv.recv_frame(frames::data(3, "world").eos()).await;
+        tx.send(()).unwrap();
+        srv.recv_frame(frames::data(1, "hello").eos()).await;
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        srv.send_frame(frames::headers(3).response(204).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("https://http2.akamai.com/")
+                .body(())
+                .unwrap()
+        };
+
+        let (response1, mut stream1) = client.send_request(request(), false).unwrap();
+        let (response3, mut stream3) = client.send_request(request(), false).unwrap();
+
+        stream1.pause_send();
+        assert_eq!(client.streams_ready_to_send(), [frame::StreamId::from(3)]);
+
+        stream1.resume_send();
+        assert_eq!(
+            client.streams_ready_to_send(),
+            [frame::StreamId::from(1), frame::StreamId::from(3)]
+        );
+
+        stream1.pause_send();
+        stream1.send_data("hello".into(), true).unwrap();
+        stream3.send_data("world".into(), true).unwrap();
+
+        h2.drive(rx).await.unwrap();
+        stream1.resume_send();
+
+        let response = h2.drive(response1).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = h2.drive(response3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Hold on to the `client` handle to avoid sending a GO_AWAY frame.
+        h2.await.unwrap();
+    };
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn settings_lowered_capacity_returns_capacity_to_connection() {
+    use futures::channel::oneshot;
+
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+    let (tx1, rx1) = oneshot::channel();
+    let (tx2, rx2) = oneshot::channel();
+
+    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE as usize;
+
+    let (th1_tx, th1_rx) = oneshot::channel();
+    // Spawn the server on a thread
+    tokio::spawn(async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        tx1.send(()).unwrap();
+        srv.recv_frame(frames::headers(1).request("POST", "https://example.com/one"))
+            .await;
+        srv.recv_frame(frames::headers(3).request("POST", "https://example.com/two"))
+            .await;
+        idle_ms(200).await;
+        // Remove all capacity from streams
+        srv.send_frame(frames::settings().initial_window_size(0))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+
+        // Let stream 3 make progress
+        srv.send_frame(frames::window_update(3, 11)).await;
+        srv.recv_frame(frames::data(3, "hello world").eos()).await;
+        // Wait to get notified
+        //
+        // A timeout is used here to avoid blocking forever if there is a
+        // failure
+        let _ = tokio::time::timeout(Duration::from_secs(5), rx2)
+            .await
+            .unwrap();
+
+        idle_ms(500).await;
+
+        // Reset initial window size
+        srv.send_frame(frames::settings().initial_window_size(window_size as u32))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+
+        // Get data from first stream
+        srv.recv_frame(frames::data(1, "hello world").eos()).await;
+
+        // Send responses
+        srv.send_frame(frames::headers(1).response(204).eos()).await;
+        srv.send_frame(frames::headers(3).response(204).eos()).await;
+        drop(srv);
+        th1_tx.send(()).unwrap();
+    });
+
+    let (mut client, h2) = client::handshake(io).await.unwrap();
+
+    let (th2_tx, th2_rx) = oneshot::channel();
+    // Drive client connection
+    tokio::spawn(async move {
+        h2.await.unwrap();
+        th2_tx.send(()).unwrap();
+    });
+
+    // Wait for server handshake to complete.
+    let _ = tokio::time::timeout(Duration::from_secs(5), rx1)
+        .await
+        .unwrap();
+
+    let request = Request::post("https://example.com/one").body(()).unwrap();
+
+    let (resp1, mut stream1) = client.send_request(request, false).unwrap();
+
+    let request = Request::post("https://example.com/two").body(()).unwrap();
+
+    let (resp2, mut stream2) = client.send_request(request, false).unwrap();
+
+    // Reserve capacity for stream one, this will consume all connection level
+    // capacity
+    stream1.reserve_capacity(window_size);
+    let stream1 = util::wait_for_capacity(stream1, window_size).await;
+
+    // Now, wait for capacity on the other stream
+    stream2.reserve_capacity(11);
+    let mut stream2 = util::wait_for_capacity(stream2, 11).await;
+
+    // Send data on stream 2
+    stream2.send_data("hello world".into(), true).unwrap();
+
+    tx2.send(()).unwrap();
+
+    // Wait for capacity on stream 1
+    let mut stream1 = util::wait_for_capacity(stream1, 11).await;
+
+    stream1.send_data("hello world".into(), true).unwrap();
+
+    // Wait for responses..
+    let resp = resp1.await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = resp2.await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    th1_rx.await.unwrap();
+    th2_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn client_increase_target_window_size() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::window_update(0, (2 << 20) - 65_535))
+            .await;
+    };
+
+    let client = async move {
+        let (_client, mut conn) = client::handshake(io).await.unwrap();
+        conn.set_target_window_size(2 << 20);
+        conn.await.unwrap();
+    };
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn increase_target_window_size_after_using_some() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384]).eos()).await;
+        srv.recv_frame(frames::window_update(0, (2 << 20) - 65_535))
+            .await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+
+        let res = client.send_request(request, true).unwrap().0;
+
+        let res = conn.drive(res).await.unwrap();
+        conn.set_target_window_size(2 << 20);
+        // drive an empty future to allow the WINDOW_UPDATE
+        // to go out while the response capacity is still in use.
+        conn.drive(yield_once()).await;
+        let _res = conn.drive(util::concat(res.into_body())).await;
+        conn.await.expect("client");
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn decrease_target_window_size() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_383]).eos()).await;
+        srv.recv_frame(frames::window_update(0, 16_384)).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        conn.set_target_window_size(16_384 * 2);
+
+        let request = Request::builder()
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+        let (resp, _) = client.send_request(request, true).unwrap();
+        let res = conn.drive(resp).await.expect("response");
+        conn.set_target_window_size(16_384);
+        let mut body = res.into_parts().1;
+        let mut cap = body.flow_control().clone();
+
+        let bytes = conn.drive(util::concat(body)).await.expect("concat");
+        assert_eq!(bytes.len(), 65_535);
+        cap.release_capacity(bytes.len()).unwrap();
+        conn.await.expect("conn");
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn client_update_initial_window_size() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let window_size = frame::DEFAULT_INITIAL_WINDOW_SIZE * 2;
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::window_update(0, window_size - 65_535))
+            .await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![b'a'; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![b'b'; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![b'c'; 16_384])).await;
+        srv.recv_frame(frames::settings().initial_window_size(window_size))
+            .await;
+        srv.send_frame(frames::settings_ack()).await;
+        // we never got a WINDOW_UPDATE, but initial update allows more
+        srv.send_frame(frames::data(1, vec![b'd'; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![b'e'; 16_384]).eos())
+            .await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        conn.set_target_window_size(window_size);
+
+        // We'll never release_capacity back...
+        async fn data(body: &mut h2::RecvStream, expect: &str) {
+            let buf = body.data().await.expect(expect).expect(expect);
+            assert_eq!(buf.len(), 16_384, "{}", expect);
+        }
+
+        let res_fut = client.get("https://http2.akamai.com/");
+
+        // Receive most of the stream's window...
+        let body = conn
+            .drive(async move {
+                let resp = res_fut.await.expect("response");
+                let mut body = resp.into_body();
+
+                data(&mut body, "data1").await;
+                data(&mut body, "data2").await;
+                data(&mut body, "data3").await;
+
+                body
+            })
+            .await;
+
+        // Update the initial window size to double
+        conn.set_initial_window_size(window_size).expect("update");
+
+        // And then ensure we got the data normally "over" the smaller
+        // initial_window_size...
+        let f = async move {
+            let mut body = body;
+            data(&mut body, "data4").await;
+            data(&mut body, "data5").await;
+            assert!(body.data().await.is_none(), "eos");
+        };
+
+        join(async move { conn.await.expect("client") }, f).await;
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn client_decrease_initial_window_size() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(frames::data(1, vec![b'a'; 100])).await;
+
+        srv.recv_frame(
+            frames::headers(3)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(3).response(200)).await;
+        srv.send_frame(frames::data(3, vec![b'a'; 100])).await;
+
+        srv.recv_frame(
+            frames::headers(5)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(5).response(200)).await;
+        srv.send_frame(frames::data(5, vec![b'a'; 100])).await;
+
+        srv.recv_frame(frames::settings().initial_window_size(0))
+            .await;
+        // check settings haven't applied before ACK
+        srv.send_frame(frames::data(1, vec![b'a'; 100]).eos()).await;
+        srv.send_frame(frames::settings_ack()).await;
+
+        // check stream 3 has no window
+        srv.send_frame(frames::data(3, vec![b'a'; 1])).await;
+        srv.recv_frame(frames::reset(3).flow_control()).await;
+
+        // check stream 5 can release capacity
+        srv.recv_frame(frames::window_update(5, 100)).await;
+
+        srv.recv_frame(frames::settings().initial_window_size(16_384))
+            .await;
+        srv.send_frame(frames::settings_ack()).await;
+
+        srv.send_frame(frames::data(5, vec![b'a'; 100])).await;
+        srv.send_frame(frames::data(5, vec![b'a'; 100]).eos()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+
+        async fn req(client: &mut client::SendRequest<Bytes>) -> h2::RecvStream {
+            let res_fut = client.get("https://http2.akamai.com/");
+
+            // Use some of the recv window
+            let resp = res_fut.await.expect("response");
+            let mut body = resp.into_body();
+
+            data(&mut body, "data1").await;
+
+            body
+        }
+
+        async fn data(body: &mut h2::RecvStream, expect: &str) {
+            let buf = body.data().await.expect(expect).expect(expect);
+            assert_eq!(buf.len(), 100, "{}", expect);
+        }
+
+        let mut body1 = conn.drive(req(&mut client)).await;
+        let mut body3 = conn.drive(req(&mut client)).await;
+        let mut body5 = conn.drive(req(&mut client)).await;
+
+        // Remove *all* window size of streams
+        conn.set_initial_window_size(0).expect("update0");
+        conn.drive(yield_once()).await;
+
+        // stream 1 received before settings ACK
+        conn.drive(async {
+            data(&mut body1, "body1 data2").await;
+            assert!(body1.is_end_stream());
+        })
+        .await;
+
+        // stream 3 received after ACK, which is stream error
+        conn.drive(async {
+            body3.data().await.expect("body3").expect_err("data2");
+        })
+        .await;
+
+        // stream 5 went negative, so release back to 0
+        assert_eq!(body5.flow_control().available_capacity(), -100);
+        assert_eq!(body5.flow_control().used_capacity(), 100);
+        body5
+            .flow_control()
+            .release_capacity(100)
+            .expect("release_capacity");
+        conn.drive(yield_once()).await;
+
+        // open up again
+        conn.set_initial_window_size(16_384).expect("update16");
+        conn.drive(yield_once()).await;
+
+        // get stream 5 data after opening up
+        conn.drive(async {
+            data(&mut body5, "body5 data2").await;
+            data(&mut body5, "body5 data3").await;
+            assert!(body3.is_end_stream());
+        })
+        .await;
+
+        conn.await.expect("client")
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn server_target_window_size() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .recv_frame(frames::window_update(0, (2 << 20) - 65_535))
+            .await;
+    };
+    let srv = async move {
+        let mut conn = server::handshake(io).await.unwrap();
+        conn.set_target_window_size(2 << 20);
+        conn.next().await;
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn server_small_initial_window_size_applied_on_ack() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        client.write_preface().await;
+        client.send_frame(frames::settings()).await;
+        client
+            .recv_frame(frames::settings().initial_window_size(16_384))
+            .await;
+
+        // Until our ACK, the server's streams still use the default window.
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        client.send_frame(frames::data(1, vec![0; 4_000])).await;
+
+        client.send_frame(frames::settings_ack()).await;
+        client.recv_frame(frames::settings_ack()).await;
+
+        // Streams opened afterwards get the smaller window.
+        client
+            .send_frame(frames::headers(3).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(3, vec![0; 16_384])).await;
+        client.send_frame(frames::data(3, vec![0; 1])).await;
+        client.recv_frame(frames::reset(3).flow_control()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .initial_window_size(16_384)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+
+        let (req, _respond) = srv.next().await.unwrap().unwrap();
+        let mut body = req.into_body();
+
+        let (req, _respond) = srv.next().await.unwrap().unwrap();
+        let mut body3 = req.into_body();
+
+        let read = async move {
+            let mut len = 0;
+            while len < 20_384 {
+                len += body.data().await.unwrap().unwrap().len();
+            }
+            // shrinking the window leaves the stream over budget
+            assert_eq!(body.flow_control().available_capacity(), -4_000);
+
+            assert_eq!(body3.data().await.unwrap().unwrap().len(), 16_384);
+            let err = body3.data().await.unwrap().unwrap_err();
+            assert_eq!(err.reason(), Some(Reason::FLOW_CONTROL_ERROR));
+        };
+
+        join(async move { assert!(srv.next().await.is_none()) }, read).await;
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn server_lowers_initial_window_size_with_streams_in_flight() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let (acked_tx, acked_rx) = futures::channel::oneshot::channel();
+    let (paused_tx, paused_rx) = futures::channel::oneshot::channel();
+    let (reset_tx, reset_rx) = futures::channel::oneshot::channel();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+
+        for id in [1, 3] {
+            client
+                .send_frame(frames::headers(id).request("POST", "https://example.com/"))
+                .await;
+            client.send_frame(frames::data(id, vec![0; 16_384])).await;
+            client.send_frame(frames::data(id, vec![0; 3_616])).await;
+        }
+
+        client
+            .recv_frame(frames::settings().initial_window_size(16_384))
+            .await;
+        client.send_frame(frames::settings_ack()).await;
+        client.ping_pong([1; 8]).await;
+        acked_tx.send(()).unwrap();
+
+        // both windows are now negative, so any DATA is a violation
+        paused_rx.await.unwrap();
+        client.send_frame(frames::data(1, vec![0; 1])).await;
+        client.recv_frame(frames::reset(1).flow_control()).await;
+        reset_tx.send(()).unwrap();
+
+        // draining stream 3 reopens its window to the new initial size; the
+        // connection also gets back the DATA ignored on stream 1
+        client.recv_frame(frames::window_update(0, 20_001)).await;
+        client.recv_frame(frames::window_update(3, 20_000)).await;
+        client.send_frame(frames::data(3, vec![0; 16_384])).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+
+        let (req, _respond1) = srv.next().await.unwrap().unwrap();
+        let mut body1 = req.into_body();
+        let (req, _respond3) = srv.next().await.unwrap().unwrap();
+        let mut body3 = req.into_body();
+
+        srv.set_initial_window_size(16_384).unwrap();
+
+        let read = async move {
+            acked_rx.await.unwrap();
+
+            for body in [&mut body1, &mut body3] {
+                let mut len = 0;
+                while len < 20_000 {
+                    len += body.data().await.unwrap().unwrap().len();
+                }
+                // 65_535 - 20_000 - (65_535 - 16_384)
+                assert_eq!(body.flow_control().available_capacity(), -3_616);
+            }
+            paused_tx.send(()).unwrap();
+
+            reset_rx.await.unwrap();
+            body3.flow_control().release_capacity(20_000).unwrap();
+            assert_eq!(body3.flow_control().available_capacity(), 16_384);
+
+            let data = body3.data().await.unwrap().unwrap();
+            assert_eq!(data.len(), 16_384);
+        };
+
+        join(async move { assert!(srv.next().await.is_none()) }, read).await;
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_settings_increase_window_size_after_using_some() {
+    // See https://github.com/hyperium/h2/issues/208
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let new_win_size = 16_384 * 4; // 1 bigger than default
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16_383])).await;
+        srv.send_frame(frames::settings().initial_window_size(new_win_size as u32))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+        srv.send_frame(frames::window_update(0, 1)).await;
+        srv.recv_frame(frames::data(1, vec![0; 1]).eos()).await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+        let (resp, mut req_body) = client.send_request(request, false).unwrap();
+        req_body
+            .send_data(vec![0; new_win_size].into(), true)
+            .unwrap();
+        let _res = conn.drive(resp).await.expect("response");
+        conn.await.expect("client");
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn reserve_capacity_after_peer_closes() {
+    // See https://github.com/hyperium/h2/issues/300
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://http2.akamai.com/"))
+            .await;
+        // close connection suddenly
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+        let (resp, mut req_body) = client.send_request(request, false).unwrap();
+        conn.drive(async move {
+            let result = resp.await;
+            assert!(result.is_err());
+        })
+        .await;
+        // As stated in #300, this would panic because the connection
+        // had already been closed.
+        req_body.reserve_capacity(1);
+        conn.await.expect("client");
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn reset_stream_waiting_for_capacity() {
+    // This tests that receiving a reset on a stream that has some available
+    // connection-level window reassigns that window to another stream.
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("GET", "http://example.com/"))
+            .await;
+        srv.recv_frame(frames::headers(3).request("GET", "http://example.com/"))
+            .await;
+        srv.recv_frame(frames::headers(5).request("GET", "http://example.com/"))
+            .await;
+        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16384])).await;
+        srv.recv_frame(frames::data(1, vec![0; 16383]).eos()).await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        // Assign enough connection window for stream 3...
+        srv.send_frame(frames::window_update(0, 1)).await;
+        // but then reset it.
+        srv.send_frame(frames::reset(3)).await;
+        // 5 should use that window instead.
+        srv.recv_frame(frames::data(5, vec![0; 1]).eos()).await;
+        srv.send_frame(frames::headers(5).response(200)).await;
+    };
+    fn request() -> Request<()> {
+        Request::builder()
+            .uri("http://example.com/")
+            .body(())
+            .unwrap()
+    }
+
+    let client = async move {
+        let (mut client, conn) = client::Builder::new()
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req1, mut send1) = client.send_request(request(), false).unwrap();
+        let (req2, mut send2) = client.send_request(request(), false).unwrap();
+        let (req3, mut send3) = client.send_request(request(), false).unwrap();
+        // Use up the connection window.
+        send1.send_data(vec![0; 65535].into(), true).unwrap();
+        // Queue up for more connection window.
+        send2.send_data(vec![0; 1].into(), true).unwrap();
+        // .. and even more.
+        send3.send_data(vec![0; 1].into(), true).unwrap();
+        join4(
+            async move { conn.await.expect("h2") },
+            async move { req1.await.expect("req1") },
+            async move { req2.await.unwrap_err() },
+            async move { req3.await.expect("req3") },
+        )
+        .await;
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn data_padding() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let mut body = Vec::new();
+    body.push(5);
+    body.extend_from_slice(&[b'z'; 100][..]);
+    body.extend_from_slice(&[b'0'; 5][..]);
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "http://example.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(
+            frames::headers(1)
+                .response(200)
+                .field("content-length", 100),
+        )
+        .await;
+        srv.send_frame(frames::data(1, body).padded().eos()).await;
+    };
+    let h2 = async move {
+        let (mut client, conn) = client::handshake(io).await.expect("handshake");
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("http://example.com/")
+            .body(())
+            .unwrap();
+
+        // first request is allowed
+        let (response, _) = client.send_request(request, true).unwrap();
+        let fut = async move {
+            let resp = response.await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = resp.into_body();
+            let bytes = util::concat(body).await.unwrap();
+            assert_eq!(bytes.len(), 100);
+        };
+        join(async move { conn.await.expect("client") }, fut).await;
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn poll_capacity_after_send_data_and_reserve() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(5))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+        srv.send_frame(frames::window_update(1, 5)).await;
+        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        stream.send_data("abcde".into(), false).unwrap();
+
+        stream.reserve_capacity(5);
+
+        // Initial window size was 5 so current capacity is 0 even if we just reserved.
+        assert_eq!(stream.capacity(), 0);
+
+        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+
+        stream.send_data("".into(), true).unwrap();
+
+        // Wait for the connection to close
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn poll_capacity_after_send_data_and_reserve_with_max_send_buffer_size() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(10))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+        srv.send_frame(frames::window_update(1, 10)).await;
+        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .max_send_buffer_size(5)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        stream.send_data("abcde".into(), false).unwrap();
+
+        stream.reserve_capacity(5);
+
+        // Initial window size was 10 but with a max send buffer size of 10 in the client,
+        // so current capacity is 0 even if we just reserved.
+        assert_eq!(stream.capacity(), 0);
+
+        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+
+        stream.send_data("".into(), true).unwrap();
+
+        // Wait for the connection to close
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn max_send_buffer_size_overflow() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(frames::data(1, &[0; 10][..])).await;
+        srv.recv_frame(frames::data(1, &[][..]).eos()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::Builder::new()
+            .max_send_buffer_size(5)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = conn.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(stream.capacity(), 0);
+        stream.reserve_capacity(10);
+        assert_eq!(
+            stream.capacity(),
+            5,
+            "polled capacity not over max buffer size"
+        );
+
+        stream.send_data([0; 10][..].into(), false).unwrap();
+
+        stream.reserve_capacity(15);
+        assert_eq!(
+            stream.capacity(),
+            0,
+            "now with buffered over the max, don't overflow"
+        );
+        stream.send_data([0; 0][..].into(), true).unwrap();
+
+        // Wait for the connection to close
+        conn.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn max_send_buffer_size_poll_capacity_wakes_task() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
+        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
+        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
+        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
+        srv.recv_frame(frames::data(1, &[][..]).eos()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::Builder::new()
+            .max_send_buffer_size(5)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = conn.drive(response).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(stream.capacity(), 0);
+        const TO_SEND: usize = 20;
+        stream.reserve_capacity(TO_SEND);
+        assert_eq!(
+            stream.capacity(),
+            5,
+            "polled capacity not over max buffer size"
+        );
+
+        let t1 = tokio::spawn(async move {
+            let mut sent = 0;
+            let buf = [0; TO_SEND];
+            loop {
+                match poll_fn(|cx| stream.poll_capacity(cx)).await {
+                    None => panic!("no cap"),
+                    Some(Err(e)) => panic!("cap error: {:?}", e),
+                    Some(Ok(cap)) => {
+                        stream
+                            .send_data(buf[sent..(sent + cap)].to_vec().into(), false)
+                            .unwrap();
+                        sent += cap;
+                        if sent >= TO_SEND {
+                            break;
+                        }
+                    }
+                }
+            }
+            stream.send_data(Bytes::new(), true).unwrap();
+        });
+
+        // Wait for the connection to close
+        conn.await.unwrap();
+        t1.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn max_send_buffer_size_engages_before_window() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(20))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &[0; 8][..])).await;
+        srv.recv_frame(frames::data(1, &[0; 8][..])).await;
+        srv.recv_frame(frames::data(1, &[][..]).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .max_send_buffer_size(8)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The window allows 20 bytes, but only 8 may be buffered.
+        stream.reserve_capacity(20);
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 8)).await;
+        assert_eq!(stream.capacity(), 8);
+
+        stream.send_data(vec![0; 8].into(), false).unwrap();
+        assert_eq!(stream.capacity(), 0);
+
+        // Once the buffered data is written, the remaining window is capped
+        // by the buffer size again rather than handed out all at once.
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 8)).await;
+        assert_eq!(stream.capacity(), 8);
+
+        stream.send_data(vec![0; 8].into(), false).unwrap();
+        stream.send_data(Bytes::new(), true).unwrap();
+
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn stalled_send_window_resets_stream() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(5))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+
+        // Never grow the window; the reset is sent once the timeout passed,
+        // ahead of the reply to a later PING.
+        idle_ms(100).await;
+        srv.send_frame(frames::ping([1; 8])).await;
+        srv.recv_frame(frames::reset(1).cancel()).await;
+        srv.recv_frame(frames::ping([1; 8]).pong()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .stalled_stream_timeout(Duration::from_millis(50))
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        stream.send_data("abcdefghij".into(), false).unwrap();
+
+        let reason = h2.drive(poll_fn(|cx| stream.poll_reset(cx))).await;
+        assert_eq!(reason.unwrap(), Reason::CANCEL);
+
+        drop(client);
+        drop(stream);
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_send_window_resets_stream_when_peer_is_silent() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(5))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+
+        // Never grow the window, nor send anything else.
+        srv.recv_frame(frames::reset(1).cancel()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .stalled_stream_timeout(Duration::from_secs(5))
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        stream.send_data("abcdefghij".into(), false).unwrap();
+
+        let start = tokio::time::Instant::now();
+        let reason = h2.drive(poll_fn(|cx| stream.poll_reset(cx))).await;
+        assert_eq!(reason.unwrap(), Reason::CANCEL);
+        assert!(start.elapsed() >= Duration::from_secs(5));
+
+        drop(client);
+        drop(stream);
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn poll_capacity_wakeup_after_window_update() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv
+            .assert_client_handshake_with_settings(frames::settings().initial_window_size(10))
+            .await;
+        assert_default_settings!(settings);
+        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+            .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+        srv.send_frame(frames::window_update(1, 5)).await;
+        srv.send_frame(frames::window_update(1, 5)).await;
+        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
+        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::Builder::new()
+            .max_send_buffer_size(5)
+            .handshake::<_, Bytes>(io)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://www.example.com/")
+            .body(())
+            .unwrap();
+
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+
+        let response = h2.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        stream.send_data("abcde".into(), false).unwrap();
+
+        stream.reserve_capacity(10);
+        assert_eq!(stream.capacity(), 0);
+
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+        h2.drive(idle_ms(10)).await;
+        stream.send_data("abcde".into(), false).unwrap();
+
+        stream.reserve_capacity(5);
+        assert_eq!(stream.capacity(), 0);
+
+        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
+        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+
+        stream.send_data("".into(), true).unwrap();
+
+        // Wait for the connection to close
+        h2.await.unwrap();
+    };
+
+    join(srv, h2).await;
+}
+
+#[tokio::test]
+async fn window_size_decremented_past_zero() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        // let _ = client.assert_server_handshake().await;
+
+        // preface
+        client.write_preface().await;
+
+        // the following http 2 bytes are fuzzer-generated
+        client.send_bytes(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await;
+        client
+            .send_bytes(&[
+                0, 0, 23, 1, 1, 0, 249, 255, 191, 131, 1, 1, 1, 70, 1, 1, 1, 1, 65, 1, 1, 65, 1, 1,
+                65, 1, 1, 1, 1, 1, 1, 190,
+            ])
+            .await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client
+            .send_bytes(&[
+                0, 0, 9, 247, 0, 121, 255, 255, 184, 1, 65, 1, 1, 1, 1, 1, 1, 190,
+            ])
+            .await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client
+            .send_bytes(&[0, 0, 3, 0, 1, 0, 249, 255, 191, 1, 1, 190])
+            .await;
+        client
+            .send_bytes(&[0, 0, 2, 50, 107, 0, 0, 0, 1, 0, 0])
+            .await;
+        client
+            .send_bytes(&[0, 0, 5, 2, 0, 0, 0, 0, 1, 128, 0, 55, 0, 0])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 3, 4, 76, 255, 71, 131])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 39, 184, 171, 74, 33, 0, 3, 107, 50, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 30, 4, 0, 0, 0, 0, 0, 0, 4, 56, 184, 171, 125, 65, 0, 35, 65, 65, 65, 61,
+                232, 87, 115, 89, 116, 0, 4, 0, 58, 33, 125, 33, 79, 3, 107, 49, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
+            ])
+            .await;
+        client.send_bytes(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 177, 1, 44, 0, 0, 0, 1, 67, 67, 67, 67, 67, 67, 131, 134, 5, 61, 67, 67, 67,
+                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67,
+                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67,
+                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 115, 102, 1, 3, 48, 43,
+                101, 64, 31, 37, 99, 99, 97, 97, 97, 97, 49, 97, 54, 97, 97, 97, 97, 49, 97, 54,
+                97, 99, 54, 53, 53, 51, 53, 99, 99, 97, 97, 99, 97, 97, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0,
+            ])
+            .await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 0, 58, 171, 125, 33, 79, 3, 107, 49, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 4, 87, 115, 89, 116])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
+            ])
+            .await;
+        client
+            .send_bytes(&[
+                0, 0, 129, 1, 44, 0, 0, 0, 1, 67, 67, 67, 67, 67, 67, 131, 134, 5, 18, 67, 67, 61,
+                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 48, 54, 53, 55, 114, 1, 4, 97, 49, 51, 116,
+                64, 2, 117, 115, 4, 103, 101, 110, 116, 64, 8, 57, 111, 110, 116, 101, 110, 115,
+                102, 7, 43, 43, 49, 48, 48, 43, 101, 192, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ])
+            .await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
+        client
+            .send_bytes(&[
+                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 0, 58, 171, 125, 33, 79, 3, 107, 49, 98,
+            ])
+            .await;
+
+        // TODO: is CANCEL the right error code to expect here?
+        // client.recv_frame(frames::reset(1).protocol_error()).await;
+    };
+
+    let srv = async move {
+        let builder = server::Builder::new();
+        let mut srv = builder.handshake::<_, Bytes>(io).await.expect("handshake");
+
+        // just keep it open
+        let res = poll_fn(move |cx| srv.poll_closed(cx)).await;
+        tracing::debug!("{:?}", res);
     };
 
-    join(srv, h2).await;
+    join(client, srv).await;
 }
 
-#[tokio::test]
-async fn poll_capacity_after_send_data_and_reserve() {
+/// Sends `len` bytes in a single `send_data` call to a peer that changed its
+/// `SETTINGS_MAX_FRAME_SIZE` from `initial` to `max_frame_size`, expecting
+/// them on the wire as DATA frames of `frames` lengths.
+async fn send_data_split(initial: u32, max_frame_size: u32, len: usize, frames: &[usize]) {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
+    srv.codec_mut()
+        .set_max_recv_frame_size(cmp::max(initial, max_frame_size) as usize);
+    let (tx, rx) = futures::channel::oneshot::channel();
 
+    let expected = frames.to_vec();
     let srv = async move {
         let settings = srv
-            .assert_client_handshake_with_settings(frames::settings().initial_window_size(5))
+            .assert_client_handshake_with_settings(frames::settings().max_frame_size(initial))
             .await;
         assert_default_settings!(settings);
+        srv.send_frame(frames::settings().max_frame_size(max_frame_size))
+            .await;
+        srv.recv_frame(frames::settings_ack()).await;
+        tx.send(()).unwrap();
+
         srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
             .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
-        srv.send_frame(frames::window_update(1, 5)).await;
-        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        for (i, &sz) in expected.iter().enumerate() {
+            let frame = frames::data(1, vec![0; sz]);
+            if i + 1 == expected.len() {
+                srv.recv_frame(frame.eos()).await;
+            } else {
+                srv.recv_frame(frame).await;
+            }
+        }
     };
 
     let h2 = async move {
         let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        h2.drive(rx).await.unwrap();
+
         let request = Request::builder()
             .method(Method::POST)
             .uri("https://www.example.com/")
@@ -1589,407 +3334,498 @@ async fn poll_capacity_after_send_data_and_reserve() {
             .unwrap();
 
         let (response, mut stream) = client.send_request(request, false).unwrap();
+        stream.send_data(vec![0; len].into(), true).unwrap();
 
         let response = h2.drive(response).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        stream.send_data("abcde".into(), false).unwrap();
+        h2.await.unwrap();
+    };
 
-        stream.reserve_capacity(5);
+    join(srv, h2).await;
+}
 
-        // Initial window size was 5 so current capacity is 0 even if we just reserved.
-        assert_eq!(stream.capacity(), 0);
+#[tokio::test]
+async fn send_data_split_at_lowered_max_frame_size() {
+    send_data_split(20_000, 16_384, 16_384 * 2 + 100, &[16_384, 16_384, 100]).await;
+}
 
-        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+#[tokio::test]
+async fn send_data_split_frame_size_aligned() {
+    send_data_split(20_000, 16_384, 16_384 * 2, &[16_384, 16_384]).await;
+}
 
-        stream.send_data("".into(), true).unwrap();
+#[tokio::test]
+async fn send_data_split_at_raised_max_frame_size() {
+    send_data_split(16_384, 20_000, 30_000, &[20_000, 10_000]).await;
+}
 
-        // Wait for the connection to close
-        h2.await.unwrap();
+#[tokio::test]
+async fn send_data_split_empty_buffer() {
+    send_data_split(16_384, 20_000, 0, &[0]).await;
+}
+
+#[tokio::test]
+async fn dropping_recv_stream_reclaims_undelivered_capacity() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        // Once the PING is answered, both DATA frames are buffered.
+        client.send_frame(frames::ping([1; 8])).await;
+        client.recv_frame(frames::ping([1; 8]).pong()).await;
+        tx.send(()).unwrap();
+
+        // The stream is still open for sending, yet the discarded data no
+        // longer counts against the connection window.
+        client.recv_frame(frames::window_update(0, 32_768)).await;
+        client.recv_frame(frames::headers(1).response(200)).await;
     };
 
-    join(srv, h2).await;
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+
+        let respond = async move {
+            rx.await.unwrap();
+            drop(req);
+            idle_ms(10).await;
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let stream = respond.send_response(rsp, false).unwrap();
+            idle_ms(10).await;
+            drop(stream);
+        };
+        let conn = async move {
+            assert!(srv.next().await.is_none());
+        };
+        join(conn, respond).await;
+    };
+
+    join(client, srv).await;
 }
 
 #[tokio::test]
-async fn poll_capacity_after_send_data_and_reserve_with_max_send_buffer_size() {
+async fn dropping_recv_stream_keeps_delivered_capacity_in_flight() {
     h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
+    let (io, mut client) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
 
-    let srv = async move {
-        let settings = srv
-            .assert_client_handshake_with_settings(frames::settings().initial_window_size(10))
-            .await;
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        client.send_frame(frames::ping([1; 8])).await;
+        client.recv_frame(frames::ping([1; 8]).pong()).await;
+        tx.send(()).unwrap();
+
+        // Only the undelivered chunk is reclaimed; the delivered one is left
+        // for the user to release.
+        client.recv_frame(frames::window_update(0, 16_384)).await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
             .await;
-        srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
-        srv.send_frame(frames::window_update(1, 10)).await;
-        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
     };
 
-    let h2 = async move {
-        let (mut client, mut h2) = client::Builder::new()
-            .max_send_buffer_size(5)
-            .handshake::<_, Bytes>(io)
-            .await
-            .unwrap();
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://www.example.com/")
-            .body(())
-            .unwrap();
-
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+
+        let respond = async move {
+            rx.await.unwrap();
+            let mut body = req.into_body();
+            let chunk = body.data().await.unwrap().unwrap();
+            assert_eq!(chunk.len(), 16_384);
+
+            let mut flow = body.flow_control().clone();
+            drop(body);
+            idle_ms(10).await;
+            assert_eq!(flow.used_capacity(), 16_384);
+            flow.release_capacity(chunk.len()).unwrap();
+
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            respond.send_response(rsp, true).unwrap();
+        };
+        let conn = async move {
+            assert!(srv.next().await.is_none());
+        };
+        join(conn, respond).await;
+    };
 
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+    join(client, srv).await;
+}
 
-        stream.send_data("abcde".into(), false).unwrap();
+/// A padded DATA frame carrying `len` bytes followed by `pad_len` bytes of
+/// padding.
+fn padded_data(len: usize, pad_len: u8) -> Vec<u8> {
+    let mut body = vec![pad_len];
+    body.extend_from_slice(&vec![b'z'; len]);
+    body.extend_from_slice(&vec![0; pad_len as usize]);
+    body
+}
 
-        stream.reserve_capacity(5);
+#[tokio::test]
+async fn recv_data_padding_counts_against_window() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
 
-        // Initial window size was 10 but with a max send buffer size of 10 in the client,
-        // so current capacity is 0 even if we just reserved.
-        assert_eq!(stream.capacity(), 0);
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_eq!(settings.initial_window_size(), Some(100));
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        // 60 bytes of data fit the window, but not with the padding.
+        client
+            .send_frame(frames::data(1, padded_data(60, 50)).padded())
+            .await;
+        client.recv_frame(frames::reset(1).flow_control()).await;
+    };
 
-        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .initial_window_size(100)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, _respond) = srv.next().await.unwrap().unwrap();
 
-        stream.send_data("".into(), true).unwrap();
+        let mut body = req.into_body();
+        assert!(body.data().await.unwrap().is_err());
 
-        // Wait for the connection to close
-        h2.await.unwrap();
+        assert!(srv.next().await.is_none());
     };
 
-    join(srv, h2).await;
+    join(client, srv).await;
 }
 
 #[tokio::test]
-async fn max_send_buffer_size_overflow() {
+async fn stream_error_releases_padding_connection_capacity() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
     let srv = async move {
         let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(
+            frames::headers(1)
+                .response(200)
+                .field("content-length", &*(16_384 * 3).to_string()),
+        )
+        .await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        // the content-length mismatch is only noticed on a padded frame
+        srv.send_frame(frames::data(1, padded_data(10, 200)).padded().eos())
+            .await;
+        srv.recv_frame(frames::reset(1).protocol_error()).await;
+        // the padding is released along with the rest of the frame
+        srv.recv_frame(frames::window_update(0, 16_384 * 2 + 10 + 201))
             .await;
-        srv.send_frame(frames::headers(1).response(200).eos()).await;
-        srv.recv_frame(frames::data(1, &[0; 10][..])).await;
-        srv.recv_frame(frames::data(1, &[][..]).eos()).await;
     };
 
     let client = async move {
-        let (mut client, mut conn) = client::Builder::new()
-            .max_send_buffer_size(5)
-            .handshake::<_, Bytes>(io)
-            .await
-            .unwrap();
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://www.example.com/")
+            .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+        let req = async {
+            let resp = client
+                .send_request(request, true)
+                .unwrap()
+                .0
+                .await
+                .expect("response");
+            let mut body = resp.into_parts().1;
+            let mut cap = body.flow_control().clone();
+            let err = body
+                .try_for_each(|_| async { Ok(()) })
+                .await
+                .expect_err("body");
+            assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+            cap.release_capacity(16_384 * 2).expect("release_capacity");
+        };
+        conn.drive(req).await;
+        conn.await.expect("client");
+    };
 
-        let response = conn.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+    join(srv, client).await;
+}
 
-        assert_eq!(stream.capacity(), 0);
-        stream.reserve_capacity(10);
-        assert_eq!(
-            stream.capacity(),
-            5,
-            "polled capacity not over max buffer size"
-        );
+#[tokio::test]
+async fn data_on_forgotten_stream_releases_padding() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
 
-        stream.send_data([0; 10][..].into(), false).unwrap();
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("POST", "https://http2.akamai.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+        // stream 1 is closed and forgotten, so its DATA is refused, but the
+        // padding is released along with the payload
+        client
+            .send_frame(frames::data(1, padded_data(16_000, 255)).padded())
+            .await;
+        client.recv_frame(frames::reset(1).stream_closed()).await;
+        // DATA racing the reset is ignored
+        client
+            .send_frame(frames::data(1, padded_data(16_000, 255)).padded())
+            .await;
+        client
+            .recv_frame(frames::window_update(0, 2 * (16_000 + 256)))
+            .await;
+    };
 
-        stream.reserve_capacity(15);
-        assert_eq!(
-            stream.capacity(),
-            0,
-            "now with buffered over the max, don't overflow"
-        );
-        stream.send_data([0; 0][..].into(), true).unwrap();
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (_, mut respond) = srv.next().await.unwrap().unwrap();
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        respond.send_response(rsp, true).unwrap();
+        drop(respond);
 
-        // Wait for the connection to close
-        conn.await.unwrap();
+        assert!(srv.next().await.is_none());
     };
 
-    join(srv, client).await;
+    join(client, srv).await;
 }
 
 #[tokio::test]
-async fn max_send_buffer_size_poll_capacity_wakes_task() {
+async fn recv_data_padding_released_automatically() {
     h2_support::trace_init!();
-    let (io, mut srv) = mock::new();
+    let (io, mut client) = mock::new();
 
-    let srv = async move {
-        let settings = srv.assert_client_handshake().await;
-        assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_eq!(settings.initial_window_size(), Some(100));
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client
+            .send_frame(frames::data(1, padded_data(10, 20)).padded().eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
             .await;
-        srv.send_frame(frames::headers(1).response(200).eos()).await;
-        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
-        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
-        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
-        srv.recv_frame(frames::data(1, &[0; 5][..])).await;
-        srv.recv_frame(frames::data(1, &[][..]).eos()).await;
     };
 
-    let client = async move {
-        let (mut client, mut conn) = client::Builder::new()
-            .max_send_buffer_size(5)
-            .handshake::<_, Bytes>(io)
-            .await
-            .unwrap();
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://www.example.com/")
-            .body(())
-            .unwrap();
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .initial_window_size(100)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
+        let mut body = req.into_body();
+        let data = body.data().await.unwrap().unwrap();
+        assert_eq!(data.len(), 10);
 
-        let response = conn.drive(response).await.unwrap();
+        // Only the data is left for the user to release.
+        assert_eq!(body.flow_control().used_capacity(), 10);
+        assert_eq!(body.flow_control().available_capacity(), 90);
 
-        assert_eq!(response.status(), StatusCode::OK);
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        respond.send_response(rsp, true).unwrap();
 
-        assert_eq!(stream.capacity(), 0);
-        const TO_SEND: usize = 20;
-        stream.reserve_capacity(TO_SEND);
-        assert_eq!(
-            stream.capacity(),
-            5,
-            "polled capacity not over max buffer size"
-        );
+        assert!(srv.next().await.is_none());
+    };
 
-        let t1 = tokio::spawn(async move {
-            let mut sent = 0;
-            let buf = [0; TO_SEND];
-            loop {
-                match poll_fn(|cx| stream.poll_capacity(cx)).await {
-                    None => panic!("no cap"),
-                    Some(Err(e)) => panic!("cap error: {:?}", e),
-                    Some(Ok(cap)) => {
-                        stream
-                            .send_data(buf[sent..(sent + cap)].to_vec().into(), false)
-                            .unwrap();
-                        sent += cap;
-                        if sent >= TO_SEND {
-                            break;
-                        }
-                    }
-                }
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn half_closed_remote_window_updates_only_touch_send_window() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let mut settings = frame::Settings::default();
+    settings.set_initial_window_size(Some(5));
+
+    let client = async move {
+        client.assert_server_handshake_with_settings(settings).await;
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(1, vec![0; 16_384])).await;
+        client
+            .send_frame(frames::data(1, vec![0; 16_384]).eos())
+            .await;
+        // The remote is done sending, so this can only grow our send window.
+        client.send_frame(frames::window_update(1, 5)).await;
+        client.ping_pong([1; 8]).await;
+        tx.send(()).unwrap();
+
+        // The released body is advertised on the connection only, as the
+        // stream will not receive any more data.
+        client.recv_frame(frames::window_update(0, 32_768)).await;
+        client.recv_frame(frames::headers(1).response(200)).await;
+        client.recv_frame(frames::data(1, "helloworld").eos()).await;
+        client.ping_pong([2; 8]).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+
+        let respond = async move {
+            rx.await.unwrap();
+            let mut body = req.into_body();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.unwrap();
+                body.flow_control().release_capacity(chunk.len()).unwrap();
             }
-            stream.send_data(Bytes::new(), true).unwrap();
-        });
+            idle_ms(10).await;
 
-        // Wait for the connection to close
-        conn.await.unwrap();
-        t1.await.unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let mut stream = respond.send_response(rsp, false).unwrap();
+            stream.send_data("helloworld".into(), true).unwrap();
+        };
+        let conn = async move {
+            assert!(srv.next().await.is_none());
+        };
+        join(conn, respond).await;
     };
 
-    join(srv, client).await;
+    join(client, srv).await;
 }
 
 #[tokio::test]
-async fn poll_capacity_wakeup_after_window_update() {
+async fn half_closed_local_window_updates_do_not_touch_recv_window() {
     h2_support::trace_init!();
     let (io, mut srv) = mock::new();
 
-    let srv = async move {
-        let settings = srv
-            .assert_client_handshake_with_settings(frames::settings().initial_window_size(10))
-            .await;
+    let mock = async move {
+        let settings = srv.assert_client_handshake().await;
         assert_default_settings!(settings);
-        srv.recv_frame(frames::headers(1).request("POST", "https://www.example.com/"))
-            .await;
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        // This would overflow the stream's send window, but the client is
+        // done sending, so it is ignored rather than treated as an error.
+        srv.send_frame(frames::window_update(1, 0x7FFF_FFFF)).await;
         srv.send_frame(frames::headers(1).response(200)).await;
-        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
-        srv.send_frame(frames::window_update(1, 5)).await;
-        srv.send_frame(frames::window_update(1, 5)).await;
-        srv.recv_frame(frames::data(1, &b"abcde"[..])).await;
-        srv.recv_frame(frames::data(1, &b""[..]).eos()).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        srv.send_frame(frames::data(1, vec![0; 16_384])).await;
+        // The receive window is still advertised as usual.
+        srv.recv_frame(frames::window_update(0, 32_768)).await;
+        srv.recv_frame(frames::window_update(1, 32_768)).await;
+        srv.send_frame(frames::data(1, "").eos()).await;
     };
 
     let h2 = async move {
-        let (mut client, mut h2) = client::Builder::new()
-            .max_send_buffer_size(5)
-            .handshake::<_, Bytes>(io)
-            .await
-            .unwrap();
+        let (mut client, h2) = client::handshake(io).await.unwrap();
         let request = Request::builder()
-            .method(Method::POST)
-            .uri("https://www.example.com/")
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
             .body(())
             .unwrap();
 
-        let (response, mut stream) = client.send_request(request, false).unwrap();
-
-        let response = h2.drive(response).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-
-        stream.send_data("abcde".into(), false).unwrap();
-
-        stream.reserve_capacity(10);
-        assert_eq!(stream.capacity(), 0);
-
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
-        h2.drive(idle_ms(10)).await;
-        stream.send_data("abcde".into(), false).unwrap();
-
-        stream.reserve_capacity(5);
-        assert_eq!(stream.capacity(), 0);
-
-        // This will panic if there is a bug causing h2 to return Ok(0) from poll_capacity.
-        let mut stream = h2.drive(util::wait_for_capacity(stream, 5)).await;
+        let req = async move {
+            let resp = client.send_request(request, true).unwrap().0.await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let mut body = resp.into_body();
 
-        stream.send_data("".into(), true).unwrap();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.unwrap();
+                body.flow_control().release_capacity(chunk.len()).unwrap();
+            }
+        };
 
-        // Wait for the connection to close
-        h2.await.unwrap();
+        join(
+            async move {
+                h2.await.unwrap();
+            },
+            req,
+        )
+        .await
     };
-
-    join(srv, h2).await;
+    join(mock, h2).await;
 }
 
 #[tokio::test]
-async fn window_size_decremented_past_zero() {
+async fn grant_recv_window_preopens_stream_window() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
-        // let _ = client.assert_server_handshake().await;
-
-        // preface
-        client.write_preface().await;
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
 
-        // the following http 2 bytes are fuzzer-generated
-        client.send_bytes(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await;
-        client
-            .send_bytes(&[
-                0, 0, 23, 1, 1, 0, 249, 255, 191, 131, 1, 1, 1, 70, 1, 1, 1, 1, 65, 1, 1, 65, 1, 1,
-                65, 1, 1, 1, 1, 1, 1, 190,
-            ])
-            .await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client
-            .send_bytes(&[
-                0, 0, 9, 247, 0, 121, 255, 255, 184, 1, 65, 1, 1, 1, 1, 1, 1, 190,
-            ])
-            .await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client
-            .send_bytes(&[0, 0, 3, 0, 1, 0, 249, 255, 191, 1, 1, 190])
-            .await;
-        client
-            .send_bytes(&[0, 0, 2, 50, 107, 0, 0, 0, 1, 0, 0])
-            .await;
-        client
-            .send_bytes(&[0, 0, 5, 2, 0, 0, 0, 0, 1, 128, 0, 55, 0, 0])
-            .await;
-        client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
-            ])
-            .await;
-        client
-            .send_bytes(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 3, 4, 76, 255, 71, 131])
-            .await;
-        client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 39, 184, 171, 74, 33, 0, 3, 107, 50, 98,
-            ])
-            .await;
-        client
-            .send_bytes(&[
-                0, 0, 30, 4, 0, 0, 0, 0, 0, 0, 4, 56, 184, 171, 125, 65, 0, 35, 65, 65, 65, 61,
-                232, 87, 115, 89, 116, 0, 4, 0, 58, 33, 125, 33, 79, 3, 107, 49, 98,
-            ])
-            .await;
-        client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
-            ])
-            .await;
-        client.send_bytes(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await;
-        client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
-            ])
-            .await;
-        client
-            .send_bytes(&[
-                0, 0, 177, 1, 44, 0, 0, 0, 1, 67, 67, 67, 67, 67, 67, 131, 134, 5, 61, 67, 67, 67,
-                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67,
-                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67,
-                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 115, 102, 1, 3, 48, 43,
-                101, 64, 31, 37, 99, 99, 97, 97, 97, 97, 49, 97, 54, 97, 97, 97, 97, 49, 97, 54,
-                97, 99, 54, 53, 53, 51, 53, 99, 99, 97, 97, 99, 97, 97, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0,
-            ])
-            .await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
         client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 0, 58, 171, 125, 33, 79, 3, 107, 49, 98,
-            ])
-            .await;
-        client
-            .send_bytes(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 4, 87, 115, 89, 116])
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
             .await;
+
+        // both grants are advertised together, up to the max window size
         client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 126, 4, 39, 184, 171, 125, 33, 0, 3, 107, 50, 98,
-            ])
+            .recv_frame(frames::window_update(1, 2_147_418_112))
             .await;
         client
-            .send_bytes(&[
-                0, 0, 129, 1, 44, 0, 0, 0, 1, 67, 67, 67, 67, 67, 67, 131, 134, 5, 18, 67, 67, 61,
-                67, 67, 67, 67, 67, 67, 67, 67, 67, 67, 48, 54, 53, 55, 114, 1, 4, 97, 49, 51, 116,
-                64, 2, 117, 115, 4, 103, 101, 110, 116, 64, 8, 57, 111, 110, 116, 101, 110, 115,
-                102, 7, 43, 43, 49, 48, 48, 43, 101, 192, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ])
+            .send_frame(frames::data(1, vec![0; 16_384]).eos())
             .await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
-        client.send_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 1]).await;
         client
-            .send_bytes(&[
-                0, 0, 12, 4, 0, 0, 0, 0, 0, 0, 4, 0, 58, 171, 125, 33, 79, 3, 107, 49, 98,
-            ])
+            .recv_frame(frames::headers(1).response(200).eos())
             .await;
-
-        // TODO: is CANCEL the right error code to expect here?
-        // client.recv_frame(frames::reset(1).protocol_error()).await;
     };
 
     let srv = async move {
-        let builder = server::Builder::new();
-        let mut srv = builder.handshake::<_, Bytes>(io).await.expect("handshake");
+        let mut srv = server::handshake(io).await.expect("handshake");
 
-        // just keep it open
-        let res = poll_fn(move |cx| srv.poll_closed(cx)).await;
-        tracing::debug!("{:?}", res);
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+        let mut body = req.into_body();
+
+        assert_eq!(body.flow_control().grant_recv_window(1_000_000), 1_000_000);
+        assert_eq!(body.flow_control().available_capacity(), 1_065_535);
+        assert_eq!(
+            body.flow_control().grant_recv_window(usize::MAX),
+            2_146_418_112
+        );
+        assert_eq!(body.flow_control().grant_recv_window(1), 0);
+
+        let read = async move {
+            let data = body.data().await.unwrap().unwrap();
+            assert_eq!(data.len(), 16_384);
+            assert!(body.data().await.is_none());
+
+            // the stream no longer receives data
+            assert_eq!(body.flow_control().grant_recv_window(1), 0);
+
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            respond.send_response(rsp, true).unwrap();
+        };
+
+        join(async move { assert!(srv.next().await.is_none()) }, read).await;
     };
 
     join(client, srv).await;
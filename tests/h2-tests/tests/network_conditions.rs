@@ -0,0 +1,78 @@
+use futures::future::join;
+use futures::StreamExt;
+use h2_support::prelude::*;
+
+async fn read_body(mut body: RecvStream) -> Bytes {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.unwrap();
+        body.flow_control().release_capacity(chunk.len()).unwrap();
+        buf.extend_from_slice(&chunk);
+    }
+    buf.freeze()
+}
+
+#[tokio::test]
+async fn request_response_under_reordering() {
+    h2_support::trace_init!();
+
+    let conditions = Conditions::new()
+        .reorder()
+        .latency(Duration::from_millis(5));
+    let sim = SimConnection::new(conditions.clone(), conditions);
+
+    let bodies = ["hello", "world"];
+
+    let server = async {
+        let mut srv = server::handshake(sim.server_io()).await.unwrap();
+
+        // Accept both requests before responding, so that the DATA frames of
+        // both responses are flushed together and can be reordered.
+        let mut streams = vec![];
+        for _ in 0..bodies.len() {
+            let (req, stream) = srv.next().await.unwrap().unwrap();
+            let id = stream.stream_id();
+            streams.push((read_body(req.into_body()).await, stream, id));
+        }
+
+        for (body, mut stream, _) in streams {
+            let rsp = Response::builder().status(200).body(()).unwrap();
+            let mut send = stream.send_response(rsp, false).unwrap();
+            send.send_data(body, true).unwrap();
+        }
+
+        poll_fn(|cx| srv.poll_closed(cx)).await.unwrap();
+    };
+
+    let client = async {
+        let (mut client, mut conn) = client::handshake(sim.client_io()).await.unwrap();
+
+        let mut responses = vec![];
+        for body in bodies {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri("https://example.com/")
+                .body(())
+                .unwrap();
+            let (rsp, mut send) = client.send_request(req, false).unwrap();
+            send.send_data(Bytes::from(body), true).unwrap();
+            responses.push(rsp);
+        }
+
+        for (rsp, body) in responses.into_iter().zip(bodies) {
+            let rsp = conn.drive(rsp).await.unwrap();
+            assert_eq!(rsp.status(), StatusCode::OK);
+            let echoed = conn.drive(read_body(rsp.into_body())).await;
+            assert_eq!(echoed, body);
+        }
+
+        drop(client);
+        conn.await.unwrap();
+    };
+
+    join(server, client).await;
+
+    assert!(sim.to_server_stats().reordered > 0);
+    assert!(sim.to_client_stats().reordered > 0);
+    assert_eq!(sim.to_server_stats().dropped, 0);
+}
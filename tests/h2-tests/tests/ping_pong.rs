@@ -93,6 +93,31 @@ async fn pong_has_highest_priority() {
     join(client, srv).await;
 }
 
+#[tokio::test]
+async fn recv_ping_on_stream_is_connection_error() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        // PING on stream 3
+        client
+            .send_bytes(&[0, 0, 8, 6, 0, 0, 0, 0, 3, 1, 1, 1, 1, 1, 1, 1, 1])
+            .await;
+        client.recv_frame(frames::go_away(0).protocol_error()).await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut s = server::handshake(io).await.expect("handshake");
+        let err = s.next().await.unwrap().unwrap_err();
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+    };
+
+    join(client, srv).await;
+}
+
 #[tokio::test]
 async fn user_ping_pong() {
     h2_support::trace_init!();
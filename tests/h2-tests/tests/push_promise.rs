@@ -313,7 +313,7 @@ async fn recv_invalid_push_promise_headers_is_stream_protocol_error() {
                 .field(http::header::CONTENT_LENGTH, 0),
         )
         .await;
-        srv.send_frame(frames::headers(1).response(404).eos()).await;
+        srv.send_frame(frames::headers(1).eos()).await;
         srv.recv_frame(frames::reset(2).protocol_error()).await;
         srv.recv_frame(frames::reset(4).protocol_error()).await;
         srv.send_frame(frames::headers(6).response(200).eos()).await;
@@ -340,6 +340,52 @@ async fn recv_invalid_push_promise_headers_is_stream_protocol_error() {
     join(mock, h2).await;
 }
 
+#[tokio::test]
+async fn recv_push_promise_head_is_accepted() {
+    h2_support::trace_init!();
+
+    let (io, mut srv) = mock::new();
+    let mock = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        srv.send_frame(
+            frames::push_promise(1, 2).request("HEAD", "https://http2.akamai.com/style.css"),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).eos()).await;
+        srv.send_frame(frames::headers(2).response(200).eos()).await;
+    };
+
+    let h2 = async move {
+        let (mut client, mut h2) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+        let (mut resp, _) = client.send_request(request, true).unwrap();
+        let check_pushed_request = async move {
+            let pushed = resp.push_promises();
+            let methods: Vec<_> = pushed
+                .map_ok(|push| push.into_parts().0.into_parts().0.method)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(methods, [Method::HEAD]);
+        };
+        h2.drive(check_pushed_request).await;
+    };
+
+    join(mock, h2).await;
+}
+
 #[test]
 #[ignore]
 fn recv_push_promise_with_wrong_authority_is_stream_error() {
@@ -1,8 +1,11 @@
 #![deny(warnings)]
 
 use futures::future::{join, poll_fn};
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
+use h2::{StateObserver, StreamState, TransitionCause};
 use h2_support::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::io::AsyncWriteExt;
 
 const SETTINGS: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
@@ -71,80 +74,133 @@ async fn server_builder_set_max_concurrent_streams() {
 }
 
 #[tokio::test]
-async fn serve_request() {
+async fn num_active_recv_streams_tracks_admitted_streams() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
-        let settings = client.assert_server_handshake().await;
-        assert_default_settings!(settings);
+        client
+            .assert_server_handshake_with_settings(frames::settings())
+            .await;
+        client
+            .send_frame(frames::headers(1).request("GET", "https://example.com/"))
+            .await;
+        client
+            .send_frame(frames::headers(3).request("GET", "https://example.com/"))
+            .await;
+        client.recv_frame(frames::reset(3).refused()).await;
+        client
+            .send_frame(frames::data(1, &b"hello"[..]).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
         client
             .send_frame(
-                frames::headers(1)
+                frames::headers(5)
                     .request("GET", "https://example.com/")
                     .eos(),
             )
             .await;
         client
-            .recv_frame(frames::headers(1).response(200).eos())
+            .recv_frame(frames::headers(5).response(200).eos())
             .await;
     };
 
-    let srv = async move {
-        let mut srv = server::handshake(io).await.expect("handshake");
-        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+    let h2 = async move {
+        let mut srv = server::Builder::new()
+            .max_concurrent_streams(1)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        assert_eq!(srv.num_active_recv_streams(), 0);
 
-        assert_eq!(req.method(), &http::Method::GET);
+        let (_req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(srv.max_concurrent_recv_streams(), 1);
+        assert_eq!(srv.num_active_recv_streams(), 1);
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        let (_req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(srv.num_active_recv_streams(), 1);
 
         let rsp = http::Response::builder().status(200).body(()).unwrap();
         stream.send_response(rsp, true).unwrap();
 
         assert!(srv.next().await.is_none());
+        assert_eq!(srv.num_active_recv_streams(), 0);
     };
 
-    join(client, srv).await;
+    join(client, h2).await;
 }
 
 #[tokio::test]
-async fn serve_connect() {
+async fn reset_lowest_priority_streams_sheds_lowest_weights() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
+    let (flushed_tx, mut flushed_rx) = futures::channel::oneshot::channel();
 
     let client = async move {
         let settings = client.assert_server_handshake().await;
         assert_default_settings!(settings);
+        for id in [1, 3, 5] {
+            client
+                .send_frame(frames::headers(id).request("POST", "https://example.com/"))
+                .await;
+        }
+        for id in [1, 3, 5] {
+            client.recv_frame(frames::headers(id).response(200)).await;
+        }
+        flushed_tx.send(()).unwrap();
         client
-            .send_frame(frames::headers(1).request("CONNECT", "localhost").eos())
+            .recv_frame(frames::reset(1).reason(Reason::ENHANCE_YOUR_CALM))
             .await;
         client
-            .recv_frame(frames::headers(1).response(200).eos())
+            .recv_frame(frames::reset(5).reason(Reason::ENHANCE_YOUR_CALM))
             .await;
     };
 
-    let srv = async move {
+    let h2 = async move {
         let mut srv = server::handshake(io).await.expect("handshake");
-        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+        let mut responses = vec![];
 
-        assert_eq!(req.method(), &http::Method::CONNECT);
+        for weight in [8, 64, 16] {
+            let (_req, mut respond) = srv.next().await.unwrap().unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let mut stream = respond.send_response(rsp, false).unwrap();
+            stream.set_weight(weight);
+            responses.push(stream);
+        }
 
-        let rsp = http::Response::builder().status(200).body(()).unwrap();
-        stream.send_response(rsp, true).unwrap();
+        // flush the responses so the resets are the only frames left to send
+        poll_fn(|cx| {
+            assert!(srv.poll_closed(cx).is_pending());
+            flushed_rx.poll_unpin(cx)
+        })
+        .await
+        .unwrap();
 
-        assert!(srv.next().await.is_none());
+        assert_eq!(
+            srv.reset_lowest_priority_streams(2, Reason::ENHANCE_YOUR_CALM),
+            2
+        );
+
+        let _ = poll_fn(|cx| srv.poll_closed(cx)).await;
+        drop(responses);
     };
 
-    join(client, srv).await;
+    join(client, h2).await;
 }
 
 #[tokio::test]
-async fn push_request() {
+async fn serve_request() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
-        client
-            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
-            .await;
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
         client
             .send_frame(
                 frames::headers(1)
@@ -152,22 +208,6 @@ async fn push_request() {
                     .eos(),
             )
             .await;
-        client
-            .recv_frame(
-                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
-            )
-            .await;
-        client
-            .recv_frame(frames::headers(2).response(200).eos())
-            .await;
-        client
-            .recv_frame(
-                frames::push_promise(1, 4).request("GET", "https://http2.akamai.com/style2.css"),
-            )
-            .await;
-        client
-            .recv_frame(frames::headers(4).response(200).eos())
-            .await;
         client
             .recv_frame(frames::headers(1).response(200).eos())
             .await;
@@ -179,38 +219,6 @@ async fn push_request() {
 
         assert_eq!(req.method(), &http::Method::GET);
 
-        // Promise stream 2
-        let mut pushed_s2 = {
-            let req = http::Request::builder()
-                .method("GET")
-                .uri("https://http2.akamai.com/style.css")
-                .body(())
-                .unwrap();
-            stream.push_request(req).unwrap()
-        };
-
-        // Promise stream 4 and push response headers
-        {
-            let req = http::Request::builder()
-                .method("GET")
-                .uri("https://http2.akamai.com/style2.css")
-                .body(())
-                .unwrap();
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            stream
-                .push_request(req)
-                .unwrap()
-                .send_response(rsp, true)
-                .unwrap();
-        }
-
-        // Push response to stream 2
-        {
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            pushed_s2.send_response(rsp, true).unwrap();
-        }
-
-        // Send response for stream 1
         let rsp = http::Response::builder().status(200).body(()).unwrap();
         stream.send_response(rsp, true).unwrap();
 
@@ -221,21 +229,17 @@ async fn push_request() {
 }
 
 #[tokio::test]
-async fn push_request_disabled() {
+async fn serve_request_with_empty_eos_data_after_headers() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
         client
-            .assert_server_handshake_with_settings(frames::settings().disable_push())
-            .await;
-        client
-            .send_frame(
-                frames::headers(1)
-                    .request("GET", "https://example.com/")
-                    .eos(),
-            )
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
             .await;
+        client.send_frame(frames::data(1, &b""[..]).eos()).await;
         client
             .recv_frame(frames::headers(1).response(200).eos())
             .await;
@@ -245,19 +249,14 @@ async fn push_request_disabled() {
         let mut srv = server::handshake(io).await.expect("handshake");
         let (req, mut stream) = srv.next().await.unwrap().unwrap();
 
-        assert_eq!(req.method(), &http::Method::GET);
+        assert_eq!(req.method(), &http::Method::POST);
 
-        // attempt to push - expect failure
-        let req = http::Request::builder()
-            .method("GET")
-            .uri("https://http2.akamai.com/style.css")
-            .body(())
-            .unwrap();
-        stream
-            .push_request(req)
-            .expect_err("push_request should error");
+        // The empty frame is surfaced as-is, then the body ends.
+        let mut body = req.into_body();
+        assert!(body.data().await.unwrap().unwrap().is_empty());
+        assert!(body.data().await.is_none());
+        assert!(body.is_end_stream());
 
-        // send normal response
         let rsp = http::Response::builder().status(200).body(()).unwrap();
         stream.send_response(rsp, true).unwrap();
 
@@ -268,36 +267,21 @@ async fn push_request_disabled() {
 }
 
 #[tokio::test]
-async fn push_request_against_concurrency() {
+async fn serve_request_with_end_stream_headers_and_continuation() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        // HEADERS with END_STREAM but without END_HEADERS: GET https /
         client
-            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(1))
-            .await;
-        client
-            .send_frame(
-                frames::headers(1)
-                    .request("GET", "https://example.com/")
-                    .eos(),
-            )
-            .await;
-        client
-            .recv_frame(
-                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
-            )
-            .await;
-        client.recv_frame(frames::headers(2).response(200)).await;
-        client
-            .recv_frame(
-                frames::push_promise(1, 4).request("GET", "https://http2.akamai.com/style2.css"),
-            )
-            .await;
-        client.recv_frame(frames::data(2, &b""[..]).eos()).await;
-        client
-            .recv_frame(frames::headers(4).response(200).eos())
+            .send_bytes(&[0, 0, 3, 1, 0x1, 0, 0, 0, 1, 0x82, 0x87, 0x84])
             .await;
+        // CONTINUATION with END_HEADERS: :authority example.com
+        let mut continuation = vec![0, 0, 13, 9, 0x4, 0, 0, 0, 1, 0x41, 0x0b];
+        continuation.extend_from_slice(b"example.com");
+        client.send_bytes(&continuation).await;
         client
             .recv_frame(frames::headers(1).response(200).eos())
             .await;
@@ -308,42 +292,20 @@ async fn push_request_against_concurrency() {
         let (req, mut stream) = srv.next().await.unwrap().unwrap();
 
         assert_eq!(req.method(), &http::Method::GET);
+        assert_eq!(req.uri(), "https://example.com/");
 
-        // Promise stream 2 and start response (concurrency limit reached)
-        let mut s2_tx = {
-            let req = http::Request::builder()
-                .method("GET")
-                .uri("https://http2.akamai.com/style.css")
-                .body(())
-                .unwrap();
-            let mut pushed_stream = stream.push_request(req).unwrap();
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            pushed_stream.send_response(rsp, false).unwrap()
-        };
-
-        // Promise stream 4 and push response
-        {
-            let pushed_req = http::Request::builder()
-                .method("GET")
-                .uri("https://http2.akamai.com/style2.css")
-                .body(())
-                .unwrap();
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            stream
-                .push_request(pushed_req)
-                .unwrap()
-                .send_response(rsp, true)
-                .unwrap();
-        }
-
-        // Send and finish response for stream 1
-        {
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            stream.send_response(rsp, true).unwrap();
-        }
+        // The END_STREAM flag of the HEADERS applies once the header block
+        // is complete.
+        let mut body = req.into_body();
+        assert!(body.is_end_stream());
+        assert_eq!(
+            body.state_durations().current(),
+            StreamState::HalfClosedRemote
+        );
+        assert!(body.data().await.is_none());
 
-        // Finish response for stream 2 (at which point stream 4 will be sent)
-        s2_tx.send_data(vec![0; 0].into(), true).unwrap();
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
 
         assert!(srv.next().await.is_none());
     };
@@ -352,64 +314,29 @@ async fn push_request_against_concurrency() {
 }
 
 #[tokio::test]
-async fn push_request_with_data() {
+async fn serve_connect() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
         client
-            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
-            .await;
-        client
-            .send_frame(
-                frames::headers(1)
-                    .request("GET", "https://example.com/")
-                    .eos(),
-            )
+            .send_frame(frames::headers(1).request("CONNECT", "localhost").eos())
             .await;
-        client.recv_frame(frames::headers(1).response(200)).await;
         client
-            .recv_frame(
-                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
-            )
+            .recv_frame(frames::headers(1).response(200).eos())
             .await;
-        client.recv_frame(frames::headers(2).response(200)).await;
-        client.recv_frame(frames::data(1, &b""[..]).eos()).await;
-        client.recv_frame(frames::data(2, &b"\x00"[..]).eos()).await;
     };
 
     let srv = async move {
         let mut srv = server::handshake(io).await.expect("handshake");
         let (req, mut stream) = srv.next().await.unwrap().unwrap();
 
-        assert_eq!(req.method(), &http::Method::GET);
-
-        // Start response to stream 1
-        let mut s1_tx = {
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            stream.send_response(rsp, false).unwrap()
-        };
-
-        // Promise stream 2, push response headers and send data
-        {
-            let pushed_req = http::Request::builder()
-                .method("GET")
-                .uri("https://http2.akamai.com/style.css")
-                .body(())
-                .unwrap();
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            let mut push_tx = stream
-                .push_request(pushed_req)
-                .unwrap()
-                .send_response(rsp, false)
-                .unwrap();
-            // Make sure nothing can queue our pushed stream before we have the PushPromise sent
-            push_tx.send_data(vec![0; 1].into(), true).unwrap();
-            push_tx.reserve_capacity(1);
-        }
+        assert_eq!(req.method(), &http::Method::CONNECT);
 
-        // End response for stream 1
-        s1_tx.send_data(vec![0; 0].into(), true).unwrap();
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
 
         assert!(srv.next().await.is_none());
     };
@@ -418,12 +345,132 @@ async fn push_request_with_data() {
 }
 
 #[tokio::test]
-async fn push_request_between_data() {
+async fn expected_scheme_rejects_mismatched_request() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
     let client = async move {
-        client
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "http://example.com/")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+
+        client
+            .send_frame(
+                frames::headers(3)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(3).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .expected_scheme(http::uri::Scheme::HTTPS)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(req.uri().scheme(), Some(&http::uri::Scheme::HTTPS));
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn expected_scheme_allows_connect() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("CONNECT", "localhost").eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .expected_scheme(http::uri::Scheme::HTTPS)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(req.method(), &http::Method::CONNECT);
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_response_headers_is_protocol_error() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).response(200).eos())
+            .await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+
+        client
+            .send_frame(
+                frames::headers(3)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(3).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(req.method(), &http::Method::GET);
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn push_request() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        client
             .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
             .await;
         client
@@ -433,8 +480,6 @@ async fn push_request_between_data() {
                     .eos(),
             )
             .await;
-        client.recv_frame(frames::headers(1).response(200)).await;
-        client.recv_frame(frames::data(1, &b""[..])).await;
         client
             .recv_frame(
                 frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
@@ -443,7 +488,17 @@ async fn push_request_between_data() {
         client
             .recv_frame(frames::headers(2).response(200).eos())
             .await;
-        client.recv_frame(frames::data(1, &b""[..]).eos()).await;
+        client
+            .recv_frame(
+                frames::push_promise(1, 4).request("GET", "https://http2.akamai.com/style2.css"),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(4).response(200).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
     };
 
     let srv = async move {
@@ -452,31 +507,40 @@ async fn push_request_between_data() {
 
         assert_eq!(req.method(), &http::Method::GET);
 
-        // Push response to stream 1 and send some data
-        let mut s1_tx = {
-            let rsp = http::Response::builder().status(200).body(()).unwrap();
-            let mut tx = stream.send_response(rsp, false).unwrap();
-            tx.send_data(vec![0; 0].into(), false).unwrap();
-            tx
+        // Promise stream 2
+        let mut pushed_s2 = {
+            let req = http::Request::builder()
+                .method("GET")
+                .uri("https://http2.akamai.com/style.css")
+                .body(())
+                .unwrap();
+            stream.push_request(req).unwrap()
         };
 
-        // Promise stream 2 and push response headers
+        // Promise stream 4 and push response headers
         {
-            let pushed_req = http::Request::builder()
+            let req = http::Request::builder()
                 .method("GET")
-                .uri("https://http2.akamai.com/style.css")
+                .uri("https://http2.akamai.com/style2.css")
                 .body(())
                 .unwrap();
             let rsp = http::Response::builder().status(200).body(()).unwrap();
             stream
-                .push_request(pushed_req)
+                .push_request(req)
                 .unwrap()
                 .send_response(rsp, true)
                 .unwrap();
         }
 
-        // End response for stream 1
-        s1_tx.send_data(vec![0; 0].into(), true).unwrap();
+        // Push response to stream 2
+        {
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            pushed_s2.send_response(rsp, true).unwrap();
+        }
+
+        // Send response for stream 1
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
 
         assert!(srv.next().await.is_none());
     };
@@ -484,31 +548,47 @@ async fn push_request_between_data() {
     join(client, srv).await;
 }
 
-#[test]
-#[ignore]
-fn accept_with_pending_connections_after_socket_close() {}
-
 #[tokio::test]
-async fn recv_invalid_authority() {
+async fn push_request_disabled() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
-    let bad_auth = util::byte_str("not:a/good authority");
-    let mut bad_headers: frame::Headers = frames::headers(1)
-        .request("GET", "https://example.com/")
-        .eos()
-        .into();
-    bad_headers.pseudo_mut().authority = Some(bad_auth);
-
     let client = async move {
-        let settings = client.assert_server_handshake().await;
-        assert_default_settings!(settings);
-        client.send_frame(bad_headers).await;
-        client.recv_frame(frames::reset(1).protocol_error()).await;
+        client
+            .assert_server_handshake_with_settings(frames::settings().disable_push())
+            .await;
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
     };
 
     let srv = async move {
         let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.method(), &http::Method::GET);
+
+        // attempt to push - expect failure
+        let req = http::Request::builder()
+            .method("GET")
+            .uri("https://http2.akamai.com/style.css")
+            .body(())
+            .unwrap();
+        stream
+            .push_request(req)
+            .expect_err("push_request should error");
+
+        // send normal response
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
         assert!(srv.next().await.is_none());
     };
 
@@ -516,36 +596,1055 @@ async fn recv_invalid_authority() {
 }
 
 #[tokio::test]
-async fn recv_connection_header() {
+async fn push_request_not_safe_and_cacheable() {
     h2_support::trace_init!();
     let (io, mut client) = mock::new();
 
-    let req = |id, name, val| {
-        frames::headers(id)
-            .request("GET", "https://example.com/")
-            .field(name, val)
-            .eos()
-    };
-
     let client = async move {
-        let settings = client.assert_server_handshake().await;
-        assert_default_settings!(settings);
-        client.send_frame(req(1, "connection", "foo")).await;
-        client.send_frame(req(3, "keep-alive", "5")).await;
-        client.send_frame(req(5, "proxy-connection", "bar")).await;
         client
-            .send_frame(req(7, "transfer-encoding", "chunked"))
+            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
             .await;
-        client.send_frame(req(9, "upgrade", "HTTP/2")).await;
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        // Only the safe, bodiless request is promised.
+        client
+            .recv_frame(
+                frames::push_promise(1, 2)
+                    .request("HEAD", "https://http2.akamai.com/style.css")
+                    .field(http::header::CONTENT_LENGTH, 0),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (_, mut stream) = srv.next().await.unwrap().unwrap();
+
+        let push = |method: &str, content_length: u64| {
+            http::Request::builder()
+                .method(method)
+                .uri("https://http2.akamai.com/style.css")
+                .header(http::header::CONTENT_LENGTH, content_length)
+                .body(())
+                .unwrap()
+        };
+
+        let err = stream.push_request(push("POST", 0)).unwrap_err();
+        assert_eq!(err.to_string(), "user error: malformed headers");
+        let err = stream.push_request(push("GET", 1)).unwrap_err();
+        assert_eq!(err.to_string(), "user error: malformed headers");
+
+        let _pushed = stream.push_request(push("HEAD", 0)).unwrap();
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn push_request_against_concurrency() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        client
+            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(1))
+            .await;
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(
+                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
+            )
+            .await;
+        client.recv_frame(frames::headers(2).response(200)).await;
+        client
+            .recv_frame(
+                frames::push_promise(1, 4).request("GET", "https://http2.akamai.com/style2.css"),
+            )
+            .await;
+        client.recv_frame(frames::data(2, &b""[..]).eos()).await;
+        client
+            .recv_frame(frames::headers(4).response(200).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.method(), &http::Method::GET);
+
+        // Promise stream 2 and start response (concurrency limit reached)
+        let mut s2_tx = {
+            let req = http::Request::builder()
+                .method("GET")
+                .uri("https://http2.akamai.com/style.css")
+                .body(())
+                .unwrap();
+            let mut pushed_stream = stream.push_request(req).unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            pushed_stream.send_response(rsp, false).unwrap()
+        };
+
+        // Promise stream 4 and push response
+        {
+            let pushed_req = http::Request::builder()
+                .method("GET")
+                .uri("https://http2.akamai.com/style2.css")
+                .body(())
+                .unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream
+                .push_request(pushed_req)
+                .unwrap()
+                .send_response(rsp, true)
+                .unwrap();
+        }
+
+        // Send and finish response for stream 1
+        {
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, true).unwrap();
+        }
+
+        // Finish response for stream 2 (at which point stream 4 will be sent)
+        s2_tx.send_data(vec![0; 0].into(), true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn push_request_with_data() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        client
+            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
+            .await;
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::headers(1).response(200)).await;
+        client
+            .recv_frame(
+                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
+            )
+            .await;
+        client.recv_frame(frames::headers(2).response(200)).await;
+        client.recv_frame(frames::data(1, &b""[..]).eos()).await;
+        client.recv_frame(frames::data(2, &b"\x00"[..]).eos()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.method(), &http::Method::GET);
+
+        // Start response to stream 1
+        let mut s1_tx = {
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, false).unwrap()
+        };
+
+        // Promise stream 2, push response headers and send data
+        {
+            let pushed_req = http::Request::builder()
+                .method("GET")
+                .uri("https://http2.akamai.com/style.css")
+                .body(())
+                .unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let mut push_tx = stream
+                .push_request(pushed_req)
+                .unwrap()
+                .send_response(rsp, false)
+                .unwrap();
+            // Make sure nothing can queue our pushed stream before we have the PushPromise sent
+            push_tx.send_data(vec![0; 1].into(), true).unwrap();
+            push_tx.reserve_capacity(1);
+        }
+
+        // End response for stream 1
+        s1_tx.send_data(vec![0; 0].into(), true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn push_request_between_data() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        client
+            .assert_server_handshake_with_settings(frames::settings().max_concurrent_streams(100))
+            .await;
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::headers(1).response(200)).await;
+        client.recv_frame(frames::data(1, &b""[..])).await;
+        client
+            .recv_frame(
+                frames::push_promise(1, 2).request("GET", "https://http2.akamai.com/style.css"),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(2).response(200).eos())
+            .await;
+        client.recv_frame(frames::data(1, &b""[..]).eos()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.method(), &http::Method::GET);
+
+        // Push response to stream 1 and send some data
+        let mut s1_tx = {
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let mut tx = stream.send_response(rsp, false).unwrap();
+            tx.send_data(vec![0; 0].into(), false).unwrap();
+            tx
+        };
+
+        // Promise stream 2 and push response headers
+        {
+            let pushed_req = http::Request::builder()
+                .method("GET")
+                .uri("https://http2.akamai.com/style.css")
+                .body(())
+                .unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream
+                .push_request(pushed_req)
+                .unwrap()
+                .send_response(rsp, true)
+                .unwrap();
+        }
+
+        // End response for stream 1
+        s1_tx.send_data(vec![0; 0].into(), true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[test]
+#[ignore]
+fn accept_with_pending_connections_after_socket_close() {}
+
+#[tokio::test]
+async fn recv_invalid_authority() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let bad_auth = util::byte_str("not:a/good authority");
+    let mut bad_headers: frame::Headers = frames::headers(1)
+        .request("GET", "https://example.com/")
+        .eos()
+        .into();
+    bad_headers.pseudo_mut().authority = Some(bad_auth);
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client.send_frame(bad_headers).await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_authority_only() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.uri().authority().unwrap(), "example.com");
+        assert!(req.headers().get("host").is_none());
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_host_matching_authority_is_dropped() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .field("host", "EXAMPLE.com")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        assert_eq!(req.uri().authority().unwrap(), "example.com");
+        assert!(req.headers().get("host").is_none());
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_host_mismatching_authority() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .field("host", "example.org")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_continuation_flood_over_header_block_size() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        // HEADERS without END_HEADERS, followed by CONTINUATION frames that
+        // never end the header block.
+        client.send_bytes(&[0, 0, 1, 1, 0, 0, 0, 0, 1, 0x82]).await;
+        for _ in 0..4 {
+            client
+                .send_bytes(&[0, 0, 4, 9, 0, 0, 0, 0, 1, 0x90, 0x90, 0x90, 0x90])
+                .await;
+        }
+        client.recv_frame(frames::go_away(0).calm()).await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .max_header_block_size(8)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn stream_context_shared_across_transitions() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_frame(frames::data(1, "hello").eos()).await;
+        client.recv_frame(frames::headers(1).response(200)).await;
+        client.recv_frame(frames::data(1, "world").eos()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+        let mut body = req.into_body();
+
+        // open
+        assert_eq!(body.context::<RequestId>(), None);
+        assert_eq!(body.insert_context(RequestId(1)), None);
+        assert_eq!(body.insert_context(RequestId(7)), Some(RequestId(1)));
+
+        let handler = async move {
+            // half closed (remote)
+            let data = body.data().await.unwrap().unwrap();
+            assert_eq!(data, "hello");
+            assert!(body.data().await.is_none());
+            assert_eq!(body.context::<RequestId>(), Some(RequestId(7)));
+
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            let mut stream = respond.send_response(rsp, false).unwrap();
+            assert_eq!(stream.context::<RequestId>(), Some(RequestId(7)));
+            assert_eq!(stream.context::<&'static str>(), None);
+            stream.insert_context("tag");
+
+            // closed
+            stream.send_data("world".into(), true).unwrap();
+            body
+        };
+
+        let (_, body) = join(async move { assert!(srv.next().await.is_none()) }, handler).await;
+        assert_eq!(body.context::<RequestId>(), Some(RequestId(7)));
+        assert_eq!(body.context::<&'static str>(), Some("tag"));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_headers_on_connection_stream() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        // HEADERS with END_HEADERS | END_STREAM on stream 0
+        client
+            .send_bytes(&[0, 0, 1, 1, 0x5, 0, 0, 0, 0, 0x82])
+            .await;
+        client.recv_frame(frames::go_away(0).protocol_error()).await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+    };
+
+    join(client, srv).await;
+}
+
+/// Returns a GET request HEADERS frame, ending the stream, that carries a
+/// `name: text/plain` field encoded without Huffman coding.
+fn request_bytes_with_field(id: u8, name: &[u8]) -> Vec<u8> {
+    // :method GET, :scheme https, :path /
+    let mut block = vec![0x82, 0x87, 0x84];
+    // :authority, literal without indexing
+    block.extend_from_slice(&[0x01, 11]);
+    block.extend_from_slice(b"example.com");
+    // literal without indexing, new name
+    block.extend_from_slice(&[0x00, name.len() as u8]);
+    block.extend_from_slice(name);
+    block.push(10);
+    block.extend_from_slice(b"text/plain");
+
+    let mut frame = vec![0, 0, block.len() as u8, 1, 0x5, 0, 0, 0, id];
+    frame.extend_from_slice(&block);
+    frame
+}
+
+#[tokio::test]
+async fn recv_headers_with_uppercase_field_name() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_bytes(&request_bytes_with_field(1, b"Content-Type"))
+            .await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+
+        client
+            .send_bytes(&request_bytes_with_field(3, b"content-type"))
+            .await;
+        client
+            .recv_frame(frames::headers(3).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+        assert_eq!(req.headers()["content-type"], "text/plain");
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn too_many_local_error_resets_sends_reset_then_go_away() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_bytes(&request_bytes_with_field(1, b"Content-Type"))
+            .await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+
+        // The stream that hits the limit still gets its own reason.
+        client
+            .send_bytes(&request_bytes_with_field(3, b"Content-Type"))
+            .await;
+        client.recv_frame(frames::reset(3).protocol_error()).await;
+        client
+            .recv_frame(frames::go_away(0).calm().data("too_many_internal_resets"))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .max_local_error_reset_streams(Some(1))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn local_error_resets_unlimited() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        for id in (1..10).step_by(2) {
+            client
+                .send_bytes(&request_bytes_with_field(id, b"Content-Type"))
+                .await;
+            client
+                .recv_frame(frames::reset(id as u32).protocol_error())
+                .await;
+        }
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .max_local_error_reset_streams(None)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_unknown_frames_are_ignored_and_observed() {
+    #[derive(Clone, Debug, Default)]
+    struct CountUnknown(Arc<AtomicUsize>);
+
+    impl StateObserver for CountUnknown {
+        fn on_unknown_frame(&self, frame_type: u8, _: h2::StreamId, _: usize) {
+            assert_eq!(frame_type, 0x20);
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let observer = CountUnknown::default();
+    let count = observer.0.clone();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_bytes(&[0, 0, 2, 0x20, 0, 0, 0, 0, 0, 1, 2])
+            .await;
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client.send_bytes(&[0, 0, 0, 0x20, 0, 0, 0, 0, 1]).await;
+        client
+            .send_frame(frames::data(1, &b"hello"[..]).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .state_observer(observer)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        let mut body = req.into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "hello");
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn recv_ping_flood_over_frame_rate_limit() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        for _ in 0..5 {
+            client.send_frame(frames::ping([1; 8])).await;
+            client.recv_frame(frames::ping([1; 8]).pong()).await;
+        }
+        client.send_frame(frames::ping([1; 8])).await;
+        client
+            .recv_frame(frames::go_away(0).calm().data("too_many_pings"))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .frame_rate_limit(5, Duration::from_secs(60))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_settings_flood_over_frame_rate_limit() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        // The preface SETTINGS counts towards the limit.
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        for _ in 0..2 {
+            client.send_frame(frames::settings()).await;
+            client.recv_frame(frames::settings_ack()).await;
+        }
+        client.send_frame(frames::settings()).await;
+        client
+            .recv_frame(frames::go_away(0).calm().data("too_many_settings"))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .frame_rate_limit(3, Duration::from_secs(60))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_empty_data_flood_over_frame_rate_limit() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        for _ in 0..4 {
+            client.send_frame(frames::data(1, &b""[..])).await;
+        }
+        client
+            .recv_frame(frames::go_away(1).calm().data("too_many_empty_data"))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .frame_rate_limit(3, Duration::from_secs(60))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_empty_data_flood_over_empty_data_rate_limit() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        // other frames are not limited
+        for _ in 0..5 {
+            client.send_frame(frames::ping([1; 8])).await;
+            client.recv_frame(frames::ping([1; 8]).pong()).await;
+        }
+        for _ in 0..4 {
+            client.send_frame(frames::data(1, &b""[..])).await;
+        }
+        client
+            .recv_frame(frames::go_away(1).calm().data("too_many_empty_data"))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .empty_data_rate_limit(3, Duration::from_secs(60))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let _req = srv.next().await.unwrap().unwrap();
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::ENHANCE_YOUR_CALM));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn empty_data_rate_limit_replaces_frame_rate_limit() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        // over the general limit, but within the empty DATA limit
+        for _ in 0..5 {
+            client.send_frame(frames::data(1, &b""[..])).await;
+        }
+        client
+            .send_frame(frames::data(1, &b"hello"[..]).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .frame_rate_limit(2, Duration::from_secs(60))
+            .empty_data_rate_limit(10, Duration::from_secs(60))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        let read = async move {
+            let mut body = req.into_body();
+            let mut len = 0;
+            while let Some(data) = body.data().await {
+                len += data.unwrap().len();
+            }
+            assert_eq!(len, 5);
+
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, true).unwrap();
+        };
+
+        join(async move { assert!(srv.next().await.is_none()) }, read).await;
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn frame_rate_limit_resets_each_interval() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        for _ in 0..3 {
+            for _ in 0..2 {
+                client.send_frame(frames::ping([1; 8])).await;
+                client.recv_frame(frames::ping([1; 8]).pong()).await;
+            }
+            idle_ms(60).await;
+        }
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .frame_rate_limit(2, Duration::from_millis(50))
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+/// Sends `frame` in place of the client's preface SETTINGS and asserts that
+/// the server rejects the connection with `debug_data`.
+async fn recv_frame_before_settings<F>(frame: F, debug_data: &'static str)
+where
+    F: Into<frame::Frame>,
+{
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let frame = frame.into();
+
+    let client = async move {
+        client.write_preface().await;
+        client.send(frame).await.unwrap();
+
+        let settings = assert_settings!(client.next().await.expect("unexpected EOF").unwrap());
+        assert!(!settings.is_ack());
+        client
+            .recv_frame(frames::go_away(0).protocol_error().data(debug_data))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let err = srv.next().await.unwrap().expect_err("server");
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_headers_before_settings() {
+    recv_frame_before_settings(
+        frames::headers(1)
+            .request("GET", "https://example.com/")
+            .eos(),
+        "HEADERS received before SETTINGS",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn recv_data_before_settings() {
+    recv_frame_before_settings(frames::data(1, "hello"), "DATA received before SETTINGS").await;
+}
+
+#[tokio::test]
+async fn recv_ping_before_settings() {
+    recv_frame_before_settings(frames::ping([1; 8]), "PING received before SETTINGS").await;
+}
+
+#[tokio::test]
+async fn recv_settings_ack_before_settings() {
+    recv_frame_before_settings(
+        frames::settings_ack(),
+        "SETTINGS ACK received before SETTINGS",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn recv_connection_header() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let req = |id, name, val| {
+        frames::headers(id)
+            .request("GET", "https://example.com/")
+            .field(name, val)
+            .eos()
+    };
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client.send_frame(req(1, "connection", "foo")).await;
+        client.send_frame(req(3, "keep-alive", "5")).await;
+        client.send_frame(req(5, "proxy-connection", "bar")).await;
+        client
+            .send_frame(req(7, "transfer-encoding", "chunked"))
+            .await;
+        client.send_frame(req(9, "upgrade", "HTTP/2")).await;
+        client.recv_frame(frames::reset(1).protocol_error()).await;
+        client.recv_frame(frames::reset(3).protocol_error()).await;
+        client.recv_frame(frames::reset(5).protocol_error()).await;
+        client.recv_frame(frames::reset(7).protocol_error()).await;
+        client.recv_frame(frames::reset(9).protocol_error()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_te_header() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let req = |id, val| {
+        frames::headers(id)
+            .request("POST", "https://example.com/")
+            .field("te", val)
+            .eos()
+    };
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client.send_frame(req(1, "gzip")).await;
+        client.send_frame(req(3, "trailers, gzip")).await;
+        client.send_frame(req(5, "trailers")).await;
+        client.send_frame(req(7, "Trailers")).await;
         client.recv_frame(frames::reset(1).protocol_error()).await;
         client.recv_frame(frames::reset(3).protocol_error()).await;
-        client.recv_frame(frames::reset(5).protocol_error()).await;
-        client.recv_frame(frames::reset(7).protocol_error()).await;
-        client.recv_frame(frames::reset(9).protocol_error()).await;
+        client
+            .recv_frame(frames::headers(5).response(200).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(7).response(200).eos())
+            .await;
     };
 
     let srv = async move {
         let mut srv = server::handshake(io).await.expect("handshake");
+
+        for te in ["trailers", "Trailers"] {
+            let (req, mut stream) = srv.next().await.unwrap().unwrap();
+            assert_eq!(req.headers()["te"], te);
+
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, true).unwrap();
+        }
+
         assert!(srv.next().await.is_none());
     };
 
@@ -590,6 +1689,57 @@ async fn sends_reset_no_error_when_req_body_is_dropped() {
     join(client, srv).await;
 }
 
+#[tokio::test]
+async fn send_misdirected_request() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://other.example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(421).eos())
+            .await;
+        // the request body is still streaming when the server closes
+        client
+            .send_frame(frames::headers(3).request("POST", "https://other.example.com/"))
+            .await;
+        client
+            .recv_frame(frames::headers(3).response(421).eos())
+            .await;
+        client
+            .recv_frame(frames::reset(3).reason(Reason::NO_ERROR))
+            .await;
+        // DATA already in flight is ignored
+        client.send_frame(frames::data(3, "hello").eos()).await;
+        client.ping_pong([1; 8]).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+
+        for _ in 0..2 {
+            let (req, mut stream) = srv.next().await.unwrap().unwrap();
+            assert_eq!(req.uri().host(), Some("other.example.com"));
+            drop(req);
+
+            stream.send_misdirected_request().unwrap();
+            assert!(stream.send_misdirected_request().is_err());
+        }
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
 #[tokio::test]
 async fn abrupt_shutdown() {
     h2_support::trace_init!();
@@ -709,6 +1859,181 @@ async fn graceful_shutdown() {
     join(client, srv).await;
 }
 
+#[tokio::test]
+async fn max_streams_per_connection_starts_graceful_shutdown() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        // Below the limit, the stream is served as usual.
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+
+        // Opening the second stream reaches the limit.
+        client
+            .send_frame(
+                frames::headers(3)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::go_away(2147483647)).await;
+        client.recv_frame(frames::ping(frame::Ping::SHUTDOWN)).await;
+        client
+            .recv_frame(frames::headers(3).response(200).eos())
+            .await;
+        client
+            .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+            .await;
+        client.recv_frame(frames::go_away(3)).await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .max_streams_per_connection(2)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+
+        for _ in 0..2 {
+            let (_req, mut stream) = srv.next().await.unwrap().unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, true).unwrap();
+        }
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn max_frames_per_poll_yields_between_frames() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    /// Returns the payloads of the PONGs the server has written so far.
+    fn pongs(client: &mut mock::Handle) -> Vec<[u8; 8]> {
+        let mut pongs = vec![];
+        while let Some(frame) = client.next().now_or_never() {
+            if let frame::Frame::Ping(ping) = frame.unwrap().unwrap() {
+                assert!(ping.is_ack());
+                pongs.push(*ping.payload());
+            }
+        }
+        pongs
+    }
+
+    let handshake = server::Builder::new()
+        .max_frames_per_poll(2)
+        .handshake::<_, Bytes>(io);
+    let client_preface = async {
+        client.write_preface().await;
+        client.send_frame(frames::settings()).await;
+    };
+    let (srv, _) = join(handshake, client_preface).await;
+    let mut srv = srv.expect("handshake");
+
+    for payload in 1..=4 {
+        client.send_frame(frames::ping([payload; 8])).await;
+    }
+
+    // The first poll reads the SETTINGS frame and the first PING.
+    assert!(poll_fn(|cx| srv.poll_closed(cx)).now_or_never().is_none());
+    assert_eq!(pongs(&mut client), [[1; 8]]);
+
+    // Each following poll picks up where the last one stopped.
+    assert!(poll_fn(|cx| srv.poll_closed(cx)).now_or_never().is_none());
+    assert_eq!(pongs(&mut client), [[2; 8], [3; 8]]);
+
+    assert!(poll_fn(|cx| srv.poll_closed(cx)).now_or_never().is_none());
+    assert_eq!(pongs(&mut client), [[4; 8]]);
+
+    drop(client);
+    poll_fn(|cx| srv.poll_closed(cx)).await.unwrap();
+}
+
+#[tokio::test]
+async fn graceful_shutdown_accepts_streams_until_second_go_away() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client.recv_frame(frames::go_away(2147483647)).await;
+        client.recv_frame(frames::ping(frame::Ping::SHUTDOWN)).await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+        // Pretend these streams were sent while the GOAWAY was in flight
+        client
+            .send_frame(
+                frames::headers(3)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .send_frame(
+                frames::headers(5)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+            .await;
+        client.recv_frame(frames::go_away(5)).await;
+        client
+            .recv_frame(frames::headers(3).response(200).eos())
+            .await;
+        client
+            .recv_frame(frames::headers(5).response(200).eos())
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (_req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        srv.graceful_shutdown();
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        // both streams opened between the two GOAWAYs are accepted
+        for _ in 0..2 {
+            let (_req, mut stream) = srv.next().await.unwrap().unwrap();
+            let rsp = http::Response::builder().status(200).body(()).unwrap();
+            stream.send_response(rsp, true).unwrap();
+        }
+
+        assert!(srv.next().await.is_none(), "unexpected request");
+    };
+
+    join(client, srv).await;
+}
+
 #[tokio::test]
 async fn goaway_even_if_client_sent_goaway() {
     h2_support::trace_init!();
@@ -1065,6 +2390,24 @@ async fn server_error_on_unclean_shutdown() {
     srv.await.expect_err("should error");
 }
 
+#[tokio::test]
+async fn server_error_on_invalid_preface() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let srv = server::Builder::new().handshake::<_, Bytes>(io);
+
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+        .await
+        .expect("write");
+
+    let err = srv.await.expect_err("should error");
+    assert!(err.is_preface_error());
+    assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+    assert!(err.to_string().contains("GET / HTTP/1.1"), "{}", err);
+}
+
 #[tokio::test]
 async fn server_error_on_status_in_request() {
     h2_support::trace_init!();
@@ -1416,3 +2759,112 @@ async fn reject_informational_status_header_in_request() {
 
     join(client, srv).await;
 }
+
+#[tokio::test]
+async fn default_settings_disable_push() {
+    h2_support::trace_init!();
+
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        // SETTINGS_ENABLE_PUSH is not advertised by default.
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        assert!(!srv.effective_settings().local().is_push_enabled());
+        assert!(srv.effective_settings().remote().is_push_enabled());
+
+        assert!(srv.next().await.is_none());
+
+        // Still disabled once the client has acknowledged our SETTINGS.
+        assert!(!srv.effective_settings().local().is_push_enabled());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn application_error_resets_stream() {
+    #[derive(Clone, Debug, Default)]
+    struct RecordCloses(Arc<Mutex<Vec<(u32, TransitionCause)>>>);
+
+    impl StateObserver for RecordCloses {
+        fn on_transition(
+            &self,
+            id: h2::StreamId,
+            _: StreamState,
+            to: StreamState,
+            cause: TransitionCause,
+        ) {
+            if to == StreamState::Closed {
+                self.0.lock().unwrap().push((id.as_u32(), cause));
+            }
+        }
+    }
+
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+    let observer = RecordCloses::default();
+    let closes = observer.0.clone();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(
+                frames::headers(1)
+                    .request("GET", "https://example.com/")
+                    .eos(),
+            )
+            .await;
+        client
+            .send_frame(frames::headers(3).request("POST", "https://example.com/"))
+            .await;
+        // any error resets with INTERNAL_ERROR
+        client.recv_frame(frames::reset(1).internal_error()).await;
+        // while an `h2::Error` picks the reason
+        client.recv_frame(frames::headers(3).response(200)).await;
+        client.send_frame(frames::data(3, &b"hello"[..])).await;
+        client
+            .recv_frame(frames::reset(3).reason(Reason::ENHANCE_YOUR_CALM))
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::Builder::new()
+            .state_observer(observer)
+            .handshake::<_, Bytes>(io)
+            .await
+            .expect("handshake");
+
+        let (_, mut respond) = srv.next().await.unwrap().unwrap();
+        let err = std::io::Error::new(std::io::ErrorKind::Other, "backend unavailable");
+        assert_eq!(respond.send_error(&err), Reason::INTERNAL_ERROR);
+
+        let (req, mut respond) = srv.next().await.unwrap().unwrap();
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        let mut stream = respond.send_response(rsp, false).unwrap();
+
+        let app = async move {
+            let mut body = req.into_body();
+            assert_eq!(body.data().await.unwrap().unwrap(), "hello");
+
+            let err = h2::Error::from(Reason::ENHANCE_YOUR_CALM);
+            assert_eq!(stream.send_error(&err), Reason::ENHANCE_YOUR_CALM);
+        };
+
+        join(app, async move { assert!(srv.next().await.is_none()) }).await;
+    };
+
+    join(client, srv).await;
+    assert_eq!(
+        *closes.lock().unwrap(),
+        [
+            (1, TransitionCause::Reset(Reason::INTERNAL_ERROR)),
+            (3, TransitionCause::Reset(Reason::ENHANCE_YOUR_CALM)),
+        ]
+    );
+}
@@ -1,5 +1,8 @@
+use futures::future::join;
 use futures::StreamExt;
 use h2_support::prelude::*;
+use h2_support::util::yield_once;
+use tokio::sync::oneshot;
 
 #[tokio::test]
 async fn recv_trailers_only() {
@@ -109,3 +112,172 @@ async fn send_trailers_immediately() {
 fn recv_trailers_without_eos() {
     // This should be a protocol error?
 }
+
+#[tokio::test]
+async fn recv_trailers_with_pseudo_headers() {
+    h2_support::trace_init!();
+    let (io, mut srv) = mock::new();
+
+    let srv = async move {
+        let settings = srv.assert_client_handshake().await;
+        assert_default_settings!(settings);
+        srv.recv_frame(
+            frames::headers(1)
+                .request("GET", "https://http2.akamai.com/")
+                .eos(),
+        )
+        .await;
+        srv.send_frame(frames::headers(1).response(200)).await;
+        // trailers must not carry `:status`
+        srv.send_frame(frames::headers(1).response(200).eos()).await;
+        srv.recv_frame(frames::reset(1).protocol_error()).await;
+    };
+
+    let client = async move {
+        let (mut client, mut conn) = client::handshake(io).await.unwrap();
+        let request = Request::builder()
+            .uri("https://http2.akamai.com/")
+            .body(())
+            .unwrap();
+
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = conn.drive(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut body = response.into_body();
+        let err = conn
+            .drive(poll_fn(|cx| body.poll_trailers(cx)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.reason(), Some(Reason::PROTOCOL_ERROR));
+
+        drop(body);
+        drop(client);
+        conn.await.unwrap();
+    };
+
+    join(srv, client).await;
+}
+
+#[tokio::test]
+async fn recv_trailers_after_empty_body() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        // trailers directly follow the request headers
+        client
+            .send_frame(frames::headers(1).field("status", "ok").eos())
+            .await;
+        client
+            .recv_frame(frames::headers(1).response(200).eos())
+            .await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        let mut body = req.into_body();
+        assert!(body.data().await.is_none());
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["status"], "ok");
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_headers_after_trailers() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client
+            .send_frame(frames::headers(1).field("status", "ok").eos())
+            .await;
+        // a third header block on the same stream
+        client
+            .send_frame(frames::headers(1).field("status", "again").eos())
+            .await;
+        client.recv_frame(frames::reset(1).stream_closed()).await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, _stream) = srv.next().await.unwrap().unwrap();
+
+        let mut body = req.into_body();
+        assert!(body.data().await.is_none());
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["status"], "ok");
+
+        assert!(srv.next().await.is_none());
+    };
+
+    join(client, srv).await;
+}
+
+#[tokio::test]
+async fn recv_headers_after_stream_closed() {
+    h2_support::trace_init!();
+    let (io, mut client) = mock::new();
+
+    // Rendezvous when the server has queued its response.
+    let (tx, rx) = oneshot::channel();
+
+    let client = async move {
+        let settings = client.assert_server_handshake().await;
+        assert_default_settings!(settings);
+        client
+            .send_frame(frames::headers(1).request("POST", "https://example.com/"))
+            .await;
+        client
+            .send_frame(frames::headers(1).field("status", "ok").eos())
+            .await;
+        rx.await.unwrap();
+        // both sides ended the stream, though the response is still queued
+        client
+            .send_frame(frames::headers(1).field("status", "again").eos())
+            .await;
+        client
+            .recv_frame(frames::go_away(1).reason(Reason::STREAM_CLOSED))
+            .await;
+        client.recv_eof().await;
+    };
+
+    let srv = async move {
+        let mut srv = server::handshake(io).await.expect("handshake");
+        let (req, mut stream) = srv.next().await.unwrap().unwrap();
+
+        let mut body = req.into_body();
+        assert!(body.data().await.is_none());
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["status"], "ok");
+
+        let rsp = http::Response::builder().status(200).body(()).unwrap();
+        stream.send_response(rsp, true).unwrap();
+        tx.send(()).unwrap();
+        yield_once().await;
+
+        let err = srv.next().await.unwrap().unwrap_err();
+        assert_eq!(err.reason(), Some(Reason::STREAM_CLOSED));
+    };
+
+    join(client, srv).await;
+}